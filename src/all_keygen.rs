@@ -1,83 +1,81 @@
-use anyhow::Result;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use curv::elliptic::curves::Secp256k1;
 use futures::StreamExt;
 
+use crate::error::TssError;
 use crate::gg20;
-use crate::utils::common::{EcdsaLocalKeyData, EddsaLocalKeyData};
+use crate::gg20::state_machine::keygen::LocalKey;
+use crate::gg20::PreParams;
+use crate::t_ed25519::keygen::EddsaLocalKey;
+use crate::utils::common::{EcdsaLocalKeyData, EddsaLocalKeyData, EddsaOfflineResult};
+use crate::utils::secure_channel::Identity;
+use crate::utils::transport::Transport;
 use crate::{
-    t_ed25519::presignature,
+    t_ed25519::{self, presignature},
     utils::common::{self, EcdsaOfflineResult, KeygenResult},
 };
 
-pub async fn keygen_and_offline(
+/// Generates both keys, presignatures for every signing group and a batch of EdDSA nonces
+///
+/// `identity` is the long-term key of the device, keygen binds it to the party index. ECDSA
+/// Paillier key and `N_tilde` come from `pre_params`, see [PreParams::generate]. Without
+/// `presign` the ECDSA key comes without presignatures, they are generated later for the signer
+/// sets in use with [generate_presignatures]: the number of signing groups grows too fast with
+/// `n` to presign for all of them. Every protocol round fails after `round_timeout` if some party
+/// didn't send its message, `None` waits forever. With `echo_broadcast` parties confirm to each
+/// other every broadcasted message, see
+/// [EchoBroadcast](crate::utils::echo_broadcast::EchoBroadcast).
+pub async fn keygen_and_offline<T>(
     request_id: &str,
-    token: &str,
-    rust_address: &str,
+    transport: &T,
+    identity: &Identity,
     rust_room: &str,
     rust_t: u16,
     rust_n: u16,
     max_nonce_per_refresh: u16,
     rust_name: &str,
-) -> Result<KeygenResult> {
+    pre_params: PreParams,
+    presign: bool,
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<KeygenResult>
+where
+    T: Transport + ?Sized,
+{
     // keygen ecdsa
-    let (party_id, ecdsa_local_key, members) = gg20::keygen::start_keygen(
+    let (party_id, ecdsa_local_key) = gg20::keygen::start_keygen(
         request_id,
-        token,
-        rust_address,
+        transport,
+        identity,
         rust_room,
         rust_t,
         rust_n,
         rust_name,
+        pre_params,
+        round_timeout,
+        echo_broadcast,
     )
     .await?;
+    // the rest of the pipeline talks only to the parties of keygen
+    let roster = ecdsa_local_key.roster.clone().unwrap_or_default();
+    let identity = &identity.with_roster(Some(&roster));
 
-    let all_parties: Vec<u16> = (1..(rust_n + 1)).collect();
-    // find all subsets in all_parties that size is t
-    let all_subsets_parties: Vec<Vec<u16>> = common::powerset(all_parties.as_slice())
-        .into_iter()
-        .filter(|subset| subset.len() == (rust_t + 1) as usize && subset.contains(&party_id))
-        .collect();
-    println!(
-        "requestId={} ecdsa - party: {} will pair with {:?}",
-        request_id,
-        party_id,
-        all_subsets_parties.clone()
-    );
-
-    let mut ecdsa_offline_data: Vec<EcdsaOfflineResult> = vec![];
-    let mut progress = 0;
-    for mut parties in all_subsets_parties.clone() {
-        parties.sort();
-        let completed_offline = crate::gg20::presignature::generate_offline_signing(
+    let ecdsa_offline_data = if presign {
+        generate_all_offline(
             request_id,
-            token,
+            transport,
+            identity,
+            rust_room,
             &ecdsa_local_key,
-            rust_address,
-            format!(
-                "{}-parties-{}",
-                rust_room,
-                parties
-                    .clone()
-                    .into_iter()
-                    .map(|x| x.to_string())
-                    .collect::<Vec<String>>()
-                    .join("_")
-            )
-            .as_str(),
-            party_id,
-            parties.clone(),
+            round_timeout,
+            echo_broadcast,
         )
-        .await?;
-        ecdsa_offline_data.push(EcdsaOfflineResult {
-            parties: parties.clone(),
-            completed_offline,
-        });
-        progress = progress + 1;
-        println!(
-            "requestId={} progress: {}%",
-            request_id,
-            progress * 100 / all_subsets_parties.clone().len()
-        );
-    }
+        .await?
+    } else {
+        vec![]
+    };
 
     // keygen eddsa
     println!(
@@ -86,22 +84,17 @@ pub async fn keygen_and_offline(
     );
     let eddsa_local_key = crate::t_ed25519::keygen::start_keygen(
         request_id,
-        token,
-        rust_address,
+        transport,
+        identity,
         rust_room,
         rust_t,
         rust_n,
         party_id,
+        round_timeout,
+        echo_broadcast,
     )
     .await?;
 
-    println!(
-        "requestId={} eddsa - party: {} will pair with {:?}",
-        request_id,
-        party_id,
-        all_subsets_parties.clone()
-    );
-
     let ecdsa = EcdsaLocalKeyData {
         local_key: ecdsa_local_key,
         offline_data: ecdsa_offline_data,
@@ -110,12 +103,14 @@ pub async fn keygen_and_offline(
 
     let eddsa_offline_data = presignature::generate_dynamic_nonces(
         request_id,
-        token,
-        &rust_address,
-        &rust_room,
+        transport,
+        identity,
+        rust_room,
         0,
         max_nonce_per_refresh,
         &eddsa_local_key,
+        round_timeout,
+        echo_broadcast,
     )
     .await?;
 
@@ -127,191 +122,827 @@ pub async fn keygen_and_offline(
             offline_data: eddsa_offline_data,
             algorithm: String::from("t_ed25519"),
         },
-        members,
+        members: transport.members(rust_room).await?,
+        roster,
     });
 }
 
-#[ignore]
-#[cfg(test)]
-mod test {
-    use std::thread;
+/// Generates a batch of EdDSA nonces for an existing key over `transport`
+pub async fn generate_nonces<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    rust_room: &str,
+    nonce_start_index: u16,
+    max_nonce_per_refresh: u16,
+    eddsa_local_key: &EddsaLocalKey,
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<EddsaOfflineResult>
+where
+    T: Transport + ?Sized,
+{
+    let identity = identity.with_roster(eddsa_local_key.roster.as_ref());
+    presignature::generate_dynamic_nonces(
+        request_id,
+        transport,
+        &identity,
+        rust_room,
+        nonce_start_index,
+        max_nonce_per_refresh,
+        eddsa_local_key,
+        round_timeout,
+        echo_broadcast,
+    )
+    .await
+}
+
+/// Generates `count` ECDSA presignatures for signer set `signers` over `transport`
+///
+/// All signers of the set must join the same `rust_room`, which must be a fresh room. New
+/// presignatures are returned alone, see [EcdsaLocalKeyData::add_presignatures].
+pub async fn generate_presignatures<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    rust_room: &str,
+    local_key: &LocalKey<Secp256k1>,
+    signers: Vec<u16>,
+    count: u16,
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<Vec<EcdsaOfflineResult>>
+where
+    T: Transport + ?Sized,
+{
+    let identity = identity.with_roster(local_key.roster.as_ref());
+    gg20::presignature::generate_presignatures(
+        request_id,
+        transport,
+        &identity,
+        local_key,
+        rust_room,
+        signers,
+        count,
+        round_timeout,
+        echo_broadcast,
+    )
+    .await
+}
+
+/// Refreshes ECDSA key shares and replaces every presignature
+///
+/// Presignatures of the previous key epoch are dropped, with `presign` new ones are generated for
+/// every signer subset like in [keygen_and_offline]. `rust_room` must be a fresh room. Paillier
+/// key and `N_tilde` of the refreshed key are taken from `pre_params`. `round_timeout` applies to
+/// every round, `echo_broadcast` to the presignature rounds, see [keygen_and_offline].
+pub async fn refresh_and_offline<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    rust_room: &str,
+    local_key_data: &EcdsaLocalKeyData,
+    pre_params: PreParams,
+    presign: bool,
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<EcdsaLocalKeyData>
+where
+    T: Transport + ?Sized,
+{
+    let identity = identity.with_roster(local_key_data.local_key.roster.as_ref());
+    let refreshed_key = gg20::refresh::start_refresh(
+        request_id,
+        transport,
+        &identity,
+        rust_room,
+        &local_key_data.local_key,
+        pre_params,
+        round_timeout,
+    )
+    .await?;
 
-    use rand::Rng;
+    let offline_data = if presign {
+        generate_all_offline(
+            request_id,
+            transport,
+            &identity,
+            rust_room,
+            &refreshed_key,
+            round_timeout,
+            echo_broadcast,
+        )
+        .await?
+    } else {
+        vec![]
+    };
+
+    Ok(EcdsaLocalKeyData {
+        local_key: refreshed_key,
+        offline_data,
+        algorithm: local_key_data.algorithm.clone(),
+    })
+}
+
+/// Refreshes EdDSA key shares and generates a new batch of nonces for the refreshed key
+///
+/// Nonces are generated in the same way as [presignature::generate_dynamic_nonces] does it for
+/// an existing key. `round_timeout` applies to every round, `echo_broadcast` to the nonce rounds.
+pub async fn refresh_and_generate_nonces<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    rust_room: &str,
+    nonce_start_index: u16,
+    max_nonce_per_refresh: u16,
+    local_key_data: &EddsaLocalKeyData,
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<EddsaLocalKeyData>
+where
+    T: Transport + ?Sized,
+{
+    let identity = identity.with_roster(local_key_data.local_key.roster.as_ref());
+    let refreshed_key = t_ed25519::refresh::start_refresh(
+        request_id,
+        transport,
+        &identity,
+        rust_room,
+        &local_key_data.local_key,
+        round_timeout,
+    )
+    .await?;
+
+    let offline_data = presignature::generate_dynamic_nonces(
+        request_id,
+        transport,
+        &identity,
+        rust_room,
+        nonce_start_index,
+        max_nonce_per_refresh,
+        &refreshed_key,
+        round_timeout,
+        echo_broadcast,
+    )
+    .await?;
 
-    use crate::{
-        gg20::signing,
-        t_ed25519::{self, presignature},
+    Ok(EddsaLocalKeyData {
+        local_key: refreshed_key,
+        offline_data,
+        algorithm: local_key_data.algorithm.clone(),
+    })
+}
+
+/// Reshares both keys to a new committee of `new_n` parties with threshold `new_t`
+///
+/// `n` is the number of participants in the room: holders of the old keys and new members.
+/// Participants which stay in the committee get fresh nonces and, with `presign`, presignatures
+/// for every signer subset. Parties leaving the committee get `None`. `rust_room` must be a fresh
+/// room. `round_timeout` applies to every round, `echo_broadcast` to the presignature and nonce
+/// rounds.
+///
+/// Parties are re-indexed in the room. Holders of the old keys check that the other holders join
+/// with the identity keys of the old roster, and the reshared keys come with the roster of the new
+/// committee.
+pub async fn reshare_and_offline<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    rust_room: &str,
+    n: u16,
+    old_keys: Option<(&EcdsaLocalKeyData, &EddsaLocalKeyData)>,
+    new_member: bool,
+    new_t: u16,
+    new_n: u16,
+    max_nonce_per_refresh: u16,
+    rust_name: &str,
+    presign: bool,
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<Option<KeygenResult>>
+where
+    T: Transport + ?Sized,
+{
+    let (room_index, room_roster, ecdsa_local_key) = gg20::reshare::start_reshare(
+        request_id,
+        transport,
+        identity,
+        rust_room,
+        n,
+        old_keys.map(|(ecdsa, _)| &ecdsa.local_key),
+        new_member,
+        new_t,
+        new_n,
+        rust_name,
+        round_timeout,
+    )
+    .await?;
+    // EdDSA key is reshared by the same participants
+    let eddsa_local_key = t_ed25519::reshare::start_reshare(
+        request_id,
+        transport,
+        &identity.with_complete_roster(&room_roster),
+        rust_room,
+        n,
+        room_index,
+        old_keys.map(|(_, eddsa)| &eddsa.local_key),
+        new_member,
+        new_t,
+        new_n,
+        round_timeout,
+    )
+    .await?;
+
+    let (ecdsa_local_key, eddsa_local_key) = match (ecdsa_local_key, eddsa_local_key) {
+        (Some(ecdsa), Some(eddsa)) => (ecdsa, eddsa),
+        (None, None) => return Ok(None),
+        _ => return Err(anyhow!("party left only one of the reshared keys")),
+    };
+    if ecdsa_local_key.roster != eddsa_local_key.roster {
+        return Err(anyhow!("reshared keys have different rosters"));
+    }
+    let roster = ecdsa_local_key.roster.clone().unwrap_or_default();
+    let identity = &identity.with_roster(Some(&roster));
+    let ecdsa_offline_data = if presign {
+        generate_all_offline(
+            request_id,
+            transport,
+            identity,
+            rust_room,
+            &ecdsa_local_key,
+            round_timeout,
+            echo_broadcast,
+        )
+        .await?
+    } else {
+        vec![]
     };
+    let eddsa_offline_data = presignature::generate_dynamic_nonces(
+        request_id,
+        transport,
+        identity,
+        rust_room,
+        0,
+        max_nonce_per_refresh,
+        &eddsa_local_key,
+        round_timeout,
+        echo_broadcast,
+    )
+    .await?;
+    let members = transport.members(rust_room).await?;
 
-    #[tokio::test]
-    async fn e2e_3_parties() {
-        let data_to_sign = "hello".as_bytes().to_vec();
-        let id: u16 = rand::thread_rng().gen();
-        let room_id = format!("{}_{}", "kg_e2e_bundle", id);
-        let room_id = room_id.as_str();
-        let address = "http://localhost:8000";
-        let t = 1;
-        let n = 3;
-        let no_nonces = 10;
-        let _signing_message = "hello".as_bytes().to_vec();
-        let machine1 = async move {
-            thread::sleep(std::time::Duration::from_secs(1));
-            println!("started machine1");
-            let keygen_future = crate::all_keygen::keygen_and_offline(
-                "requestId",
-                "user1",
-                address,
-                room_id,
+    Ok(Some(KeygenResult {
+        party_id: ecdsa_local_key.i,
+        ecdsa: EcdsaLocalKeyData {
+            local_key: ecdsa_local_key,
+            offline_data: ecdsa_offline_data,
+            algorithm: String::from("gg20"),
+        },
+        eddsa: EddsaLocalKeyData {
+            local_key: eddsa_local_key,
+            offline_data: eddsa_offline_data,
+            algorithm: String::from("t_ed25519"),
+        },
+        members,
+        roster,
+    }))
+}
+
+/// Recovers both key shares of party `lost_i` with help of `t+1` `helpers`
+///
+/// Helpers pass their key data, the replacement device passes `None` and its new Paillier key and
+/// `N_tilde` in `pre_params`. Other survivors may join as `observers` with their key data: they
+/// skip the EdDSA recovery, but learn the new Paillier key and identity key of the replacement
+/// device. Only the helpers, the observers and the replacement device join `rust_room`, which
+/// must be a fresh room. Survivors drop presignatures of signer sets containing `lost_i` since
+/// the lost device's ones are gone and its Paillier key changes. With `presign` new ones are
+/// generated for the signer sets formed by the participants which contain the replacement
+/// device. The replacement device comes without EdDSA nonces, they are generated by all `n`
+/// parties with [generate_nonces]. Survivors who didn't take part have to
+/// [refresh](refresh_and_offline) with everybody else before presigning with the replacement
+/// device. `round_timeout` applies to every round, `echo_broadcast` to the presignature rounds.
+///
+/// Names of the parties stay with the caller, `members` of the result are empty.
+pub async fn recover_and_offline<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    rust_room: &str,
+    t: u16,
+    n: u16,
+    old_keys: Option<(&EcdsaLocalKeyData, &EddsaLocalKeyData)>,
+    pre_params: Option<PreParams>,
+    lost_i: u16,
+    helpers: &[u16],
+    observers: &[u16],
+    presign: bool,
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<KeygenResult>
+where
+    T: Transport + ?Sized,
+{
+    let party_id = old_keys
+        .map(|(ecdsa, _)| ecdsa.local_key.i)
+        .unwrap_or(lost_i);
+    let mut distinct = helpers.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    let mut survivors = [helpers, observers].concat();
+    survivors.sort_unstable();
+    survivors.dedup();
+    if lost_i == 0
+        || lost_i > n
+        || distinct.len() != usize::from(t) + 1
+        || distinct.len() != helpers.len()
+        || survivors.len() != helpers.len() + observers.len()
+        || survivors.iter().any(|&j| j == 0 || j > n || j == lost_i)
+        || (party_id != lost_i && !survivors.contains(&party_id))
+    {
+        return Err(TssError::invalid_input(format_args!(
+            "party {} can't recover party {} of a {}-of-{} key with helpers {:?} observers {:?}",
+            party_id,
+            lost_i,
+            t + 1,
+            n,
+            helpers,
+            observers
+        ))
+        .into());
+    }
+
+    // replacement device comes with a new identity key
+    let mut roster = old_keys.and_then(|(ecdsa, _)| ecdsa.local_key.roster.clone());
+    if let Some(roster) = &mut roster {
+        roster.remove(&lost_i);
+    }
+    let identity = identity.with_roster(roster.as_ref());
+    let ecdsa_local_key = gg20::recover::start_recovery(
+        request_id,
+        transport,
+        &identity,
+        rust_room,
+        t,
+        n,
+        old_keys.map(|(ecdsa, _)| &ecdsa.local_key),
+        pre_params,
+        lost_i,
+        helpers,
+        observers,
+        round_timeout,
+    )
+    .await?;
+    // the replacement device learns identity keys of the others from the helpers
+    let roster = ecdsa_local_key.roster.clone().unwrap_or_default();
+    let identity = identity.with_roster(Some(&roster));
+    let eddsa_local_key = match old_keys {
+        // EdDSA keys carry no Paillier key, observers only learn the new identity key
+        Some((_, eddsa)) if !helpers.contains(&party_id) => EddsaLocalKey {
+            roster: Some(roster.clone()),
+            ..eddsa.local_key.clone()
+        },
+        _ => {
+            t_ed25519::recover::start_recovery(
+                request_id,
+                transport,
+                &identity,
+                rust_room,
                 t,
                 n,
-                1,
-                "A",
+                old_keys.map(|(_, eddsa)| &eddsa.local_key),
+                lost_i,
+                helpers,
+                round_timeout,
             )
-            .await;
-            let keygen_result = keygen_future.unwrap();
-            println!(
-                "machine1: keygen result {}",
-                serde_json::to_string(&keygen_result).unwrap()
-            );
+            .await?
+        }
+    };
 
-            let eddsa_generated_nonce = presignature::generate_dynamic_nonces(
-                "requestId",
-                "user1",
-                address,
-                room_id,
-                0,
-                no_nonces,
-                &keygen_result.eddsa.local_key,
-            )
-            .await;
-            println!(
-                "machine1: eddsa_generated_nonce count {}",
-                eddsa_generated_nonce.unwrap().completed_offline.len()
-            );
-            keygen_result
-        };
-        let machine2 = async move {
-            thread::sleep(std::time::Duration::from_secs(1));
-            println!("started machine2");
-            let keygen_future = crate::all_keygen::keygen_and_offline(
-                "requestId",
-                "user2",
-                address,
-                room_id,
-                t,
-                n,
-                1,
-                "B",
+    let mut ecdsa_offline_data: Vec<EcdsaOfflineResult> = old_keys
+        .map(|(ecdsa, _)| ecdsa.offline_data.clone())
+        .unwrap_or_default();
+    ecdsa_offline_data.retain(|offline| !offline.parties.contains(&lost_i));
+    if presign {
+        let recovery_parties = gg20::recover::recovery_parties(&survivors, lost_i);
+        let signer_sets = common::powerset(recovery_parties.as_slice())
+            .into_iter()
+            .filter(|subset| {
+                subset.len() == usize::from(t) + 1
+                    && subset.contains(&lost_i)
+                    && subset.contains(&party_id)
+            })
+            .collect();
+        ecdsa_offline_data.extend(
+            generate_offline(
+                request_id,
+                transport,
+                &identity,
+                rust_room,
+                &ecdsa_local_key,
+                signer_sets,
+                round_timeout,
+                echo_broadcast,
             )
-            .await;
-            let keygen_result = keygen_future.unwrap();
-            println!(
-                "machine2: keygen result {}",
-                serde_json::to_string(&keygen_result).unwrap()
-            );
+            .await?,
+        );
+    }
 
-            let eddsa_generated_nonce = presignature::generate_dynamic_nonces(
-                "requestId",
-                "user2",
-                address,
-                room_id,
-                0,
-                no_nonces,
-                &keygen_result.eddsa.local_key,
+    // shares don't change, so nonces of the survivors still sign
+    let eddsa_offline_data = match old_keys {
+        Some((_, eddsa)) => eddsa.offline_data.clone(),
+        None => EddsaOfflineResult {
+            parties: (1..=n).collect(),
+            nonce_start_index: 0,
+            nonce_size: 0,
+            completed_offline: vec![],
+            consumed_nonces: Default::default(),
+            key_epoch: Some(eddsa_local_key.epoch()),
+        },
+    };
+
+    Ok(KeygenResult {
+        party_id,
+        ecdsa: EcdsaLocalKeyData {
+            local_key: ecdsa_local_key,
+            offline_data: ecdsa_offline_data,
+            algorithm: old_keys
+                .map(|(ecdsa, _)| ecdsa.algorithm.clone())
+                .unwrap_or_else(|| String::from("gg20")),
+        },
+        eddsa: EddsaLocalKeyData {
+            local_key: eddsa_local_key,
+            offline_data: eddsa_offline_data,
+            algorithm: old_keys
+                .map(|(_, eddsa)| eddsa.algorithm.clone())
+                .unwrap_or_else(|| String::from("t_ed25519")),
+        },
+        members: vec![],
+        roster,
+    })
+}
+
+async fn generate_all_offline<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    rust_room: &str,
+    ecdsa_local_key: &LocalKey<Secp256k1>,
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<Vec<EcdsaOfflineResult>>
+where
+    T: Transport + ?Sized,
+{
+    let party_id = ecdsa_local_key.i;
+    let all_parties: Vec<u16> = (1..(ecdsa_local_key.n + 1)).collect();
+    // find all subsets in all_parties that size is t
+    let all_subsets_parties: Vec<Vec<u16>> = common::powerset(all_parties.as_slice())
+        .into_iter()
+        .filter(|subset| {
+            subset.len() == (ecdsa_local_key.t + 1) as usize && subset.contains(&party_id)
+        })
+        .collect();
+    generate_offline(
+        request_id,
+        transport,
+        identity,
+        rust_room,
+        ecdsa_local_key,
+        all_subsets_parties,
+        round_timeout,
+        echo_broadcast,
+    )
+    .await
+}
+
+/// Generates one presignature for each of `all_subsets_parties`
+async fn generate_offline<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    rust_room: &str,
+    ecdsa_local_key: &LocalKey<Secp256k1>,
+    all_subsets_parties: Vec<Vec<u16>>,
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<Vec<EcdsaOfflineResult>>
+where
+    T: Transport + ?Sized,
+{
+    let party_id = ecdsa_local_key.i;
+    println!(
+        "requestId={} ecdsa - party: {} will pair with {:?}",
+        request_id,
+        party_id,
+        all_subsets_parties.clone()
+    );
+
+    let mut ecdsa_offline_data: Vec<EcdsaOfflineResult> = vec![];
+    let mut progress = 0;
+    for mut parties in all_subsets_parties.clone() {
+        parties.sort();
+        let completed_offline = crate::gg20::presignature::generate_offline_signing(
+            request_id,
+            transport,
+            identity,
+            ecdsa_local_key,
+            format!(
+                "{}-parties-{}",
+                rust_room,
+                parties
+                    .clone()
+                    .into_iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<String>>()
+                    .join("_")
             )
-            .await;
-            println!(
-                "machine2: eddsa_generated_nonce count {}",
-                eddsa_generated_nonce.unwrap().completed_offline.len()
-            );
-            keygen_result
-        };
-        let machine3 = async move {
-            thread::sleep(std::time::Duration::from_secs(1));
-            println!("started machine3");
-            let keygen_future = crate::all_keygen::keygen_and_offline(
+            .as_str(),
+            party_id,
+            parties.clone(),
+            round_timeout,
+            echo_broadcast,
+        )
+        .await?;
+        ecdsa_offline_data.push(EcdsaOfflineResult {
+            parties: parties.clone(),
+            completed_offline,
+            consumed: false,
+        });
+        progress = progress + 1;
+        println!(
+            "requestId={} progress: {}%",
+            request_id,
+            progress * 100 / all_subsets_parties.clone().len()
+        );
+    }
+    Ok(ecdsa_offline_data)
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::utils::common::SigningState;
+    use crate::utils::transport::InMemoryTransport;
+    use crate::{gg20::signing, t_ed25519};
+
+    use super::*;
+
+    /// Runs keygen pipeline of `n` parties within one process
+    pub async fn simulate_keygen_and_offline(
+        t: u16,
+        n: u16,
+        max_nonce_per_refresh: u16,
+        echo_broadcast: bool,
+        presign: bool,
+    ) -> Vec<KeygenResult> {
+        let transport = InMemoryTransport::new();
+        let names: Vec<String> = (1..=n).map(|i| format!("party{}", i)).collect();
+        let identities: Vec<Identity> = names.iter().map(|_| Identity::generate()).collect();
+        let parties = names.iter().zip(&identities).map(|(name, identity)| {
+            keygen_and_offline(
                 "requestId",
-                "user3",
-                address,
-                room_id,
+                &transport,
+                identity,
+                "room",
                 t,
                 n,
-                1,
-                "C",
+                max_nonce_per_refresh,
+                name,
+                PreParams::generate(gg20::DEFAULT_MODULUS_BITS),
+                presign,
+                None,
+                echo_broadcast,
             )
-            .await;
-            let keygen_result = keygen_future.unwrap();
-            println!(
-                "machine3: keygen result {}",
-                serde_json::to_string(&keygen_result).unwrap()
-            );
+        });
+        futures::future::join_all(parties)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap()
+    }
 
-            let eddsa_generated_nonce = presignature::generate_dynamic_nonces(
-                "requestId",
-                "user3",
-                address,
-                room_id,
-                0,
-                no_nonces,
-                &keygen_result.eddsa.local_key,
-            )
-            .await;
-            println!(
-                "machine3: eddsa_generated_nonce count {}",
-                eddsa_generated_nonce.unwrap().completed_offline.len()
+    #[tokio::test]
+    async fn e2e_3_parties() {
+        let data_to_sign = "hello".as_bytes().to_vec();
+        let t = 1;
+        let n = 3;
+        let mut results = simulate_keygen_and_offline(t, n, 1, false, true).await;
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| result.party_id)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        for result in &results {
+            // every party takes part in 2 of 3 signing groups
+            assert_eq!(result.ecdsa.offline_data.len(), 2);
+            assert_eq!(result.eddsa.offline_data.completed_offline.len(), 1);
+            assert_eq!(
+                result.ecdsa.local_key.public_key(),
+                results[0].ecdsa.local_key.public_key()
             );
-            keygen_result
-        };
-        let (keygen_result1, keygen_result2, _keygen_result3) =
-            tokio::join!(machine1, machine2, machine3);
+            assert_eq!(
+                result.eddsa.local_key.agg_pubkey,
+                results[0].eddsa.local_key.agg_pubkey
+            );
+            // all parties agree on identity keys, which are stored in the local keys
+            assert_eq!(result.roster.len(), 3);
+            assert_eq!(result.roster, results[0].roster);
+            assert_eq!(result.ecdsa.local_key.roster.as_ref(), Some(&result.roster));
+            assert_eq!(result.eddsa.local_key.roster.as_ref(), Some(&result.roster));
+        }
 
-        let parties = vec![keygen_result1.party_id, keygen_result2.party_id];
-        let mut state_ecdsa = crate::utils::common::SigningState {
+        let parties = vec![results[0].party_id, results[2].party_id];
+        let mut state_ecdsa = SigningState {
             t,
             n,
             signing_parts: vec![],
             signature: None,
         };
-        signing::sign(
-            &mut state_ecdsa,
-            &keygen_result1.ecdsa,
-            data_to_sign.clone(),
-            keygen_result1.party_id,
-            parties.clone(),
-        )
-        .unwrap();
-        signing::sign(
-            &mut state_ecdsa,
-            &keygen_result2.ecdsa,
-            data_to_sign.clone(),
-            keygen_result2.party_id,
-            parties.clone(),
-        )
-        .unwrap();
-        println!("signed ecdsa message: {:?}", state_ecdsa.signature);
+        for i in [0, 2] {
+            let keygen_result = &mut results[i];
+            signing::sign(
+                &mut state_ecdsa,
+                &mut keygen_result.ecdsa,
+                data_to_sign.clone(),
+                keygen_result.party_id,
+                parties.clone(),
+            )
+            .unwrap();
+        }
+        assert!(state_ecdsa.signature.is_some());
 
-        let mut state_eddsa = crate::utils::common::SigningState {
+        let mut state_eddsa = SigningState {
             t,
             n,
             signing_parts: vec![],
             signature: None,
         };
-        t_ed25519::signing::sign(
-            &mut state_eddsa,
-            &keygen_result1.eddsa,
-            data_to_sign.clone(),
-            keygen_result1.party_id,
-            0,
-        )
-        .unwrap();
-        t_ed25519::signing::sign(
-            &mut state_eddsa,
-            &keygen_result2.eddsa,
-            data_to_sign.clone(),
-            keygen_result2.party_id,
-            0,
+        for i in [0, 2] {
+            let keygen_result = &mut results[i];
+            t_ed25519::signing::sign(
+                &mut state_eddsa,
+                &mut keygen_result.eddsa,
+                data_to_sign.clone(),
+                keygen_result.party_id,
+                0,
+            )
+            .unwrap();
+        }
+        assert!(state_eddsa.signature.is_some());
+    }
+
+    #[tokio::test]
+    async fn keygen_with_echo_broadcast() {
+        let results = simulate_keygen_and_offline(1, 3, 1, true, true).await;
+        for result in &results {
+            assert_eq!(result.ecdsa.offline_data.len(), 2);
+            assert_eq!(result.eddsa.offline_data.completed_offline.len(), 1);
+            assert_eq!(
+                result.ecdsa.local_key.public_key(),
+                results[0].ecdsa.local_key.public_key()
+            );
+            assert_eq!(
+                result.eddsa.local_key.agg_pubkey,
+                results[0].eddsa.local_key.agg_pubkey
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn generates_more_nonces_for_existing_key() {
+        let results = simulate_keygen_and_offline(1, 2, 1, false, true).await;
+        let transport = InMemoryTransport::new();
+        let identities: Vec<Identity> = results.iter().map(|_| Identity::generate()).collect();
+        let nonces =
+            futures::future::join_all(results.iter().zip(&identities).map(|(result, identity)| {
+                presignature::generate_dynamic_nonces(
+                    "requestId",
+                    &transport,
+                    identity,
+                    "room",
+                    1,
+                    3,
+                    &result.eddsa.local_key,
+                    None,
+                    false,
+                )
+            }))
+            .await;
+        for nonces in nonces {
+            let nonces = nonces.unwrap();
+            assert_eq!((nonces.nonce_start_index, nonces.nonce_size), (1, 4));
+            assert_eq!(nonces.completed_offline.len(), 3);
+        }
+    }
+
+    #[tokio::test]
+    async fn presigns_on_demand_for_one_signer_set() {
+        let mut results = simulate_keygen_and_offline(1, 3, 1, false, false).await;
+        for result in &results {
+            assert!(result.ecdsa.offline_data.is_empty());
+        }
+
+        let transport = InMemoryTransport::new();
+        let identities: Vec<Identity> = results.iter().map(|_| Identity::generate()).collect();
+        let signers = vec![results[0].party_id, results[2].party_id];
+        let presignatures = futures::future::join_all([0, 2].map(|i| {
+            gg20::presignature::generate_presignatures(
+                "requestId",
+                &transport,
+                &identities[i],
+                &results[i].ecdsa.local_key,
+                "room",
+                signers.clone(),
+                2,
+                None,
+                false,
+            )
+        }))
+        .await;
+        for (i, presignatures) in [0, 2].into_iter().zip(presignatures) {
+            let presignatures = presignatures.unwrap();
+            assert_eq!(presignatures.len(), 2);
+            results[i].ecdsa.add_presignatures(presignatures);
+            assert_eq!(results[i].ecdsa.remaining_presignatures(), 2);
+        }
+
+        for message in [b"first", b"other"] {
+            let mut state = SigningState {
+                t: 1,
+                n: 3,
+                signing_parts: vec![],
+                signature: None,
+            };
+            for i in [0, 2] {
+                signing::sign(
+                    &mut state,
+                    &mut results[i].ecdsa,
+                    message.to_vec(),
+                    results[i].party_id,
+                    signers.clone(),
+                )
+                .unwrap();
+            }
+            assert!(state.signature.is_some());
+        }
+        assert_eq!(results[0].ecdsa.remaining_presignatures(), 0);
+    }
+
+    #[tokio::test]
+    async fn signers_agree_on_presignature_after_dropped_session() {
+        let mut results = simulate_keygen_and_offline(1, 3, 1, false, false).await;
+        let transport = InMemoryTransport::new();
+        let identities: Vec<Identity> = results.iter().map(|_| Identity::generate()).collect();
+        let signers = vec![results[0].party_id, results[2].party_id];
+        let presignatures = futures::future::join_all([0, 2].map(|i| {
+            gg20::presignature::generate_presignatures(
+                "requestId",
+                &transport,
+                &identities[i],
+                &results[i].ecdsa.local_key,
+                "room",
+                signers.clone(),
+                2,
+                None,
+                false,
+            )
+        }))
+        .await;
+        for (i, presignatures) in [0, 2].into_iter().zip(presignatures) {
+            results[i].ecdsa.add_presignatures(presignatures.unwrap());
+        }
+        let new_state = || SigningState {
+            t: 1,
+            n: 3,
+            signing_parts: vec![],
+            signature: None,
+        };
+
+        // the first signer consumes a presignature, the session is dropped before the second signs
+        signing::sign(
+            &mut new_state(),
+            &mut results[0].ecdsa,
+            b"dropped".to_vec(),
+            results[0].party_id,
+            signers.clone(),
         )
         .unwrap();
-        println!("signed eddsa message: {:?}", state_eddsa.signature);
+
+        let mut state = new_state();
+        for i in [0, 2] {
+            signing::sign(
+                &mut state,
+                &mut results[i].ecdsa,
+                b"next".to_vec(),
+                results[i].party_id,
+                signers.clone(),
+            )
+            .unwrap();
+        }
+        assert!(state.signature.is_some());
+        assert_eq!(results[0].ecdsa.remaining_presignatures(), 0);
+        // the presignature of the dropped session is still unused by the second signer
+        assert_eq!(results[2].ecdsa.remaining_presignatures(), 1);
     }
 }
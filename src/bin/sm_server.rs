@@ -0,0 +1,489 @@
+//! Reference state manager relaying protocol messages between parties
+//!
+//! Implements the server side of [SmClient](tssv3::utils::sm_client::SmClient) so that the whole
+//! keygen / signing flow can be run against a local stand-in:
+//!
+//! * `POST rooms/{room}/issue_unique_idx` issues an index of the party in the room
+//! * `GET rooms/{room}/subscribe` streams messages of the room as server-sent events
+//! * `POST rooms/{room}/broadcast` sends a message to everyone in the room
+//! * `GET|POST rooms/{room}/status` reads members of the room or reports progress of a party
+//!
+//! Parties are identified by `X-Token` header. Only parties which got an index can subscribe to
+//! and broadcast in the room, at most `--max-parties` of them. Rooms are dropped together with
+//! their messages once nobody used them for `--room-ttl-secs`.
+//!
+//! Build with `cargo run --features sm-server --bin sm_server`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_std::channel::{self, Receiver, Sender};
+use secp256k1::PublicKey;
+use serde::Serialize;
+use structopt::StructOpt;
+use thiserror::Error;
+use tide::{Request, StatusCode};
+
+use tssv3::utils::common::IssueIndexMsg;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "sm_server")]
+struct Cli {
+    #[structopt(long, default_value = "127.0.0.1:8000")]
+    address: String,
+    /// Drops rooms which had no activity for this long
+    #[structopt(long, default_value = "600")]
+    room_ttl_secs: u64,
+    /// Maximum number of parties joining a single room
+    #[structopt(long, default_value = "16")]
+    max_parties: u16,
+}
+
+#[derive(Debug, Error, PartialEq)]
+enum RoomError {
+    #[error("missing X-Token header")]
+    MissingToken,
+    #[error("room {0} is full")]
+    RoomFull(String),
+    #[error("index {index} is already taken in room {room}")]
+    IndexTaken { room: String, index: u16 },
+    #[error("index {index} is not one of the parties of room {room}")]
+    IndexOutOfRange { room: String, index: u16 },
+    #[error("party didn't join room {0}")]
+    NotJoined(String),
+}
+
+impl From<RoomError> for tide::Error {
+    fn from(err: RoomError) -> Self {
+        let status = match err {
+            RoomError::MissingToken => StatusCode::Unauthorized,
+            RoomError::RoomFull(_) | RoomError::NotJoined(_) => StatusCode::Forbidden,
+            RoomError::IndexTaken { .. } => StatusCode::Conflict,
+            RoomError::IndexOutOfRange { .. } => StatusCode::BadRequest,
+        };
+        tide::Error::from_str(status, err.to_string())
+    }
+}
+
+#[derive(Clone)]
+struct Rooms {
+    rooms: Arc<Mutex<HashMap<String, Room>>>,
+    room_ttl: Duration,
+    max_parties: u16,
+}
+
+struct Room {
+    last_activity: Instant,
+    /// Parties by issued index
+    members: BTreeMap<u16, Member>,
+    /// Progress reported by the party with given token
+    progress: HashMap<String, u16>,
+    history: Vec<String>,
+    subscribers: Vec<Sender<(usize, String)>>,
+}
+
+impl Room {
+    fn new() -> Self {
+        Self {
+            last_activity: Instant::now(),
+            members: BTreeMap::new(),
+            progress: HashMap::new(),
+            history: vec![],
+            subscribers: vec![],
+        }
+    }
+
+    fn is_member(&self, token: &str) -> bool {
+        self.members.values().any(|member| member.token == token)
+    }
+}
+
+struct Member {
+    token: String,
+    name: Option<String>,
+    /// Long-term identity key the party joined with
+    identity: Option<PublicKey>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct RoomStatus {
+    members: Vec<RoomMember>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct RoomMember {
+    party_id: u16,
+    name: Option<String>,
+    identity: Option<PublicKey>,
+    progress: u16,
+}
+
+#[derive(Serialize)]
+struct IssuedUniqueIdx {
+    unique_idx: u16,
+}
+
+impl Rooms {
+    fn new(room_ttl: Duration, max_parties: u16) -> Self {
+        Self {
+            rooms: Arc::default(),
+            room_ttl,
+            max_parties,
+        }
+    }
+
+    fn with_room<R>(&self, room_id: &str, f: impl FnOnce(&mut Room) -> R) -> R {
+        let mut rooms = self.rooms.lock().expect("rooms lock poisoned");
+        let room = rooms.entry(room_id.to_owned()).or_insert_with(Room::new);
+        room.last_activity = Instant::now();
+        f(room)
+    }
+
+    /// Issues an index to the party, party asking again gets the same index
+    fn issue_index(
+        &self,
+        room_id: &str,
+        token: &str,
+        msg: IssueIndexMsg,
+    ) -> Result<u16, RoomError> {
+        self.with_room(room_id, |room| {
+            if let Some((&index, _)) = room.members.iter().find(|(_, m)| m.token == token) {
+                return Ok(index);
+            }
+            if room.members.len() >= usize::from(self.max_parties) {
+                return Err(RoomError::RoomFull(room_id.to_owned()));
+            }
+            let index = match msg.party_id {
+                Some(index) if !msg.parties.contains(&index) => {
+                    return Err(RoomError::IndexOutOfRange {
+                        room: room_id.to_owned(),
+                        index,
+                    })
+                }
+                Some(index) if room.members.contains_key(&index) => {
+                    return Err(RoomError::IndexTaken {
+                        room: room_id.to_owned(),
+                        index,
+                    })
+                }
+                Some(index) => index,
+                None => msg
+                    .parties
+                    .iter()
+                    .copied()
+                    .find(|i| !room.members.contains_key(i))
+                    .ok_or_else(|| RoomError::RoomFull(room_id.to_owned()))?,
+            };
+            room.members.insert(
+                index,
+                Member {
+                    token: token.to_owned(),
+                    name: msg.party_name,
+                    identity: msg.identity,
+                },
+            );
+            Ok(index)
+        })
+    }
+
+    /// Subscribes to messages of the room following `last_event_id`
+    ///
+    /// Messages are paired with their position in the room, which is sent as id of the event so
+    /// that reconnecting client resumes where it stopped.
+    fn subscribe(
+        &self,
+        room_id: &str,
+        token: &str,
+        last_event_id: Option<usize>,
+    ) -> Result<Receiver<(usize, String)>, RoomError> {
+        self.with_room(room_id, |room| {
+            if !room.is_member(token) {
+                return Err(RoomError::NotJoined(room_id.to_owned()));
+            }
+            let (sender, receiver) = channel::unbounded();
+            let start = last_event_id.map_or(0, |id| id + 1);
+            for (id, message) in room.history.iter().enumerate().skip(start) {
+                let _ = sender.try_send((id, message.clone()));
+            }
+            room.subscribers.push(sender);
+            Ok(receiver)
+        })
+    }
+
+    fn broadcast(&self, room_id: &str, token: &str, message: String) -> Result<(), RoomError> {
+        self.with_room(room_id, |room| {
+            if !room.is_member(token) {
+                return Err(RoomError::NotJoined(room_id.to_owned()));
+            }
+            let id = room.history.len();
+            room.history.push(message.clone());
+            room.subscribers
+                .retain(|subscriber| subscriber.try_send((id, message.clone())).is_ok());
+            Ok(())
+        })
+    }
+
+    fn report_progress(&self, room_id: &str, token: &str, progress: u16) {
+        self.with_room(room_id, |room| {
+            room.progress.insert(token.to_owned(), progress);
+        })
+    }
+
+    /// Members of the room and of its sub-rooms (`{room}-ecdsa`, `{room}-parties-1_2`, ...)
+    ///
+    /// Every party is listed once with the index issued in the first room it joined.
+    fn status(&self, room_id: &str) -> RoomStatus {
+        let rooms = self.rooms.lock().expect("rooms lock poisoned");
+        let prefix = format!("{}-", room_id);
+        let progress = rooms.get(room_id).map(|room| &room.progress);
+        let mut members: Vec<RoomMember> = vec![];
+        let mut tokens: Vec<&str> = vec![];
+        let mut related: Vec<_> = rooms
+            .iter()
+            .filter(|(id, _)| id.as_str() == room_id || id.starts_with(&prefix))
+            .map(|(_, room)| room)
+            .collect();
+        related.sort_by_key(|room| std::cmp::Reverse(room.members.len()));
+        for room in related {
+            for (&party_id, member) in &room.members {
+                if tokens.contains(&member.token.as_str()) {
+                    continue;
+                }
+                tokens.push(&member.token);
+                members.push(RoomMember {
+                    party_id,
+                    name: member.name.clone(),
+                    identity: member.identity,
+                    progress: progress
+                        .and_then(|progress| progress.get(&member.token))
+                        .copied()
+                        .unwrap_or(0),
+                });
+            }
+        }
+        members.sort_by_key(|member| member.party_id);
+        RoomStatus { members }
+    }
+
+    /// Drops rooms idle for longer than the TTL, disconnecting their subscribers
+    fn remove_expired(&self) {
+        let mut rooms = self.rooms.lock().expect("rooms lock poisoned");
+        rooms.retain(|_, room| room.last_activity.elapsed() < self.room_ttl);
+    }
+}
+
+fn token(req: &Request<Rooms>) -> Result<String, RoomError> {
+    req.header("X-Token")
+        .map(|token| token.as_str().to_owned())
+        .ok_or(RoomError::MissingToken)
+}
+
+async fn issue_unique_idx(mut req: Request<Rooms>) -> tide::Result {
+    let token = token(&req)?;
+    let msg: IssueIndexMsg = req.body_json().await?;
+    let unique_idx = req
+        .state()
+        .issue_index(req.param("room_id")?, &token, msg)?;
+    Ok(tide::Body::from_json(&IssuedUniqueIdx { unique_idx })?.into())
+}
+
+async fn broadcast(mut req: Request<Rooms>) -> tide::Result {
+    let token = token(&req)?;
+    let message = req.body_string().await?;
+    req.state()
+        .broadcast(req.param("room_id")?, &token, message)?;
+    Ok(StatusCode::Ok.into())
+}
+
+async fn subscribe(req: Request<Rooms>, sender: tide::sse::Sender) -> tide::Result<()> {
+    let token = token(&req)?;
+    let last_event_id = req
+        .header("Last-Event-ID")
+        .and_then(|id| id.as_str().parse().ok());
+    let messages = req
+        .state()
+        .subscribe(req.param("room_id")?, &token, last_event_id)?;
+    while let Ok((id, message)) = messages.recv().await {
+        sender
+            .send("new-message", message, Some(&id.to_string()))
+            .await?;
+    }
+    Ok(())
+}
+
+async fn get_status(req: Request<Rooms>) -> tide::Result {
+    let status = req.state().status(req.param("room_id")?);
+    Ok(tide::Body::from_json(&status)?.into())
+}
+
+async fn post_status(mut req: Request<Rooms>) -> tide::Result {
+    let token = token(&req)?;
+    let progress: u16 = req
+        .body_string()
+        .await?
+        .trim()
+        .parse()
+        .map_err(|e| tide::Error::new(StatusCode::BadRequest, e))?;
+    req.state()
+        .report_progress(req.param("room_id")?, &token, progress);
+    Ok(StatusCode::Ok.into())
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::from_args();
+    let rooms = Rooms::new(Duration::from_secs(cli.room_ttl_secs), cli.max_parties);
+
+    let expiring = rooms.clone();
+    async_std::task::spawn(async move {
+        loop {
+            async_std::task::sleep(Duration::from_secs(1)).await;
+            expiring.remove_expired();
+        }
+    });
+
+    let mut app = tide::with_state(rooms);
+    app.at("/rooms/:room_id/issue_unique_idx")
+        .post(issue_unique_idx);
+    app.at("/rooms/:room_id/subscribe")
+        .get(tide::sse::endpoint(subscribe));
+    app.at("/rooms/:room_id/broadcast").post(broadcast);
+    app.at("/rooms/:room_id/status")
+        .get(get_status)
+        .post(post_status);
+
+    println!("state manager is listening on {}", cli.address);
+    async_std::task::block_on(app.listen(cli.address))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn join(
+        rooms: &Rooms,
+        room_id: &str,
+        token: &str,
+        party_id: Option<u16>,
+    ) -> Result<u16, RoomError> {
+        rooms.issue_index(
+            room_id,
+            token,
+            IssueIndexMsg {
+                parties: vec![1, 2, 3],
+                party_id,
+                party_name: Some(token.to_uppercase()),
+                identity: None,
+            },
+        )
+    }
+
+    #[test]
+    fn limits_parties_of_the_room() {
+        let rooms = Rooms::new(Duration::from_secs(60), 2);
+        assert_eq!(join(&rooms, "room", "a", None), Ok(1));
+        assert_eq!(join(&rooms, "room", "b", None), Ok(2));
+        // rejoining party keeps its index
+        assert_eq!(join(&rooms, "room", "a", None), Ok(1));
+        assert_eq!(
+            join(&rooms, "room", "c", None),
+            Err(RoomError::RoomFull("room".to_owned()))
+        );
+        assert_eq!(join(&rooms, "other", "c", Some(1)), Ok(1));
+        assert_eq!(
+            join(&rooms, "other", "d", Some(1)),
+            Err(RoomError::IndexTaken {
+                room: "other".to_owned(),
+                index: 1
+            })
+        );
+    }
+
+    #[test]
+    fn issues_only_indexes_of_the_parties() {
+        let rooms = Rooms::new(Duration::from_secs(60), 3);
+        assert_eq!(
+            join(&rooms, "room", "a", Some(4)),
+            Err(RoomError::IndexOutOfRange {
+                room: "room".to_owned(),
+                index: 4
+            })
+        );
+        assert!(rooms.status("room").members.is_empty());
+
+        let identity = tssv3::utils::secure_channel::Identity::generate().public_key();
+        let msg = IssueIndexMsg {
+            parties: vec![1, 2, 3],
+            party_id: Some(3),
+            party_name: None,
+            identity: Some(identity),
+        };
+        assert_eq!(rooms.issue_index("room", "a", msg), Ok(3));
+        assert_eq!(rooms.status("room").members[0].identity, Some(identity));
+    }
+
+    #[test]
+    fn relays_messages_to_members() {
+        let rooms = Rooms::new(Duration::from_secs(60), 3);
+        join(&rooms, "room", "a", None).unwrap();
+        join(&rooms, "room", "b", None).unwrap();
+        assert_eq!(
+            rooms.broadcast("room", "c", "intruder".to_owned()),
+            Err(RoomError::NotJoined("room".to_owned()))
+        );
+        assert!(rooms.subscribe("room", "c", None).is_err());
+
+        rooms.broadcast("room", "a", "first".to_owned()).unwrap();
+        let early = rooms.subscribe("room", "b", None).unwrap();
+        rooms.broadcast("room", "b", "second".to_owned()).unwrap();
+        let resumed = rooms.subscribe("room", "a", Some(0)).unwrap();
+
+        assert_eq!(early.try_recv(), Ok((0, "first".to_owned())));
+        assert_eq!(early.try_recv(), Ok((1, "second".to_owned())));
+        assert_eq!(resumed.try_recv(), Ok((1, "second".to_owned())));
+        assert!(resumed.try_recv().is_err());
+    }
+
+    #[test]
+    fn reports_members_of_sub_rooms() {
+        let rooms = Rooms::new(Duration::from_secs(60), 3);
+        join(&rooms, "room-ecdsa", "a", None).unwrap();
+        join(&rooms, "room-ecdsa", "b", None).unwrap();
+        join(&rooms, "room-parties-1_2", "b", Some(2)).unwrap();
+        join(&rooms, "roommate-ecdsa", "c", None).unwrap();
+        rooms.report_progress("room", "b", 50);
+
+        assert_eq!(
+            rooms.status("room"),
+            RoomStatus {
+                members: vec![
+                    RoomMember {
+                        party_id: 1,
+                        name: Some("A".to_owned()),
+                        identity: None,
+                        progress: 0,
+                    },
+                    RoomMember {
+                        party_id: 2,
+                        name: Some("B".to_owned()),
+                        identity: None,
+                        progress: 50,
+                    },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn drops_idle_rooms() {
+        let rooms = Rooms::new(Duration::from_millis(10), 3);
+        join(&rooms, "room", "a", None).unwrap();
+        let messages = rooms.subscribe("room", "a", None).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        rooms.remove_expired();
+        assert!(rooms.status("room").members.is_empty());
+        // subscribers of the dropped room are disconnected
+        assert!(messages.is_closed());
+    }
+}
@@ -1,264 +1,760 @@
+//! C interface of the library
+//!
+//! Every function takes its request as a JSON C string. Synchronous functions return the
+//! response as a JSON C string, asynchronous ones post it to the Dart port of the request. A
+//! failed request is answered with the JSON of [TssError] instead, see [crate::error].
+
 use std::ffi::{CStr, CString};
+use std::future::Future;
 use std::os::raw::c_char;
 
 use allo_isolate::Isolate;
-use rustmodel::{
-    KeyScheme, NativeGenerateDynamicNonceRequest, NativeKeygenRequest, NativeSigningRequest,
-};
+use anyhow::Context;
+use rustmodel::{NativeGenerateDynamicNonceRequest, NativeKeygenRequest, NativeSigningRequest};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
+use crate::error::TssError;
 use crate::gg20;
-use crate::t_ed25519;
-use crate::t_ed25519::presignature::generate_dynamic_nonces;
 use crate::utils::common::{
-    decrypt_ecdsa, decrypt_eddsa, encrypt_eddsa_keygen_result, encrypt_keygen_result,
-    signing_state_base64_to_obj, signing_state_obj_to_base64,
+    decrypt_ecdsa, decrypt_eddsa, decrypt_keygen_result, derive_public_key_request,
+    encrypt_ecdsa_keygen_result, encrypt_eddsa_keygen_result, encrypt_keygen_result, reencrypt,
+    sign_request, xpub_request, KeygenResult,
+};
+use crate::utils::encryption::encrypt;
+use crate::utils::model::{
+    NativeDerivePublicKeyRequest, NativeGeneratePreParamsRequest,
+    NativeGeneratePresignaturesRequest, NativeIdentityKey, NativeProtocolOptions,
+    NativeRecoverRequest, NativeReencryptRequest, NativeRefreshRequest, NativeRequestPort,
+    NativeReshareRequest, NativeSigningOptions, NativeXpubRequest, PRE_PARAMS_ASSOCIATED_DATA,
 };
+use crate::utils::secure_channel::Identity;
+use crate::utils::sm_client::SmClient;
+use crate::utils::transport::Transport;
 
+/// Parses the JSON request behind `c_request`
+fn parse_request<T: DeserializeOwned>(c_request: *const c_char) -> Result<T, TssError> {
+    let request = unsafe { CStr::from_ptr(c_request) }
+        .to_str()
+        .map_err(|e| TssError::invalid_input(format_args!("request is not UTF-8: {}", e)))?;
+    serde_json::from_str(request)
+        .map_err(|e| TssError::invalid_input(format_args!("malformed request: {}", e)))
+}
+
+/// Port to post the response of an asynchronous request to
+///
+/// `None` if the request doesn't even name a port, then it can't be answered.
+fn response_port(c_request: *const c_char) -> Option<Isolate> {
+    match parse_request::<NativeRequestPort>(c_request) {
+        Ok(request) => Some(Isolate::new(request.port)),
+        Err(err) => {
+            println!("dropping request without port: {}", err);
+            None
+        }
+    }
+}
+
+/// JSON of `response`, or of the error it failed with
+fn to_response<T: Serialize>(response: Result<T, TssError>) -> String {
+    response
+        .and_then(|r| serde_json::to_string(&r).map_err(TssError::internal))
+        .unwrap_or_else(|err| err.to_json())
+}
+
+/// Passes `response` to the caller as a C string
+///
+/// JSON escapes NUL in strings, so a raw one means the response is broken and an error is
+/// returned instead.
+fn into_c_string(response: String) -> *mut c_char {
+    CString::new(response)
+        .unwrap_or_else(|e| CString::new(TssError::internal(e).to_json()).unwrap_or_default())
+        .into_raw()
+}
+
+/// Runs `protocol` to completion on a runtime of the current thread
+fn block_on<T>(protocol: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .context("start runtime")?
+        .block_on(protocol)
+}
+
+/// Signs a message, see [NativeSigningOptions] for signing with a child key
 #[no_mangle]
 pub extern "C" fn c_sign(c_request: *const c_char) -> *mut c_char {
-    let rust_request = unsafe { CStr::from_ptr(c_request) }
-        .to_str()
-        .unwrap()
-        .to_string();
-    let request: NativeSigningRequest = match serde_json::from_str(rust_request.as_str()) {
+    let response = parse_request::<NativeSigningRequest>(c_request).and_then(|request| {
+        let options = parse_request::<NativeSigningOptions>(c_request)?;
+        let path = options.path()?;
+        sign_request(request, &path).map_err(TssError::from)
+    });
+    into_c_string(to_response(response))
+}
+
+/// Returns extended public key of a stored ECDSA key as [NativeXpubResponse] JSON
+///
+/// Child keys which sign with a `path` of [NativeSigningOptions] derive from it.
+///
+/// [NativeXpubResponse]: crate::utils::model::NativeXpubResponse
+#[no_mangle]
+pub extern "C" fn c_xpub(c_request: *const c_char) -> *mut c_char {
+    let response = parse_request::<NativeXpubRequest>(c_request)
+        .and_then(|request| xpub_request(request).map_err(TssError::from));
+    into_c_string(to_response(response))
+}
+
+/// Returns public key of a child of a stored ECDSA or EdDSA key as
+/// [NativeDerivePublicKeyResponse] JSON
+///
+/// The key signs for the child with the same `path` of [NativeSigningOptions].
+///
+/// [NativeDerivePublicKeyResponse]: crate::utils::model::NativeDerivePublicKeyResponse
+#[no_mangle]
+pub extern "C" fn c_derive_public_key(c_request: *const c_char) -> *mut c_char {
+    let response = parse_request::<NativeDerivePublicKeyRequest>(c_request)
+        .and_then(|request| derive_public_key_request(request).map_err(TssError::from));
+    into_c_string(to_response(response))
+}
+
+/// Encrypts a stored wallet with a new password, returns the [EncryptedKeygenResult] as JSON
+///
+/// Fails without a result if the old password doesn't decrypt both keys.
+///
+/// [EncryptedKeygenResult]: rustmodel::EncryptedKeygenResult
+#[no_mangle]
+pub extern "C" fn c_reencrypt(c_request: *const c_char) -> *mut c_char {
+    let response = parse_request::<NativeReencryptRequest>(c_request).and_then(|request| {
+        reencrypt(
+            request.encrypted_keygen_result,
+            request.old_password.as_str(),
+            request.new_password.as_str(),
+        )
+        .map_err(TssError::from)
+    });
+    into_c_string(to_response(response))
+}
+
+/// Generates long-term identity key of the device, returns [NativeIdentityKey] as JSON
+#[no_mangle]
+pub extern "C" fn c_generate_identity_key() -> *mut c_char {
+    let identity_key = NativeIdentityKey::from(&Identity::generate());
+    into_c_string(to_response(Ok(identity_key)))
+}
+
+/// Generates Paillier key and `N_tilde` for a future ECDSA keygen or refresh in background
+///
+/// Posts the parameters encrypted with the password of the request, to be passed later as
+/// `encrypted_pre_params` of [NativeProtocolOptions].
+#[no_mangle]
+pub extern "C" fn c_generate_pre_params(c_request: *const c_char) {
+    let isolate = match response_port(c_request) {
+        Some(isolate) => isolate,
+        None => return,
+    };
+    let started = parse_request::<NativeGeneratePreParamsRequest>(c_request).and_then(|request| {
+        let options = parse_request::<NativeProtocolOptions>(c_request)?;
+        let kdf = options.kdf()?;
+        let modulus_bits = options.modulus_bits()?;
+        Ok((request, modulus_bits, kdf))
+    });
+    let (request, modulus_bits, kdf) = match started {
         Ok(r) => r,
-        Err(e) => {
-            return CString::new(format!("error: {}", e.to_string()))
-                .unwrap()
-                .into_raw();
+        Err(err) => {
+            isolate.post(err.to_json());
+            return;
         }
     };
-    let mut state = signing_state_base64_to_obj(&request.state_base64);
-    let data = match hex::decode(request.hex_data) {
+    std::thread::spawn(move || {
+        let pre_params = gg20::PreParams::generate(modulus_bits);
+        let encrypted_pre_params = serde_json::to_string(&pre_params)
+            .map_err(anyhow::Error::from)
+            .and_then(|r| {
+                encrypt(
+                    &r,
+                    request.password.as_str(),
+                    PRE_PARAMS_ASSOCIATED_DATA,
+                    &kdf,
+                )
+            });
+        match encrypted_pre_params {
+            Ok(r) => isolate.post(r),
+            Err(err) => isolate.post(TssError::from(err).to_json()),
+        };
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn c_keygen(c_request: *const c_char) {
+    if let Some(isolate) = response_port(c_request) {
+        start_keygen(c_request, SmClient::connect, move |response| {
+            isolate.post(response);
+        });
+    }
+}
+
+/// Starts keygen of [c_keygen] in background, `respond` receives the response
+///
+/// Protocols run over the relay returned by `connect` for request id, token and address of the
+/// request.
+fn start_keygen<T, C, R>(c_request: *const c_char, connect: C, respond: R)
+where
+    T: Transport + Send + 'static,
+    C: FnOnce(&str, &str, &str) -> Result<T, TssError>,
+    R: FnOnce(String) + Send + 'static,
+{
+    let started = parse_request::<NativeKeygenRequest>(c_request).and_then(|request| {
+        let options = parse_request::<NativeProtocolOptions>(c_request)?;
+        let identity = options.keygen_identity()?;
+        let kdf = options.kdf()?;
+        let pre_params = options.pre_params(request.password.as_str())?;
+        let modulus_bits = options.modulus_bits()?;
+        let transport = connect(&request.request_id, &request.token, &request.address)?;
+        Ok((
+            request,
+            options,
+            kdf,
+            identity,
+            pre_params,
+            modulus_bits,
+            transport,
+        ))
+    });
+    let (request, options, kdf, identity, pre_params, modulus_bits, transport) = match started {
         Ok(r) => r,
-        Err(e) => {
-            return CString::new(format!("error: {}", e.to_string()))
-                .unwrap()
-                .into_raw();
+        Err(err) => {
+            respond(err.to_json());
+            return;
         }
     };
-    return match if request.key_scheme == KeyScheme::ECDSA {
-        gg20::signing::sign(
-            &mut state,
-            &(match decrypt_ecdsa(&request.encrypted_local_key, request.password.as_str()) {
-                Ok(r) => r,
-                Err(e) => {
-                    return CString::new(format!("error: {}", e.to_string()))
-                        .unwrap()
-                        .into_raw();
-                }
-            }),
-            data,
-            request.party_id as u16,
-            request
-                .signers
-                .into_iter()
-                .map(|x| x as u16)
-                .collect::<Vec<u16>>(),
-        )
-    } else {
-        t_ed25519::signing::sign(
-            &mut state,
-            &(match decrypt_eddsa(&request.encrypted_local_key, request.password.as_str()) {
-                Ok(r) => r,
-                Err(e) => {
-                    return CString::new(format!("error: {}", e.to_string()))
-                        .unwrap()
-                        .into_raw();
-                }
-            }),
-            data,
-            request.party_id as u16,
-            0,
-        )
-    } {
-        Ok(()) => {
-            let state_result_base64 = signing_state_obj_to_base64(request.key_scheme, &state);
-            let state_result_base64_json = match serde_json::to_string(&state_result_base64) {
-                Ok(state_result_base64_json) => state_result_base64_json,
-                Err(e) => {
-                    return CString::new(format!("error: {}", e.to_string()))
-                        .unwrap()
-                        .into_raw();
-                }
-            };
-            CString::new(state_result_base64_json).unwrap().into_raw()
-        }
-        Err(e) => {
-            return CString::new(format!("error: {}", e.to_string()))
-                .unwrap()
-                .into_raw();
+    std::thread::spawn(move || {
+        let pre_params = pre_params.unwrap_or_else(|| gg20::PreParams::generate(modulus_bits));
+        let encrypted_keygen_result = block_on(crate::all_keygen::keygen_and_offline(
+            request.request_id.as_str(),
+            &transport,
+            &identity,
+            request.room.as_str(),
+            request.t as u16,
+            request.n as u16,
+            crate::utils::constants::CONST_MAX_NONCE_PER_REFRESH,
+            request.signer_name.as_str(),
+            pre_params,
+            !options.skip_presign,
+            options.round_timeout(),
+            options.echo_broadcast,
+        ))
+        .map_err(TssError::from)
+        .and_then(|keygen_result| {
+            encrypt_keygen_result(keygen_result, request.password.as_str(), &kdf)
+        });
+        respond(to_response(encrypted_keygen_result));
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn c_generate_nonce(c_request: *const c_char) {
+    let isolate = match response_port(c_request) {
+        Some(isolate) => isolate,
+        None => return,
+    };
+    let started =
+        parse_request::<NativeGenerateDynamicNonceRequest>(c_request).and_then(|request| {
+            let options = parse_request::<NativeProtocolOptions>(c_request)?;
+            let identity = options.identity()?;
+            let kdf = options.kdf()?;
+            let local_key_data =
+                decrypt_eddsa(&request.encrypted_local_key, request.password.as_str())?;
+            let transport =
+                SmClient::connect(&request.request_id, &request.token, &request.address)?;
+            Ok((request, options, kdf, identity, local_key_data, transport))
+        });
+    let (request, options, kdf, identity, local_key_data, transport) = match started {
+        Ok(r) => r,
+        Err(err) => {
+            isolate.post(err.to_json());
+            return;
         }
     };
+    std::thread::spawn(move || {
+        let encrypted_keygen_result = block_on(crate::all_keygen::generate_nonces(
+            request.request_id.as_str(),
+            &transport,
+            &identity,
+            request.room.as_str(),
+            request.nonce_start_index as u16,
+            request.nonce_size as u16,
+            &local_key_data.local_key,
+            options.round_timeout(),
+            options.echo_broadcast,
+        ))
+        .map_err(TssError::from)
+        .and_then(|keygen_result| {
+            encrypt_eddsa_keygen_result(
+                &local_key_data.local_key,
+                &keygen_result,
+                request.password.as_str(),
+                local_key_data.algorithm.as_str(),
+                &kdf,
+            )
+        });
+        isolate.post(to_response(encrypted_keygen_result));
+    });
 }
 
+/// Generates presignatures of an existing ECDSA key for one signer set
+///
+/// Posts the key with the new presignatures added, it replaces the key of the request.
 #[no_mangle]
-pub extern "C" fn c_keygen(c_request: *const c_char) {
-    let rust_request = unsafe { CStr::from_ptr(c_request) }
-        .to_str()
-        .unwrap()
-        .to_string();
-    let request: NativeKeygenRequest = serde_json::from_str(rust_request.as_str()).unwrap();
-    let isolate = Isolate::new(request.port);
-    std::thread::spawn(move || {
-        match tokio::runtime::Builder::new_current_thread()
-            .build()
-            .unwrap()
-            .block_on(crate::all_keygen::keygen_and_offline(
-                request.request_id.as_str(),
-                request.token.as_str(),
-                request.address.as_str(),
-                request.room.as_str(),
-                request.t as u16,
-                request.n as u16,
-                crate::utils::constants::CONST_MAX_NONCE_PER_REFRESH,
-                request.signer_name.as_str(),
-            )) {
-            Ok(keygen_result) => {
-                let encrypted_keygen_result =
-                    encrypt_keygen_result(keygen_result, request.password.as_str());
-                let encrypted_keygen_result_json =
-                    match serde_json::to_string(&encrypted_keygen_result) {
-                        Ok(r) => r,
-                        Err(err) => {
-                            isolate.post(format!("error: {}", err.to_string()));
-                            return;
-                        }
-                    };
-                isolate.post(encrypted_keygen_result_json);
-            }
-            Err(err) => {
-                isolate.post(format!("error: {}", err.to_string()));
-            }
+pub extern "C" fn c_generate_presignatures(c_request: *const c_char) {
+    let isolate = match response_port(c_request) {
+        Some(isolate) => isolate,
+        None => return,
+    };
+    let started =
+        parse_request::<NativeGeneratePresignaturesRequest>(c_request).and_then(|request| {
+            let options = parse_request::<NativeProtocolOptions>(c_request)?;
+            let identity = options.identity()?;
+            let kdf = options.kdf()?;
+            let local_key_data =
+                decrypt_ecdsa(&request.encrypted_local_key, request.password.as_str())?;
+            let transport =
+                SmClient::connect(&request.request_id, &request.token, &request.address)?;
+            Ok((request, options, kdf, identity, local_key_data, transport))
+        });
+    let (request, options, kdf, identity, mut local_key_data, transport) = match started {
+        Ok(r) => r,
+        Err(err) => {
+            isolate.post(err.to_json());
+            return;
         }
+    };
+    std::thread::spawn(move || {
+        let encrypted_keygen_result = block_on(crate::all_keygen::generate_presignatures(
+            request.request_id.as_str(),
+            &transport,
+            &identity,
+            request.room.as_str(),
+            &local_key_data.local_key,
+            request.signers.clone(),
+            request.count,
+            options.round_timeout(),
+            options.echo_broadcast,
+        ))
+        .map_err(TssError::from)
+        .and_then(|presignatures| {
+            local_key_data.add_presignatures(presignatures);
+            encrypt_ecdsa_keygen_result(
+                &local_key_data.local_key,
+                &local_key_data.offline_data,
+                request.password.as_str(),
+                local_key_data.algorithm.as_str(),
+                &kdf,
+            )
+        });
+        isolate.post(to_response(encrypted_keygen_result));
     });
 }
 
 #[no_mangle]
-pub extern "C" fn c_generate_nonce(c_request: *const c_char) {
-    let rust_request = unsafe { CStr::from_ptr(c_request) }
-        .to_str()
-        .unwrap()
-        .to_string();
-    let request: NativeGenerateDynamicNonceRequest =
-        serde_json::from_str(rust_request.as_str()).unwrap();
-    let isolate = Isolate::new(request.port);
+pub extern "C" fn c_refresh_ecdsa(c_request: *const c_char) {
+    let isolate = match response_port(c_request) {
+        Some(isolate) => isolate,
+        None => return,
+    };
+    let started = parse_request::<NativeRefreshRequest>(c_request).and_then(|request| {
+        let options = parse_request::<NativeProtocolOptions>(c_request)?;
+        let identity = options.identity()?;
+        let kdf = options.kdf()?;
+        let pre_params = options.pre_params(request.password.as_str())?;
+        let local_key_data =
+            decrypt_ecdsa(&request.encrypted_local_key, request.password.as_str())?;
+        let transport = SmClient::connect(&request.request_id, &request.token, &request.address)?;
+        Ok((
+            request,
+            options,
+            kdf,
+            identity,
+            pre_params,
+            local_key_data,
+            transport,
+        ))
+    });
+    let (request, options, kdf, identity, pre_params, local_key_data, transport) = match started {
+        Ok(r) => r,
+        Err(err) => {
+            isolate.post(err.to_json());
+            return;
+        }
+    };
+    std::thread::spawn(move || {
+        let pre_params = pre_params
+            .unwrap_or_else(|| gg20::PreParams::generate(local_key_data.local_key.modulus_bits()));
+        let encrypted_keygen_result = block_on(crate::all_keygen::refresh_and_offline(
+            request.request_id.as_str(),
+            &transport,
+            &identity,
+            request.room.as_str(),
+            &local_key_data,
+            pre_params,
+            !options.skip_presign,
+            options.round_timeout(),
+            options.echo_broadcast,
+        ))
+        .map_err(TssError::from)
+        .and_then(|refreshed| {
+            encrypt_ecdsa_keygen_result(
+                &refreshed.local_key,
+                &refreshed.offline_data,
+                request.password.as_str(),
+                refreshed.algorithm.as_str(),
+                &kdf,
+            )
+        });
+        isolate.post(to_response(encrypted_keygen_result));
+    });
+}
 
-    match decrypt_eddsa(&request.encrypted_local_key, request.password.as_str()) {
-        Ok(local_key_data) => {
-            std::thread::spawn(move || {
-                match tokio::runtime::Builder::new_current_thread()
-                    .build()
-                    .unwrap()
-                    .block_on(generate_dynamic_nonces(
-                        request.request_id.as_str(),
-                        request.token.as_str(),
-                        request.address.as_str(),
-                        request.room.as_str(),
-                        request.nonce_start_index as u16,
-                        request.nonce_size as u16,
-                        &local_key_data.local_key,
-                    )) {
-                    Ok(keygen_result) => {
-                        let encrypted_keygen_result = encrypt_eddsa_keygen_result(
-                            &local_key_data.local_key,
-                            &keygen_result,
-                            request.password.as_str(),
-                            local_key_data.algorithm.as_str(),
-                        );
-                        let encrypted_keygen_result_json =
-                            match serde_json::to_string(&encrypted_keygen_result) {
-                                Ok(r) => r,
-                                Err(err) => {
-                                    isolate.post(format!("error: {}", err.to_string()));
-                                    return;
-                                }
-                            };
-                        isolate.post(encrypted_keygen_result_json);
-                    }
-                    Err(err) => {
-                        isolate.post(format!("error: {}", err.to_string()));
-                    }
-                }
-            });
+#[no_mangle]
+pub extern "C" fn c_refresh_eddsa(c_request: *const c_char) {
+    let isolate = match response_port(c_request) {
+        Some(isolate) => isolate,
+        None => return,
+    };
+    let started =
+        parse_request::<NativeGenerateDynamicNonceRequest>(c_request).and_then(|request| {
+            let options = parse_request::<NativeProtocolOptions>(c_request)?;
+            let identity = options.identity()?;
+            let kdf = options.kdf()?;
+            let local_key_data =
+                decrypt_eddsa(&request.encrypted_local_key, request.password.as_str())?;
+            let transport =
+                SmClient::connect(&request.request_id, &request.token, &request.address)?;
+            Ok((request, options, kdf, identity, local_key_data, transport))
+        });
+    let (request, options, kdf, identity, local_key_data, transport) = match started {
+        Ok(r) => r,
+        Err(err) => {
+            isolate.post(err.to_json());
+            return;
         }
-        Err(e) => {
-            isolate.post(
-                CString::new(format!("error: {}", e.to_string()))
-                    .unwrap()
-                    .into_raw(),
-            );
+    };
+    std::thread::spawn(move || {
+        let encrypted_keygen_result = block_on(crate::all_keygen::refresh_and_generate_nonces(
+            request.request_id.as_str(),
+            &transport,
+            &identity,
+            request.room.as_str(),
+            request.nonce_start_index as u16,
+            request.nonce_size as u16,
+            &local_key_data,
+            options.round_timeout(),
+            options.echo_broadcast,
+        ))
+        .map_err(TssError::from)
+        .and_then(|refreshed| {
+            encrypt_eddsa_keygen_result(
+                &refreshed.local_key,
+                &refreshed.offline_data,
+                request.password.as_str(),
+                refreshed.algorithm.as_str(),
+                &kdf,
+            )
+        });
+        isolate.post(to_response(encrypted_keygen_result));
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn c_reshare(c_request: *const c_char) {
+    let isolate = match response_port(c_request) {
+        Some(isolate) => isolate,
+        None => return,
+    };
+    let started = parse_request::<NativeReshareRequest>(c_request).and_then(|request| {
+        let options = parse_request::<NativeProtocolOptions>(c_request)?;
+        let identity = options.identity()?;
+        let kdf = options.kdf()?;
+        let old_keys = request
+            .encrypted_keygen_result
+            .as_ref()
+            .map(|result| decrypt_keygen_result(result, request.password.as_str()))
+            .transpose()?;
+        let transport = SmClient::connect(&request.request_id, &request.token, &request.address)?;
+        Ok((request, options, kdf, identity, old_keys, transport))
+    });
+    let (request, options, kdf, identity, old_keys, transport) = match started {
+        Ok(r) => r,
+        Err(err) => {
+            isolate.post(err.to_json());
+            return;
         }
     };
+    std::thread::spawn(move || {
+        let encrypted_keygen_result = block_on(crate::all_keygen::reshare_and_offline(
+            request.request_id.as_str(),
+            &transport,
+            &identity,
+            request.room.as_str(),
+            request.n as u16,
+            old_keys.as_ref().map(|(ecdsa, eddsa)| (ecdsa, eddsa)),
+            request.new_member,
+            request.new_t as u16,
+            request.new_n as u16,
+            crate::utils::constants::CONST_MAX_NONCE_PER_REFRESH,
+            request.signer_name.as_str(),
+            !options.skip_presign,
+            options.round_timeout(),
+            options.echo_broadcast,
+        ))
+        .map_err(TssError::from)
+        .and_then(|keygen_result| {
+            keygen_result
+                .map(|result| encrypt_keygen_result(result, request.password.as_str(), &kdf))
+                .transpose()
+        });
+        isolate.post(to_response(encrypted_keygen_result));
+    });
+}
+
+/// Recovers both keys of a lost device with help of `t+1` surviving parties
+///
+/// Helpers and `observers` send their keys, the replacement device sends none and keeps the names
+/// of the parties empty. The replacement device takes its new Paillier key and `N_tilde` from
+/// `encrypted_pre_params` of [NativeProtocolOptions] if present. The recovered key comes without
+/// nonces, see
+/// [recover_and_offline](crate::all_keygen::recover_and_offline).
+#[no_mangle]
+pub extern "C" fn c_recover(c_request: *const c_char) {
+    let isolate = match response_port(c_request) {
+        Some(isolate) => isolate,
+        None => return,
+    };
+    let started = parse_request::<NativeRecoverRequest>(c_request).and_then(|request| {
+        let options = parse_request::<NativeProtocolOptions>(c_request)?;
+        let identity = options.identity()?;
+        let kdf = options.kdf()?;
+        let old_keys = request
+            .encrypted_keygen_result
+            .as_ref()
+            .map(|result| decrypt_keygen_result(result, request.password.as_str()))
+            .transpose()?;
+        let pre_params = options.pre_params(request.password.as_str())?;
+        let modulus_bits = options.modulus_bits()?;
+        let transport = SmClient::connect(&request.request_id, &request.token, &request.address)?;
+        Ok((
+            request,
+            options,
+            kdf,
+            identity,
+            old_keys,
+            pre_params,
+            modulus_bits,
+            transport,
+        ))
+    });
+    let (request, options, kdf, identity, old_keys, pre_params, modulus_bits, transport) =
+        match started {
+            Ok(r) => r,
+            Err(err) => {
+                isolate.post(err.to_json());
+                return;
+            }
+        };
+    std::thread::spawn(move || {
+        // only the replacement device comes with a new Paillier key
+        let pre_params = match old_keys {
+            Some(_) => None,
+            None => Some(pre_params.unwrap_or_else(|| gg20::PreParams::generate(modulus_bits))),
+        };
+        let encrypted_keygen_result = block_on(crate::all_keygen::recover_and_offline(
+            request.request_id.as_str(),
+            &transport,
+            &identity,
+            request.room.as_str(),
+            request.t,
+            request.n,
+            old_keys.as_ref().map(|(ecdsa, eddsa)| (ecdsa, eddsa)),
+            pre_params,
+            request.lost_i,
+            &request.helpers,
+            &request.observers,
+            !options.skip_presign,
+            options.round_timeout(),
+            options.echo_broadcast,
+        ))
+        .map_err(TssError::from)
+        .and_then(|result| {
+            let members = request
+                .encrypted_keygen_result
+                .map(|old| old.members)
+                .unwrap_or_default();
+            encrypt_keygen_result(
+                KeygenResult { members, ..result },
+                request.password.as_str(),
+                &kdf,
+            )
+        });
+        isolate.post(to_response(encrypted_keygen_result));
+    });
 }
 
 #[cfg(test)]
 mod test {
     use std::ffi::CString;
+    use std::os::raw::c_char;
+    use std::sync::mpsc;
 
-    use rand::Rng;
-    use rustmodel::NativeKeygenRequest;
+    use rustmodel::{EncryptedKeygenResult, KeyScheme, SigningStateBase64};
+    use serde_json::json;
 
-    #[test]
-    fn e2e() {
-        let id: u16 = rand::thread_rng().gen();
-        let room_id = format!("{}_{}", "kg_e2e_c", id).to_owned();
-        crate::cexport::c_keygen(
-            CString::new(
-                serde_json::to_string(&NativeKeygenRequest {
-                    request_id: String::from("requestId"),
-                    token: String::from("user1"),
-                    t: 1,
-                    n: 3,
-                    address: "http://localhost:8000".to_owned(),
-                    room: room_id.clone(),
-                    signer_name: "A".to_owned(),
-                    port: 8888,
-                    password: String::from("123"),
-                })
-                .unwrap(),
-            )
+    use crate::utils::common::{decrypt_keygen_result, signing_state_obj_to_base64, SigningState};
+    use crate::utils::encryption::KdfParams;
+    use crate::utils::model::NativeSigningResponse;
+    use crate::utils::secure_channel::Identity;
+    use crate::utils::transport::InMemoryTransport;
+
+    use super::{c_sign, start_keygen};
+
+    /// Takes back the response of a synchronous function
+    fn take_response(response: *mut c_char) -> String {
+        unsafe { CString::from_raw(response) }
+            .into_string()
             .unwrap()
-            .into_raw(),
-        );
-        std::thread::sleep(std::time::Duration::from_secs(2));
-        crate::cexport::c_keygen(
-            CString::new(
-                serde_json::to_string(&NativeKeygenRequest {
-                    request_id: String::from("requestId"),
-                    token: String::from("user2"),
-                    t: 1,
-                    n: 3,
-                    address: "http://localhost:8000".to_owned(),
-                    room: room_id.clone(),
-                    signer_name: "B".to_owned(),
-                    port: 8888,
-                    password: String::from("123"),
+    }
+
+    /// Passes signing `state` through `signers` with `c_sign`, returns the last response
+    fn sign(
+        keys: &[EncryptedKeygenResult],
+        mut state: SigningStateBase64,
+        signers: &[i32],
+        data: &[u8],
+    ) -> NativeSigningResponse {
+        let mut response = None;
+        for key in keys.iter().filter(|key| signers.contains(&key.party_id)) {
+            let key_with_scheme = key
+                .encrypted_keygen_with_scheme
+                .iter()
+                .find(|key| key.key_scheme == state.key_scheme)
+                .unwrap();
+            let request = CString::new(
+                json!({
+                    "hex_data": hex::encode(data),
+                    "state_base64": state,
+                    "password": "123",
+                    "encrypted_local_key": key_with_scheme.encrypted_local_key,
+                    "key_scheme": state.key_scheme,
+                    "party_id": key.party_id,
+                    "signers": signers,
+                    "nonce": key_with_scheme.nonce_start_index,
                 })
-                .unwrap(),
+                .to_string(),
             )
-            .unwrap()
-            .into_raw(),
-        );
-        std::thread::sleep(std::time::Duration::from_secs(2));
-        crate::cexport::c_keygen(
-            CString::new(
-                serde_json::to_string(&NativeKeygenRequest {
-                    request_id: String::from("requestId"),
-                    token: String::from("user3"),
-                    t: 1,
-                    n: 3,
-                    address: "http://localhost:8000".to_owned(),
-                    room: room_id.clone(),
-                    signer_name: "C".to_owned(),
-                    port: 8888,
-                    password: String::from("123"),
+            .unwrap();
+            let signed = take_response(c_sign(request.as_ptr()));
+            let signed: NativeSigningResponse =
+                serde_json::from_str(&signed).unwrap_or_else(|e| panic!("{}: {}", e, signed));
+            state = signed.state.clone();
+            response = Some(signed);
+        }
+        response.unwrap()
+    }
+
+    #[test]
+    fn e2e() {
+        let transport = InMemoryTransport::new();
+        let responses: Vec<_> = (1..=3)
+            .map(|i| {
+                let request = CString::new(
+                    json!({
+                        "request_id": "requestId",
+                        "token": "token",
+                        "address": "http://127.0.0.1:9/",
+                        "room": "room",
+                        "t": 1,
+                        "n": 3,
+                        "signer_name": format!("party{}", i),
+                        "port": i,
+                        "password": "123",
+                        "identity_key": Identity::generate().to_hex(),
+                        "kdf": KdfParams::MIN,
+                    })
+                    .to_string(),
+                )
+                .unwrap();
+                let (sender, responses) = mpsc::channel();
+                start_keygen(
+                    request.as_ptr(),
+                    |_, _, _| Ok(transport.clone()),
+                    move |response| sender.send(response).unwrap(),
+                );
+                responses
+            })
+            .collect();
+        // keys reach the app encrypted and come back with signing requests
+        let keys: Vec<EncryptedKeygenResult> = responses
+            .iter()
+            .map(|responses| {
+                let response = responses.recv().unwrap();
+                let encrypted: EncryptedKeygenResult = serde_json::from_str(&response)
+                    .unwrap_or_else(|e| panic!("{}: {}", e, response));
+                assert!(decrypt_keygen_result(&encrypted, "wrong").is_err());
+                // encrypted shares are bound to the public fields stored next to them
+                let mut tampered: EncryptedKeygenResult = serde_json::from_str(&response).unwrap();
+                tampered.encrypted_keygen_with_scheme[0]
+                    .encrypted_local_key
+                    .algorithm = "other".to_string();
+                assert!(decrypt_keygen_result(&tampered, "123").is_err());
+                assert!(decrypt_keygen_result(&encrypted, "123").is_ok());
+                encrypted
+            })
+            .collect();
+        let mut party_ids: Vec<_> = keys.iter().map(|key| key.party_id).collect();
+        party_ids.sort_unstable();
+        assert_eq!(party_ids, vec![1, 2, 3]);
+
+        let data_to_sign = "hello".as_bytes();
+        let state = |scheme| {
+            let state = SigningState {
+                t: 1,
+                n: 3,
+                signing_parts: vec![],
+                signature: None,
+            };
+            signing_state_obj_to_base64(scheme, &state).unwrap()
+        };
+        let ecdsa = sign(&keys, state(KeyScheme::ECDSA), &[2, 3], data_to_sign);
+        let eddsa = sign(&keys, state(KeyScheme::EDDSA), &[2, 3], data_to_sign);
+        assert!(ecdsa.state.signature.is_some());
+        assert!(eddsa.state.signature.is_some());
+    }
+
+    #[test]
+    fn keygen_rejects_modulus_bits_out_of_range() {
+        for bits in [1024, 8192] {
+            let request = CString::new(
+                json!({
+                    "request_id": "requestId",
+                    "token": "token",
+                    "address": "http://127.0.0.1:9/",
+                    "room": "room",
+                    "t": 1,
+                    "n": 3,
+                    "signer_name": "party1",
+                    "port": 1,
+                    "password": "123",
+                    "identity_key": Identity::generate().to_hex(),
+                    "paillier_modulus_bits": bits,
                 })
-                .unwrap(),
+                .to_string(),
             )
-            .unwrap()
-            .into_raw(),
-        );
-        std::thread::sleep(std::time::Duration::from_secs(10));
+            .unwrap();
+            let (sender, responses) = mpsc::channel();
+            start_keygen(
+                request.as_ptr(),
+                |_, _, _| Ok(InMemoryTransport::new()),
+                move |response| sender.send(response).unwrap(),
+            );
+            let response: serde_json::Value =
+                serde_json::from_str(&responses.recv().unwrap()).unwrap();
+            assert_eq!(response["error"]["code"], "INVALID_INPUT");
+        }
     }
 }
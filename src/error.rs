@@ -0,0 +1,216 @@
+//! Errors reported to wallet apps
+//!
+//! Protocols and storage fail with [anyhow] errors internally. Failures apps have to react to
+//! are raised as [TssError] at their origin and found again in the error chain when the error
+//! crosses the FFI boundary, anything else is reported as [TssError::Internal].
+//!
+//! FFI functions return the error as JSON object `{"error": {"code": ..., "message": ...}}`,
+//! where `code` is one of the stable codes listed by [TssError::code] and the rest of the fields
+//! depend on the code. For example, a party caught cheating in a presignature protocol gives
+//!
+//! ```json
+//! {"error": {"code": "PROTOCOL_ABORT", "message": "...", "culprits": [2]}}
+//! ```
+
+use std::fmt;
+
+use round_based::async_runtime;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Error with a stable code apps can branch on
+///
+/// `message` is meant for logs, its text may change between versions.
+#[derive(Clone, Debug, Error, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "code", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TssError {
+    /// Stored key couldn't be decrypted: the password is wrong or the key was tampered with
+    #[error("{message}")]
+    WrongPassword { message: String },
+    /// Every presignature or nonce that could sign the message has already been used
+    #[error("{message}")]
+    NonceExhausted { message: String },
+    /// Protocol was aborted
+    ///
+    /// `culprits` are indexes (starting from 1) of parties of the protocol room proven to
+    /// misbehave. Empty if nobody is to blame, e.g. if a party went offline.
+    #[error("{message}")]
+    ProtocolAbort { culprits: Vec<u16>, message: String },
+    /// Relay of protocol messages failed or couldn't be reached
+    #[error("{message}")]
+    Transport { message: String },
+    /// Request is malformed or can't be served, e.g. the message is already signed
+    #[error("{message}")]
+    InvalidInput { message: String },
+    /// Any other failure
+    #[error("{message}")]
+    Internal { message: String },
+}
+
+impl TssError {
+    pub fn wrong_password(message: impl fmt::Display) -> Self {
+        TssError::WrongPassword {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn nonce_exhausted(message: impl fmt::Display) -> Self {
+        TssError::NonceExhausted {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn transport(message: impl fmt::Display) -> Self {
+        TssError::Transport {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn invalid_input(message: impl fmt::Display) -> Self {
+        TssError::InvalidInput {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn internal(message: impl fmt::Display) -> Self {
+        TssError::Internal {
+            message: message.to_string(),
+        }
+    }
+
+    /// Stable code of the error, the `code` field of its JSON
+    pub fn code(&self) -> &'static str {
+        match self {
+            TssError::WrongPassword { .. } => "WRONG_PASSWORD",
+            TssError::NonceExhausted { .. } => "NONCE_EXHAUSTED",
+            TssError::ProtocolAbort { .. } => "PROTOCOL_ABORT",
+            TssError::Transport { .. } => "TRANSPORT",
+            TssError::InvalidInput { .. } => "INVALID_INPUT",
+            TssError::Internal { .. } => "INTERNAL",
+        }
+    }
+
+    /// Parties to blame for a [ProtocolAbort](TssError::ProtocolAbort), empty for other errors
+    pub fn culprits(&self) -> &[u16] {
+        match self {
+            TssError::ProtocolAbort { culprits, .. } => culprits,
+            _ => &[],
+        }
+    }
+
+    /// Error as returned by FFI functions
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Response<'a> {
+            error: &'a TssError,
+        }
+        serde_json::to_string(&Response { error: self }).unwrap_or_else(|_| {
+            format!(
+                r#"{{"error":{{"code":"{}","message":"unserializable error"}}}}"#,
+                self.code()
+            )
+        })
+    }
+
+    fn message_mut(&mut self) -> &mut String {
+        match self {
+            TssError::WrongPassword { message }
+            | TssError::NonceExhausted { message }
+            | TssError::ProtocolAbort { message, .. }
+            | TssError::Transport { message }
+            | TssError::InvalidInput { message }
+            | TssError::Internal { message } => message,
+        }
+    }
+}
+
+/// Finds the error raised at the origin of `err`, its message is replaced with the whole chain
+impl From<anyhow::Error> for TssError {
+    fn from(err: anyhow::Error) -> Self {
+        let message = format!("{:#}", err);
+        match err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<TssError>())
+        {
+            Some(origin) => {
+                let mut typed = origin.clone();
+                *typed.message_mut() = message;
+                typed
+            }
+            None => TssError::Internal { message },
+        }
+    }
+}
+
+/// Error of a protocol state machine which can name parties that misbehaved
+pub trait Blame {
+    /// Indexes (starting from 1) of parties proven to misbehave, empty if nobody is to blame
+    fn culprits(&self) -> Vec<u16>;
+}
+
+/// Classifies failure of a protocol execution: failing channels are a transport failure,
+/// anything else aborts the protocol
+impl<E, RE, SE> From<async_runtime::Error<E, RE, SE>> for TssError
+where
+    E: Blame + fmt::Display,
+    RE: fmt::Display,
+    SE: fmt::Display,
+{
+    fn from(err: async_runtime::Error<E, RE, SE>) -> Self {
+        match err {
+            async_runtime::Error::Recv(_)
+            | async_runtime::Error::RecvEof
+            | async_runtime::Error::Send(_) => TssError::transport(err),
+            async_runtime::Error::HandleIncoming(e) | async_runtime::Error::ProceedRound(e) => {
+                TssError::ProtocolAbort {
+                    culprits: e.culprits(),
+                    message: e.to_string(),
+                }
+            }
+            err => TssError::ProtocolAbort {
+                culprits: vec![],
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+/// Culprits of a round failure which names `bad_actors` by their position (starting from 0)
+pub(crate) fn culprits_of(bad_actors: &[usize]) -> Vec<u16> {
+    bad_actors
+        .iter()
+        .filter_map(|&i| u16::try_from(i + 1).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn keeps_code_of_the_origin() {
+        let err = Err::<(), _>(TssError::ProtocolAbort {
+            culprits: vec![2],
+            message: "party 2 cheated".to_owned(),
+        })
+        .context("presigning failed")
+        .unwrap_err();
+        let err = TssError::from(err);
+        assert_eq!(err.code(), "PROTOCOL_ABORT");
+        assert_eq!(err.culprits(), &[2]);
+        assert_eq!(err.to_string(), "presigning failed: party 2 cheated");
+
+        let json: serde_json::Value = serde_json::from_str(&err.to_json()).unwrap();
+        assert_eq!(json["error"]["code"], "PROTOCOL_ABORT");
+        assert_eq!(json["error"]["culprits"], serde_json::json!([2]));
+    }
+
+    #[test]
+    fn unknown_errors_are_internal() {
+        let err = TssError::from(anyhow::anyhow!("something broke"));
+        assert_eq!(err.code(), "INTERNAL");
+        assert!(err.culprits().is_empty());
+    }
+}
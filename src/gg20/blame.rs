@@ -1,7 +1,8 @@
 #![allow(non_snake_case)]
 use crate::gg20::mta::{MessageA, MessageB};
+use crate::gg20::sigma::ECDDHProof;
 use crate::gg20::ErrorType;
-use curv::cryptographic_primitives::proofs::sigma_ec_ddh::ECDDHProof;
+use crate::utils::session_id::SessionId;
 use curv::cryptographic_primitives::proofs::sigma_ec_ddh::ECDDHStatement;
 use curv::cryptographic_primitives::proofs::sigma_ec_ddh::ECDDHWitness;
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
@@ -12,7 +13,6 @@ use paillier::DecryptionKey;
 use paillier::Paillier;
 use paillier::{EncryptionKey, Randomness, RawCiphertext, RawPlaintext};
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LocalStatePhase5 {
@@ -98,7 +98,7 @@ impl GlobalStatePhase5 {
         }
     }
 
-    pub fn phase5_blame(&self) -> Result<(), ErrorType> {
+    pub fn phase5_blame(&self, sid: &SessionId) -> Result<(), ErrorType> {
         let len = self.delta_vec.len();
         let mut bad_signers_vec = Vec::new();
 
@@ -112,6 +112,7 @@ impl GlobalStatePhase5 {
         let alpha_beta_matrix = (0..len)
             .map(|i| {
                 let message_a = MessageA::a_with_predefined_randomness(
+                    sid,
                     &self.k_vec[i],
                     &self.encryption_key_vec[i],
                     &self.k_randomness_vec[i],
@@ -128,6 +129,7 @@ impl GlobalStatePhase5 {
                         .map(|j| {
                             let ind = if j < i { j } else { j + 1 };
                             let (message_b, beta) = MessageB::b_with_predefined_randomness(
+                                sid,
                                 &self.gamma_vec[ind],
                                 &self.encryption_key_vec[i],
                                 message_a.clone(),
@@ -202,7 +204,7 @@ impl GlobalStatePhase5 {
         bad_signers_vec.sort_unstable();
         bad_signers_vec.dedup();
         let err_type = ErrorType {
-            error_type: "phase6_blame".to_string(),
+            error_type: "phase5_blame".to_string(),
             bad_actors: bad_signers_vec,
         };
         Err(err_type)
@@ -215,7 +217,7 @@ pub struct LocalStatePhase6 {
     pub k_randomness: BigInt,
     pub miu: Vec<BigInt>, // we need the value before reduction
     pub miu_randomness: Vec<BigInt>,
-    pub proof_of_eq_dlog: ECDDHProof<Secp256k1, Sha256>,
+    pub proof_of_eq_dlog: ECDDHProof,
 }
 
 // It is assumed the second message of MtAwc (ciphertext from b to a) is broadcasted in the original protocol
@@ -227,7 +229,7 @@ pub struct GlobalStatePhase6 {
     pub miu_randomness_vec: Vec<Vec<BigInt>>,
     pub g_w_vec: Vec<Point<Secp256k1>>,
     pub encryption_key_vec: Vec<EncryptionKey>,
-    pub proof_vec: Vec<ECDDHProof<Secp256k1, Sha256>>,
+    pub proof_vec: Vec<ECDDHProof>,
     pub S_vec: Vec<Point<Secp256k1>>,
     pub m_a_vec: Vec<MessageA>,
     pub m_b_mat: Vec<Vec<MessageB>>,
@@ -241,10 +243,11 @@ impl GlobalStatePhase6 {
     }
 
     pub fn ecddh_proof(
+        sid: &SessionId,
         sigma_i: &Scalar<Secp256k1>,
         R: &Point<Secp256k1>,
         S: &Point<Secp256k1>,
-    ) -> ECDDHProof<Secp256k1, Sha256> {
+    ) -> ECDDHProof {
         let delta = ECDDHStatement {
             g1: Point::generator().to_point(),
             g2: R.clone(),
@@ -252,7 +255,7 @@ impl GlobalStatePhase6 {
             h2: S.clone(),
         };
         let w = ECDDHWitness { x: sigma_i.clone() };
-        ECDDHProof::prove(&w, &delta)
+        ECDDHProof::prove(sid, &w, &delta)
     }
 
     // TODO: check all parties submitted inputs
@@ -274,7 +277,7 @@ impl GlobalStatePhase6 {
             .collect::<Vec<BigInt>>();
         let proof_vec = (0..len)
             .map(|i| local_state_vec[i].proof_of_eq_dlog.clone())
-            .collect::<Vec<ECDDHProof<Secp256k1, Sha256>>>();
+            .collect::<Vec<ECDDHProof>>();
         let miu_randomness_vec = (0..len)
             .map(|i| {
                 (0..len - 1)
@@ -304,7 +307,7 @@ impl GlobalStatePhase6 {
         }
     }
 
-    pub fn phase6_blame(&self, R: &Point<Secp256k1>) -> Result<(), ErrorType> {
+    pub fn phase6_blame(&self, sid: &SessionId, R: &Point<Secp256k1>) -> Result<(), ErrorType> {
         let len = self.k_vec.len();
         let mut bad_signers_vec = Vec::new();
 
@@ -325,6 +328,7 @@ impl GlobalStatePhase6 {
         // check correctness of k
         for i in 0..len {
             if MessageA::a_with_predefined_randomness(
+                sid,
                 &self.k_vec[i],
                 &self.encryption_key_vec[i],
                 &self.k_randomness_vec[i],
@@ -388,7 +392,7 @@ impl GlobalStatePhase6 {
                     h2: self.S_vec[i].clone(),
                 };
 
-                let result = self.proof_vec[i].verify(&statement);
+                let result = self.proof_vec[i].verify(sid, &statement);
                 if result.is_err() {
                     bad_signers_vec.push(i)
                 }
@@ -0,0 +1,244 @@
+//! BIP32 non-hardened derivation of child keys from a threshold ECDSA key
+//!
+//! Secret key is never reconstructed, so only public derivation is possible: every child key is
+//! the master key shifted by a public additive tweak `Y' = Y + tweak * G`. Parties apply the same
+//! tweak to their presignatures (see [CompletedOfflineStage::derive_child]) to sign for the
+//! child key.
+//!
+//! Hardened indexes (`i >= 2^31`) require the secret key and are rejected.
+//!
+//! [CompletedOfflineStage::derive_child]: crate::gg20::state_machine::sign::CompletedOfflineStage::derive_child
+
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use curv::BigInt;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256, Sha512};
+use thiserror::Error;
+
+use crate::gg20::state_machine::keygen::LocalKey;
+
+/// Chain code of an extended key
+pub type ChainCode = [u8; 32];
+
+/// First index of hardened children
+pub const HARDENED_OFFSET: u32 = 1 << 31;
+
+/// Version bytes of mainnet extended public key (`xpub`)
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+
+/// Child key derived from the master key
+#[derive(Clone, Debug, PartialEq)]
+pub struct DerivedKey {
+    pub public_key: Point<Secp256k1>,
+    pub chain_code: ChainCode,
+    /// Sum of tweaks along the path, `public_key = master_public_key + tweak * G`
+    pub tweak: Scalar<Secp256k1>,
+}
+
+/// Derives a child of `public_key` at non-hardened `path`
+pub fn derive_path(
+    public_key: &Point<Secp256k1>,
+    chain_code: &ChainCode,
+    path: &[u32],
+) -> Result<DerivedKey, DerivationError> {
+    let mut derived = DerivedKey {
+        public_key: public_key.clone(),
+        chain_code: *chain_code,
+        tweak: Scalar::zero(),
+    };
+    for &index in path {
+        let (child_public_key, child_chain_code, tweak) =
+            derive_child(&derived.public_key, &derived.chain_code, index)?;
+        derived = DerivedKey {
+            public_key: child_public_key,
+            chain_code: child_chain_code,
+            tweak: derived.tweak + tweak,
+        };
+    }
+    Ok(derived)
+}
+
+/// CKDpub: derives child public key, its chain code and the tweak applied to the parent key
+pub fn derive_child(
+    public_key: &Point<Secp256k1>,
+    chain_code: &ChainCode,
+    index: u32,
+) -> Result<(Point<Secp256k1>, ChainCode, Scalar<Secp256k1>), DerivationError> {
+    if index >= HARDENED_OFFSET {
+        return Err(DerivationError::HardenedIndex(index));
+    }
+    let mut mac = Hmac::<Sha512>::new_from_slice(chain_code).expect("any key size is accepted");
+    mac.update(&public_key.to_bytes(true));
+    mac.update(&index.to_be_bytes());
+    let i = mac.finalize().into_bytes();
+    let (il, ir) = i.split_at(32);
+
+    let il = BigInt::from_bytes(il);
+    if &il >= Scalar::<Secp256k1>::group_order() {
+        return Err(DerivationError::InvalidChild(index));
+    }
+    let tweak = Scalar::<Secp256k1>::from(&il);
+    let child_public_key = public_key + Point::generator() * &tweak;
+    if child_public_key.is_zero() {
+        return Err(DerivationError::InvalidChild(index));
+    }
+    let mut child_chain_code = [0u8; 32];
+    child_chain_code.copy_from_slice(ir);
+    Ok((child_public_key, child_chain_code, tweak))
+}
+
+/// Parses derivation path like `m/44/60/0/0/7`
+///
+/// Hardened components (`44'` or `44h`) are rejected.
+pub fn parse_path(path: &str) -> Result<Vec<u32>, DerivationError> {
+    let mut components = path.split('/');
+    if components.next() != Some("m") {
+        return Err(DerivationError::InvalidPath(path.to_string()));
+    }
+    components
+        .map(|component| {
+            if component.ends_with('\'') || component.ends_with('h') {
+                let index = component[..component.len() - 1]
+                    .parse::<u32>()
+                    .map_err(|_| DerivationError::InvalidPath(path.to_string()))?;
+                return Err(DerivationError::HardenedIndex(
+                    index.saturating_add(HARDENED_OFFSET),
+                ));
+            }
+            component
+                .parse::<u32>()
+                .map_err(|_| DerivationError::InvalidPath(path.to_string()))
+        })
+        .collect()
+}
+
+/// Serializes master public key as Base58Check encoded `xpub`
+pub fn encode_xpub(public_key: &Point<Secp256k1>, chain_code: &ChainCode) -> String {
+    let mut payload = Vec::with_capacity(78);
+    payload.extend_from_slice(&XPUB_VERSION);
+    // depth, parent fingerprint and child number are zero for the master key
+    payload.extend_from_slice(&[0u8; 9]);
+    payload.extend_from_slice(chain_code);
+    payload.extend_from_slice(&public_key.to_bytes(true));
+    base58_check(&payload)
+}
+
+fn base58_check(payload: &[u8]) -> String {
+    const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+    let checksum = Sha256::digest(&Sha256::digest(payload));
+    let data = [payload, &checksum[..4]].concat();
+
+    // base58 digits in little-endian order
+    let mut digits: Vec<u8> = vec![];
+    for &byte in &data {
+        let mut carry = u32::from(byte);
+        for digit in digits.iter_mut() {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let leading_zeros = data.iter().take_while(|byte| **byte == 0).count();
+    std::iter::repeat('1')
+        .take(leading_zeros)
+        .chain(
+            digits
+                .iter()
+                .rev()
+                .map(|digit| char::from(ALPHABET[usize::from(*digit)])),
+        )
+        .collect()
+}
+
+impl LocalKey<Secp256k1> {
+    /// Extended public key of the master key
+    pub fn xpub(&self) -> Result<String, DerivationError> {
+        let chain_code = self
+            .chain_code
+            .as_ref()
+            .ok_or(DerivationError::MissingChainCode)?;
+        Ok(encode_xpub(&self.public_key(), chain_code))
+    }
+
+    /// Derives child key at non-hardened `path`
+    pub fn derive(&self, path: &[u32]) -> Result<DerivedKey, DerivationError> {
+        let chain_code = self
+            .chain_code
+            .as_ref()
+            .ok_or(DerivationError::MissingChainCode)?;
+        derive_path(&self.public_key(), chain_code, path)
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum DerivationError {
+    #[error("key was generated without chain code")]
+    MissingChainCode,
+    #[error("hardened index {0} can't be derived from a threshold key")]
+    HardenedIndex(u32),
+    #[error("child {0} is invalid, use the next index")]
+    InvalidChild(u32),
+    #[error("invalid derivation path: {0}")]
+    InvalidPath(String),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gg20::state_machine::keygen::test::simulate_keygen;
+    use crate::gg20::state_machine::sign::test::{simulate_offline_stage, simulate_signing};
+
+    use super::*;
+
+    #[test]
+    fn encodes_bip32_test_vector_xpub() {
+        let chain_code =
+            hex::decode("873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508")
+                .unwrap();
+        let public_key = Point::<Secp256k1>::from_bytes(
+            &hex::decode("0339a36013301597daef41fbe593a02cc513d0b55527ec2df1050e2e8ff49c85c2")
+                .unwrap(),
+        )
+        .unwrap();
+        let mut code = [0u8; 32];
+        code.copy_from_slice(&chain_code);
+        assert_eq!(
+            encode_xpub(&public_key, &code),
+            "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8"
+        );
+    }
+
+    #[test]
+    fn parses_path() {
+        assert_eq!(parse_path("m/44/60/0/0/7"), Ok(vec![44, 60, 0, 0, 7]));
+        assert_eq!(parse_path("m"), Ok(vec![]));
+        assert_eq!(
+            parse_path("m/44'/60/0"),
+            Err(DerivationError::HardenedIndex(44 + HARDENED_OFFSET))
+        );
+        assert!(parse_path("44/60").is_err());
+    }
+
+    #[test]
+    fn signs_for_derived_key() {
+        let keys = simulate_keygen(1, 3);
+        assert_eq!(keys[0].chain_code, keys[2].chain_code);
+        let derived = keys[0].derive(&[44, 60, 0, 0, 7]).unwrap();
+        assert_eq!(
+            derived.public_key,
+            keys[0].public_key() + Point::generator() * &derived.tweak
+        );
+
+        let offline = simulate_offline_stage(keys, &[1, 3])
+            .into_iter()
+            .map(|stage| stage.derive_child(&derived.tweak))
+            .collect::<Vec<_>>();
+        assert_eq!(offline[0].public_key(), &derived.public_key);
+        simulate_signing(offline, b"derived key signs");
+    }
+}
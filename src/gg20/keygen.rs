@@ -1,49 +1,70 @@
-use crate::gg20::state_machine::keygen::{Keygen, LocalKey};
-use anyhow::{anyhow, Context, Result};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
 use curv::elliptic::curves::Secp256k1;
 use futures::StreamExt;
 use round_based::async_runtime::AsyncProtocol;
-use rustmodel::KeygenMember;
 
-use crate::utils;
-
-use crate::utils::sm_client::join_computation;
+use crate::error::TssError;
+use crate::gg20::party_i::PreParams;
+use crate::gg20::state_machine::keygen::{Keygen, LocalKey};
+use crate::utils::echo_broadcast::EchoBroadcast;
+use crate::utils::secure_channel::Identity;
+use crate::utils::session_id::SessionId;
+use crate::utils::transport::{join_computation, Transport};
 
-pub async fn start_keygen(
+/// Generates ECDSA key among `n` parties of the room
+///
+/// Parties get their indexes in order of joining the room. Returns the index issued to the party
+/// together with its key, which keeps identity keys of the parties bound to their indexes.
+/// Paillier key and `N_tilde` of the party are taken from `pre_params`. With `echo_broadcast`
+/// every party makes sure that the others received the same commitments.
+pub async fn start_keygen<T>(
     request_id: &str,
-    token: &str,
-    address: &str,
+    transport: &T,
+    identity: &Identity,
     room: &str,
     t: u16,
     n: u16,
     name: &str,
-) -> Result<(u16, LocalKey<Secp256k1>, Vec<KeygenMember>)> {
-    let (party_id, incoming, outgoing) = join_computation(
-        request_id,
-        token,
-        surf::Url::parse(address)?,
-        &format!("{}-ecdsa", room),
-        (1..(n + 1)).collect(),
+    pre_params: PreParams,
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<(u16, LocalKey<Secp256k1>)>
+where
+    T: Transport + ?Sized,
+{
+    let room_id = format!("{}-ecdsa", room);
+    let parties: Vec<u16> = (1..(n + 1)).collect();
+    let (party_id, roster, incoming, outgoing) = join_computation(
+        transport,
+        &room_id,
+        parties.clone(),
         None,
         Some(name.to_string()),
+        identity,
     )
     .await
     .context("join computation")?;
+    println!(
+        "requestId={} start ecdsa keygen for party: {} room {}",
+        request_id, party_id, room
+    );
 
     let incoming = incoming.fuse();
     tokio::pin!(incoming);
     tokio::pin!(outgoing);
 
-    let keygen = Keygen::new(party_id, t, n)?;
-    let local_share = AsyncProtocol::new(keygen, incoming, outgoing)
+    let sid = SessionId::new("ecdsa-keygen", &room_id, &parties).with_roster(&roster);
+    let mut keygen = Keygen::new(party_id, t, n, pre_params, sid)?;
+    keygen.set_round_timeout(round_timeout);
+    let identity_keys = roster.values().copied().collect();
+    let keygen = EchoBroadcast::new(keygen, echo_broadcast, sid, identity, identity_keys)?;
+    let mut local_share = AsyncProtocol::new(keygen, incoming, outgoing)
         .run()
         .await
-        .map_err(|e| anyhow!("protocol execution terminated with error: {}", e))?;
-    Ok((
-        party_id,
-        local_share,
-        utils::common::get_progress(request_id, token, surf::Url::parse(address)?, room)
-            .await
-            .members,
-    ))
+        .map_err(TssError::from)
+        .context("protocol execution terminated with error")?;
+    local_share.roster = Some(roster);
+    Ok((party_id, local_share))
 }
@@ -1,15 +1,23 @@
 use std::fmt;
 
 mod blame;
+pub mod derivation;
 pub mod keygen;
 pub mod mta;
 mod party_i;
 pub mod presignature;
+pub mod recover;
+pub mod refresh;
+pub mod reshare;
+pub mod sigma;
 pub mod signing;
 pub mod state_machine;
+pub mod zk_modulus;
 pub mod zk_pdl;
 pub mod zk_pdl_with_slack;
 
+pub use party_i::{PreParams, DEFAULT_MODULUS_BITS, MAX_MODULUS_BITS};
+
 #[cfg(test)]
 mod test;
 
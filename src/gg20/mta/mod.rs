@@ -1,5 +1,4 @@
 use curv::arithmetic::traits::Samplable;
-use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
 use curv::BigInt;
 use paillier::traits::EncryptWithChosenRandomness;
@@ -8,10 +7,11 @@ use paillier::{DecryptionKey, EncryptionKey, Paillier, Randomness, RawCiphertext
 use zk_paillier::zkproofs::DLogStatement;
 
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
 
 use crate::gg20::mta::range_proofs::AliceProof;
+use crate::gg20::sigma::DLogProof;
 use crate::gg20::Error::{self, InvalidKey};
+use crate::utils::session_id::SessionId;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MessageA {
@@ -22,8 +22,8 @@ pub struct MessageA {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MessageB {
     pub c: BigInt, // paillier encryption
-    pub b_proof: DLogProof<Secp256k1, Sha256>,
-    pub beta_tag_proof: DLogProof<Secp256k1, Sha256>,
+    pub b_proof: DLogProof,
+    pub beta_tag_proof: DLogProof,
 }
 
 impl MessageA {
@@ -31,17 +31,21 @@ impl MessageA {
     /// - other parties' `h1,h2,N_tilde`s for range proofs.
     /// If range proofs are not needed (one example is identification of aborts where we
     /// only want to reconstruct a ciphertext), `dlog_statements` can be an empty slice.
+    /// Range proofs are bound to the session `sid`.
     pub fn a(
+        sid: &SessionId,
         a: &Scalar<Secp256k1>,
         alice_ek: &EncryptionKey,
         dlog_statements: &[DLogStatement],
     ) -> (Self, BigInt) {
         let randomness = BigInt::sample_below(&alice_ek.n);
-        let m_a = MessageA::a_with_predefined_randomness(a, alice_ek, &randomness, dlog_statements);
+        let m_a =
+            MessageA::a_with_predefined_randomness(sid, a, alice_ek, &randomness, dlog_statements);
         (m_a, randomness)
     }
 
     pub fn a_with_predefined_randomness(
+        sid: &SessionId,
         a: &Scalar<Secp256k1>,
         alice_ek: &EncryptionKey,
         randomness: &BigInt,
@@ -58,7 +62,14 @@ impl MessageA {
         let alice_range_proofs = dlog_statements
             .iter()
             .map(|dlog_statement| {
-                AliceProof::generate(&a.to_bigint(), &c_a, alice_ek, dlog_statement, randomness)
+                AliceProof::generate(
+                    sid,
+                    &a.to_bigint(),
+                    &c_a,
+                    alice_ek,
+                    dlog_statement,
+                    randomness,
+                )
             })
             .collect::<Vec<AliceProof>>();
 
@@ -71,6 +82,7 @@ impl MessageA {
 
 impl MessageB {
     pub fn b(
+        sid: &SessionId,
         b: &Scalar<Secp256k1>,
         alice_ek: &EncryptionKey,
         m_a: MessageA,
@@ -79,6 +91,7 @@ impl MessageB {
         let beta_tag = BigInt::sample_below(&alice_ek.n);
         let randomness = BigInt::sample_below(&alice_ek.n);
         let (m_b, beta) = MessageB::b_with_predefined_randomness(
+            sid,
             b,
             alice_ek,
             m_a,
@@ -91,6 +104,7 @@ impl MessageB {
     }
 
     pub fn b_with_predefined_randomness(
+        sid: &SessionId,
         b: &Scalar<Secp256k1>,
         alice_ek: &EncryptionKey,
         m_a: MessageA,
@@ -106,7 +120,7 @@ impl MessageB {
             .range_proofs
             .iter()
             .zip(dlog_statements)
-            .map(|(proof, dlog_statement)| proof.verify(&m_a.c, alice_ek, dlog_statement))
+            .map(|(proof, dlog_statement)| proof.verify(sid, &m_a.c, alice_ek, dlog_statement))
             .all(|x| x)
         {
             return Err(InvalidKey);
@@ -126,8 +140,8 @@ impl MessageB {
         );
         let c_b = Paillier::add(alice_ek, b_c_a, c_beta_tag);
         let beta = Scalar::<Secp256k1>::zero() - &beta_tag_fe;
-        let dlog_proof_b = DLogProof::prove(b);
-        let dlog_proof_beta_tag = DLogProof::prove(&beta_tag_fe);
+        let dlog_proof_b = DLogProof::prove(sid, b);
+        let dlog_proof_beta_tag = DLogProof::prove(sid, &beta_tag_fe);
 
         Ok((
             Self {
@@ -141,6 +155,7 @@ impl MessageB {
 
     pub fn verify_proofs_get_alpha(
         &self,
+        sid: &SessionId,
         dk: &DecryptionKey,
         a: &Scalar<Secp256k1>,
     ) -> Result<(Scalar<Secp256k1>, BigInt), Error> {
@@ -149,8 +164,8 @@ impl MessageB {
         let alpha = Scalar::<Secp256k1>::from(alice_share.0.as_ref());
         let g_alpha = g * &alpha;
         let ba_btag = &self.b_proof.pk * a + &self.beta_tag_proof.pk;
-        if DLogProof::verify(&self.b_proof).is_ok()
-            && DLogProof::verify(&self.beta_tag_proof).is_ok()
+        if self.b_proof.verify(sid).is_ok()
+            && self.beta_tag_proof.verify(sid).is_ok()
             // we prove the correctness of the ciphertext using this check and the proof of knowledge of dlog of beta_tag
             && ba_btag == g_alpha
         {
@@ -8,6 +8,7 @@
 //! There are some deviations from the original specification:
 //! 1) In Bob's proofs `gamma` is sampled from `[0;q^2 * N]` and `tau` from `[0;q^3 * N_tilde]`.
 //! 2) A non-interactive version is implemented, with challenge `e` computed via Fiat-Shamir.
+//! 3) The challenge `e` also hashes the [SessionId], so proofs don't verify outside of their session.
 
 use curv::arithmetic::traits::*;
 use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
@@ -22,6 +23,8 @@ use serde::{Deserialize, Serialize};
 use std::borrow::Borrow;
 use zeroize::Zeroize;
 
+use crate::utils::session_id::SessionId;
+
 /// Represents the first round of the interactive version of the proof
 #[derive(Zeroize)]
 #[zeroize(drop)]
@@ -104,6 +107,7 @@ impl AliceProof {
     /// verify Alice's proof using the proof and public keys
     pub fn verify(
         &self,
+        sid: &SessionId,
         cipher: &BigInt,
         alice_ek: &EncryptionKey,
         dlog_statement: &DLogStatement,
@@ -141,6 +145,7 @@ impl AliceProof {
         let u = (gs1 * BigInt::mod_pow(&self.s, N, NN) * cipher_e_inv) % NN;
 
         let e = Sha256::new()
+            .chain(sid)
             .chain_bigint(N)
             .chain_bigint(&Gen)
             .chain_bigint(cipher)
@@ -158,6 +163,7 @@ impl AliceProof {
     /// Requires randomness used for encrypting Alice's secret a.
     /// It is assumed that secp256k1 curve is used.
     pub fn generate(
+        sid: &SessionId,
         a: &BigInt,
         cipher: &BigInt,
         alice_ek: &EncryptionKey,
@@ -173,6 +179,7 @@ impl AliceProof {
 
         let Gen = alice_ek.n.borrow() + 1;
         let e = Sha256::new()
+            .chain(sid)
             .chain_bigint(&alice_ek.n)
             .chain_bigint(&Gen)
             .chain_bigint(cipher)
@@ -320,6 +327,7 @@ pub struct BobProof {
 impl BobProof {
     pub fn verify(
         &self,
+        sid: &SessionId,
         a_enc: &BigInt,
         mta_avc_out: &BigInt,
         alice_ek: &EncryptionKey,
@@ -395,12 +403,12 @@ impl BobProof {
                 values_to_hash.push(&u_y_coor);
                 values_to_hash
                     .into_iter()
-                    .fold(Sha256::new(), |acc, b| acc.chain_bigint(b))
+                    .fold(Sha256::new().chain(sid), |acc, b| acc.chain_bigint(b))
                     .result_bigint()
             }
             None => values_to_hash
                 .into_iter()
-                .fold(Sha256::new(), |acc, b| acc.chain_bigint(b))
+                .fold(Sha256::new().chain(sid), |acc, b| acc.chain_bigint(b))
                 .result_bigint(),
         };
 
@@ -412,6 +420,7 @@ impl BobProof {
     }
 
     pub fn generate(
+        sid: &SessionId,
         a_encrypted: &BigInt,
         mta_encrypted: &BigInt,
         b: &Scalar<Secp256k1>,
@@ -460,12 +469,12 @@ impl BobProof {
             values_to_hash.push(&u_y_coor);
             values_to_hash
                 .into_iter()
-                .fold(Sha256::new(), |acc, b| acc.chain_bigint(b))
+                .fold(Sha256::new().chain(sid), |acc, b| acc.chain_bigint(b))
                 .result_bigint()
         } else {
             values_to_hash
                 .into_iter()
-                .fold(Sha256::new(), |acc, b| acc.chain_bigint(b))
+                .fold(Sha256::new().chain(sid), |acc, b| acc.chain_bigint(b))
                 .result_bigint()
         };
 
@@ -498,6 +507,7 @@ pub struct BobProofExt {
 impl BobProofExt {
     pub fn verify(
         &self,
+        sid: &SessionId,
         a_enc: &BigInt,
         mta_avc_out: &BigInt,
         alice_ek: &EncryptionKey,
@@ -506,6 +516,7 @@ impl BobProofExt {
     ) -> bool {
         // check basic proof first
         if !self.proof.verify(
+            sid,
             a_enc,
             mta_avc_out,
             alice_ek,
@@ -563,6 +574,7 @@ pub(crate) mod tests {
     use paillier::{Add, DecryptionKey, Mul, Paillier, RawCiphertext, RawPlaintext};
 
     fn generate(
+        sid: &SessionId,
         a_encrypted: &BigInt,
         mta_encrypted: &BigInt,
         b: &Scalar<Secp256k1>,
@@ -573,6 +585,7 @@ pub(crate) mod tests {
     ) -> BobProofExt {
         // proving a basic proof (with modified hash)
         let (bob_proof, u) = BobProof::generate(
+            sid,
             a_encrypted,
             mta_encrypted,
             b,
@@ -615,6 +628,7 @@ pub(crate) mod tests {
     #[test]
     fn alice_zkp() {
         let (dlog_statement, ek, _) = generate_init();
+        let sid = SessionId::new("ecdsa-sign", "room", &[1, 2]);
 
         // Alice's secret value
         let a = Scalar::<Secp256k1>::random().to_bigint();
@@ -628,14 +642,18 @@ pub(crate) mod tests {
         .clone()
         .into_owned();
 
-        let alice_proof = AliceProof::generate(&a, &cipher, &ek, &dlog_statement, &r);
+        let alice_proof = AliceProof::generate(&sid, &a, &cipher, &ek, &dlog_statement, &r);
 
-        assert!(alice_proof.verify(&cipher, &ek, &dlog_statement));
+        assert!(alice_proof.verify(&sid, &cipher, &ek, &dlog_statement));
+        // proof doesn't verify in another session
+        let other = SessionId::new("ecdsa-sign", "another room", &[1, 2]);
+        assert!(!alice_proof.verify(&other, &cipher, &ek, &dlog_statement));
     }
 
     #[test]
     fn bob_zkp() {
         let (dlog_statement, ek, _) = generate_init();
+        let sid = SessionId::new("ecdsa-sign", "room", &[1, 2]);
 
         (0..5).for_each(|_| {
             let alice_public_key = &ek;
@@ -668,6 +686,7 @@ pub(crate) mod tests {
                 let mta_out = Paillier::add(alice_public_key, b_times_enc_a, enc_beta_prim);
 
                 let (bob_proof, _) = BobProof::generate(
+                    &sid,
                     &encrypted_a,
                     &mta_out.0.clone().into_owned(),
                     &b,
@@ -678,6 +697,7 @@ pub(crate) mod tests {
                     false,
                 );
                 assert!(bob_proof.verify(
+                    &sid,
                     &encrypted_a,
                     &mta_out.0.clone().into_owned(),
                     alice_public_key,
@@ -689,6 +709,7 @@ pub(crate) mod tests {
                 let ec_gen = Point::generator();
                 let X = ec_gen * &b;
                 let bob_proof = generate(
+                    &sid,
                     &encrypted_a,
                     &mta_out.0.clone().into_owned(),
                     &b,
@@ -698,6 +719,7 @@ pub(crate) mod tests {
                     &r,
                 );
                 assert!(bob_proof.verify(
+                    &sid,
                     &encrypted_a,
                     &mta_out.0.clone().into_owned(),
                     alice_public_key,
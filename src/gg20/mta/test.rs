@@ -1,5 +1,6 @@
 use crate::gg20::mta::range_proofs::tests::generate_init;
 use crate::gg20::mta::{MessageA, MessageB};
+use crate::utils::session_id::SessionId;
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Scalar};
 
 #[test]
@@ -7,12 +8,20 @@ fn test_mta() {
     let alice_input = Scalar::<Secp256k1>::random();
     let (dlog_statement, ek_alice, dk_alice) = generate_init();
     let bob_input = Scalar::<Secp256k1>::random();
-    let (m_a, _) = MessageA::a(&alice_input, &ek_alice, &[dlog_statement.clone()]);
-    let (m_b, beta, _, _) = MessageB::b(&bob_input, &ek_alice, m_a, &[dlog_statement]).unwrap();
+    let sid = SessionId::new("ecdsa-sign", "room", &[1, 2]);
+    let (m_a, _) = MessageA::a(&sid, &alice_input, &ek_alice, &[dlog_statement.clone()]);
+    let (m_b, beta, _, _) =
+        MessageB::b(&sid, &bob_input, &ek_alice, m_a, &[dlog_statement]).unwrap();
     let alpha = m_b
-        .verify_proofs_get_alpha(&dk_alice, &alice_input)
+        .verify_proofs_get_alpha(&sid, &dk_alice, &alice_input)
         .expect("wrong dlog or m_b");
 
+    // message of another session is rejected
+    let other = SessionId::new("ecdsa-sign", "another room", &[1, 2]);
+    assert!(m_b
+        .verify_proofs_get_alpha(&other, &dk_alice, &alice_input)
+        .is_err());
+
     let left = alpha.0 + beta;
     let right = alice_input * bob_input;
     assert_eq!(left, right);
@@ -1,14 +1,13 @@
 #![allow(non_snake_case)]
 
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 
 use centipede::juggling::proof_system::{Helgamalsegmented, Witness};
 use centipede::juggling::segmentation::Msegmentation;
 use curv::arithmetic::traits::*;
-use curv::cryptographic_primitives::commitments::hash_commitment::HashCommitment;
-use curv::cryptographic_primitives::commitments::traits::Commitment;
-use curv::cryptographic_primitives::proofs::sigma_correct_homomorphic_elgamal_enc::*;
-use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
+use curv::cryptographic_primitives::proofs::sigma_correct_homomorphic_elgamal_enc::{
+    HomoElGamalStatement, HomoElGamalWitness,
+};
 use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Curve, Point, Scalar};
 use curv::BigInt;
@@ -20,18 +19,28 @@ use paillier::{
 };
 
 use serde::{Deserialize, Serialize};
-use zk_paillier::zkproofs::NiCorrectKeyProof;
-use zk_paillier::zkproofs::{CompositeDLogProof, DLogStatement};
+use zk_paillier::zkproofs::DLogStatement;
 
+use crate::gg20::sigma::{DLogProof, HomoELGamalProof, PedersenProof};
+use crate::gg20::zk_modulus::{
+    CorrectKeyProof, NoSmallFactorProof, PaillierBlumModulusProof, RingPedersenProof,
+};
 use crate::gg20::zk_pdl_with_slack::{PDLwSlackProof, PDLwSlackStatement, PDLwSlackWitness};
 use crate::gg20::ErrorType;
-use curv::cryptographic_primitives::proofs::sigma_valid_pedersen::PedersenProof;
+use crate::utils::secret::{zeroize_decryption_key, Redacted};
+use crate::utils::session_id::SessionId;
+use zeroize::Zeroize;
 
 use std::convert::TryInto;
 
 const SECURITY: usize = 256;
 const PAILLIER_MIN_BIT_LENGTH: usize = 2047;
-const PAILLIER_MAX_BIT_LENGTH: usize = 2048;
+const PAILLIER_MAX_BIT_LENGTH: usize = MAX_MODULUS_BITS;
+
+/// Bit length of Paillier and `N_tilde` moduli unless configured otherwise
+pub const DEFAULT_MODULUS_BITS: usize = 2048;
+/// Largest bit length of Paillier and `N_tilde` moduli accepted from other parties
+pub const MAX_MODULUS_BITS: usize = 4096;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Parameters {
@@ -39,7 +48,7 @@ pub struct Parameters {
     pub share_count: u16, //n
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Keys<E: Curve = Secp256k1> {
     pub u_i: Scalar<E>,
     pub y_i: Point<E>,
@@ -51,23 +60,177 @@ pub struct Keys<E: Curve = Secp256k1> {
     pub h2: BigInt,
     pub xhi: BigInt,
     pub xhi_inv: BigInt,
+    /// Factors of `N_tilde`
+    pub dk_tilde: DecryptionKey,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl<E: Curve> Debug for Keys<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Keys")
+            .field("u_i", &Redacted)
+            .field("y_i", &self.y_i)
+            .field("dk", &Redacted)
+            .field("ek", &self.ek)
+            .field("party_index", &self.party_index)
+            .field("N_tilde", &self.N_tilde)
+            .field("h1", &self.h1)
+            .field("h2", &self.h2)
+            .field("xhi", &Redacted)
+            .field("xhi_inv", &Redacted)
+            .field("dk_tilde", &Redacted)
+            .finish()
+    }
+}
+
+impl<E: Curve> Drop for Keys<E> {
+    fn drop(&mut self) {
+        zeroize_decryption_key(&mut self.dk);
+        zeroize_decryption_key(&mut self.dk_tilde);
+        self.xhi.zeroize();
+        self.xhi_inv.zeroize();
+    }
+}
+
+/// Paillier key and `h1`, `h2`, `N_tilde` of a party, the expensive part of its keys
+///
+/// Generating safe primes takes most of the time of keygen and refresh, so they can be
+/// generated ahead of time with [PreParams::generate] and stored until the party joins a
+/// protocol. Every set must be used once: parties reject a modulus that another party uses.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PreParams {
+    pub ek: EncryptionKey,
+    pub dk: DecryptionKey,
+    pub N_tilde: BigInt,
+    pub h1: BigInt,
+    pub h2: BigInt,
+    pub xhi: BigInt,
+    pub xhi_inv: BigInt,
+    pub dk_tilde: DecryptionKey,
+}
+
+impl PreParams {
+    /// Generates safe-prime Paillier key and `N_tilde` of `modulus_bits`
+    pub fn generate(modulus_bits: usize) -> Self {
+        let (ek, dk) = Paillier::keypair_safe_primes_with_modulus_size(modulus_bits).keys();
+        let (N_tilde, h1, h2, xhi, xhi_inv, dk_tilde) = generate_h1_h2_N_tilde(modulus_bits);
+        Self {
+            ek,
+            dk,
+            N_tilde,
+            h1,
+            h2,
+            xhi,
+            xhi_inv,
+            dk_tilde,
+        }
+    }
+
+    /// Bit length of the Paillier modulus
+    pub fn modulus_bits(&self) -> usize {
+        self.dk.p.bit_length() + self.dk.q.bit_length()
+    }
+}
+
+impl Debug for PreParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PreParams")
+            .field("ek", &self.ek)
+            .field("dk", &Redacted)
+            .field("N_tilde", &self.N_tilde)
+            .field("h1", &self.h1)
+            .field("h2", &self.h2)
+            .field("xhi", &Redacted)
+            .field("xhi_inv", &Redacted)
+            .field("dk_tilde", &Redacted)
+            .finish()
+    }
+}
+
+impl Drop for PreParams {
+    fn drop(&mut self) {
+        zeroize_decryption_key(&mut self.dk);
+        zeroize_decryption_key(&mut self.dk_tilde);
+        self.xhi.zeroize();
+        self.xhi_inv.zeroize();
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PartyPrivate {
     u_i: Scalar<Secp256k1>,
     x_i: Scalar<Secp256k1>,
     dk: DecryptionKey,
 }
 
+impl Debug for PartyPrivate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PartyPrivate")
+            .field("u_i", &Redacted)
+            .field("x_i", &Redacted)
+            .field("dk", &Redacted)
+            .finish()
+    }
+}
+
+impl Drop for PartyPrivate {
+    fn drop(&mut self) {
+        zeroize_decryption_key(&mut self.dk);
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct KeyGenBroadcastMessage1 {
     pub e: EncryptionKey,
     pub dlog_statement: DLogStatement,
     pub com: BigInt,
-    pub correct_key_proof: NiCorrectKeyProof,
-    pub composite_dlog_proof_base_h1: CompositeDLogProof,
-    pub composite_dlog_proof_base_h2: CompositeDLogProof,
+    pub correct_key_proof: CorrectKeyProof,
+    pub ring_pedersen_proof_base_h1: RingPedersenProof,
+    pub ring_pedersen_proof_base_h2: RingPedersenProof,
+    pub modulus_proof: PaillierBlumModulusProof,
+    pub n_tilde_modulus_proof: PaillierBlumModulusProof,
+}
+
+impl KeyGenBroadcastMessage1 {
+    /// Verifies Paillier key and h1, h2, N_tilde proofs, the commitment is not checked
+    ///
+    /// Both moduli must be Paillier-Blum integers of [DEFAULT_MODULUS_BITS] up to
+    /// [MAX_MODULUS_BITS] bits.
+    pub fn verify_correct_key_and_h1h2(&self, sid: &SessionId) -> bool {
+        let dlog_statement_base_h2 = DLogStatement {
+            N: self.dlog_statement.N.clone(),
+            g: self.dlog_statement.ni.clone(),
+            ni: self.dlog_statement.g.clone(),
+        };
+        self.correct_key_proof.verify(sid, &self.e.n).is_ok()
+            && self.e.n.bit_length() >= PAILLIER_MIN_BIT_LENGTH
+            && self.e.n.bit_length() <= PAILLIER_MAX_BIT_LENGTH
+            && self.dlog_statement.N.bit_length() >= PAILLIER_MIN_BIT_LENGTH
+            && self.dlog_statement.N.bit_length() <= PAILLIER_MAX_BIT_LENGTH
+            && self
+                .ring_pedersen_proof_base_h1
+                .verify(sid, &self.dlog_statement)
+                .is_ok()
+            && self
+                .ring_pedersen_proof_base_h2
+                .verify(sid, &dlog_statement_base_h2)
+                .is_ok()
+            && self.modulus_proof.verify(sid, &self.e.n).is_ok()
+            && self
+                .n_tilde_modulus_proof
+                .verify(sid, &self.dlog_statement.N)
+                .is_ok()
+    }
+}
+
+/// Secret share dealt to a single party
+///
+/// The dealer also proves that its Paillier modulus has no small factors, the proof is made
+/// against `h1`, `h2` and `N_tilde` of the receiver.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyGenShareMessage {
+    pub vss_scheme: VerifiableSS<Secp256k1>,
+    pub secret_share: Scalar<Secp256k1>,
+    pub no_small_factor_proof: NoSmallFactorProof,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -76,13 +239,22 @@ pub struct KeyGenDecommitMessage1 {
     pub y_i: Point<Secp256k1>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SharedKeys {
     pub y: Point<Secp256k1>,
     pub x_i: Scalar<Secp256k1>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl Debug for SharedKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedKeys")
+            .field("y", &self.y)
+            .field("x_i", &Redacted)
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SignKeys {
     pub w_i: Scalar<Secp256k1>,
     pub g_w_i: Point<Secp256k1>,
@@ -91,6 +263,18 @@ pub struct SignKeys {
     pub g_gamma_i: Point<Secp256k1>,
 }
 
+impl Debug for SignKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SignKeys")
+            .field("w_i", &Redacted)
+            .field("g_w_i", &self.g_w_i)
+            .field("k_i", &Redacted)
+            .field("gamma_i", &Redacted)
+            .field("g_gamma_i", &self.g_gamma_i)
+            .finish()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SignBroadcastPhase1 {
     pub com: BigInt,
@@ -118,93 +302,58 @@ pub struct SignatureRecid {
     pub recid: u8,
 }
 
-pub fn generate_h1_h2_N_tilde() -> (BigInt, BigInt, BigInt, BigInt, BigInt) {
-    // note, should be safe primes:
-    // let (ek_tilde, dk_tilde) = Paillier::keypair_safe_primes().keys();;
-    let (ek_tilde, dk_tilde) = Paillier::keypair().keys();
+pub fn generate_h1_h2_N_tilde(
+    modulus_bits: usize,
+) -> (BigInt, BigInt, BigInt, BigInt, BigInt, DecryptionKey) {
+    let (ek_tilde, dk_tilde) = Paillier::keypair_safe_primes_with_modulus_size(modulus_bits).keys();
     let one = BigInt::one();
     let phi = (&dk_tilde.p - &one) * (&dk_tilde.q - &one);
-    let h1 = BigInt::sample_below(&ek_tilde.n);
-    let (mut xhi, mut xhi_inv) = loop {
+    // h1 is a quadratic residue, so it generates the group of squares of order phi / 4
+    let r = BigInt::sample_below(&ek_tilde.n);
+    let h1 = BigInt::mod_mul(&r, &r, &ek_tilde.n);
+    let (xhi, xhi_inv) = loop {
         let xhi_ = BigInt::sample_below(&phi);
         match BigInt::mod_inv(&xhi_, &phi) {
             Some(inv) => break (xhi_, inv),
             None => continue,
         }
     };
+    // Ring-Pedersen proofs show h2 = h1^xhi and h1 = h2^xhi_inv
     let h2 = BigInt::mod_pow(&h1, &xhi, &ek_tilde.n);
-    xhi = BigInt::sub(&phi, &xhi);
-    xhi_inv = BigInt::sub(&phi, &xhi_inv);
 
-    (ek_tilde.n, h1, h2, xhi, xhi_inv)
+    (ek_tilde.n, h1, h2, xhi, xhi_inv, dk_tilde)
 }
 
 impl Keys {
-    pub fn create(index: usize) -> Self {
-        let u = Scalar::<Secp256k1>::random();
-        let y = Point::generator() * &u;
-        let (ek, dk) = Paillier::keypair().keys();
-        let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde();
-
-        Self {
-            u_i: u,
-            y_i: y,
-            dk,
-            ek,
-            party_index: index,
-            N_tilde,
-            h1,
-            h2,
-            xhi,
-            xhi_inv,
-        }
+    /// Generates keys of the party around pregenerated Paillier key and `N_tilde`
+    pub fn create(index: usize, pre_params: PreParams) -> Self {
+        Self::create_from(Scalar::<Secp256k1>::random(), index, pre_params)
     }
 
-    // we recommend using safe primes if the code is used in production
-    pub fn create_safe_prime(index: usize) -> Self {
-        let u = Scalar::<Secp256k1>::random();
-        let y = Point::generator() * &u;
-
-        let (ek, dk) = Paillier::keypair_safe_primes().keys();
-        let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde();
-
-        Self {
-            u_i: u,
-            y_i: y,
-            dk,
-            ek,
-            party_index: index,
-            N_tilde,
-            h1,
-            h2,
-            xhi,
-            xhi_inv,
-        }
-    }
-    pub fn create_from(u: Scalar<Secp256k1>, index: usize) -> Self {
+    pub fn create_from(u: Scalar<Secp256k1>, index: usize, pre_params: PreParams) -> Self {
         let y = Point::generator() * &u;
-        let (ek, dk) = Paillier::keypair().keys();
-        let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde();
 
         Self {
             u_i: u,
             y_i: y,
-            dk,
-            ek,
+            dk: pre_params.dk.clone(),
+            ek: pre_params.ek.clone(),
             party_index: index,
-            N_tilde,
-            h1,
-            h2,
-            xhi,
-            xhi_inv,
+            N_tilde: pre_params.N_tilde.clone(),
+            h1: pre_params.h1.clone(),
+            h2: pre_params.h2.clone(),
+            xhi: pre_params.xhi.clone(),
+            xhi_inv: pre_params.xhi_inv.clone(),
+            dk_tilde: pre_params.dk_tilde.clone(),
         }
     }
 
     pub fn phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(
         &self,
+        sid: &SessionId,
     ) -> (KeyGenBroadcastMessage1, KeyGenDecommitMessage1) {
         let blind_factor = BigInt::sample(SECURITY);
-        let correct_key_proof = NiCorrectKeyProof::proof(&self.dk, None);
+        let correct_key_proof = CorrectKeyProof::prove(sid, &self.ek.n, &self.dk.p, &self.dk.q);
 
         let dlog_statement_base_h1 = DLogStatement {
             N: self.N_tilde.clone(),
@@ -217,12 +366,18 @@ impl Keys {
             ni: self.h1.clone(),
         };
 
-        let composite_dlog_proof_base_h1 =
-            CompositeDLogProof::prove(&dlog_statement_base_h1, &self.xhi);
-        let composite_dlog_proof_base_h2 =
-            CompositeDLogProof::prove(&dlog_statement_base_h2, &self.xhi_inv);
-
-        let com = HashCommitment::<Sha256>::create_commitment_with_user_defined_randomness(
+        let one = BigInt::one();
+        let phi_tilde = (&self.dk_tilde.p - &one) * (&self.dk_tilde.q - &one);
+        let ring_pedersen_proof_base_h1 =
+            RingPedersenProof::prove(sid, &dlog_statement_base_h1, &self.xhi, &phi_tilde);
+        let ring_pedersen_proof_base_h2 =
+            RingPedersenProof::prove(sid, &dlog_statement_base_h2, &self.xhi_inv, &phi_tilde);
+        let modulus_proof =
+            PaillierBlumModulusProof::prove(sid, &self.ek.n, &self.dk.p, &self.dk.q);
+        let n_tilde_modulus_proof =
+            PaillierBlumModulusProof::prove(sid, &self.N_tilde, &self.dk_tilde.p, &self.dk_tilde.q);
+
+        let com = sid.commit::<Sha256>(
             &BigInt::from_bytes(self.y_i.to_bytes(true).as_ref()),
             &blind_factor,
         );
@@ -231,8 +386,10 @@ impl Keys {
             dlog_statement: dlog_statement_base_h1,
             com,
             correct_key_proof,
-            composite_dlog_proof_base_h1,
-            composite_dlog_proof_base_h2,
+            ring_pedersen_proof_base_h1,
+            ring_pedersen_proof_base_h2,
+            modulus_proof,
+            n_tilde_modulus_proof,
         };
         let decom1 = KeyGenDecommitMessage1 {
             blind_factor,
@@ -243,6 +400,7 @@ impl Keys {
 
     pub fn phase1_verify_com_phase3_verify_correct_key_verify_dlog_phase2_distribute(
         &self,
+        sid: &SessionId,
         params: &Parameters,
         decom_vec: &[KeyGenDecommitMessage1],
         bc1_vec: &[KeyGenBroadcastMessage1],
@@ -254,32 +412,12 @@ impl Keys {
         // test paillier correct key, h1,h2 correct generation and test decommitments
         let correct_key_correct_decom_all = (0..bc1_vec.len())
             .map(|i| {
-                let dlog_statement_base_h2 = DLogStatement {
-                    N: bc1_vec[i].dlog_statement.N.clone(),
-                    g: bc1_vec[i].dlog_statement.ni.clone(),
-                    ni: bc1_vec[i].dlog_statement.g.clone(),
-                };
-                let test_res =
-                    HashCommitment::<Sha256>::create_commitment_with_user_defined_randomness(
-                        &BigInt::from_bytes(&decom_vec[i].y_i.to_bytes(true)),
-                        &decom_vec[i].blind_factor,
-                    ) == bc1_vec[i].com
-                        && bc1_vec[i]
-                            .correct_key_proof
-                            .verify(&bc1_vec[i].e, zk_paillier::zkproofs::SALT_STRING)
-                            .is_ok()
-                        && bc1_vec[i].e.n.bit_length() >= PAILLIER_MIN_BIT_LENGTH
-                        && bc1_vec[i].e.n.bit_length() <= PAILLIER_MAX_BIT_LENGTH
-                        && bc1_vec[i].dlog_statement.N.bit_length() >= PAILLIER_MIN_BIT_LENGTH
-                        && bc1_vec[i].dlog_statement.N.bit_length() <= PAILLIER_MAX_BIT_LENGTH
-                        && bc1_vec[i]
-                            .composite_dlog_proof_base_h1
-                            .verify(&bc1_vec[i].dlog_statement)
-                            .is_ok()
-                        && bc1_vec[i]
-                            .composite_dlog_proof_base_h2
-                            .verify(&dlog_statement_base_h2)
-                            .is_ok();
+                let test_res = sid.commit::<Sha256>(
+                    &BigInt::from_bytes(&decom_vec[i].y_i.to_bytes(true)),
+                    &decom_vec[i].blind_factor,
+                ) == bc1_vec[i].com
+                    && bc1_vec[i].verify_correct_key_and_h1h2(sid)
+                    && !reuses_modulus(bc1_vec, i);
                 if !test_res {
                     bad_actors_vec.push(i);
                     false
@@ -303,14 +441,78 @@ impl Keys {
         }
     }
 
+    /// Proves to every other party that our Paillier modulus has no small factors, proofs are
+    /// returned in order of the receiving parties
+    pub fn phase2_prove_no_small_factor(
+        &self,
+        sid: &SessionId,
+        bc1_vec: &[KeyGenBroadcastMessage1],
+    ) -> Result<Vec<NoSmallFactorProof>, ErrorType> {
+        let mut bad_actors_vec = Vec::new();
+        let proofs = (0..bc1_vec.len())
+            .filter(|&i| i + 1 != self.party_index)
+            .filter_map(|i| {
+                let setup = &bc1_vec[i].dlog_statement;
+                match NoSmallFactorProof::prove(sid, &self.ek.n, &self.dk.p, &self.dk.q, setup) {
+                    Ok(proof) => Some(proof),
+                    Err(_) => {
+                        bad_actors_vec.push(i);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        if bad_actors_vec.is_empty() {
+            Ok(proofs)
+        } else {
+            Err(ErrorType {
+                error_type: "invalid h1, h2".to_string(),
+                bad_actors: bad_actors_vec,
+            })
+        }
+    }
+
+    /// Verifies no small factor proofs of every other party, `proofs` are ordered by the sending
+    /// party and don't include our own
+    pub fn phase3_verify_no_small_factor(
+        &self,
+        sid: &SessionId,
+        bc1_vec: &[KeyGenBroadcastMessage1],
+        proofs: &[&NoSmallFactorProof],
+    ) -> Result<(), ErrorType> {
+        assert_eq!(proofs.len() + 1, bc1_vec.len());
+        let setup = DLogStatement {
+            N: self.N_tilde.clone(),
+            g: self.h1.clone(),
+            ni: self.h2.clone(),
+        };
+        let bad_actors_vec: Vec<usize> = (0..bc1_vec.len())
+            .filter(|&i| i + 1 != self.party_index)
+            .zip(proofs)
+            .filter(|(i, proof)| proof.verify(sid, &bc1_vec[*i].e.n, &setup).is_err())
+            .map(|(i, _)| i)
+            .collect();
+
+        if bad_actors_vec.is_empty() {
+            Ok(())
+        } else {
+            Err(ErrorType {
+                error_type: "bad no small factor proof".to_string(),
+                bad_actors: bad_actors_vec,
+            })
+        }
+    }
+
     pub fn phase2_verify_vss_construct_keypair_phase3_pok_dlog(
         &self,
+        sid: &SessionId,
         params: &Parameters,
         y_vec: &[Point<Secp256k1>],
         secret_shares_vec: &[Scalar<Secp256k1>],
         vss_scheme_vec: &[VerifiableSS<Secp256k1>],
         index: usize,
-    ) -> Result<(SharedKeys, DLogProof<Secp256k1, Sha256>), ErrorType> {
+    ) -> Result<(SharedKeys, DLogProof), ErrorType> {
         let mut bad_actors_vec = Vec::new();
         assert_eq!(y_vec.len(), usize::from(params.share_count));
         assert_eq!(secret_shares_vec.len(), usize::from(params.share_count));
@@ -343,7 +545,7 @@ impl Keys {
             let x_i = secret_shares_vec
                 .iter()
                 .fold(Scalar::<Secp256k1>::zero(), |acc, x| acc + x);
-            let dlog_proof = DLogProof::prove(&x_i);
+            let dlog_proof = DLogProof::prove(sid, &x_i);
             Ok((SharedKeys { y, x_i }, dlog_proof))
         } else {
             Err(err_type)
@@ -387,8 +589,9 @@ impl Keys {
     }
 
     pub fn verify_dlog_proofs_check_against_vss(
+        sid: &SessionId,
         params: &Parameters,
-        dlog_proofs_vec: &[DLogProof<Secp256k1, Sha256>],
+        dlog_proofs_vec: &[DLogProof],
         y_vec: &[Point<Secp256k1>],
         vss_vec: &[VerifiableSS<Secp256k1>],
     ) -> Result<(), ErrorType> {
@@ -398,7 +601,7 @@ impl Keys {
         let xi_commitments = Keys::get_commitments_to_xi(vss_vec);
         let xi_dlog_verify = (0..y_vec.len())
             .map(|i| {
-                let ver_res = DLogProof::verify(&dlog_proofs_vec[i]).is_ok();
+                let ver_res = dlog_proofs_vec[i].verify(sid).is_ok();
                 let verify_against_vss = xi_commitments[i] == dlog_proofs_vec[i].pk;
                 if !ver_res || !verify_against_vss {
                     bad_actors_vec.push(i);
@@ -422,12 +625,30 @@ impl Keys {
     }
 }
 
+/// Whether party `i` uses the same modulus as Paillier key and `N_tilde` or shares a modulus with
+/// another party
+///
+/// A copied modulus comes with valid proofs of its owner, so every party using it is blamed.
+fn reuses_modulus(bc1_vec: &[KeyGenBroadcastMessage1], i: usize) -> bool {
+    let (n, n_tilde) = (&bc1_vec[i].e.n, &bc1_vec[i].dlog_statement.N);
+    n == n_tilde
+        || bc1_vec
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .any(|(_, other)| {
+                [&other.e.n, &other.dlog_statement.N]
+                    .iter()
+                    .any(|&m| m == n || m == n_tilde)
+            })
+}
+
 impl PartyPrivate {
     pub fn set_private(key: Keys, shared_key: SharedKeys) -> Self {
         Self {
-            u_i: key.u_i,
+            u_i: key.u_i.clone(),
             x_i: shared_key.x_i,
-            dk: key.dk,
+            dk: key.dk.clone(),
         }
     }
 
@@ -440,47 +661,13 @@ impl PartyPrivate {
         Paillier::decrypt(&self.dk, &RawCiphertext::from(ciphertext))
     }
 
-    pub fn refresh_private_key(&self, factor: &Scalar<Secp256k1>, index: usize) -> Keys {
-        let u: Scalar<Secp256k1> = &self.u_i + factor;
-        let y = Point::generator() * &u;
-        let (ek, dk) = Paillier::keypair().keys();
-
-        let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde();
-
-        Keys {
-            u_i: u,
-            y_i: y,
-            dk,
-            ek,
-            party_index: index,
-            N_tilde,
-            h1,
-            h2,
-            xhi,
-            xhi_inv,
-        }
-    }
-
-    // we recommend using safe primes if the code is used in production
-    pub fn refresh_private_key_safe_prime(&self, factor: &Scalar<Secp256k1>, index: usize) -> Keys {
-        let u: Scalar<Secp256k1> = &self.u_i + factor;
-        let y = Point::generator() * &u;
-        let (ek, dk) = Paillier::keypair_safe_primes().keys();
-
-        let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde();
-
-        Keys {
-            u_i: u,
-            y_i: y,
-            dk,
-            ek,
-            party_index: index,
-            N_tilde,
-            h1,
-            h2,
-            xhi,
-            xhi_inv,
-        }
+    pub fn refresh_private_key(
+        &self,
+        factor: &Scalar<Secp256k1>,
+        index: usize,
+        pre_params: PreParams,
+    ) -> Keys {
+        Keys::create_from(&self.u_i + factor, index, pre_params)
     }
 
     // used for verifiable recovery
@@ -554,11 +741,11 @@ impl SignKeys {
         }
     }
 
-    pub fn phase1_broadcast(&self) -> (SignBroadcastPhase1, SignDecommitPhase1) {
+    pub fn phase1_broadcast(&self, sid: &SessionId) -> (SignBroadcastPhase1, SignDecommitPhase1) {
         let blind_factor = BigInt::sample(SECURITY);
         let g = Point::generator();
         let g_gamma_i = g * &self.gamma_i;
-        let com = HashCommitment::<Sha256>::create_commitment_with_user_defined_randomness(
+        let com = sid.commit::<Sha256>(
             &BigInt::from_bytes(g_gamma_i.to_bytes(true).as_ref()),
             &blind_factor,
         );
@@ -602,17 +789,14 @@ impl SignKeys {
     }
 
     pub fn phase3_compute_t_i(
+        sid: &SessionId,
         sigma_i: &Scalar<Secp256k1>,
-    ) -> (
-        Point<Secp256k1>,
-        Scalar<Secp256k1>,
-        PedersenProof<Secp256k1, Sha256>,
-    ) {
+    ) -> (Point<Secp256k1>, Scalar<Secp256k1>, PedersenProof) {
         let g_sigma_i = Point::generator() * sigma_i;
         let l = Scalar::<Secp256k1>::random();
         let h_l = Point::<Secp256k1>::base_point2() * &l;
         let T = g_sigma_i + h_l;
-        let T_zk_proof = PedersenProof::<Secp256k1, Sha256>::prove(sigma_i, &l);
+        let T_zk_proof = PedersenProof::prove(sid, sigma_i, &l);
 
         (T, l, T_zk_proof)
     }
@@ -624,8 +808,9 @@ impl SignKeys {
     }
 
     pub fn phase4(
+        sid: &SessionId,
         delta_inv: &Scalar<Secp256k1>,
-        b_proof_vec: &[&DLogProof<Secp256k1, Sha256>],
+        b_proof_vec: &[&DLogProof],
         phase1_decommit_vec: Vec<SignDecommitPhase1>,
         bc1_vec: &[SignBroadcastPhase1],
         index: usize,
@@ -635,7 +820,7 @@ impl SignKeys {
             .map(|j| {
                 let ind = if j < index { j } else { j + 1 };
                 let res = b_proof_vec[j].pk == phase1_decommit_vec[ind].g_gamma_i
-                    && HashCommitment::<Sha256>::create_commitment_with_user_defined_randomness(
+                    && sid.commit::<Sha256>(
                         &BigInt::from_bytes(
                             phase1_decommit_vec[ind].g_gamma_i.to_bytes(true).as_ref(),
                         ),
@@ -673,6 +858,7 @@ impl SignKeys {
 
 impl LocalSignature {
     pub fn phase5_proof_pdl(
+        sid: &SessionId,
         R_dash: &Point<Secp256k1>,
         R: &Point<Secp256k1>,
         k_ciphertext: &BigInt,
@@ -697,10 +883,11 @@ impl LocalSignature {
             r: k_enc_randomness.clone(),
         };
 
-        PDLwSlackProof::prove(&pdl_w_slack_witness, &pdl_w_slack_statement)
+        PDLwSlackProof::prove(sid, &pdl_w_slack_witness, &pdl_w_slack_statement)
     }
 
     pub fn phase5_verify_pdl(
+        sid: &SessionId,
         pdl_w_slack_proof_vec: &[PDLwSlackProof],
         R_dash: &Point<Secp256k1>,
         R: &Point<Secp256k1>,
@@ -728,7 +915,7 @@ impl LocalSignature {
                         h2: dlog_statement[s[ind]].ni.clone(),
                         N_tilde: dlog_statement[s[ind]].N.clone(),
                     };
-                    let ver_res = pdl_w_slack_proof_vec[j].verify(&pdl_w_slack_statement);
+                    let ver_res = pdl_w_slack_proof_vec[j].verify(sid, &pdl_w_slack_statement);
                     if ver_res.is_err() {
                         bad_actors_vec.push(i);
                         false
@@ -760,11 +947,12 @@ impl LocalSignature {
     }
 
     pub fn phase6_compute_S_i_and_proof_of_consistency(
+        sid: &SessionId,
         R: &Point<Secp256k1>,
         T: &Point<Secp256k1>,
         sigma: &Scalar<Secp256k1>,
         l: &Scalar<Secp256k1>,
-    ) -> (Point<Secp256k1>, HomoELGamalProof<Secp256k1, Sha256>) {
+    ) -> (Point<Secp256k1>, HomoELGamalProof) {
         let S = R * sigma;
         let delta = HomoElGamalStatement {
             G: R.clone(),
@@ -777,14 +965,15 @@ impl LocalSignature {
             x: l.clone(),
             r: sigma.clone(),
         };
-        let proof = HomoELGamalProof::prove(&witness, &delta);
+        let proof = HomoELGamalProof::prove(sid, &witness, &delta);
 
         (S, proof)
     }
 
     pub fn phase6_verify_proof(
+        sid: &SessionId,
         S_vec: &[Point<Secp256k1>],
-        proof_vec: &[HomoELGamalProof<Secp256k1, Sha256>],
+        proof_vec: &[HomoELGamalProof],
         R_vec: &[Point<Secp256k1>],
         T_vec: &[Point<Secp256k1>],
     ) -> Result<(), ErrorType> {
@@ -798,7 +987,7 @@ impl LocalSignature {
                 D: T_vec[i].clone(),
                 E: S_vec[i].clone(),
             };
-            if proof_vec[i].verify(&delta).is_err() {
+            if proof_vec[i].verify(sid, &delta).is_err() {
                 verify_proofs = false;
                 bad_actors_vec.push(i);
             };
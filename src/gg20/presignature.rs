@@ -1,21 +1,33 @@
-use anyhow::{anyhow, Context, Result};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
 use curv::elliptic::curves::Secp256k1;
 use futures::StreamExt;
 use round_based::async_runtime::AsyncProtocol;
 
+use crate::error::TssError;
 use crate::gg20::state_machine::keygen::LocalKey;
-use crate::gg20::state_machine::sign::{CompletedOfflineStage, OfflineStage};
-use crate::utils::sm_client::join_computation;
+use crate::gg20::state_machine::sign::{BatchOfflineStage, CompletedOfflineStage, OfflineStage};
+use crate::utils::common::EcdsaOfflineResult;
+use crate::utils::echo_broadcast::EchoBroadcast;
+use crate::utils::secure_channel::Identity;
+use crate::utils::session_id::SessionId;
+use crate::utils::transport::{join_subset_computation, Transport};
 
-pub async fn generate_offline_signing(
+pub async fn generate_offline_signing<T>(
     request_id: &str,
-    token: &str,
+    transport: &T,
+    identity: &Identity,
     local_share: &LocalKey<Secp256k1>,
-    address: &str,
     room: &str,
     party_id: u16,
     parties: Vec<u16>,
-) -> Result<CompletedOfflineStage> {
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<CompletedOfflineStage>
+where
+    T: Transport + ?Sized,
+{
     println!(
         "requestId={} start offline for party: {} in group {:?} room {}",
         request_id,
@@ -24,17 +36,11 @@ pub async fn generate_offline_signing(
         room.clone()
     );
 
-    let (party_id, incoming, outgoing) = join_computation(
-        request_id,
-        token,
-        surf::Url::parse(address).unwrap(),
-        &format!("{}-offline", room),
-        parties.clone(),
-        Some(party_id),
-        None,
-    )
-    .await
-    .context("join offline computation")?;
+    let room_id = format!("{}-offline", room);
+    let (party_id, roster, incoming, outgoing) =
+        join_subset_computation(transport, &room_id, parties.clone(), party_id, identity)
+            .await
+            .context("join offline computation")?;
 
     println!(
         "requestId={} offline t{} for parties {:?}",
@@ -45,15 +51,22 @@ pub async fn generate_offline_signing(
     tokio::pin!(incoming);
     tokio::pin!(outgoing);
 
-    let signing = OfflineStage::new(party_id, parties.clone(), local_share.clone())?;
+    let sid = SessionId::new("ecdsa-presign", &room_id, &parties).with_roster(&roster);
+    let mut signing = OfflineStage::new(party_id, parties.clone(), local_share.clone(), sid)?;
+    signing.set_round_timeout(round_timeout);
+    let identity_keys = parties
+        .iter()
+        .filter_map(|j| roster.get(j).copied())
+        .collect();
+    let signing = EchoBroadcast::new(signing, echo_broadcast, sid, identity, identity_keys)?;
     let completed_offline_stage = AsyncProtocol::new(signing, incoming, outgoing)
         .run()
         .await
-        .map_err(|e| {
-            anyhow!(
-                "offline generation failed for parties {:?} with error: {}",
-                parties,
-                e
+        .map_err(TssError::from)
+        .with_context(|| {
+            format!(
+                "offline generation failed for parties {:?} with error",
+                parties
             )
         })?;
     println!(
@@ -62,3 +75,97 @@ pub async fn generate_offline_signing(
     );
     Ok(completed_offline_stage)
 }
+
+/// Generates `count` presignatures for signer set `parties` in one batched offline stage
+///
+/// The set must have exactly `t+1` members including the local party. Every member calls it
+/// with the same `room`, `parties` and `count`.
+pub async fn generate_presignatures<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    local_share: &LocalKey<Secp256k1>,
+    room: &str,
+    mut parties: Vec<u16>,
+    count: u16,
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> Result<Vec<EcdsaOfflineResult>>
+where
+    T: Transport + ?Sized,
+{
+    parties.sort_unstable();
+    parties.dedup();
+    if parties.len() != usize::from(local_share.t) + 1
+        || !parties.contains(&local_share.i)
+        || parties.iter().any(|&i| i == 0 || i > local_share.n)
+    {
+        return Err(TssError::invalid_input(format_args!(
+            "party {} can't presign with {:?}, signer set of a {}-of-{} key has {} parties",
+            local_share.i,
+            parties,
+            local_share.t + 1,
+            local_share.n,
+            local_share.t + 1
+        ))
+        .into());
+    }
+    if count == 0 {
+        return Ok(vec![]);
+    }
+    let group = parties
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<String>>()
+        .join("_");
+
+    println!(
+        "requestId={} start presigning {} for parties {:?} room {}",
+        request_id, count, parties, room
+    );
+
+    let room_id = format!("{}-parties-{}-offline", room, group);
+    let (party_id, roster, incoming, outgoing) = join_subset_computation(
+        transport,
+        &room_id,
+        parties.clone(),
+        local_share.i,
+        identity,
+    )
+    .await
+    .context("join presigning computation")?;
+
+    let incoming = incoming.fuse();
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+
+    let sid = SessionId::new("ecdsa-presign", &room_id, &parties).with_roster(&roster);
+    let mut presigning =
+        BatchOfflineStage::new(party_id, parties.clone(), local_share.clone(), sid, count)?;
+    presigning.set_round_timeout(round_timeout);
+    let identity_keys = parties
+        .iter()
+        .filter_map(|j| roster.get(j).copied())
+        .collect();
+    let presigning = EchoBroadcast::new(presigning, echo_broadcast, sid, identity, identity_keys)?;
+    let completed = AsyncProtocol::new(presigning, incoming, outgoing)
+        .run()
+        .await
+        .map_err(TssError::from)
+        .with_context(|| format!("presigning failed for parties {:?} with error", parties))?;
+    println!(
+        "requestId={} completed {} presignatures for parties {:?}",
+        request_id,
+        completed.len(),
+        parties
+    );
+
+    Ok(completed
+        .into_iter()
+        .map(|completed_offline| EcdsaOfflineResult {
+            parties: parties.clone(),
+            completed_offline,
+            consumed: false,
+        })
+        .collect())
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use curv::elliptic::curves::Secp256k1;
+use futures::StreamExt;
+use round_based::async_runtime::AsyncProtocol;
+
+use crate::error::TssError;
+use crate::gg20::party_i::PreParams;
+use crate::gg20::state_machine::keygen::LocalKey;
+use crate::gg20::state_machine::recover::KeyRecovery;
+use crate::utils::secure_channel::Identity;
+use crate::utils::session_id::SessionId;
+use crate::utils::transport::{join_subset_computation, Transport};
+
+/// Recovers share of party `lost_i` on a replacement device with help of `t+1` `helpers`
+///
+/// Every helper passes its `local_key`, the replacement device passes `None` and joins the room
+/// as `lost_i`. Paillier key and `N_tilde` of the replacement device come from `pre_params`,
+/// helpers pass `None`. Survivors listed in `observers` pass their `local_key` as well, they
+/// don't help to recover the share but learn the new Paillier key of `lost_i`. All of them pass
+/// the same `helpers` and `observers`, other survivors don't take part.
+pub async fn start_recovery<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    room: &str,
+    t: u16,
+    n: u16,
+    local_key: Option<&LocalKey<Secp256k1>>,
+    pre_params: Option<PreParams>,
+    lost_i: u16,
+    helpers: &[u16],
+    observers: &[u16],
+    round_timeout: Option<Duration>,
+) -> Result<LocalKey<Secp256k1>>
+where
+    T: Transport + ?Sized,
+{
+    let party_id = local_key.map(|key| key.i).unwrap_or(lost_i);
+    println!(
+        "requestId={} start ecdsa recovery of party {} for party: {} with helpers {:?} room {}",
+        request_id, lost_i, party_id, helpers, room
+    );
+
+    let room_id = format!("{}-ecdsa-recover", room);
+    let parties = recovery_parties(&[helpers, observers].concat(), lost_i);
+    let (room_i, roster, incoming, outgoing) =
+        join_subset_computation(transport, &room_id, parties.clone(), party_id, identity)
+            .await
+            .context("join recovery computation")?;
+
+    let incoming = incoming.fuse();
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+
+    let sid = SessionId::new("ecdsa-recover", &room_id, &parties).with_roster(&roster);
+    let mut recovery = KeyRecovery::new(
+        room_i,
+        parties,
+        helpers.to_vec(),
+        t,
+        n,
+        local_key.cloned(),
+        pre_params,
+        lost_i,
+        sid,
+    )?;
+    recovery.set_round_timeout(round_timeout);
+    let mut local_key = AsyncProtocol::new(recovery, incoming, outgoing)
+        .run()
+        .await
+        .map_err(TssError::from)
+        .context("recovery execution terminated with error")?;
+    println!(
+        "requestId={} completed ecdsa recovery for party: {}",
+        request_id, party_id
+    );
+    // survivors learn the identity key of the replacement device, which learns the others' keys
+    // from the helpers
+    let mut full_roster = local_key.roster.take().unwrap_or_default();
+    full_roster.extend(roster);
+    local_key.roster = Some(full_roster);
+    Ok(local_key)
+}
+
+/// Key indexes of the recovery participants in the order of their room indexes
+///
+/// `survivors` are the helpers of the protocol and any observers.
+pub(crate) fn recovery_parties(survivors: &[u16], lost_i: u16) -> Vec<u16> {
+    let mut parties = survivors.to_vec();
+    parties.push(lost_i);
+    parties.sort_unstable();
+    parties
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use curv::elliptic::curves::Secp256k1;
+use futures::StreamExt;
+use round_based::async_runtime::AsyncProtocol;
+
+use crate::error::TssError;
+use crate::gg20::party_i::PreParams;
+use crate::gg20::state_machine::keygen::LocalKey;
+use crate::gg20::state_machine::refresh::KeyRefresh;
+use crate::utils::secure_channel::Identity;
+use crate::utils::session_id::SessionId;
+use crate::utils::transport::{join_computation, Transport};
+
+pub async fn start_refresh<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    room: &str,
+    local_key: &LocalKey<Secp256k1>,
+    pre_params: PreParams,
+    round_timeout: Option<Duration>,
+) -> Result<LocalKey<Secp256k1>>
+where
+    T: Transport + ?Sized,
+{
+    println!(
+        "requestId={} start ecdsa refresh for party: {} room {}",
+        request_id, local_key.i, room
+    );
+
+    let room_id = format!("{}-ecdsa-refresh", room);
+    let parties: Vec<u16> = (1..(local_key.n + 1)).collect();
+    let (_party_id, roster, incoming, outgoing) = join_computation(
+        transport,
+        &room_id,
+        parties.clone(),
+        Some(local_key.i),
+        None,
+        identity,
+    )
+    .await
+    .context("join refresh computation")?;
+
+    let incoming = incoming.fuse();
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+
+    let sid = SessionId::new("ecdsa-refresh", &room_id, &parties).with_roster(&roster);
+    let mut refresh = KeyRefresh::new(local_key.clone(), pre_params, sid)?;
+    refresh.set_round_timeout(round_timeout);
+    let mut refreshed_key = AsyncProtocol::new(refresh, incoming, outgoing)
+        .run()
+        .await
+        .map_err(TssError::from)
+        .context("refresh execution terminated with error")?;
+    println!(
+        "requestId={} completed ecdsa refresh for party: {}",
+        request_id, local_key.i
+    );
+    refreshed_key.roster = Some(roster);
+    Ok(refreshed_key)
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use curv::elliptic::curves::Secp256k1;
+use futures::StreamExt;
+use round_based::async_runtime::AsyncProtocol;
+
+use crate::error::TssError;
+use crate::gg20::state_machine::keygen::LocalKey;
+use crate::gg20::state_machine::reshare::KeyReshare;
+use crate::utils::secure_channel::{Identity, Roster};
+use crate::utils::session_id::SessionId;
+use crate::utils::transport::{join_computation, Transport};
+
+/// Reshares ECDSA key among `n` participants of the room
+///
+/// Holders of the old key and new members join the room the same way as in keygen, so a party
+/// without a share only needs its name. Returns the room index issued to the party, identity keys
+/// of the participants by room index and the new key of the party, or `None` if the party leaves
+/// the committee.
+pub async fn start_reshare<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    room: &str,
+    n: u16,
+    old_key: Option<&LocalKey<Secp256k1>>,
+    new_member: bool,
+    new_t: u16,
+    new_n: u16,
+    name: &str,
+    round_timeout: Option<Duration>,
+) -> Result<(u16, Roster, Option<LocalKey<Secp256k1>>)>
+where
+    T: Transport + ?Sized,
+{
+    let room_id = format!("{}-ecdsa-reshare", room);
+    let parties: Vec<u16> = (1..(n + 1)).collect();
+    let (party_id, roster, incoming, outgoing) = join_computation(
+        transport,
+        &room_id,
+        parties.clone(),
+        None,
+        Some(name.to_string()),
+        identity,
+    )
+    .await
+    .context("join reshare computation")?;
+    println!(
+        "requestId={} start ecdsa reshare for party: {} room {}",
+        request_id, party_id, room
+    );
+
+    let incoming = incoming.fuse();
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+
+    let sid = SessionId::new("ecdsa-reshare", &room_id, &parties).with_roster(&roster);
+    let mut reshare = KeyReshare::new(
+        party_id,
+        n,
+        old_key.cloned(),
+        new_member,
+        new_t,
+        new_n,
+        sid,
+        roster.clone(),
+    )?;
+    reshare.set_round_timeout(round_timeout);
+    let reshared_key = AsyncProtocol::new(reshare, incoming, outgoing)
+        .run()
+        .await
+        .map_err(TssError::from)
+        .context("reshare execution terminated with error")?;
+    println!(
+        "requestId={} completed ecdsa reshare for party: {}",
+        request_id, party_id
+    );
+    Ok((party_id, roster, reshared_key))
+}
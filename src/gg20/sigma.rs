@@ -0,0 +1,281 @@
+#![allow(non_snake_case)]
+
+//! Sigma protocols of [curv::cryptographic_primitives::proofs] bound to a session
+//!
+//! Proofs are the same as in curv, except that the Fiat-Shamir challenge also hashes the
+//! [SessionId] and the name of the proof. Statements and witnesses of curv are used as is.
+
+use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
+use curv::cryptographic_primitives::proofs::sigma_correct_homomorphic_elgamal_enc::{
+    HomoElGamalStatement, HomoElGamalWitness,
+};
+use curv::cryptographic_primitives::proofs::sigma_ec_ddh::{ECDDHStatement, ECDDHWitness};
+use curv::cryptographic_primitives::proofs::ProofError;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::utils::session_id::SessionId;
+
+/// Proof of knowledge of discrete log of `pk`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DLogProof {
+    pub pk: Point<Secp256k1>,
+    pub pk_t_rand_commitment: Point<Secp256k1>,
+    pub challenge_response: Scalar<Secp256k1>,
+}
+
+impl DLogProof {
+    pub fn prove(sid: &SessionId, sk: &Scalar<Secp256k1>) -> Self {
+        let g = Point::generator();
+        let sk_t_rand = Scalar::<Secp256k1>::random();
+        let pk_t_rand_commitment = g * &sk_t_rand;
+        let pk = g * sk;
+        let challenge = challenge(sid, b"dlog", &[&pk_t_rand_commitment, &g.to_point(), &pk]);
+        let challenge_response = sk_t_rand - challenge * sk;
+        DLogProof {
+            pk,
+            pk_t_rand_commitment,
+            challenge_response,
+        }
+    }
+
+    pub fn verify(&self, sid: &SessionId) -> Result<(), ProofError> {
+        let g = Point::generator();
+        let challenge = challenge(
+            sid,
+            b"dlog",
+            &[&self.pk_t_rand_commitment, &g.to_point(), &self.pk],
+        );
+        let pk_verifier = g * &self.challenge_response + &self.pk * &challenge;
+        if pk_verifier == self.pk_t_rand_commitment {
+            Ok(())
+        } else {
+            Err(ProofError)
+        }
+    }
+}
+
+/// Proof that `com = g^m * h^r` is a Pedersen commitment to `m` where `e = g^m`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PedersenProof {
+    pub e: Point<Secp256k1>,
+    pub a1: Point<Secp256k1>,
+    pub a2: Point<Secp256k1>,
+    pub com: Point<Secp256k1>,
+    pub z1: Scalar<Secp256k1>,
+    pub z2: Scalar<Secp256k1>,
+}
+
+impl PedersenProof {
+    pub fn prove(sid: &SessionId, m: &Scalar<Secp256k1>, r: &Scalar<Secp256k1>) -> Self {
+        let g = Point::generator();
+        let h = Point::<Secp256k1>::base_point2();
+        let s1 = Scalar::<Secp256k1>::random();
+        let s2 = Scalar::<Secp256k1>::random();
+        let a1 = g * &s1;
+        let a2 = h * &s2;
+        let com = g * m + h * r;
+        let e = g * m;
+        let challenge = challenge(sid, b"pedersen", &[&g.to_point(), h, &com, &a1, &a2, &e]);
+        let z1 = s1 + &challenge * m;
+        let z2 = s2 + challenge * r;
+        PedersenProof {
+            e,
+            a1,
+            a2,
+            com,
+            z1,
+            z2,
+        }
+    }
+
+    pub fn verify(&self, sid: &SessionId) -> Result<(), ProofError> {
+        let g = Point::generator();
+        let h = Point::<Secp256k1>::base_point2();
+        let challenge = challenge(
+            sid,
+            b"pedersen",
+            &[&g.to_point(), h, &self.com, &self.a1, &self.a2, &self.e],
+        );
+        let g_z1 = g * &self.z1;
+        let e_by_challenge_plus_a1 = &self.e * &challenge + &self.a1;
+        let g_z1_h_z2 = &g_z1 + h * &self.z2;
+        let com_by_challenge_plus_a1_a2 = &self.com * &challenge + &self.a1 + &self.a2;
+        if g_z1 == e_by_challenge_plus_a1 && g_z1_h_z2 == com_by_challenge_plus_a1_a2 {
+            Ok(())
+        } else {
+            Err(ProofError)
+        }
+    }
+}
+
+/// Proof that `(D, E) = (x*H + r*Y, r*G)` is a correct homomorphic ElGamal encryption
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct HomoELGamalProof {
+    pub T: Point<Secp256k1>,
+    pub A3: Point<Secp256k1>,
+    pub z1: Scalar<Secp256k1>,
+    pub z2: Scalar<Secp256k1>,
+}
+
+impl HomoELGamalProof {
+    pub fn prove(
+        sid: &SessionId,
+        w: &HomoElGamalWitness<Secp256k1>,
+        delta: &HomoElGamalStatement<Secp256k1>,
+    ) -> Self {
+        let s1 = Scalar::<Secp256k1>::random();
+        let s2 = Scalar::<Secp256k1>::random();
+        let A1 = &delta.H * &s1;
+        let A2 = &delta.Y * &s2;
+        let A3 = &delta.G * &s2;
+        let T = A1 + A2;
+        let e = challenge(
+            sid,
+            b"homo-elgamal",
+            &[&T, &A3, &delta.G, &delta.H, &delta.Y, &delta.D, &delta.E],
+        );
+        let z1 = s1 + &w.x * &e;
+        let z2 = s2 + &w.r * e;
+        HomoELGamalProof { T, A3, z1, z2 }
+    }
+
+    pub fn verify(
+        &self,
+        sid: &SessionId,
+        delta: &HomoElGamalStatement<Secp256k1>,
+    ) -> Result<(), ProofError> {
+        let e = challenge(
+            sid,
+            b"homo-elgamal",
+            &[
+                &self.T, &self.A3, &delta.G, &delta.H, &delta.Y, &delta.D, &delta.E,
+            ],
+        );
+        let z1H_plus_z2Y = &delta.H * &self.z1 + &delta.Y * &self.z2;
+        let T_plus_eD = &self.T + &delta.D * &e;
+        let z2G = &delta.G * &self.z2;
+        let A3_plus_eE = &self.A3 + &delta.E * &e;
+        if z1H_plus_z2Y == T_plus_eD && z2G == A3_plus_eE {
+            Ok(())
+        } else {
+            Err(ProofError)
+        }
+    }
+}
+
+/// Proof of equality of discrete logs of `h1` and `h2` in bases `g1` and `g2`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ECDDHProof {
+    pub a1: Point<Secp256k1>,
+    pub a2: Point<Secp256k1>,
+    pub z: Scalar<Secp256k1>,
+}
+
+impl ECDDHProof {
+    pub fn prove(
+        sid: &SessionId,
+        w: &ECDDHWitness<Secp256k1>,
+        delta: &ECDDHStatement<Secp256k1>,
+    ) -> Self {
+        let s = Scalar::<Secp256k1>::random();
+        let a1 = &delta.g1 * &s;
+        let a2 = &delta.g2 * &s;
+        let e = challenge(
+            sid,
+            b"ec-ddh",
+            &[&delta.g1, &delta.h1, &delta.g2, &delta.h2, &a1, &a2],
+        );
+        let z = s + e * &w.x;
+        ECDDHProof { a1, a2, z }
+    }
+
+    pub fn verify(
+        &self,
+        sid: &SessionId,
+        delta: &ECDDHStatement<Secp256k1>,
+    ) -> Result<(), ProofError> {
+        let e = challenge(
+            sid,
+            b"ec-ddh",
+            &[
+                &delta.g1, &delta.h1, &delta.g2, &delta.h2, &self.a1, &self.a2,
+            ],
+        );
+        let z_g1 = &delta.g1 * &self.z;
+        let z_g2 = &delta.g2 * &self.z;
+        let a1_plus_e_h1 = &self.a1 + &delta.h1 * &e;
+        let a2_plus_e_h2 = &self.a2 + &delta.h2 * &e;
+        if z_g1 == a1_plus_e_h1 && z_g2 == a2_plus_e_h2 {
+            Ok(())
+        } else {
+            Err(ProofError)
+        }
+    }
+}
+
+fn challenge(sid: &SessionId, proof: &[u8], points: &[&Point<Secp256k1>]) -> Scalar<Secp256k1> {
+    Sha256::new()
+        .chain(sid)
+        .chain(proof)
+        .chain_points(points.iter().copied())
+        .result_scalar()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn proofs_verify_only_in_their_session() {
+        let sid = SessionId::new("ecdsa-sign", "room", &[1, 2]);
+        let other = SessionId::new("ecdsa-sign", "another room", &[1, 2]);
+
+        let x = Scalar::<Secp256k1>::random();
+        let dlog_proof = DLogProof::prove(&sid, &x);
+        assert!(dlog_proof.verify(&sid).is_ok());
+        assert!(dlog_proof.verify(&other).is_err());
+
+        let l = Scalar::<Secp256k1>::random();
+        let pedersen_proof = PedersenProof::prove(&sid, &x, &l);
+        assert!(pedersen_proof.verify(&sid).is_ok());
+        assert!(pedersen_proof.verify(&other).is_err());
+
+        let R = Point::generator() * Scalar::<Secp256k1>::random();
+        let h = Point::<Secp256k1>::base_point2();
+        let statement = HomoElGamalStatement {
+            G: R.clone(),
+            H: h.clone(),
+            Y: Point::generator().to_point(),
+            D: Point::generator() * &x + h * &l,
+            E: &R * &x,
+        };
+        let witness = HomoElGamalWitness {
+            x: l.clone(),
+            r: x.clone(),
+        };
+        let heg_proof = HomoELGamalProof::prove(&sid, &witness, &statement);
+        assert!(heg_proof.verify(&sid, &statement).is_ok());
+        assert!(heg_proof.verify(&other, &statement).is_err());
+
+        let statement = ECDDHStatement {
+            g1: Point::generator().to_point(),
+            h1: Point::generator() * &x,
+            g2: R.clone(),
+            h2: &R * &x,
+        };
+        let ecddh_proof = ECDDHProof::prove(&sid, &ECDDHWitness { x }, &statement);
+        assert!(ecddh_proof.verify(&sid, &statement).is_ok());
+        assert!(ecddh_proof.verify(&other, &statement).is_err());
+    }
+
+    #[test]
+    fn wrong_witness_is_rejected() {
+        let sid = SessionId::new("ecdsa-sign", "room", &[1, 2]);
+        let x = Scalar::<Secp256k1>::random();
+        let mut dlog_proof = DLogProof::prove(&sid, &x);
+        dlog_proof.pk = Point::generator() * Scalar::<Secp256k1>::random();
+        assert!(dlog_proof.verify(&sid).is_err());
+    }
+}
@@ -1,49 +1,118 @@
 use std::collections::HashSet;
 
+use anyhow::Context;
 use anyhow::Result;
-use anyhow::{anyhow, Context};
 use chrono::prelude::*;
 use curv::arithmetic::Converter;
 use curv::BigInt;
 use rustmodel::SignatureRecidHex;
 
+use crate::error::TssError;
 use crate::gg20::party_i;
-use crate::gg20::state_machine::sign::{PartialSignature, SignManual};
+use crate::gg20::state_machine::sign::{CompletedOfflineStage, PartialSignature, SignManual};
 use crate::utils::common::{
-    EcdsaLocalKeyData, PartialSignatureType, SignedPartialSignature, SigningState,
+    EcdsaLocalKeyData, EcdsaPartialSignature, PartialSignatureType, SignedPartialSignature,
+    SigningState,
 };
 
+/// Signs with a presignature of `signers`, which is marked consumed
+///
+/// The first signer picks its first unused presignature and records it in `state`, the others
+/// sign with the same one. Fails if the presignature already signed something: signing two
+/// messages with one presignature leaks the key.
 pub fn sign(
     state: &mut SigningState,
-    local_key: &EcdsaLocalKeyData,
+    local_key: &mut EcdsaLocalKeyData,
     data_to_sign: Vec<u8>,
     party_id: u16,
     signers: Vec<u16>,
+) -> Result<()> {
+    sign_derived(state, local_key, data_to_sign, party_id, signers, &[])
+}
+
+/// Signs with the child key at non-hardened BIP32 `path`, empty path signs with the master key
+pub fn sign_derived(
+    state: &mut SigningState,
+    local_key: &mut EcdsaLocalKeyData,
+    data_to_sign: Vec<u8>,
+    party_id: u16,
+    signers: Vec<u16>,
+    path: &[u32],
 ) -> Result<()> {
     if state.signing_parts.len() as u16 > state.t as u16 {
         // this already full signed
-        Err(anyhow!("already signed"))
+        Err(TssError::invalid_input("already signed").into())
     } else {
         let signers_set: HashSet<u16> = signers.into_iter().collect();
-        let completed_offline_stage = local_key
-            .offline_data
+        let parts = state
+            .signing_parts
+            .iter()
+            .map(|x| match &x.part {
+                PartialSignatureType::ECDSA(p) => Ok(p),
+                _ => Err(TssError::invalid_input(format_args!(
+                    "party {} sent a partial signature of another scheme",
+                    x.party_id
+                ))),
+            })
+            .collect::<Result<Vec<&EcdsaPartialSignature>, _>>()?;
+        let chosen = parts.first().map(|p| p.presignature.as_str());
+        if parts
             .iter()
-            .find(|x| signers_set.eq(&x.parties.clone().into_iter().collect()))
-            .unwrap()
-            .completed_offline
-            .clone();
+            .any(|p| Some(p.presignature.as_str()) != chosen)
+        {
+            return Err(TssError::invalid_input("signers used different presignatures").into());
+        }
+        let current_key = &local_key.local_key;
+        let presignature = local_key
+            .offline_data
+            .iter_mut()
+            .find(|x| {
+                signers_set.eq(&x.parties.clone().into_iter().collect())
+                    && x.completed_offline.is_for_key(current_key)
+                    && match chosen {
+                        Some(id) => presignature_id(&x.completed_offline) == id,
+                        None => !x.consumed,
+                    }
+            })
+            .ok_or_else(|| match chosen {
+                Some(id) => TssError::invalid_input(format_args!(
+                    "no presignature {} of the current key for signers {:?}",
+                    id, signers_set
+                )),
+                None => TssError::nonce_exhausted(format_args!(
+                    "no unused presignature of the current key for signers {:?}",
+                    signers_set
+                )),
+            })?;
+        if presignature.consumed {
+            return Err(TssError::nonce_exhausted(format_args!(
+                "presignature {} has already been used",
+                presignature_id(&presignature.completed_offline)
+            ))
+            .into());
+        }
+        // consumed before anything is computed, a failed attempt must not make it usable again
+        presignature.consumed = true;
+        let id = presignature_id(&presignature.completed_offline);
+        let completed_offline_stage = presignature.completed_offline.clone();
+        let completed_offline_stage = if path.is_empty() {
+            completed_offline_stage
+        } else {
+            let derived = local_key
+                .local_key
+                .derive(path)
+                .map_err(TssError::invalid_input)?;
+            completed_offline_stage.derive_child(&derived.tweak)
+        };
         let msg = BigInt::from_bytes(&data_to_sign);
         let (signing, partial_signature) =
             SignManual::new(msg.clone(), completed_offline_stage.clone())?;
+        let partial_signature = EcdsaPartialSignature {
+            presignature: id,
+            part: partial_signature,
+        };
         if state.signing_parts.len() as u16 > state.t as u16 - 1 {
-            let parts: Vec<SignedPartialSignature> = state.signing_parts.clone();
-            let gt: Vec<PartialSignature> = parts
-                .into_iter()
-                .map(|x| match x.part {
-                    PartialSignatureType::ECDSA(p) => p,
-                    _ => panic!("wrong signature type"),
-                })
-                .collect();
+            let gt: Vec<PartialSignature> = parts.into_iter().map(|p| p.part.clone()).collect();
             let signature = signing.complete(&gt).context("online stage failed")?;
             state.signing_parts.push(SignedPartialSignature {
                 party_id,
@@ -58,7 +127,12 @@ pub fn sign(
             match party_i::verify(&signature, completed_offline_stage.public_key(), &msg) {
                 Ok(_) => (),
                 Err(_) => {
-                    return Err(anyhow!("signature verification failed"));
+                    // a wrong partial signature can come from any of the signers
+                    return Err(TssError::ProtocolAbort {
+                        culprits: vec![],
+                        message: "signature verification failed".to_owned(),
+                    }
+                    .into());
                 }
             }
         } else {
@@ -72,6 +146,10 @@ pub fn sign(
     }
 }
 
+fn presignature_id(presignature: &CompletedOfflineStage) -> String {
+    hex::encode(presignature.r().to_bytes(true).to_vec())
+}
+
 #[cfg(test)]
 mod test {
     use crate::gg20::signing::sign;
@@ -80,9 +158,9 @@ mod test {
 
     #[test]
     fn should_sign_a_message() {
-        let shard1: KeygenResult =
+        let mut shard1: KeygenResult =
             serde_json::from_str(test_wallets::wallet1_shard1().as_str()).unwrap();
-        let shard2: KeygenResult =
+        let mut shard2: KeygenResult =
             serde_json::from_str(test_wallets::wallet1_shard2().as_str()).unwrap();
         let _shard3: KeygenResult =
             serde_json::from_str(test_wallets::wallet1_shard3().as_str()).unwrap();
@@ -103,7 +181,7 @@ mod test {
                 .unwrap();
         sign(
             &mut state,
-            &shard1.ecdsa,
+            &mut shard1.ecdsa,
             message_to_sign.clone(),
             1,
             vec![1, 2],
@@ -111,7 +189,7 @@ mod test {
         .unwrap();
         sign(
             &mut state,
-            &shard2.ecdsa,
+            &mut shard2.ecdsa,
             message_to_sign.clone(),
             2,
             vec![1, 2],
@@ -127,4 +205,57 @@ mod test {
         );
         assert_eq!(state.signature.unwrap().recid, 0);
     }
+
+    #[test]
+    fn presignature_signs_only_once() {
+        let mut shard1: KeygenResult =
+            serde_json::from_str(test_wallets::wallet1_shard1().as_str()).unwrap();
+        let remaining = shard1.ecdsa.remaining_presignatures();
+        let new_state = || SigningState {
+            t: 1,
+            n: 3,
+            signing_parts: vec![],
+            signature: None,
+        };
+
+        sign(
+            &mut new_state(),
+            &mut shard1.ecdsa,
+            b"first".to_vec(),
+            1,
+            vec![1, 2],
+        )
+        .unwrap();
+        assert_eq!(shard1.ecdsa.remaining_presignatures(), remaining - 1);
+        assert!(sign(
+            &mut new_state(),
+            &mut shard1.ecdsa,
+            b"second".to_vec(),
+            1,
+            vec![1, 2]
+        )
+        .is_err());
+        // presignatures of other signer sets are still usable
+        sign(
+            &mut new_state(),
+            &mut shard1.ecdsa,
+            b"second".to_vec(),
+            1,
+            vec![1, 3],
+        )
+        .unwrap();
+
+        // consumption survives serialization of the key
+        let stored = serde_json::to_string(&shard1.ecdsa).unwrap();
+        let mut restored: crate::utils::common::EcdsaLocalKeyData =
+            serde_json::from_str(&stored).unwrap();
+        assert!(sign(
+            &mut new_state(),
+            &mut restored,
+            b"third".to_vec(),
+            1,
+            vec![1, 3]
+        )
+        .is_err());
+    }
 }
@@ -4,22 +4,24 @@ use std::fmt;
 use std::mem::replace;
 use std::time::Duration;
 
-use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
-use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
-use curv::elliptic::curves::{secp256_k1::Secp256k1, Scalar};
+use curv::elliptic::curves::secp256_k1::Secp256k1;
 use round_based::containers::{
     push::{Push, PushExt},
     *,
 };
 use round_based::{IsCritical, Msg, StateMachine};
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
 use thiserror::Error;
 
+use crate::error::{culprits_of, Blame};
 use crate::gg20;
+use crate::gg20::party_i::{KeyGenShareMessage, PreParams};
+use crate::gg20::sigma::DLogProof;
+use crate::utils::session_id::SessionId;
 
 mod rounds;
 
+use super::traits::RoundBlame;
 use private::InternalError;
 pub use rounds::{LocalKey, ProceedError};
 use rounds::{Round0, Round1, Round2, Round3, Round4};
@@ -33,13 +35,15 @@ pub struct Keygen {
 
     msgs1: Option<Store<BroadcastMsgs<gg20::party_i::KeyGenBroadcastMessage1>>>,
     msgs2: Option<Store<BroadcastMsgs<gg20::party_i::KeyGenDecommitMessage1>>>,
-    msgs3: Option<Store<P2PMsgs<(VerifiableSS<Secp256k1>, Scalar<Secp256k1>)>>>,
-    msgs4: Option<Store<BroadcastMsgs<DLogProof<Secp256k1, Sha256>>>>,
+    msgs3: Option<Store<P2PMsgs<KeyGenShareMessage>>>,
+    msgs4: Option<Store<BroadcastMsgs<DLogProof>>>,
 
     msgs_queue: Vec<Msg<ProtocolMessage>>,
 
     party_i: u16,
     party_n: u16,
+
+    round_timeout: Option<Duration>,
 }
 
 impl Keygen {
@@ -47,13 +51,17 @@ impl Keygen {
     ///
     /// Takes party index `i` (in range `[1; n]`), threshold value `t`, and total number of
     /// parties `n`. Party index identifies this party in the protocol, so it must be guaranteed
-    /// to be unique.
+    /// to be unique. Paillier key and `N_tilde` of the party are taken from `pre_params`, which
+    /// may be generated long before keygen. All parties must agree on session identifier `sid`,
+    /// commitments and proofs of other sessions are rejected.
     ///
     /// Returns error if:
     /// * `n` is less than 2, returns [Error::TooFewParties]
     /// * `t` is not in range `[1; n-1]`, returns [Error::InvalidThreshold]
     /// * `i` is not in range `[1; n]`, returns [Error::InvalidPartyIndex]
-    pub fn new(i: u16, t: u16, n: u16) -> Result<Self> {
+    /// * Paillier modulus of `pre_params` is not in range `[2048; 4096]` bits, returns
+    ///   [Error::InvalidModulusBits]
+    pub fn new(i: u16, t: u16, n: u16, pre_params: PreParams, sid: SessionId) -> Result<Self> {
         if n < 2 {
             return Err(Error::TooFewParties);
         }
@@ -63,8 +71,18 @@ impl Keygen {
         if i == 0 || i > n {
             return Err(Error::InvalidPartyIndex);
         }
+        let modulus_bits = pre_params.modulus_bits();
+        if !(gg20::DEFAULT_MODULUS_BITS..=gg20::MAX_MODULUS_BITS).contains(&modulus_bits) {
+            return Err(Error::InvalidModulusBits);
+        }
         let mut state = Self {
-            round: R::Round0(Round0 { party_i: i, t, n }),
+            round: R::Round0(Round0 {
+                sid,
+                party_i: i,
+                t,
+                n,
+                pre_params,
+            }),
 
             msgs1: Some(Round1::expects_messages(i, n)),
             msgs2: Some(Round2::expects_messages(i, n)),
@@ -75,12 +93,22 @@ impl Keygen {
 
             party_i: i,
             party_n: n,
+
+            round_timeout: None,
         };
 
         state.proceed_round(false)?;
         Ok(state)
     }
 
+    /// Sets how long every round waits for messages of other parties
+    ///
+    /// When the deadline is reached, protocol fails with [Error::RoundTimeout] listing the
+    /// parties we are still waiting for. By default (`None`) a round waits forever.
+    pub fn set_round_timeout(&mut self, timeout: Option<Duration>) {
+        self.round_timeout = timeout;
+    }
+
     fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
     where
         F: FnMut(T) -> M + 'a,
@@ -289,11 +317,16 @@ impl StateMachine for Keygen {
     }
 
     fn round_timeout(&self) -> Option<Duration> {
-        None
+        self.round_timeout
     }
 
     fn round_timeout_reached(&mut self) -> Self::Err {
-        panic!("no timeout was set")
+        let (missing_messages, missing_parties) = self.round_blame();
+        Error::RoundTimeout {
+            round: self.current_round(),
+            missing_messages,
+            missing_parties,
+        }
     }
 
     fn is_finished(&self) -> bool {
@@ -337,7 +370,7 @@ impl StateMachine for Keygen {
     }
 }
 
-impl super::traits::RoundBlame for Keygen {
+impl RoundBlame for Keygen {
     /// Returns number of unwilling parties and a vector of their party indexes.
     fn round_blame(&self) -> (u16, Vec<u16>) {
         let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
@@ -421,8 +454,8 @@ pub struct ProtocolMessage(M);
 enum M {
     Round1(gg20::party_i::KeyGenBroadcastMessage1),
     Round2(gg20::party_i::KeyGenDecommitMessage1),
-    Round3((VerifiableSS<Secp256k1>, Scalar<Secp256k1>)),
-    Round4(DLogProof<Secp256k1, Sha256>),
+    Round3(KeyGenShareMessage),
+    Round4(DLogProof),
 }
 
 // Error
@@ -446,6 +479,9 @@ pub enum Error {
     /// Party index `i` is not in range `[1; n]`
     #[error("party index is not in range [1; n]")]
     InvalidPartyIndex,
+    /// Bit length of the moduli is not in range `[2048; 4096]`
+    #[error("modulus bit length is not in range [2048; 4096]")]
+    InvalidModulusBits,
 
     /// Received message didn't pass pre-validation
     #[error("received message didn't pass pre-validation: {0}")]
@@ -455,6 +491,17 @@ pub enum Error {
         "didn't expect to receive message from round {msg_round} (being at round {current_round})"
     )]
     ReceivedOutOfOrderMessage { current_round: u16, msg_round: u16 },
+    /// Round deadline set by [Keygen::set_round_timeout] was reached
+    ///
+    /// `missing_parties` are indexes of parties which didn't send their messages for the round.
+    #[error(
+        "round {round} timed out waiting for {missing_messages} messages from parties {missing_parties:?}"
+    )]
+    RoundTimeout {
+        round: u16,
+        missing_messages: u16,
+        missing_parties: Vec<u16>,
+    },
     /// [Keygen::pick_output] called twice
     #[error("pick_output called twice")]
     DoublePickOutput,
@@ -471,6 +518,21 @@ impl IsCritical for Error {
     }
 }
 
+impl Blame for Error {
+    fn culprits(&self) -> Vec<u16> {
+        match self {
+            Error::ProceedRound(
+                ProceedError::Round2VerifyCommitments(err)
+                | ProceedError::Round2ProveNoSmallFactor(err)
+                | ProceedError::Round3VerifyNoSmallFactor(err)
+                | ProceedError::Round3VerifyVssConstruct(err)
+                | ProceedError::Round4VerifyDLogProof(err),
+            ) => culprits_of(&err.bad_actors),
+            _ => vec![],
+        }
+    }
+}
+
 impl From<InternalError> for Error {
     fn from(err: InternalError) -> Self {
         Self::InternalError(err)
@@ -494,13 +556,15 @@ pub mod test {
     use round_based::dev::Simulation;
 
     use super::*;
+    use crate::gg20::DEFAULT_MODULUS_BITS;
 
     pub fn simulate_keygen(t: u16, n: u16) -> Vec<LocalKey<Secp256k1>> {
         let mut simulation = Simulation::new();
         simulation.enable_benchmarks(true);
 
+        let sid = session_id(n);
         for i in 1..=n {
-            simulation.add_party(Keygen::new(i, t, n).unwrap());
+            simulation.add_party(Keygen::new(i, t, n, pre_params(), sid).unwrap());
         }
 
         let keys = simulation.run().unwrap();
@@ -525,4 +589,76 @@ pub mod test {
     fn simulate_keygen_t2_n3() {
         simulate_keygen(2, 3);
     }
+
+    pub fn session_id(n: u16) -> SessionId {
+        let parties: Vec<u16> = (1..=n).collect();
+        SessionId::new("ecdsa-keygen", "simulation", &parties)
+    }
+
+    pub fn pre_params() -> PreParams {
+        PreParams::generate(DEFAULT_MODULUS_BITS)
+    }
+
+    #[test]
+    fn keygen_consumes_pre_params() {
+        let pre_params: Vec<_> = (1..=2).map(|_| pre_params()).collect();
+        let mut simulation = Simulation::new();
+        for (i, params) in (1..=2).zip(&pre_params) {
+            simulation.add_party(Keygen::new(i, 1, 2, params.clone(), session_id(2)).unwrap());
+        }
+        let keys = simulation.run().unwrap();
+        for (key, params) in keys.iter().zip(&pre_params) {
+            assert_eq!(key.paillier_dk.p, params.dk.p);
+            assert_eq!(
+                key.h1_h2_n_tilde_vec[usize::from(key.i - 1)].N,
+                params.N_tilde
+            );
+        }
+    }
+
+    #[test]
+    fn keygen_rejects_commitments_of_another_session() {
+        let mut simulation = Simulation::new();
+        let other = SessionId::new("ecdsa-keygen", "another room", &[1, 2, 3]);
+        simulation.add_party(Keygen::new(1, 1, 3, pre_params(), other).unwrap());
+        for i in 2..=3 {
+            simulation.add_party(Keygen::new(i, 1, 3, pre_params(), session_id(3)).unwrap());
+        }
+        match simulation.run() {
+            Err(Error::ProceedRound(ProceedError::Round2VerifyCommitments(_))) => (),
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn keygen_rejects_small_modulus() {
+        assert!(matches!(
+            Keygen::new(1, 1, 2, PreParams::generate(1024), session_id(2)),
+            Err(Error::InvalidModulusBits)
+        ));
+    }
+
+    #[test]
+    fn round_timeout_blames_silent_parties() {
+        let mut party1 = Keygen::new(1, 1, 3, pre_params(), session_id(3)).unwrap();
+        let mut party2 = Keygen::new(2, 1, 3, pre_params(), session_id(3)).unwrap();
+        assert_eq!(party1.round_timeout(), None);
+        party1.set_round_timeout(Some(Duration::from_secs(30)));
+        assert_eq!(party1.round_timeout(), Some(Duration::from_secs(30)));
+
+        party1.proceed().unwrap();
+        party2.proceed().unwrap();
+        for msg in party2.message_queue().drain(..) {
+            party1.handle_incoming(msg).unwrap();
+        }
+
+        match party1.round_timeout_reached() {
+            Error::RoundTimeout {
+                round: 1,
+                missing_messages: 1,
+                missing_parties,
+            } => assert_eq!(missing_parties, vec![3]),
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
 }
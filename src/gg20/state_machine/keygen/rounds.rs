@@ -1,7 +1,9 @@
-use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
+use std::fmt;
+
+use curv::arithmetic::{BitManipulation, Converter};
 use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Curve, Point, Scalar};
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -12,13 +14,22 @@ use round_based::containers::{self, BroadcastMsgs, P2PMsgs, Store};
 use round_based::Msg;
 use zk_paillier::zkproofs::DLogStatement;
 
-use crate::gg20::party_i::{KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys};
+use crate::gg20::derivation::ChainCode;
+use crate::gg20::party_i::{
+    KeyGenBroadcastMessage1, KeyGenDecommitMessage1, KeyGenShareMessage, Keys, PreParams,
+};
+use crate::gg20::sigma::DLogProof;
 use crate::gg20::{self, ErrorType};
+use crate::utils::secret::{zeroize_decryption_key, Redacted};
+use crate::utils::secure_channel::Roster;
+use crate::utils::session_id::SessionId;
 
 pub struct Round0 {
+    pub sid: SessionId,
     pub party_i: u16,
     pub t: u16,
     pub n: u16,
+    pub pre_params: PreParams,
 }
 
 impl Round0 {
@@ -26,9 +37,9 @@ impl Round0 {
     where
         O: Push<Msg<gg20::party_i::KeyGenBroadcastMessage1>>,
     {
-        let party_keys = Keys::create(self.party_i as usize);
-        let (bc1, decom1) =
-            party_keys.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2();
+        let party_keys = Keys::create(self.party_i as usize, self.pre_params);
+        let (bc1, decom1) = party_keys
+            .phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(&self.sid);
 
         output.push(Msg {
             sender: self.party_i,
@@ -39,6 +50,7 @@ impl Round0 {
             keys: party_keys,
             bc1,
             decom1,
+            sid: self.sid,
             party_i: self.party_i,
             t: self.t,
             n: self.n,
@@ -53,6 +65,7 @@ pub struct Round1 {
     keys: Keys,
     bc1: KeyGenBroadcastMessage1,
     decom1: KeyGenDecommitMessage1,
+    sid: SessionId,
     party_i: u16,
     t: u16,
     n: u16,
@@ -77,6 +90,7 @@ impl Round1 {
             received_comm: input.into_vec_including_me(self.bc1),
             decom: self.decom1,
 
+            sid: self.sid,
             party_i: self.party_i,
             t: self.t,
             n: self.n,
@@ -95,6 +109,7 @@ pub struct Round2 {
     received_comm: Vec<KeyGenBroadcastMessage1>,
     decom: KeyGenDecommitMessage1,
 
+    sid: SessionId,
     party_i: u16,
     t: u16,
     n: u16,
@@ -107,7 +122,7 @@ impl Round2 {
         mut output: O,
     ) -> Result<Round3>
     where
-        O: Push<Msg<(VerifiableSS<Secp256k1>, Scalar<Secp256k1>)>>,
+        O: Push<Msg<KeyGenShareMessage>>,
     {
         let params = gg20::party_i::Parameters {
             threshold: self.t,
@@ -118,24 +133,40 @@ impl Round2 {
         let vss_result = self
             .keys
             .phase1_verify_com_phase3_verify_correct_key_verify_dlog_phase2_distribute(
+                &self.sid,
                 &params,
                 &received_decom,
                 &self.received_comm,
             )
             .map_err(ProceedError::Round2VerifyCommitments)?;
+        let no_small_factor_proofs = self
+            .keys
+            .phase2_prove_no_small_factor(&self.sid, &self.received_comm)
+            .map_err(ProceedError::Round2ProveNoSmallFactor)?;
 
-        for (i, share) in vss_result.1.iter().enumerate() {
-            if i + 1 == usize::from(self.party_i) {
-                continue;
-            }
-
+        let receivers = (0..vss_result.1.len()).filter(|&i| i + 1 != usize::from(self.party_i));
+        for (i, no_small_factor_proof) in receivers.zip(no_small_factor_proofs) {
             output.push(Msg {
                 sender: self.party_i,
                 receiver: Some(i as u16 + 1),
-                body: (vss_result.0.clone(), share.clone()),
+                body: KeyGenShareMessage {
+                    vss_scheme: vss_result.0.clone(),
+                    secret_share: vss_result.1[i].clone(),
+                    no_small_factor_proof,
+                },
             })
         }
 
+        // blind factors are committed before anyone reveals theirs, so no party can bias the
+        // chain code
+        let chain_code = received_decom
+            .iter()
+            .fold(Sha256::new().chain(b"gg20 chain code"), |hasher, decom| {
+                hasher.chain(decom.blind_factor.to_bytes())
+            })
+            .finalize()
+            .into();
+
         Ok(Round3 {
             keys: self.keys,
 
@@ -144,7 +175,9 @@ impl Round2 {
 
             own_vss: vss_result.0.clone(),
             own_share: vss_result.1[usize::from(self.party_i - 1)].clone(),
+            chain_code,
 
+            sid: self.sid,
             party_i: self.party_i,
             t: self.t,
             n: self.n,
@@ -166,33 +199,43 @@ pub struct Round3 {
 
     own_vss: VerifiableSS<Secp256k1>,
     own_share: Scalar<Secp256k1>,
+    chain_code: ChainCode,
 
+    sid: SessionId,
     party_i: u16,
     t: u16,
     n: u16,
 }
 
 impl Round3 {
-    pub fn proceed<O>(
-        self,
-        input: P2PMsgs<(VerifiableSS<Secp256k1>, Scalar<Secp256k1>)>,
-        mut output: O,
-    ) -> Result<Round4>
+    pub fn proceed<O>(self, input: P2PMsgs<KeyGenShareMessage>, mut output: O) -> Result<Round4>
     where
-        O: Push<Msg<DLogProof<Secp256k1, Sha256>>>,
+        O: Push<Msg<DLogProof>>,
     {
         let params = gg20::party_i::Parameters {
             threshold: self.t,
             share_count: self.n,
         };
-        let (vss_schemes, party_shares): (Vec<_>, Vec<_>) = input
-            .into_vec_including_me((self.own_vss, self.own_share))
+        let received = input.into_vec();
+        let no_small_factor_proofs: Vec<_> = received
+            .iter()
+            .map(|msg| &msg.no_small_factor_proof)
+            .collect();
+        self.keys
+            .phase3_verify_no_small_factor(&self.sid, &self.bc_vec, &no_small_factor_proofs)
+            .map_err(ProceedError::Round3VerifyNoSmallFactor)?;
+        let (mut vss_schemes, mut party_shares): (Vec<_>, Vec<_>) = received
             .into_iter()
+            .map(|msg| (msg.vss_scheme, msg.secret_share))
             .unzip();
+        let me = usize::from(self.party_i - 1);
+        vss_schemes.insert(me, self.own_vss);
+        party_shares.insert(me, self.own_share);
 
         let (shared_keys, dlog_proof) = self
             .keys
             .phase2_verify_vss_construct_keypair_phase3_pok_dlog(
+                &self.sid,
                 &params,
                 &self.y_vec,
                 &party_shares,
@@ -214,7 +257,9 @@ impl Round3 {
             shared_keys,
             own_dlog_proof: dlog_proof,
             vss_vec: vss_schemes,
+            chain_code: self.chain_code,
 
+            sid: self.sid,
             party_i: self.party_i,
             t: self.t,
             n: self.n,
@@ -223,10 +268,7 @@ impl Round3 {
     pub fn is_expensive(&self) -> bool {
         true
     }
-    pub fn expects_messages(
-        i: u16,
-        n: u16,
-    ) -> Store<P2PMsgs<(VerifiableSS<Secp256k1>, Scalar<Secp256k1>)>> {
+    pub fn expects_messages(i: u16, n: u16) -> Store<P2PMsgs<KeyGenShareMessage>> {
         containers::P2PMsgsStore::new(i, n)
     }
 }
@@ -236,19 +278,18 @@ pub struct Round4 {
     y_vec: Vec<Point<Secp256k1>>,
     bc_vec: Vec<gg20::party_i::KeyGenBroadcastMessage1>,
     shared_keys: gg20::party_i::SharedKeys,
-    own_dlog_proof: DLogProof<Secp256k1, Sha256>,
+    own_dlog_proof: DLogProof,
     vss_vec: Vec<VerifiableSS<Secp256k1>>,
+    chain_code: ChainCode,
 
+    sid: SessionId,
     party_i: u16,
     t: u16,
     n: u16,
 }
 
 impl Round4 {
-    pub fn proceed(
-        self,
-        input: BroadcastMsgs<DLogProof<Secp256k1, Sha256>>,
-    ) -> Result<LocalKey<Secp256k1>> {
+    pub fn proceed(self, input: BroadcastMsgs<DLogProof>) -> Result<LocalKey<Secp256k1>> {
         let params = gg20::party_i::Parameters {
             threshold: self.t,
             share_count: self.n,
@@ -256,6 +297,7 @@ impl Round4 {
         let dlog_proofs = input.into_vec_including_me(self.own_dlog_proof.clone());
 
         Keys::verify_dlog_proofs_check_against_vss(
+            &self.sid,
             &params,
             &dlog_proofs,
             &self.y_vec,
@@ -279,7 +321,7 @@ impl Round4 {
         let y_sum = tail.iter().fold(head[0].clone(), |acc, x| acc + x);
 
         let local_key = LocalKey {
-            paillier_dk: self.keys.dk,
+            paillier_dk: self.keys.dk.clone(),
             pk_vec,
 
             keys_linear: self.shared_keys.clone(),
@@ -292,6 +334,8 @@ impl Round4 {
             i: self.party_i,
             t: self.t,
             n: self.n,
+            chain_code: Some(self.chain_code),
+            roster: None,
         };
 
         Ok(local_key)
@@ -299,13 +343,16 @@ impl Round4 {
     pub fn is_expensive(&self) -> bool {
         true
     }
-    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<DLogProof<Secp256k1, Sha256>>> {
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<DLogProof>> {
         containers::BroadcastMsgsStore::new(i, n)
     }
 }
 
 /// Local secret obtained by party after [keygen](super::Keygen) protocol is completed
-#[derive(Serialize, Deserialize, Clone, Debug)]
+///
+/// Factors of the Paillier key are wiped on drop and left out of `Debug` output along with
+/// the key share.
+#[derive(Serialize, Deserialize, Clone)]
 pub struct LocalKey<E: Curve> {
     pub paillier_dk: paillier::DecryptionKey,
     pub pk_vec: Vec<Point<E>>,
@@ -317,6 +364,12 @@ pub struct LocalKey<E: Curve> {
     pub i: u16,
     pub t: u16,
     pub n: u16,
+    /// BIP32 chain code of the key, `None` for keys generated before derivation was supported
+    #[serde(default)]
+    pub chain_code: Option<ChainCode>,
+    /// Identity keys of the parties, `None` for keys generated before identities were bound
+    #[serde(default)]
+    pub roster: Option<Roster>,
 }
 
 impl LocalKey<Secp256k1> {
@@ -324,6 +377,37 @@ impl LocalKey<Secp256k1> {
     pub fn public_key(&self) -> Point<Secp256k1> {
         self.y_sum_s.clone()
     }
+
+    /// Bit length of our Paillier modulus
+    pub fn modulus_bits(&self) -> usize {
+        let bits = self.paillier_dk.p.bit_length() + self.paillier_dk.q.bit_length();
+        bits.clamp(gg20::DEFAULT_MODULUS_BITS, gg20::MAX_MODULUS_BITS)
+    }
+}
+
+impl<E: Curve> fmt::Debug for LocalKey<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LocalKey")
+            .field("paillier_dk", &Redacted)
+            .field("pk_vec", &self.pk_vec)
+            .field("keys_linear", &self.keys_linear)
+            .field("paillier_key_vec", &self.paillier_key_vec)
+            .field("y_sum_s", &self.y_sum_s)
+            .field("h1_h2_n_tilde_vec", &self.h1_h2_n_tilde_vec)
+            .field("vss_scheme", &self.vss_scheme)
+            .field("i", &self.i)
+            .field("t", &self.t)
+            .field("n", &self.n)
+            .field("chain_code", &self.chain_code)
+            .field("roster", &self.roster)
+            .finish()
+    }
+}
+
+impl<E: Curve> Drop for LocalKey<E> {
+    fn drop(&mut self) {
+        zeroize_decryption_key(&mut self.paillier_dk);
+    }
 }
 
 // Errors
@@ -338,6 +422,10 @@ type Result<T> = std::result::Result<T, ProceedError>;
 pub enum ProceedError {
     #[error("round 2: verify commitments: {0:?}")]
     Round2VerifyCommitments(ErrorType),
+    #[error("round 2: prove no small factor: {0:?}")]
+    Round2ProveNoSmallFactor(ErrorType),
+    #[error("round 3: verify no small factor: {0:?}")]
+    Round3VerifyNoSmallFactor(ErrorType),
     #[error("round 3: verify vss construction: {0:?}")]
     Round3VerifyVssConstruct(ErrorType),
     #[error("round 4: verify dlog proof: {0:?}")]
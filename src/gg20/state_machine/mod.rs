@@ -1,3 +1,6 @@
 pub mod keygen;
+pub mod recover;
+pub mod refresh;
+pub mod reshare;
 pub mod sign;
 pub mod traits;
@@ -0,0 +1,728 @@
+//! Recovery of a lost party's share with help of `t+1` surviving parties
+//!
+//! Every helper `j` weights its share with the Lagrange coefficient that evaluates the shared
+//! polynomial at the lost party's index over the helper set, splits the result into random
+//! additive parts and sends one part to every other helper. Each helper sends the sum of parts it
+//! received to the replacement device, which adds them up to the lost share `x_i`. No helper sees
+//! another helper's weighted share, so none of them learns `x_i`.
+//!
+//! Replacement device brings fresh Paillier key and `h1`/`h2`/`N_tilde` and proves to every
+//! helper that the Paillier modulus has no small factors, helpers update them in their
+//! [LocalKey]. Public share of the lost party stays the same. Survivors outside of the
+//! helper set may join as observers: they don't contribute to the lost share, but check the
+//! proofs of the replacement device and update its Paillier key in their [LocalKey] as well.
+//! Survivors who don't take part keep the old Paillier key of the lost party, they can't presign
+//! with the replacement device until all `n` parties [refresh](super::refresh) the key.
+
+use std::fmt;
+use std::mem::replace;
+use std::time::Duration;
+
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+use round_based::containers::{
+    push::{Push, PushExt},
+    *,
+};
+use round_based::{IsCritical, Msg, StateMachine};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::error::{culprits_of, Blame};
+use crate::gg20;
+use crate::gg20::party_i::PreParams;
+use crate::gg20::state_machine::keygen::LocalKey;
+use crate::utils::session_id::SessionId;
+
+mod rounds;
+
+use super::traits::RoundBlame;
+use private::InternalError;
+pub use rounds::{Blinding, HelperInfo, Introduction, ProceedError, RecoveredPart};
+use rounds::{Round0, Round1, Round2, Round3};
+
+/// Share recovery protocol state machine
+///
+/// Successfully completed recovery produces [LocalKey] of the lost party for the replacement
+/// device, and updated [LocalKey] for every helper and observer.
+pub struct KeyRecovery {
+    round: R,
+
+    msgs1: Option<Store<BroadcastMsgs<Introduction>>>,
+    msgs2: Option<Store<P2PMsgs<Blinding>>>,
+    msgs3: Option<Store<P2PMsgs<RecoveredPart>>>,
+
+    msgs_queue: Vec<Msg<ProtocolMessage>>,
+
+    party_i: u16,
+    party_n: u16,
+
+    round_timeout: Option<Duration>,
+}
+
+impl KeyRecovery {
+    /// Constructs a party of share recovery protocol
+    ///
+    /// `s_l` lists key indexes of the participants: `t+1` `helpers`, `lost_i` and any observers.
+    /// Parties are numbered by their position in `s_l`, so `i` is in range `[1; s_l.len()]` and
+    /// `s_l[i-1]` is the key index of the party. Helpers and observers pass their `local_key`,
+    /// the replacement device passes `None` and its new Paillier key and `N_tilde` in
+    /// `pre_params`, see [PreParams::generate]. All of them pass the same `s_l`, `helpers` and
+    /// session identifier `sid`.
+    ///
+    /// Returns error if:
+    /// * `n` is less than 3, returns [Error::TooFewParties]
+    /// * `t` is not in range `[1; n-2]` (at least `t+1` survivors are needed), returns
+    ///   [Error::InvalidThreshold]
+    /// * `s_l` doesn't consist of distinct indexes in range `[1; n]` including `lost_i`, or
+    ///   `helpers` aren't `t+1` distinct indexes of `s_l` other than `lost_i`, returns
+    ///   [Error::InvalidHelpers]
+    /// * `i` is not in range `[1; s_l.len()]` or `lost_i` is not in range `[1; n]`, returns
+    ///   [Error::InvalidPartyIndex]
+    /// * survivor doesn't pass its key or passes `pre_params`, or replacement device passes a
+    ///   key or no `pre_params`, returns [Error::InvalidRole]
+    /// * `local_key` doesn't match `s_l[i-1]`, `t`, `n`, returns [Error::InvalidLocalKey]
+    /// * Paillier modulus of `pre_params` is not in range `[2048; 4096]` bits, returns
+    ///   [Error::InvalidModulusBits]
+    pub fn new(
+        i: u16,
+        s_l: Vec<u16>,
+        helpers: Vec<u16>,
+        t: u16,
+        n: u16,
+        local_key: Option<LocalKey<Secp256k1>>,
+        pre_params: Option<PreParams>,
+        lost_i: u16,
+        sid: SessionId,
+    ) -> Result<Self> {
+        if n < 3 {
+            return Err(Error::TooFewParties);
+        }
+        if t == 0 || t + 1 >= n {
+            return Err(Error::InvalidThreshold);
+        }
+        if lost_i == 0 || lost_i > n {
+            return Err(Error::InvalidPartyIndex);
+        }
+        let mut distinct = s_l.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        let mut distinct_helpers = helpers.clone();
+        distinct_helpers.sort_unstable();
+        distinct_helpers.dedup();
+        if s_l.len() != distinct.len()
+            || !s_l.contains(&lost_i)
+            || s_l.iter().any(|&j| j == 0 || j > n)
+            || distinct_helpers.len() != usize::from(t) + 1
+            || helpers.len() != distinct_helpers.len()
+            || helpers.iter().any(|j| *j == lost_i || !s_l.contains(j))
+        {
+            return Err(Error::InvalidHelpers);
+        }
+        if i == 0 || usize::from(i) > s_l.len() {
+            return Err(Error::InvalidPartyIndex);
+        }
+        let key_i = s_l[usize::from(i - 1)];
+        if local_key.is_some() == (key_i == lost_i) || local_key.is_some() == pre_params.is_some() {
+            return Err(Error::InvalidRole);
+        }
+        if let Some(key) = &local_key {
+            if key.i != key_i
+                || key.t != t
+                || key.n != n
+                || key.pk_vec.len() != usize::from(n)
+                || key.paillier_key_vec.len() != usize::from(n)
+                || key.h1_h2_n_tilde_vec.len() != usize::from(n)
+            {
+                return Err(Error::InvalidLocalKey);
+            }
+        }
+        if let Some(pre_params) = &pre_params {
+            let modulus_bits = pre_params.modulus_bits();
+            if !(gg20::DEFAULT_MODULUS_BITS..=gg20::MAX_MODULUS_BITS).contains(&modulus_bits) {
+                return Err(Error::InvalidModulusBits);
+            }
+        }
+        let m = s_l.len() as u16;
+        let mut state = Self {
+            round: R::Round0(Round0 {
+                sid,
+                i,
+                s_l,
+                helpers,
+                t,
+                n,
+                local_key,
+                pre_params,
+                lost_i,
+            }),
+
+            msgs1: Some(Round1::expects_messages(i, m)),
+            msgs2: Some(Round2::expects_messages(i, m)),
+            msgs3: Some(Round3::expects_messages(i, m)),
+
+            msgs_queue: vec![],
+
+            party_i: i,
+            party_n: m,
+
+            round_timeout: None,
+        };
+
+        state.proceed_round(false)?;
+        Ok(state)
+    }
+
+    /// Sets deadline for collecting messages of each recovery round
+    ///
+    /// Expired deadline fails the protocol with [Error::RoundTimeout]. `None` (default) means
+    /// no deadline.
+    pub fn set_round_timeout(&mut self, timeout: Option<Duration>) {
+        self.round_timeout = timeout;
+    }
+
+    fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
+    where
+        F: FnMut(T) -> M + 'a,
+    {
+        (&mut self.msgs_queue).gmap(move |m: Msg<T>| m.map_body(|m| ProtocolMessage(f(m))))
+    }
+
+    /// Proceeds round state if it received enough messages and if it's cheap to compute or
+    /// `may_block == true`
+    fn proceed_round(&mut self, may_block: bool) -> Result<()> {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        let next_state: R;
+        let try_again: bool = match replace(&mut self.round, R::Gone) {
+            R::Round0(round) if !round.is_expensive() || may_block => {
+                next_state = round
+                    .proceed(self.gmap_queue(M::Round1))
+                    .map(R::Round1)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round0(_) => {
+                next_state = s;
+                false
+            }
+            R::Round1(round) if !store1_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs1.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round2))
+                    .map(R::Round2)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round1(_) => {
+                next_state = s;
+                false
+            }
+            R::Round2(round) if !store2_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs2.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round3))
+                    .map(R::Round3)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round2(_) => {
+                next_state = s;
+                false
+            }
+            R::Round3(round) if !store3_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs3.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs)
+                    .map(R::Final)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round3(_) => {
+                next_state = s;
+                false
+            }
+            s @ R::Final(_) | s @ R::Gone => {
+                next_state = s;
+                false
+            }
+        };
+
+        self.round = next_state;
+        if try_again {
+            self.proceed_round(may_block)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl StateMachine for KeyRecovery {
+    type MessageBody = ProtocolMessage;
+    type Err = Error;
+    type Output = LocalKey<Secp256k1>;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<()> {
+        let current_round = self.current_round();
+
+        match msg.body {
+            ProtocolMessage(M::Round1(m)) => {
+                let store = self
+                    .msgs1
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 1,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            ProtocolMessage(M::Round2(m)) => {
+                let store = self
+                    .msgs2
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 2,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            ProtocolMessage(M::Round3(m)) => {
+                let store = self
+                    .msgs3
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 3,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+        }
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        &mut self.msgs_queue
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        match &self.round {
+            R::Round0(_) => true,
+            R::Round1(_) => !store1_wants_more,
+            R::Round2(_) => !store2_wants_more,
+            R::Round3(_) => !store3_wants_more,
+            R::Final(_) | R::Gone => false,
+        }
+    }
+
+    fn proceed(&mut self) -> Result<()> {
+        self.proceed_round(true)
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        self.round_timeout
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        let (missing_messages, missing_parties) = self.round_blame();
+        Error::RoundTimeout {
+            round: self.current_round(),
+            missing_messages,
+            missing_parties,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.round, R::Final(_))
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output>> {
+        match self.round {
+            R::Final(_) => (),
+            R::Gone => return Some(Err(Error::DoublePickOutput)),
+            _ => return None,
+        }
+
+        match replace(&mut self.round, R::Gone) {
+            R::Final(result) => Some(Ok(result)),
+            _ => unreachable!("guaranteed by match expression above"),
+        }
+    }
+
+    fn current_round(&self) -> u16 {
+        match &self.round {
+            R::Round0(_) => 0,
+            R::Round1(_) => 1,
+            R::Round2(_) => 2,
+            R::Round3(_) => 3,
+            R::Final(_) | R::Gone => 4,
+        }
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        Some(3)
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.party_i
+    }
+
+    fn parties(&self) -> u16 {
+        self.party_n
+    }
+}
+
+impl RoundBlame for KeyRecovery {
+    /// Returns number of unwilling parties and a vector of their party indexes.
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store3_blame = self.msgs3.as_ref().map(|s| s.blame()).unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Round2(_) => store2_blame,
+            R::Round3(_) => store3_blame,
+            R::Final(_) | R::Gone => default,
+        }
+    }
+}
+
+impl fmt::Debug for KeyRecovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let current_round = match &self.round {
+            R::Round0(_) => "0",
+            R::Round1(_) => "1",
+            R::Round2(_) => "2",
+            R::Round3(_) => "3",
+            R::Final(_) => "[Final]",
+            R::Gone => "[Gone]",
+        };
+        let msgs1 = match self.msgs1.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs2 = match self.msgs2.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs3 = match self.msgs3.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        write!(
+            f,
+            "{{KeyRecovery at round={} msgs1={} msgs2={} msgs3={} queue=[len={}]}}",
+            current_round,
+            msgs1,
+            msgs2,
+            msgs3,
+            self.msgs_queue.len()
+        )
+    }
+}
+
+// Rounds
+
+enum R {
+    Round0(Round0),
+    Round1(Round1),
+    Round2(Round2),
+    Round3(Round3),
+    Final(LocalKey<Secp256k1>),
+    Gone,
+}
+
+// Messages
+
+/// Protocol message which parties send on wire
+///
+/// Hides actual messages structure so it could be changed without breaking semver policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolMessage(M);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum M {
+    Round1(Introduction),
+    Round2(Blinding),
+    Round3(RecoveredPart),
+}
+
+// Error
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Error type of share recovery protocol
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Round proceeding resulted in error
+    #[error("proceed round: {0}")]
+    ProceedRound(#[source] ProceedError),
+
+    /// Too few parties (`n < 3`)
+    #[error("at least 3 parties are required for share recovery")]
+    TooFewParties,
+    /// Threshold value `t` is not in range `[1; n-2]`
+    #[error("threshold is not in range [1; n-2]")]
+    InvalidThreshold,
+    /// Party index `i` or `lost_i` is out of range
+    #[error("party index is out of range")]
+    InvalidPartyIndex,
+    /// Participants are not `t+1` distinct helpers and the lost party
+    #[error("recovery takes t+1 distinct helpers and the lost party")]
+    InvalidHelpers,
+    /// Survivor came without its key, or replacement device came with one
+    #[error("only the replacement party comes without a local key")]
+    InvalidRole,
+    /// Local key doesn't match the party index and key parameters
+    #[error("local key is inconsistent with its parameters")]
+    InvalidLocalKey,
+    /// Bit length of the moduli is not in range `[2048; 4096]`
+    #[error("modulus bit length is not in range [2048; 4096]")]
+    InvalidModulusBits,
+
+    /// Received message didn't pass pre-validation
+    #[error("received message didn't pass pre-validation: {0}")]
+    HandleMessage(#[source] StoreErr),
+    /// Received message which we didn't expect to receive now (e.g. message from previous round)
+    #[error(
+        "didn't expect to receive message from round {msg_round} (being at round {current_round})"
+    )]
+    ReceivedOutOfOrderMessage { current_round: u16, msg_round: u16 },
+    /// Round deadline set by [KeyRecovery::set_round_timeout] was reached
+    #[error(
+        "round {round} timed out waiting for {missing_messages} messages from parties {missing_parties:?}"
+    )]
+    RoundTimeout {
+        round: u16,
+        missing_messages: u16,
+        missing_parties: Vec<u16>,
+    },
+    /// [KeyRecovery::pick_output] called twice
+    #[error("pick_output called twice")]
+    DoublePickOutput,
+
+    /// Some internal assertions were failed, which is a bug
+    #[doc(hidden)]
+    #[error("internal error: {0:?}")]
+    InternalError(InternalError),
+}
+
+impl IsCritical for Error {
+    fn is_critical(&self) -> bool {
+        true
+    }
+}
+
+impl Blame for Error {
+    fn culprits(&self) -> Vec<u16> {
+        match self {
+            Error::ProceedRound(
+                ProceedError::Round1InvalidIntroduction(err)
+                | ProceedError::Round1ProveNoSmallFactor(err)
+                | ProceedError::Round2MissingBlinding(err)
+                | ProceedError::Round2VerifyNoSmallFactor(err)
+                | ProceedError::Round3MissingPart(err),
+            ) => culprits_of(&err.bad_actors),
+            _ => vec![],
+        }
+    }
+}
+
+impl From<InternalError> for Error {
+    fn from(err: InternalError) -> Self {
+        Self::InternalError(err)
+    }
+}
+
+mod private {
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum InternalError {
+        /// [Messages store](super::MessageStore) reported that it received all messages it wanted to receive,
+        /// but refused to return message container
+        RetrieveRoundMessages(super::StoreErr),
+        #[doc(hidden)]
+        StoreGone,
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use curv::elliptic::curves::Point;
+    use round_based::dev::Simulation;
+
+    use crate::gg20::state_machine::keygen::test::simulate_keygen;
+    use crate::gg20::state_machine::sign::test::{simulate_offline_stage, simulate_signing};
+
+    use super::*;
+
+    /// Recovers share of `lost_i` with `helpers` while `observers` learn the new Paillier key,
+    /// returns keys of the participants ordered by their key index
+    pub fn simulate_recovery(
+        keys: &[LocalKey<Secp256k1>],
+        lost_i: u16,
+        helpers: &[u16],
+        observers: &[u16],
+    ) -> Vec<LocalKey<Secp256k1>> {
+        let mut simulation = Simulation::new();
+        simulation.enable_benchmarks(true);
+
+        let (t, n) = (keys[0].t, keys[0].n);
+        let mut s_l = [helpers, observers].concat();
+        s_l.push(lost_i);
+        s_l.sort_unstable();
+        let sid = SessionId::new("ecdsa-recover", "simulation", &s_l);
+        for (i, &key_i) in (1..).zip(&s_l) {
+            // the lost party comes back without its key and with new Paillier key
+            let (local_key, pre_params) = if key_i == lost_i {
+                (None, Some(PreParams::generate(gg20::DEFAULT_MODULUS_BITS)))
+            } else {
+                (Some(keys[usize::from(key_i - 1)].clone()), None)
+            };
+            let party = KeyRecovery::new(
+                i,
+                s_l.clone(),
+                helpers.to_vec(),
+                t,
+                n,
+                local_key,
+                pre_params,
+                lost_i,
+                sid,
+            );
+            simulation.add_party(party.unwrap());
+        }
+
+        let keys = simulation.run().unwrap();
+
+        println!("Benchmark results:");
+        println!("{:#?}", simulation.benchmark_results().unwrap());
+
+        keys
+    }
+
+    #[test]
+    fn simulate_recovery_t1_n3() {
+        let keys = simulate_keygen(1, 3);
+        let recovered = simulate_recovery(&keys, 2, &[1, 3], &[]);
+
+        let lost = &recovered[1];
+        assert_eq!(lost.keys_linear.x_i, keys[1].keys_linear.x_i);
+        assert_eq!(lost.pk_vec[1], Point::generator() * &lost.keys_linear.x_i);
+        assert_eq!(lost.y_sum_s, keys[1].y_sum_s);
+        for new in &recovered {
+            assert_eq!(new.pk_vec, keys[0].pk_vec);
+            assert_eq!(new.paillier_key_vec[1], lost.paillier_key_vec[1]);
+            assert_ne!(new.paillier_key_vec[1], keys[1].paillier_key_vec[1]);
+        }
+
+        let offline = simulate_offline_stage(recovered, &[1, 2]);
+        simulate_signing(offline, b"recovered key signs");
+    }
+
+    #[test]
+    fn simulate_recovery_t2_n5() {
+        let keys = simulate_keygen(2, 5);
+        // parties 1 and 3 stay offline
+        let recovered = simulate_recovery(&keys, 5, &[2, 3, 4], &[]);
+        assert_eq!(recovered.len(), 4);
+        let lost = &recovered[3];
+        assert_eq!(lost.i, 5);
+        assert_eq!(lost.keys_linear.x_i, keys[4].keys_linear.x_i);
+        assert_eq!(lost.paillier_key_vec[0], keys[0].paillier_key_vec[0]);
+
+        let mut signers = keys;
+        for key in recovered {
+            let i = usize::from(key.i - 1);
+            signers[i] = key;
+        }
+        let offline = simulate_offline_stage(signers, &[2, 4, 5]);
+        simulate_signing(offline, b"recovered key signs");
+    }
+
+    #[test]
+    fn simulate_recovery_with_observer() {
+        let keys = simulate_keygen(1, 4);
+        // party 3 doesn't help, but learns the new Paillier key of party 4
+        let recovered = simulate_recovery(&keys, 4, &[1, 2], &[3]);
+        assert_eq!(recovered.len(), 4);
+        let lost = &recovered[3];
+        assert_eq!(lost.keys_linear.x_i, keys[3].keys_linear.x_i);
+        let observer = &recovered[2];
+        assert_eq!(observer.keys_linear.x_i, keys[2].keys_linear.x_i);
+        for new in &recovered {
+            assert_eq!(new.paillier_key_vec[3], lost.paillier_key_vec[3]);
+            assert_eq!(new.h1_h2_n_tilde_vec[3].N, lost.h1_h2_n_tilde_vec[3].N);
+        }
+
+        let offline = simulate_offline_stage(recovered, &[3, 4]);
+        simulate_signing(offline, b"observer signs with recovered key");
+    }
+
+    #[test]
+    fn rejects_helper_set_of_wrong_size() {
+        let sid = SessionId::new("ecdsa-recover", "simulation", &[1, 2, 3, 4]);
+        let result = KeyRecovery::new(1, vec![1, 2, 3, 4], vec![1, 2, 3], 1, 4, None, None, 4, sid);
+        assert!(matches!(result, Err(Error::InvalidHelpers)));
+        let result = KeyRecovery::new(1, vec![1, 2, 3], vec![1, 3], 1, 4, None, None, 3, sid);
+        assert!(matches!(result, Err(Error::InvalidHelpers)));
+    }
+
+    #[test]
+    fn rejects_replacement_without_pre_params() {
+        let keys = simulate_keygen(1, 3);
+        let sid = SessionId::new("ecdsa-recover", "simulation", &[1, 2, 3]);
+        let result = KeyRecovery::new(3, vec![1, 2, 3], vec![1, 2], 1, 3, None, None, 3, sid);
+        assert!(matches!(result, Err(Error::InvalidRole)));
+        let pre_params = Some(PreParams::generate(gg20::DEFAULT_MODULUS_BITS));
+        let result = KeyRecovery::new(
+            1,
+            vec![1, 2, 3],
+            vec![1, 2],
+            1,
+            3,
+            Some(keys[0].clone()),
+            pre_params,
+            3,
+            sid,
+        );
+        assert!(matches!(result, Err(Error::InvalidRole)));
+    }
+}
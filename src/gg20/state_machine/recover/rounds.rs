@@ -0,0 +1,530 @@
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use paillier::EncryptionKey;
+use round_based::containers::push::Push;
+use round_based::containers::{self, BroadcastMsgs, P2PMsgs, Store};
+use round_based::Msg;
+use zk_paillier::zkproofs::DLogStatement;
+
+use crate::gg20::derivation::ChainCode;
+use crate::gg20::party_i::{KeyGenBroadcastMessage1, Keys, PreParams, SharedKeys};
+use crate::gg20::state_machine::keygen::LocalKey;
+use crate::gg20::zk_modulus::NoSmallFactorProof;
+use crate::gg20::ErrorType;
+use crate::utils::secure_channel::Roster;
+use crate::utils::session_id::SessionId;
+
+/// First message of every participant: public key data of a helper or an observer, or
+/// Paillier/h1h2 setup of the replacement party
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Introduction {
+    pub helper: Option<HelperInfo>,
+    pub paillier: Option<KeyGenBroadcastMessage1>,
+}
+
+/// Public data of the key held by a helper or an observer
+///
+/// Carries Paillier keys and h1h2 setups of all `n` parties, as the replacement party doesn't
+/// hear from the parties outside of the recovery.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HelperInfo {
+    pub public_key: Point<Secp256k1>,
+    pub pk_vec: Vec<Point<Secp256k1>>,
+    pub vss_scheme: VerifiableSS<Secp256k1>,
+    pub paillier_key_vec: Vec<EncryptionKey>,
+    pub h1_h2_n_tilde_vec: Vec<DLogStatement>,
+    pub chain_code: Option<ChainCode>,
+    pub roster: Option<Roster>,
+}
+
+/// Additive share of a helper's weighted secret sent to another helper, `None` unless both sender
+/// and receiver are helpers
+///
+/// Replacement party proves to every helper and observer that its new Paillier modulus has no
+/// small factors, the proof is made against `h1`, `h2` and `N_tilde` of the receiver.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Blinding {
+    pub delta: Option<Scalar<Secp256k1>>,
+    pub no_small_factor_proof: Option<NoSmallFactorProof>,
+}
+
+/// Sum of blindings received by a helper, sent to the replacement party only
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecoveredPart {
+    pub sigma: Option<Scalar<Secp256k1>>,
+}
+
+pub struct Round0 {
+    pub sid: SessionId,
+    pub i: u16,
+    pub s_l: Vec<u16>,
+    pub helpers: Vec<u16>,
+    pub t: u16,
+    pub n: u16,
+    pub local_key: Option<LocalKey<Secp256k1>>,
+    pub pre_params: Option<PreParams>,
+    pub lost_i: u16,
+}
+
+impl Round0 {
+    pub fn proceed<O>(self, mut output: O) -> Result<Round1>
+    where
+        O: Push<Msg<Introduction>>,
+    {
+        let keys = self
+            .pre_params
+            .map(|pre_params| Keys::create(usize::from(self.lost_i), pre_params));
+        let introduction = Introduction {
+            helper: self.local_key.as_ref().map(|key| HelperInfo {
+                public_key: key.y_sum_s.clone(),
+                pk_vec: key.pk_vec.clone(),
+                vss_scheme: key.vss_scheme.clone(),
+                paillier_key_vec: key.paillier_key_vec.clone(),
+                h1_h2_n_tilde_vec: key.h1_h2_n_tilde_vec.clone(),
+                chain_code: key.chain_code,
+                roster: key.roster.clone(),
+            }),
+            paillier: keys.as_ref().map(|keys| {
+                keys.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(&self.sid)
+                    .0
+            }),
+        };
+        output.push(Msg {
+            sender: self.i,
+            receiver: None,
+            body: introduction.clone(),
+        });
+        Ok(Round1 {
+            introduction,
+            keys,
+            local_key: self.local_key,
+            i: self.i,
+            s_l: self.s_l,
+            helpers: self.helpers,
+            t: self.t,
+            n: self.n,
+            lost_i: self.lost_i,
+            sid: self.sid,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        // replacement party proves its Paillier key and h1, h2
+        self.local_key.is_none()
+    }
+}
+
+pub struct Round1 {
+    introduction: Introduction,
+    keys: Option<Keys>,
+    local_key: Option<LocalKey<Secp256k1>>,
+    i: u16,
+    s_l: Vec<u16>,
+    helpers: Vec<u16>,
+    t: u16,
+    n: u16,
+    lost_i: u16,
+    sid: SessionId,
+}
+
+impl Round1 {
+    pub fn proceed<O>(self, input: BroadcastMsgs<Introduction>, mut output: O) -> Result<Round2>
+    where
+        O: Push<Msg<Blinding>>,
+    {
+        let introductions = input.into_vec_including_me(self.introduction);
+        let lost = lost_position(&self.s_l, self.lost_i);
+        // replacement party takes the first helper's view and the others have to agree with it
+        let first_helper = if lost == 0 { 1 } else { 0 };
+        let reference = match &introductions[first_helper].helper {
+            Some(helper) => helper.clone(),
+            None => {
+                return Err(ProceedError::Round1InvalidIntroduction(ErrorType {
+                    error_type: "helper didn't share public key".to_string(),
+                    bad_actors: vec![first_helper],
+                }))
+            }
+        };
+        let bad_actors: Vec<usize> = introductions
+            .iter()
+            .enumerate()
+            .filter(|(k, introduction)| {
+                let valid = match (&introduction.helper, &introduction.paillier) {
+                    (None, Some(bc1)) if *k == lost => bc1.verify_correct_key_and_h1h2(&self.sid),
+                    (Some(helper), None) if *k != lost => {
+                        helper.public_key == reference.public_key
+                            && helper.pk_vec == reference.pk_vec
+                            && helper.pk_vec.len() == usize::from(self.n)
+                            && helper.paillier_key_vec == reference.paillier_key_vec
+                            && helper.paillier_key_vec.len() == usize::from(self.n)
+                            && same_setups(&helper.h1_h2_n_tilde_vec, &reference.h1_h2_n_tilde_vec)
+                            && helper.h1_h2_n_tilde_vec.len() == usize::from(self.n)
+                            && helper.chain_code == reference.chain_code
+                            && helper.roster == reference.roster
+                            && helper.vss_scheme.parameters.threshold == self.t
+                            && helper.vss_scheme.parameters.share_count == self.n
+                    }
+                    _ => false,
+                };
+                !valid
+            })
+            .map(|(k, _)| k)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round1InvalidIntroduction(ErrorType {
+                error_type: "invalid introduction".to_string(),
+                bad_actors,
+            }));
+        }
+        if let Some(key) = &self.local_key {
+            if key.y_sum_s != reference.public_key || key.pk_vec != reference.pk_vec {
+                return Err(ProceedError::Round1InvalidIntroduction(ErrorType {
+                    error_type: "helper holds another key".to_string(),
+                    bad_actors: vec![first_helper],
+                }));
+            }
+        }
+
+        let is_helper = is_helper(&self.s_l, &self.helpers, self.i);
+        let mut own_blinding = Blinding {
+            delta: None,
+            no_small_factor_proof: None,
+        };
+        if let Some(key) = self.local_key.as_ref().filter(|_| is_helper) {
+            // weighted secret is split into random additive parts, one per helper, so neither
+            // helper learns the others' contribution to the recovered share
+            let lambda = lagrange_coefficient_at(self.lost_i, key.i, &self.helpers);
+            let mut own_delta = lambda * &key.keys_linear.x_i;
+            for (j, _) in (1..)
+                .zip(&self.s_l)
+                .filter(|(_, k)| self.helpers.contains(k))
+            {
+                if j == self.i {
+                    continue;
+                }
+                let delta = Scalar::<Secp256k1>::random();
+                own_delta = own_delta - &delta;
+                output.push(Msg {
+                    sender: self.i,
+                    receiver: Some(j),
+                    body: Blinding {
+                        delta: Some(delta),
+                        no_small_factor_proof: None,
+                    },
+                });
+            }
+            own_blinding.delta = Some(own_delta);
+        }
+        let mut no_small_factor_proofs = vec![];
+        if let Some(keys) = &self.keys {
+            let mut bad_actors = vec![];
+            for (k, &key_j) in self.s_l.iter().enumerate().filter(|(k, _)| *k != lost) {
+                let setup = &reference.h1_h2_n_tilde_vec[usize::from(key_j - 1)];
+                match NoSmallFactorProof::prove(
+                    &self.sid, &keys.ek.n, &keys.dk.p, &keys.dk.q, setup,
+                ) {
+                    Ok(proof) => no_small_factor_proofs.push(proof),
+                    Err(_) => bad_actors.push(k),
+                }
+            }
+            if !bad_actors.is_empty() {
+                return Err(ProceedError::Round1ProveNoSmallFactor(ErrorType {
+                    error_type: "invalid h1, h2".to_string(),
+                    bad_actors,
+                }));
+            }
+        }
+        // helpers have sent their parts to each other, everybody else gets an empty blinding or a
+        // proof of the replacement party, which come in order of the survivors
+        let mut no_small_factor_proofs = no_small_factor_proofs.into_iter();
+        for (j, key_j) in (1..).zip(&self.s_l) {
+            if j == self.i || (is_helper && self.helpers.contains(key_j)) {
+                continue;
+            }
+            output.push(Msg {
+                sender: self.i,
+                receiver: Some(j),
+                body: Blinding {
+                    delta: None,
+                    no_small_factor_proof: no_small_factor_proofs.next(),
+                },
+            });
+        }
+
+        let replacement = introductions[lost]
+            .paillier
+            .clone()
+            .ok_or(ProceedError::Round1MissingReplacement)?;
+        Ok(Round2 {
+            own_blinding,
+            reference,
+            replacement,
+            keys: self.keys,
+            local_key: self.local_key,
+            i: self.i,
+            s_l: self.s_l,
+            helpers: self.helpers,
+            t: self.t,
+            n: self.n,
+            lost_i: self.lost_i,
+            sid: self.sid,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, m: u16) -> Store<BroadcastMsgs<Introduction>> {
+        containers::BroadcastMsgsStore::new(i, m)
+    }
+}
+
+pub struct Round2 {
+    own_blinding: Blinding,
+    reference: HelperInfo,
+    replacement: KeyGenBroadcastMessage1,
+    keys: Option<Keys>,
+    local_key: Option<LocalKey<Secp256k1>>,
+    i: u16,
+    s_l: Vec<u16>,
+    helpers: Vec<u16>,
+    t: u16,
+    n: u16,
+    lost_i: u16,
+    sid: SessionId,
+}
+
+impl Round2 {
+    pub fn proceed<O>(self, input: P2PMsgs<Blinding>, mut output: O) -> Result<Round3>
+    where
+        O: Push<Msg<RecoveredPart>>,
+    {
+        let blindings = input.into_vec_including_me(self.own_blinding);
+        let lost = lost_position(&self.s_l, self.lost_i);
+
+        if let Some(key) = &self.local_key {
+            let setup = &key.h1_h2_n_tilde_vec[usize::from(key.i - 1)];
+            let proof_ok = match &blindings[lost].no_small_factor_proof {
+                Some(proof) => proof
+                    .verify(&self.sid, &self.replacement.e.n, setup)
+                    .is_ok(),
+                None => false,
+            };
+            if !proof_ok {
+                return Err(ProceedError::Round2VerifyNoSmallFactor(ErrorType {
+                    error_type: "bad no small factor proof".to_string(),
+                    bad_actors: vec![lost],
+                }));
+            }
+        }
+        let is_helper = is_helper(&self.s_l, &self.helpers, self.i);
+        if is_helper {
+            let mut bad_actors = vec![];
+            let mut sigma = Scalar::<Secp256k1>::zero();
+            for (k, blinding) in blindings
+                .iter()
+                .enumerate()
+                .filter(|(k, _)| self.helpers.contains(&self.s_l[*k]))
+            {
+                match &blinding.delta {
+                    Some(delta) => sigma = sigma + delta,
+                    None => bad_actors.push(k),
+                }
+            }
+            if !bad_actors.is_empty() {
+                return Err(ProceedError::Round2MissingBlinding(ErrorType {
+                    error_type: "missing blinding".to_string(),
+                    bad_actors,
+                }));
+            }
+            output.push(Msg {
+                sender: self.i,
+                receiver: Some(lost as u16 + 1),
+                body: RecoveredPart { sigma: Some(sigma) },
+            });
+        }
+        for (j, &key_j) in (1..).zip(&self.s_l) {
+            if j == self.i || (is_helper && key_j == self.lost_i) {
+                continue;
+            }
+            output.push(Msg {
+                sender: self.i,
+                receiver: Some(j),
+                body: RecoveredPart { sigma: None },
+            });
+        }
+
+        Ok(Round3 {
+            // nobody sends a part to itself
+            own_part: RecoveredPart { sigma: None },
+            reference: self.reference,
+            replacement: self.replacement,
+            keys: self.keys,
+            local_key: self.local_key,
+            s_l: self.s_l,
+            helpers: self.helpers,
+            t: self.t,
+            n: self.n,
+            lost_i: self.lost_i,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+    pub fn expects_messages(i: u16, m: u16) -> Store<P2PMsgs<Blinding>> {
+        containers::P2PMsgsStore::new(i, m)
+    }
+}
+
+pub struct Round3 {
+    own_part: RecoveredPart,
+    reference: HelperInfo,
+    replacement: KeyGenBroadcastMessage1,
+    keys: Option<Keys>,
+    local_key: Option<LocalKey<Secp256k1>>,
+    s_l: Vec<u16>,
+    helpers: Vec<u16>,
+    t: u16,
+    n: u16,
+    lost_i: u16,
+}
+
+impl Round3 {
+    pub fn proceed(self, input: P2PMsgs<RecoveredPart>) -> Result<LocalKey<Secp256k1>> {
+        let lost = usize::from(self.lost_i - 1);
+
+        if let Some(mut local_key) = self.local_key {
+            // public share of the recovered party stays the same, only its Paillier key and
+            // h1h2 setup are replaced by helpers and observers alike
+            local_key.paillier_key_vec[lost] = self.replacement.e;
+            local_key.h1_h2_n_tilde_vec[lost] = self.replacement.dlog_statement;
+            return Ok(local_key);
+        }
+        let keys = self.keys.ok_or(ProceedError::Round1MissingReplacement)?;
+        let parts = input.into_vec_including_me(self.own_part);
+
+        let mut bad_actors = vec![];
+        let mut x_i = Scalar::<Secp256k1>::zero();
+        for (k, part) in parts
+            .iter()
+            .enumerate()
+            .filter(|(k, _)| self.helpers.contains(&self.s_l[*k]))
+        {
+            match &part.sigma {
+                Some(sigma) => x_i = x_i + sigma,
+                None => bad_actors.push(k),
+            }
+        }
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round3MissingPart(ErrorType {
+                error_type: "missing recovered part".to_string(),
+                bad_actors,
+            }));
+        }
+
+        let reference = self.reference;
+        if reference.pk_vec[lost] != Point::generator() * &x_i {
+            return Err(ProceedError::Round3PublicShareMismatch);
+        }
+
+        let mut paillier_key_vec = reference.paillier_key_vec;
+        paillier_key_vec[lost] = self.replacement.e;
+        let mut h1_h2_n_tilde_vec = reference.h1_h2_n_tilde_vec;
+        h1_h2_n_tilde_vec[lost] = self.replacement.dlog_statement;
+
+        Ok(LocalKey {
+            paillier_dk: keys.dk.clone(),
+            pk_vec: reference.pk_vec,
+
+            keys_linear: SharedKeys {
+                y: reference.public_key.clone(),
+                x_i,
+            },
+            paillier_key_vec,
+            y_sum_s: reference.public_key,
+            h1_h2_n_tilde_vec,
+
+            vss_scheme: reference.vss_scheme,
+
+            i: self.lost_i,
+            t: self.t,
+            n: self.n,
+            chain_code: reference.chain_code,
+            roster: reference.roster,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+    pub fn expects_messages(i: u16, m: u16) -> Store<P2PMsgs<RecoveredPart>> {
+        containers::P2PMsgsStore::new(i, m)
+    }
+}
+
+/// Position of the replacement party in `s_l`, starting from 0
+fn lost_position(s_l: &[u16], lost_i: u16) -> usize {
+    s_l.iter()
+        .position(|j| *j == lost_i)
+        .expect("checked by the state machine constructor")
+}
+
+/// Whether party `i` numbered by its position in `s_l` is one of the `helpers`
+fn is_helper(s_l: &[u16], helpers: &[u16], i: u16) -> bool {
+    helpers.contains(&s_l[usize::from(i - 1)])
+}
+
+/// Whether helpers agree on h1h2 setups of all parties
+fn same_setups(a: &[DLogStatement], b: &[DLogStatement]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| a.N == b.N && a.g == b.g && a.ni == b.ni)
+}
+
+/// Lagrange coefficient of party `j` for evaluating the shared polynomial at point `x` over the
+/// set of `parties`
+fn lagrange_coefficient_at(x: u16, j: u16, parties: &[u16]) -> Scalar<Secp256k1> {
+    let scalar = |k: u16| Scalar::<Secp256k1>::from(u64::from(k));
+    let (num, denom) = parties.iter().filter(|k| **k != j).fold(
+        (
+            Scalar::<Secp256k1>::from(1u64),
+            Scalar::<Secp256k1>::from(1u64),
+        ),
+        |(num, denom), &k| {
+            (
+                num * (scalar(x) - scalar(k)),
+                denom * (scalar(j) - scalar(k)),
+            )
+        },
+    );
+    num * denom
+        .invert()
+        .expect("party indexes are distinct, so the denominator is never zero")
+}
+
+// Errors
+
+type Result<T> = std::result::Result<T, ProceedError>;
+
+/// Proceeding protocol error
+///
+/// Subset of [recovery errors](enum@super::Error) that can occur at protocol proceeding (i.e.
+/// after every message was received and pre-validated).
+#[derive(Debug, Error)]
+pub enum ProceedError {
+    #[error("round 1: invalid introduction: {0:?}")]
+    Round1InvalidIntroduction(ErrorType),
+    #[error("round 1: replacement party didn't send its Paillier key")]
+    Round1MissingReplacement,
+    #[error("round 1: prove no small factor: {0:?}")]
+    Round1ProveNoSmallFactor(ErrorType),
+    #[error("round 2: helper didn't send its blinding: {0:?}")]
+    Round2MissingBlinding(ErrorType),
+    #[error("round 2: verify no small factor: {0:?}")]
+    Round2VerifyNoSmallFactor(ErrorType),
+    #[error("round 3: helper didn't send its part of the share: {0:?}")]
+    Round3MissingPart(ErrorType),
+    #[error("round 3: recovered share doesn't match the public share")]
+    Round3PublicShareMismatch,
+}
@@ -0,0 +1,626 @@
+//! Proactive refresh of GG20 key shares
+//!
+//! Every party re-deals its Lagrange-weighted share `λ_i · x_i` with a fresh Feldman VSS and sums
+//! the shares it receives. The joint secret (and therefore `y_sum_s`) is unchanged, but every
+//! `x_i` lies on a brand new polynomial, so shares from a previous epoch can't be combined with
+//! the refreshed ones. Paillier keys and `h1`/`h2`/`N_tilde` are replaced at the same time by
+//! fresh [PreParams], usually of the same size as the current Paillier key.
+//!
+//! Refresh must be carried out by all `n` parties of the original keygen. Presignatures produced
+//! by [OfflineStage](super::sign::OfflineStage) before refresh embed old shares, so they have to be
+//! dropped and generated again.
+
+use std::fmt;
+use std::mem::replace;
+use std::time::Duration;
+
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+use round_based::containers::{
+    push::{Push, PushExt},
+    *,
+};
+use round_based::{IsCritical, Msg, StateMachine};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::error::{culprits_of, Blame};
+use crate::gg20;
+use crate::gg20::party_i::{KeyGenShareMessage, PreParams};
+use crate::gg20::sigma::DLogProof;
+use crate::gg20::state_machine::keygen::LocalKey;
+use crate::utils::session_id::SessionId;
+
+mod rounds;
+
+use super::traits::RoundBlame;
+use private::InternalError;
+pub use rounds::ProceedError;
+use rounds::{Round0, Round1, Round2, Round3, Round4};
+
+/// Key refresh protocol state machine
+///
+/// Successfully completed refresh produces a new [LocalKey] with the same public key.
+pub struct KeyRefresh {
+    round: R,
+
+    msgs1: Option<Store<BroadcastMsgs<gg20::party_i::KeyGenBroadcastMessage1>>>,
+    msgs2: Option<Store<BroadcastMsgs<gg20::party_i::KeyGenDecommitMessage1>>>,
+    msgs3: Option<Store<P2PMsgs<KeyGenShareMessage>>>,
+    msgs4: Option<Store<BroadcastMsgs<DLogProof>>>,
+
+    msgs_queue: Vec<Msg<ProtocolMessage>>,
+
+    party_i: u16,
+    party_n: u16,
+
+    round_timeout: Option<Duration>,
+}
+
+impl KeyRefresh {
+    /// Constructs a party of key refresh protocol
+    ///
+    /// Takes party's current `local_key`, fresh `pre_params` that replace its Paillier key and
+    /// `N_tilde`, and identifier `sid` of the refresh session shared by all parties. Party index
+    /// is taken from the key, every party from keygen must take part in refresh.
+    ///
+    /// Returns error if:
+    /// * `i` is not in range `[1; n]`, returns [Error::InvalidPartyIndex]
+    /// * `t` is not in range `[1; n-1]`, returns [Error::InvalidThreshold]
+    /// * number of public shares or Paillier keys differs from `n`, returns [Error::InvalidLocalKey]
+    /// * Paillier modulus of `pre_params` is not in range `[2048; 4096]` bits, returns
+    ///   [Error::InvalidModulusBits]
+    pub fn new(
+        local_key: LocalKey<Secp256k1>,
+        pre_params: PreParams,
+        sid: SessionId,
+    ) -> Result<Self> {
+        let (i, t, n) = (local_key.i, local_key.t, local_key.n);
+        if t == 0 || t >= n {
+            return Err(Error::InvalidThreshold);
+        }
+        if i == 0 || i > n {
+            return Err(Error::InvalidPartyIndex);
+        }
+        if local_key.pk_vec.len() != usize::from(n)
+            || local_key.paillier_key_vec.len() != usize::from(n)
+            || local_key.h1_h2_n_tilde_vec.len() != usize::from(n)
+        {
+            return Err(Error::InvalidLocalKey);
+        }
+        let modulus_bits = pre_params.modulus_bits();
+        if !(gg20::DEFAULT_MODULUS_BITS..=gg20::MAX_MODULUS_BITS).contains(&modulus_bits) {
+            return Err(Error::InvalidModulusBits);
+        }
+        let mut state = Self {
+            round: R::Round0(Round0 {
+                local_key,
+                pre_params,
+                sid,
+            }),
+
+            msgs1: Some(Round1::expects_messages(i, n)),
+            msgs2: Some(Round2::expects_messages(i, n)),
+            msgs3: Some(Round3::expects_messages(i, n)),
+            msgs4: Some(Round4::expects_messages(i, n)),
+
+            msgs_queue: vec![],
+
+            party_i: i,
+            party_n: n,
+
+            round_timeout: None,
+        };
+
+        state.proceed_round(false)?;
+        Ok(state)
+    }
+
+    /// Sets deadline for collecting messages of each refresh round
+    ///
+    /// Expired deadline fails the protocol with [Error::RoundTimeout]. `None` (default) means
+    /// no deadline.
+    pub fn set_round_timeout(&mut self, timeout: Option<Duration>) {
+        self.round_timeout = timeout;
+    }
+
+    fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
+    where
+        F: FnMut(T) -> M + 'a,
+    {
+        (&mut self.msgs_queue).gmap(move |m: Msg<T>| m.map_body(|m| ProtocolMessage(f(m))))
+    }
+
+    /// Proceeds round state if it received enough messages and if it's cheap to compute or
+    /// `may_block == true`
+    fn proceed_round(&mut self, may_block: bool) -> Result<()> {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store4_wants_more = self.msgs4.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        let next_state: R;
+        let try_again: bool = match replace(&mut self.round, R::Gone) {
+            R::Round0(round) if !round.is_expensive() || may_block => {
+                next_state = round
+                    .proceed(self.gmap_queue(M::Round1))
+                    .map(R::Round1)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round0(_) => {
+                next_state = s;
+                false
+            }
+            R::Round1(round) if !store1_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs1.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round2))
+                    .map(R::Round2)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round1(_) => {
+                next_state = s;
+                false
+            }
+            R::Round2(round) if !store2_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs2.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round3))
+                    .map(R::Round3)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round2(_) => {
+                next_state = s;
+                false
+            }
+            R::Round3(round) if !store3_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs3.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round4))
+                    .map(R::Round4)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round3(_) => {
+                next_state = s;
+                false
+            }
+            R::Round4(round) if !store4_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs4.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs)
+                    .map(R::Final)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round4(_) => {
+                next_state = s;
+                false
+            }
+            s @ R::Final(_) | s @ R::Gone => {
+                next_state = s;
+                false
+            }
+        };
+
+        self.round = next_state;
+        if try_again {
+            self.proceed_round(may_block)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl StateMachine for KeyRefresh {
+    type MessageBody = ProtocolMessage;
+    type Err = Error;
+    type Output = LocalKey<Secp256k1>;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<()> {
+        let current_round = self.current_round();
+
+        match msg.body {
+            ProtocolMessage(M::Round1(m)) => {
+                let store = self
+                    .msgs1
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 1,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            ProtocolMessage(M::Round2(m)) => {
+                let store = self
+                    .msgs2
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 2,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            ProtocolMessage(M::Round3(m)) => {
+                let store = self
+                    .msgs3
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 3,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            ProtocolMessage(M::Round4(m)) => {
+                let store = self
+                    .msgs4
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 4,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+        }
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        &mut self.msgs_queue
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store4_wants_more = self.msgs4.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        match &self.round {
+            R::Round0(_) => true,
+            R::Round1(_) => !store1_wants_more,
+            R::Round2(_) => !store2_wants_more,
+            R::Round3(_) => !store3_wants_more,
+            R::Round4(_) => !store4_wants_more,
+            R::Final(_) | R::Gone => false,
+        }
+    }
+
+    fn proceed(&mut self) -> Result<()> {
+        self.proceed_round(true)
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        self.round_timeout
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        let (missing_messages, missing_parties) = self.round_blame();
+        Error::RoundTimeout {
+            round: self.current_round(),
+            missing_messages,
+            missing_parties,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.round, R::Final(_))
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output>> {
+        match self.round {
+            R::Final(_) => (),
+            R::Gone => return Some(Err(Error::DoublePickOutput)),
+            _ => return None,
+        }
+
+        match replace(&mut self.round, R::Gone) {
+            R::Final(result) => Some(Ok(result)),
+            _ => unreachable!("guaranteed by match expression above"),
+        }
+    }
+
+    fn current_round(&self) -> u16 {
+        match &self.round {
+            R::Round0(_) => 0,
+            R::Round1(_) => 1,
+            R::Round2(_) => 2,
+            R::Round3(_) => 3,
+            R::Round4(_) => 4,
+            R::Final(_) | R::Gone => 5,
+        }
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        Some(4)
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.party_i
+    }
+
+    fn parties(&self) -> u16 {
+        self.party_n
+    }
+}
+
+impl RoundBlame for KeyRefresh {
+    /// Returns number of unwilling parties and a vector of their party indexes.
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store3_blame = self.msgs3.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store4_blame = self.msgs4.as_ref().map(|s| s.blame()).unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Round2(_) => store2_blame,
+            R::Round3(_) => store3_blame,
+            R::Round4(_) => store4_blame,
+            R::Final(_) | R::Gone => default,
+        }
+    }
+}
+
+impl fmt::Debug for KeyRefresh {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let current_round = match &self.round {
+            R::Round0(_) => "0",
+            R::Round1(_) => "1",
+            R::Round2(_) => "2",
+            R::Round3(_) => "3",
+            R::Round4(_) => "4",
+            R::Final(_) => "[Final]",
+            R::Gone => "[Gone]",
+        };
+        let msgs1 = match self.msgs1.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs2 = match self.msgs2.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs3 = match self.msgs3.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs4 = match self.msgs4.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        write!(
+            f,
+            "{{KeyRefresh at round={} msgs1={} msgs2={} msgs3={} msgs4={} queue=[len={}]}}",
+            current_round,
+            msgs1,
+            msgs2,
+            msgs3,
+            msgs4,
+            self.msgs_queue.len()
+        )
+    }
+}
+
+// Rounds
+
+enum R {
+    Round0(Round0),
+    Round1(Round1),
+    Round2(Round2),
+    Round3(Round3),
+    Round4(Round4),
+    Final(LocalKey<Secp256k1>),
+    Gone,
+}
+
+// Messages
+
+/// Protocol message which parties send on wire
+///
+/// Hides actual messages structure so it could be changed without breaking semver policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolMessage(M);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum M {
+    Round1(gg20::party_i::KeyGenBroadcastMessage1),
+    Round2(gg20::party_i::KeyGenDecommitMessage1),
+    Round3(KeyGenShareMessage),
+    Round4(DLogProof),
+}
+
+// Error
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Error type of key refresh protocol
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Round proceeding resulted in error
+    #[error("proceed round: {0}")]
+    ProceedRound(#[source] ProceedError),
+
+    /// Threshold value `t` is not in range `[1; n-1]`
+    #[error("threshold is not in range [1; n-1]")]
+    InvalidThreshold,
+    /// Party index `i` is not in range `[1; n]`
+    #[error("party index is not in range [1; n]")]
+    InvalidPartyIndex,
+    /// Local key doesn't hold public data of exactly `n` parties
+    #[error("local key is inconsistent with its parameters")]
+    InvalidLocalKey,
+    /// Bit length of the moduli is not in range `[2048; 4096]`
+    #[error("modulus bit length is not in range [2048; 4096]")]
+    InvalidModulusBits,
+
+    /// Received message didn't pass pre-validation
+    #[error("received message didn't pass pre-validation: {0}")]
+    HandleMessage(#[source] StoreErr),
+    /// Received message which we didn't expect to receive now (e.g. message from previous round)
+    #[error(
+        "didn't expect to receive message from round {msg_round} (being at round {current_round})"
+    )]
+    ReceivedOutOfOrderMessage { current_round: u16, msg_round: u16 },
+    /// Round deadline set by [KeyRefresh::set_round_timeout] was reached
+    #[error(
+        "round {round} timed out waiting for {missing_messages} messages from parties {missing_parties:?}"
+    )]
+    RoundTimeout {
+        round: u16,
+        missing_messages: u16,
+        missing_parties: Vec<u16>,
+    },
+    /// [KeyRefresh::pick_output] called twice
+    #[error("pick_output called twice")]
+    DoublePickOutput,
+
+    /// Some internal assertions were failed, which is a bug
+    #[doc(hidden)]
+    #[error("internal error: {0:?}")]
+    InternalError(InternalError),
+}
+
+impl IsCritical for Error {
+    fn is_critical(&self) -> bool {
+        true
+    }
+}
+
+impl Blame for Error {
+    fn culprits(&self) -> Vec<u16> {
+        match self {
+            Error::ProceedRound(
+                ProceedError::Round2UnexpectedPublicShare(err)
+                | ProceedError::Round2VerifyCommitments(err)
+                | ProceedError::Round2ProveNoSmallFactor(err)
+                | ProceedError::Round3VerifyNoSmallFactor(err)
+                | ProceedError::Round3VerifyVssConstruct(err)
+                | ProceedError::Round4VerifyDLogProof(err),
+            ) => culprits_of(&err.bad_actors),
+            _ => vec![],
+        }
+    }
+}
+
+impl From<InternalError> for Error {
+    fn from(err: InternalError) -> Self {
+        Self::InternalError(err)
+    }
+}
+
+mod private {
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum InternalError {
+        /// [Messages store](super::MessageStore) reported that it received all messages it wanted to receive,
+        /// but refused to return message container
+        RetrieveRoundMessages(super::StoreErr),
+        #[doc(hidden)]
+        StoreGone,
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use curv::elliptic::curves::Point;
+    use round_based::dev::Simulation;
+
+    use crate::gg20::state_machine::keygen::test::simulate_keygen;
+    use crate::gg20::state_machine::sign::test::{simulate_offline_stage, simulate_signing};
+
+    use super::*;
+
+    pub fn simulate_refresh(keys: Vec<LocalKey<Secp256k1>>) -> Vec<LocalKey<Secp256k1>> {
+        let mut simulation = Simulation::new();
+        simulation.enable_benchmarks(true);
+
+        let parties: Vec<u16> = (1..=keys.len() as u16).collect();
+        let sid = SessionId::new("ecdsa-refresh", "simulation", &parties);
+        for key in keys {
+            let pre_params = PreParams::generate(key.modulus_bits());
+            simulation.add_party(KeyRefresh::new(key, pre_params, sid).unwrap());
+        }
+
+        let keys = simulation.run().unwrap();
+
+        println!("Benchmark results:");
+        println!("{:#?}", simulation.benchmark_results().unwrap());
+
+        keys
+    }
+
+    fn check_refreshed(old_keys: &[LocalKey<Secp256k1>], new_keys: &[LocalKey<Secp256k1>]) {
+        for (old, new) in old_keys.iter().zip(new_keys) {
+            assert_eq!(old.y_sum_s, new.y_sum_s);
+            assert_eq!(old.keys_linear.y, new.keys_linear.y);
+            assert_ne!(old.keys_linear.x_i, new.keys_linear.x_i);
+            assert_ne!(old.paillier_dk.p, new.paillier_dk.p);
+            assert_eq!(new_keys[0].pk_vec, new.pk_vec);
+            assert_eq!(
+                new.pk_vec[usize::from(new.i - 1)],
+                Point::generator() * &new.keys_linear.x_i
+            );
+        }
+    }
+
+    #[test]
+    fn simulate_refresh_t1_n3() {
+        let keys = simulate_keygen(1, 3);
+        let refreshed = simulate_refresh(keys.clone());
+        check_refreshed(&keys, &refreshed);
+
+        let offline = simulate_offline_stage(refreshed, &[1, 3]);
+        simulate_signing(offline, b"refreshed key signs");
+    }
+
+    #[test]
+    fn simulate_refresh_t2_n3() {
+        let keys = simulate_keygen(2, 3);
+        let refreshed = simulate_refresh(keys.clone());
+        check_refreshed(&keys, &refreshed);
+    }
+}
@@ -0,0 +1,376 @@
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+
+use thiserror::Error;
+
+use paillier::EncryptionKey;
+use round_based::containers::push::Push;
+use round_based::containers::{self, BroadcastMsgs, P2PMsgs, Store};
+use round_based::Msg;
+use zk_paillier::zkproofs::DLogStatement;
+
+use crate::gg20::party_i::{
+    KeyGenBroadcastMessage1, KeyGenDecommitMessage1, KeyGenShareMessage, Keys, PreParams,
+};
+use crate::gg20::sigma::DLogProof;
+use crate::gg20::state_machine::keygen::LocalKey;
+use crate::gg20::{self, ErrorType};
+use crate::utils::session_id::SessionId;
+
+pub struct Round0 {
+    pub local_key: LocalKey<Secp256k1>,
+    pub pre_params: PreParams,
+    pub sid: SessionId,
+}
+
+impl Round0 {
+    pub fn proceed<O>(self, mut output: O) -> Result<Round1>
+    where
+        O: Push<Msg<gg20::party_i::KeyGenBroadcastMessage1>>,
+    {
+        let party_i = self.local_key.i;
+        let n = self.local_key.n;
+        let lambda_i = lagrange_coefficient(&self.local_key, party_i);
+        let w_i = lambda_i * &self.local_key.keys_linear.x_i;
+
+        // fresh Paillier and h1/h2/N_tilde, the dealt secret is our weighted share
+        let party_keys = Keys::create_from(w_i, party_i as usize, self.pre_params);
+        let (bc1, decom1) = party_keys
+            .phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(&self.sid);
+
+        output.push(Msg {
+            sender: party_i,
+            receiver: None,
+            body: bc1.clone(),
+        });
+
+        // what every party is expected to re-deal
+        let expected_y_vec = (1..=n)
+            .map(|j| {
+                &self.local_key.pk_vec[usize::from(j - 1)]
+                    * lagrange_coefficient(&self.local_key, j)
+            })
+            .collect();
+
+        Ok(Round1 {
+            keys: party_keys,
+            bc1,
+            decom1,
+            expected_y_vec,
+            sid: self.sid,
+            old_key: self.local_key,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+}
+
+pub struct Round1 {
+    keys: Keys,
+    bc1: KeyGenBroadcastMessage1,
+    decom1: KeyGenDecommitMessage1,
+    expected_y_vec: Vec<Point<Secp256k1>>,
+    sid: SessionId,
+    old_key: LocalKey<Secp256k1>,
+}
+
+impl Round1 {
+    pub fn proceed<O>(
+        self,
+        input: BroadcastMsgs<KeyGenBroadcastMessage1>,
+        mut output: O,
+    ) -> Result<Round2>
+    where
+        O: Push<Msg<gg20::party_i::KeyGenDecommitMessage1>>,
+    {
+        output.push(Msg {
+            sender: self.old_key.i,
+            receiver: None,
+            body: self.decom1.clone(),
+        });
+        Ok(Round2 {
+            keys: self.keys,
+            received_comm: input.into_vec_including_me(self.bc1),
+            decom: self.decom1,
+            expected_y_vec: self.expected_y_vec,
+            sid: self.sid,
+            old_key: self.old_key,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<KeyGenBroadcastMessage1>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+}
+
+pub struct Round2 {
+    keys: Keys,
+    received_comm: Vec<KeyGenBroadcastMessage1>,
+    decom: KeyGenDecommitMessage1,
+    expected_y_vec: Vec<Point<Secp256k1>>,
+    sid: SessionId,
+    old_key: LocalKey<Secp256k1>,
+}
+
+impl Round2 {
+    pub fn proceed<O>(
+        self,
+        input: BroadcastMsgs<KeyGenDecommitMessage1>,
+        mut output: O,
+    ) -> Result<Round3>
+    where
+        O: Push<Msg<KeyGenShareMessage>>,
+    {
+        let params = gg20::party_i::Parameters {
+            threshold: self.old_key.t,
+            share_count: self.old_key.n,
+        };
+        let party_i = self.old_key.i;
+        let received_decom = input.into_vec_including_me(self.decom);
+
+        let bad_actors: Vec<usize> = received_decom
+            .iter()
+            .zip(&self.expected_y_vec)
+            .enumerate()
+            .filter(|(_, (decom, expected))| decom.y_i != **expected)
+            .map(|(i, _)| i)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round2UnexpectedPublicShare(ErrorType {
+                error_type: "unexpected public share".to_string(),
+                bad_actors,
+            }));
+        }
+
+        let vss_result = self
+            .keys
+            .phase1_verify_com_phase3_verify_correct_key_verify_dlog_phase2_distribute(
+                &self.sid,
+                &params,
+                &received_decom,
+                &self.received_comm,
+            )
+            .map_err(ProceedError::Round2VerifyCommitments)?;
+        let no_small_factor_proofs = self
+            .keys
+            .phase2_prove_no_small_factor(&self.sid, &self.received_comm)
+            .map_err(ProceedError::Round2ProveNoSmallFactor)?;
+
+        let receivers = (0..vss_result.1.len()).filter(|&i| i + 1 != usize::from(party_i));
+        for (i, no_small_factor_proof) in receivers.zip(no_small_factor_proofs) {
+            output.push(Msg {
+                sender: party_i,
+                receiver: Some(i as u16 + 1),
+                body: KeyGenShareMessage {
+                    vss_scheme: vss_result.0.clone(),
+                    secret_share: vss_result.1[i].clone(),
+                    no_small_factor_proof,
+                },
+            })
+        }
+
+        Ok(Round3 {
+            keys: self.keys,
+
+            y_vec: self.expected_y_vec,
+            bc_vec: self.received_comm,
+
+            own_vss: vss_result.0.clone(),
+            own_share: vss_result.1[usize::from(party_i - 1)].clone(),
+
+            sid: self.sid,
+            old_key: self.old_key,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<KeyGenDecommitMessage1>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+}
+
+pub struct Round3 {
+    keys: Keys,
+
+    y_vec: Vec<Point<Secp256k1>>,
+    bc_vec: Vec<KeyGenBroadcastMessage1>,
+
+    own_vss: VerifiableSS<Secp256k1>,
+    own_share: Scalar<Secp256k1>,
+
+    sid: SessionId,
+    old_key: LocalKey<Secp256k1>,
+}
+
+impl Round3 {
+    pub fn proceed<O>(self, input: P2PMsgs<KeyGenShareMessage>, mut output: O) -> Result<Round4>
+    where
+        O: Push<Msg<DLogProof>>,
+    {
+        let params = gg20::party_i::Parameters {
+            threshold: self.old_key.t,
+            share_count: self.old_key.n,
+        };
+        let received = input.into_vec();
+        let no_small_factor_proofs: Vec<_> = received
+            .iter()
+            .map(|msg| &msg.no_small_factor_proof)
+            .collect();
+        self.keys
+            .phase3_verify_no_small_factor(&self.sid, &self.bc_vec, &no_small_factor_proofs)
+            .map_err(ProceedError::Round3VerifyNoSmallFactor)?;
+        let (mut vss_schemes, mut party_shares): (Vec<_>, Vec<_>) = received
+            .into_iter()
+            .map(|msg| (msg.vss_scheme, msg.secret_share))
+            .unzip();
+        let me = usize::from(self.old_key.i - 1);
+        vss_schemes.insert(me, self.own_vss);
+        party_shares.insert(me, self.own_share);
+
+        let (shared_keys, dlog_proof) = self
+            .keys
+            .phase2_verify_vss_construct_keypair_phase3_pok_dlog(
+                &self.sid,
+                &params,
+                &self.y_vec,
+                &party_shares,
+                &vss_schemes,
+                self.old_key.i.into(),
+            )
+            .map_err(ProceedError::Round3VerifyVssConstruct)?;
+        if shared_keys.y != self.old_key.y_sum_s {
+            return Err(ProceedError::Round3PublicKeyChanged);
+        }
+
+        output.push(Msg {
+            sender: self.old_key.i,
+            receiver: None,
+            body: dlog_proof.clone(),
+        });
+
+        Ok(Round4 {
+            keys: self.keys,
+            y_vec: self.y_vec,
+            bc_vec: self.bc_vec,
+            shared_keys,
+            own_dlog_proof: dlog_proof,
+            vss_vec: vss_schemes,
+
+            sid: self.sid,
+            old_key: self.old_key,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<P2PMsgs<KeyGenShareMessage>> {
+        containers::P2PMsgsStore::new(i, n)
+    }
+}
+
+pub struct Round4 {
+    keys: Keys,
+    y_vec: Vec<Point<Secp256k1>>,
+    bc_vec: Vec<KeyGenBroadcastMessage1>,
+    shared_keys: gg20::party_i::SharedKeys,
+    own_dlog_proof: DLogProof,
+    vss_vec: Vec<VerifiableSS<Secp256k1>>,
+
+    sid: SessionId,
+    old_key: LocalKey<Secp256k1>,
+}
+
+impl Round4 {
+    pub fn proceed(self, input: BroadcastMsgs<DLogProof>) -> Result<LocalKey<Secp256k1>> {
+        let params = gg20::party_i::Parameters {
+            threshold: self.old_key.t,
+            share_count: self.old_key.n,
+        };
+        let dlog_proofs = input.into_vec_including_me(self.own_dlog_proof.clone());
+
+        Keys::verify_dlog_proofs_check_against_vss(
+            &self.sid,
+            &params,
+            &dlog_proofs,
+            &self.y_vec,
+            &self.vss_vec,
+        )
+        .map_err(ProceedError::Round4VerifyDLogProof)?;
+        let pk_vec = dlog_proofs
+            .iter()
+            .map(|proof| proof.pk.clone())
+            .collect::<Vec<Point<Secp256k1>>>();
+
+        let paillier_key_vec = self
+            .bc_vec
+            .iter()
+            .map(|bc1| bc1.e.clone())
+            .collect::<Vec<EncryptionKey>>();
+        let h1_h2_n_tilde_vec = self
+            .bc_vec
+            .iter()
+            .map(|bc1| bc1.dlog_statement.clone())
+            .collect::<Vec<DLogStatement>>();
+
+        let party_i = self.old_key.i;
+        Ok(LocalKey {
+            paillier_dk: self.keys.dk.clone(),
+            pk_vec,
+
+            keys_linear: self.shared_keys,
+            paillier_key_vec,
+            y_sum_s: self.old_key.y_sum_s.clone(),
+            h1_h2_n_tilde_vec,
+
+            vss_scheme: self.vss_vec[usize::from(party_i - 1)].clone(),
+
+            i: party_i,
+            t: self.old_key.t,
+            n: self.old_key.n,
+            chain_code: self.old_key.chain_code,
+            roster: self.old_key.roster.clone(),
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<DLogProof>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+}
+
+/// Lagrange coefficient of party `j` (keygen index) when all `n` parties participate
+fn lagrange_coefficient(local_key: &LocalKey<Secp256k1>, j: u16) -> Scalar<Secp256k1> {
+    let s: Vec<u16> = (0..local_key.n).collect();
+    VerifiableSS::<Secp256k1>::map_share_to_new_params(&local_key.vss_scheme.parameters, j - 1, &s)
+}
+
+// Errors
+
+type Result<T> = std::result::Result<T, ProceedError>;
+
+/// Proceeding protocol error
+///
+/// Subset of [refresh errors](enum@super::Error) that can occur at protocol proceeding (i.e. after
+/// every message was received and pre-validated).
+#[derive(Debug, Error)]
+pub enum ProceedError {
+    #[error("round 2: public share doesn't match the key being refreshed: {0:?}")]
+    Round2UnexpectedPublicShare(ErrorType),
+    #[error("round 2: verify commitments: {0:?}")]
+    Round2VerifyCommitments(ErrorType),
+    #[error("round 2: prove no small factor: {0:?}")]
+    Round2ProveNoSmallFactor(ErrorType),
+    #[error("round 3: verify no small factor: {0:?}")]
+    Round3VerifyNoSmallFactor(ErrorType),
+    #[error("round 3: verify vss construction: {0:?}")]
+    Round3VerifyVssConstruct(ErrorType),
+    #[error("round 3: refreshed shares don't sum up to the original public key")]
+    Round3PublicKeyChanged,
+    #[error("round 4: verify dlog proof: {0:?}")]
+    Round4VerifyDLogProof(ErrorType),
+}
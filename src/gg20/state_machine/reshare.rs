@@ -0,0 +1,689 @@
+//! Resharing of GG20 key to a new threshold and a new set of parties
+//!
+//! Holders of the old key (at least `t+1` of them) deal their Lagrange-weighted shares with a
+//! Feldman VSS of degree `t'` to the new committee of `n'` parties. The sum of dealt shares is a
+//! new sharing of the same secret, so the public key doesn't change. New committee members
+//! generate fresh Paillier keys and `h1`/`h2`/`N_tilde` and prove to each other that the Paillier
+//! moduli have no small factors, like in [keygen](super::keygen).
+//!
+//! Parties are identified by their index in the room; each of them announces whether it deals an
+//! old key and whether it joins the new committee. New party indexes are assigned in the order of
+//! room indexes of the new committee members.
+//!
+//! The new key comes with the roster of identity keys the new committee members joined the room
+//! with. Dealers whose old key has a roster check that the other dealers joined with the identity
+//! keys listed in it.
+
+use std::fmt;
+use std::mem::replace;
+use std::time::Duration;
+
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+use round_based::containers::{
+    push::{Push, PushExt},
+    *,
+};
+use round_based::{IsCritical, Msg, StateMachine};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::error::{culprits_of, Blame};
+use crate::gg20::state_machine::keygen::LocalKey;
+use crate::utils::secure_channel::Roster;
+use crate::utils::session_id::SessionId;
+
+mod rounds;
+
+use super::traits::RoundBlame;
+use private::InternalError;
+pub use rounds::{Announcement, DealerInfo, Dealing, DealtShare, ProceedError};
+use rounds::{Round0, Round1, Round2, Round3};
+
+/// Key resharing protocol state machine
+///
+/// Successfully completed resharing produces a new [LocalKey] with the same public key for every
+/// member of the new committee, and `None` for parties which leave.
+pub struct KeyReshare {
+    round: R,
+
+    msgs1: Option<Store<BroadcastMsgs<Announcement>>>,
+    msgs2: Option<Store<BroadcastMsgs<Dealing>>>,
+    msgs3: Option<Store<P2PMsgs<DealtShare>>>,
+
+    msgs_queue: Vec<Msg<ProtocolMessage>>,
+
+    party_i: u16,
+    party_n: u16,
+
+    round_timeout: Option<Duration>,
+}
+
+impl KeyReshare {
+    /// Constructs a party of key resharing protocol
+    ///
+    /// Takes party index `i` in the room of `n` participants, party's `old_key` if it holds one,
+    /// whether the party joins the new committee, threshold `new_t` and size `new_n` of the
+    /// new committee, identifier `sid` of the session shared by all participants and their
+    /// identity keys `roster` by room index.
+    ///
+    /// Returns error if:
+    /// * `n` is less than 2, returns [Error::TooFewParties]
+    /// * `i` is not in range `[1; n]`, returns [Error::InvalidPartyIndex]
+    /// * `new_t` is not in range `[1; new_n-1]`, returns [Error::InvalidThreshold]
+    /// * party neither holds a key nor joins the new committee, returns [Error::NoRole]
+    /// * `old_key` holds public data of other than `n` parties, returns [Error::InvalidLocalKey]
+    pub fn new(
+        i: u16,
+        n: u16,
+        old_key: Option<LocalKey<Secp256k1>>,
+        new_member: bool,
+        new_t: u16,
+        new_n: u16,
+        sid: SessionId,
+        roster: Roster,
+    ) -> Result<Self> {
+        if n < 2 {
+            return Err(Error::TooFewParties);
+        }
+        if i == 0 || i > n {
+            return Err(Error::InvalidPartyIndex);
+        }
+        if new_t == 0 || new_t >= new_n {
+            return Err(Error::InvalidThreshold);
+        }
+        if old_key.is_none() && !new_member {
+            return Err(Error::NoRole);
+        }
+        if let Some(key) = &old_key {
+            if key.i == 0
+                || key.i > key.n
+                || key.pk_vec.len() != usize::from(key.n)
+                || key.paillier_key_vec.len() != usize::from(key.n)
+                || key.h1_h2_n_tilde_vec.len() != usize::from(key.n)
+            {
+                return Err(Error::InvalidLocalKey);
+            }
+        }
+        let mut state = Self {
+            round: R::Round0(Round0 {
+                sid,
+                roster,
+                i,
+                old_key,
+                new_member,
+                new_t,
+                new_n,
+            }),
+
+            msgs1: Some(Round1::expects_messages(i, n)),
+            msgs2: Some(Round2::expects_messages(i, n)),
+            msgs3: Some(Round3::expects_messages(i, n)),
+
+            msgs_queue: vec![],
+
+            party_i: i,
+            party_n: n,
+
+            round_timeout: None,
+        };
+
+        state.proceed_round(false)?;
+        Ok(state)
+    }
+
+    /// Sets deadline for collecting messages of each resharing round
+    ///
+    /// A participant which doesn't show up makes the protocol fail with [Error::RoundTimeout]
+    /// once the deadline passes. `None` (default) means no deadline.
+    pub fn set_round_timeout(&mut self, timeout: Option<Duration>) {
+        self.round_timeout = timeout;
+    }
+
+    fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
+    where
+        F: FnMut(T) -> M + 'a,
+    {
+        (&mut self.msgs_queue).gmap(move |m: Msg<T>| m.map_body(|m| ProtocolMessage(f(m))))
+    }
+
+    /// Proceeds round state if it received enough messages and if it's cheap to compute or
+    /// `may_block == true`
+    fn proceed_round(&mut self, may_block: bool) -> Result<()> {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        let next_state: R;
+        let try_again: bool = match replace(&mut self.round, R::Gone) {
+            R::Round0(round) if !round.is_expensive() || may_block => {
+                next_state = round
+                    .proceed(self.gmap_queue(M::Round1))
+                    .map(R::Round1)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round0(_) => {
+                next_state = s;
+                false
+            }
+            R::Round1(round) if !store1_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs1.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round2))
+                    .map(R::Round2)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round1(_) => {
+                next_state = s;
+                false
+            }
+            R::Round2(round) if !store2_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs2.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round3))
+                    .map(R::Round3)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round2(_) => {
+                next_state = s;
+                false
+            }
+            R::Round3(round) if !store3_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs3.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs)
+                    .map(R::Final)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round3(_) => {
+                next_state = s;
+                false
+            }
+            s @ R::Final(_) | s @ R::Gone => {
+                next_state = s;
+                false
+            }
+        };
+
+        self.round = next_state;
+        if try_again {
+            self.proceed_round(may_block)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl StateMachine for KeyReshare {
+    type MessageBody = ProtocolMessage;
+    type Err = Error;
+    type Output = Option<LocalKey<Secp256k1>>;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<()> {
+        let current_round = self.current_round();
+
+        match msg.body {
+            ProtocolMessage(M::Round1(m)) => {
+                let store = self
+                    .msgs1
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 1,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            ProtocolMessage(M::Round2(m)) => {
+                let store = self
+                    .msgs2
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 2,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            ProtocolMessage(M::Round3(m)) => {
+                let store = self
+                    .msgs3
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 3,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+        }
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        &mut self.msgs_queue
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        match &self.round {
+            R::Round0(_) => true,
+            R::Round1(_) => !store1_wants_more,
+            R::Round2(_) => !store2_wants_more,
+            R::Round3(_) => !store3_wants_more,
+            R::Final(_) | R::Gone => false,
+        }
+    }
+
+    fn proceed(&mut self) -> Result<()> {
+        self.proceed_round(true)
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        self.round_timeout
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        let (missing_messages, missing_parties) = self.round_blame();
+        Error::RoundTimeout {
+            round: self.current_round(),
+            missing_messages,
+            missing_parties,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.round, R::Final(_))
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output>> {
+        match self.round {
+            R::Final(_) => (),
+            R::Gone => return Some(Err(Error::DoublePickOutput)),
+            _ => return None,
+        }
+
+        match replace(&mut self.round, R::Gone) {
+            R::Final(result) => Some(Ok(result)),
+            _ => unreachable!("guaranteed by match expression above"),
+        }
+    }
+
+    fn current_round(&self) -> u16 {
+        match &self.round {
+            R::Round0(_) => 0,
+            R::Round1(_) => 1,
+            R::Round2(_) => 2,
+            R::Round3(_) => 3,
+            R::Final(_) | R::Gone => 4,
+        }
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        Some(3)
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.party_i
+    }
+
+    fn parties(&self) -> u16 {
+        self.party_n
+    }
+}
+
+impl RoundBlame for KeyReshare {
+    /// Returns number of unwilling parties and a vector of their party indexes.
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store3_blame = self.msgs3.as_ref().map(|s| s.blame()).unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Round2(_) => store2_blame,
+            R::Round3(_) => store3_blame,
+            R::Final(_) | R::Gone => default,
+        }
+    }
+}
+
+impl fmt::Debug for KeyReshare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let current_round = match &self.round {
+            R::Round0(_) => "0",
+            R::Round1(_) => "1",
+            R::Round2(_) => "2",
+            R::Round3(_) => "3",
+            R::Final(_) => "[Final]",
+            R::Gone => "[Gone]",
+        };
+        let msgs1 = match self.msgs1.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs2 = match self.msgs2.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs3 = match self.msgs3.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        write!(
+            f,
+            "{{KeyReshare at round={} msgs1={} msgs2={} msgs3={} queue=[len={}]}}",
+            current_round,
+            msgs1,
+            msgs2,
+            msgs3,
+            self.msgs_queue.len()
+        )
+    }
+}
+
+// Rounds
+
+enum R {
+    Round0(Round0),
+    Round1(Round1),
+    Round2(Round2),
+    Round3(Round3),
+    Final(Option<LocalKey<Secp256k1>>),
+    Gone,
+}
+
+// Messages
+
+/// Protocol message which parties send on wire
+///
+/// Hides actual messages structure so it could be changed without breaking semver policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolMessage(M);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum M {
+    Round1(Announcement),
+    Round2(Dealing),
+    Round3(DealtShare),
+}
+
+// Error
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Error type of key resharing protocol
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Round proceeding resulted in error
+    #[error("proceed round: {0}")]
+    ProceedRound(#[source] ProceedError),
+
+    /// Too few parties (`n < 2`)
+    #[error("at least 2 parties are required for resharing")]
+    TooFewParties,
+    /// Threshold value `new_t` is not in range `[1; new_n-1]`
+    #[error("threshold is not in range [1; n-1]")]
+    InvalidThreshold,
+    /// Party index `i` is not in range `[1; n]`
+    #[error("party index is not in range [1; n]")]
+    InvalidPartyIndex,
+    /// Party neither deals an old key nor joins the new committee
+    #[error("party has nothing to do in resharing")]
+    NoRole,
+    /// Old key doesn't hold public data of exactly `n` parties
+    #[error("local key is inconsistent with its parameters")]
+    InvalidLocalKey,
+
+    /// Received message didn't pass pre-validation
+    #[error("received message didn't pass pre-validation: {0}")]
+    HandleMessage(#[source] StoreErr),
+    /// Received message which we didn't expect to receive now (e.g. message from previous round)
+    #[error(
+        "didn't expect to receive message from round {msg_round} (being at round {current_round})"
+    )]
+    ReceivedOutOfOrderMessage { current_round: u16, msg_round: u16 },
+    /// Round deadline set by [KeyReshare::set_round_timeout] was reached
+    #[error(
+        "round {round} timed out waiting for {missing_messages} messages from parties {missing_parties:?}"
+    )]
+    RoundTimeout {
+        round: u16,
+        missing_messages: u16,
+        missing_parties: Vec<u16>,
+    },
+    /// [KeyReshare::pick_output] called twice
+    #[error("pick_output called twice")]
+    DoublePickOutput,
+
+    /// Some internal assertions were failed, which is a bug
+    #[doc(hidden)]
+    #[error("internal error: {0:?}")]
+    InternalError(InternalError),
+}
+
+impl IsCritical for Error {
+    fn is_critical(&self) -> bool {
+        true
+    }
+}
+
+impl Blame for Error {
+    fn culprits(&self) -> Vec<u16> {
+        match self {
+            Error::ProceedRound(
+                ProceedError::Round1InvalidDealer(err)
+                | ProceedError::Round2InvalidDealing(err)
+                | ProceedError::Round2ProveNoSmallFactor(err)
+                | ProceedError::Round3InvalidShare(err)
+                | ProceedError::Round3VerifyNoSmallFactor(err),
+            ) => culprits_of(&err.bad_actors),
+            _ => vec![],
+        }
+    }
+}
+
+impl From<InternalError> for Error {
+    fn from(err: InternalError) -> Self {
+        Self::InternalError(err)
+    }
+}
+
+mod private {
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum InternalError {
+        /// [Messages store](super::MessageStore) reported that it received all messages it wanted to receive,
+        /// but refused to return message container
+        RetrieveRoundMessages(super::StoreErr),
+        #[doc(hidden)]
+        StoreGone,
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use curv::elliptic::curves::Point;
+    use round_based::dev::Simulation;
+
+    use crate::gg20::state_machine::keygen::test::simulate_keygen;
+    use crate::gg20::state_machine::sign::test::{simulate_offline_stage, simulate_signing};
+    use crate::utils::secure_channel::Identity;
+
+    use super::*;
+
+    /// Party of resharing: its old key (if any) and whether it joins the new committee
+    pub type Participant = (Option<LocalKey<Secp256k1>>, bool);
+
+    pub fn simulate_reshare(
+        participants: Vec<Participant>,
+        roster: &Roster,
+        new_t: u16,
+        new_n: u16,
+    ) -> Vec<Option<LocalKey<Secp256k1>>> {
+        let mut simulation = Simulation::new();
+        simulation.enable_benchmarks(true);
+
+        let n = participants.len() as u16;
+        let parties: Vec<u16> = (1..=n).collect();
+        let sid = SessionId::new("ecdsa-reshare", "simulation", &parties);
+        for (i, (old_key, new_member)) in (1..).zip(participants) {
+            let party =
+                KeyReshare::new(i, n, old_key, new_member, new_t, new_n, sid, roster.clone());
+            simulation.add_party(party.unwrap());
+        }
+
+        let keys = simulation.run().unwrap();
+
+        println!("Benchmark results:");
+        println!("{:#?}", simulation.benchmark_results().unwrap());
+
+        keys
+    }
+
+    /// Identity keys of `n` participants of the room
+    pub fn room_roster(n: u16) -> Roster {
+        (1..=n)
+            .map(|i| (i, Identity::generate().public_key()))
+            .collect()
+    }
+
+    fn check_reshared(old_key: &LocalKey<Secp256k1>, new_keys: &[LocalKey<Secp256k1>], t: u16) {
+        for (i, new) in (1..).zip(new_keys) {
+            assert_eq!(new.i, i);
+            assert_eq!(new.t, t);
+            assert_eq!(new.n, new_keys.len() as u16);
+            assert_eq!(old_key.y_sum_s, new.y_sum_s);
+            assert_eq!(new_keys[0].pk_vec, new.pk_vec);
+            assert_eq!(
+                new.pk_vec[usize::from(new.i - 1)],
+                Point::generator() * &new.keys_linear.x_i
+            );
+        }
+    }
+
+    #[test]
+    fn simulate_reshare_t1_n3_to_t2_n4() {
+        let mut keys = simulate_keygen(1, 3);
+        let old_roster = room_roster(3);
+        for key in &mut keys {
+            key.roster = Some(old_roster.clone());
+        }
+        // party 2 lost its share, parties 1 and 3 invite two new members
+        let mut roster = room_roster(4);
+        roster.insert(1, old_roster[&1]);
+        roster.insert(2, old_roster[&3]);
+        let reshared = simulate_reshare(
+            vec![
+                (Some(keys[0].clone()), true),
+                (Some(keys[2].clone()), true),
+                (None, true),
+                (None, true),
+            ],
+            &roster,
+            2,
+            4,
+        );
+        let reshared: Vec<_> = reshared.into_iter().map(Option::unwrap).collect();
+        check_reshared(&keys[0], &reshared, 2);
+        for key in &reshared {
+            assert_eq!(key.roster.as_ref(), Some(&roster));
+        }
+
+        let offline = simulate_offline_stage(reshared, &[1, 3, 4]);
+        simulate_signing(offline, b"reshared key signs");
+    }
+
+    #[test]
+    fn simulate_reshare_with_leaving_dealer() {
+        let keys = simulate_keygen(1, 3);
+        let roster = room_roster(3);
+        // party 2 deals its share and leaves the wallet
+        let reshared = simulate_reshare(
+            vec![
+                (Some(keys[0].clone()), true),
+                (Some(keys[1].clone()), false),
+                (None, true),
+            ],
+            &roster,
+            1,
+            2,
+        );
+        assert!(reshared[1].is_none());
+        let reshared: Vec<_> = reshared.into_iter().flatten().collect();
+        check_reshared(&keys[0], &reshared, 1);
+        // the leaving dealer isn't in the roster of the new committee
+        let expected: Roster = vec![(1, roster[&1]), (2, roster[&3])].into_iter().collect();
+        for key in &reshared {
+            assert_eq!(key.roster.as_ref(), Some(&expected));
+        }
+
+        let offline = simulate_offline_stage(reshared, &[1, 2]);
+        simulate_signing(offline, b"reshared key signs");
+    }
+
+    #[test]
+    fn reshare_refuses_dealer_with_another_identity_key() {
+        let mut keys = simulate_keygen(1, 3);
+        let old_roster = room_roster(3);
+        for key in &mut keys {
+            key.roster = Some(old_roster.clone());
+        }
+        // party 3 of the old key joins the room with another identity key
+        let mut roster = room_roster(3);
+        roster.insert(1, old_roster[&1]);
+        let participants = vec![
+            (Some(keys[0].clone()), true),
+            (Some(keys[2].clone()), true),
+            (None, true),
+        ];
+
+        let mut simulation = Simulation::new();
+        let sid = SessionId::new("ecdsa-reshare", "simulation", &[1, 2, 3]);
+        for (i, (old_key, new_member)) in (1..).zip(participants) {
+            let party = KeyReshare::new(i, 3, old_key, new_member, 1, 3, sid, roster.clone());
+            simulation.add_party(party.unwrap());
+        }
+        match simulation.run().unwrap_err() {
+            Error::ProceedRound(ProceedError::Round1InvalidDealer(err)) => {
+                assert_eq!(err.bad_actors, vec![1])
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+}
@@ -0,0 +1,578 @@
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use paillier::EncryptionKey;
+use round_based::containers::push::Push;
+use round_based::containers::{self, BroadcastMsgs, P2PMsgs, Store};
+use round_based::Msg;
+use zk_paillier::zkproofs::DLogStatement;
+
+use crate::gg20::derivation::ChainCode;
+use crate::gg20::party_i::{KeyGenBroadcastMessage1, Keys, PreParams, SharedKeys};
+use crate::gg20::state_machine::keygen::LocalKey;
+use crate::gg20::zk_modulus::NoSmallFactorProof;
+use crate::gg20::{self, ErrorType};
+use crate::utils::secure_channel::Roster;
+use crate::utils::session_id::SessionId;
+
+/// Role announced by every participant in the first round
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Announcement {
+    pub dealer: Option<DealerInfo>,
+    pub new_member: bool,
+}
+
+/// Public data of the key held by a dealer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DealerInfo {
+    pub old_index: u16,
+    pub old_t: u16,
+    pub old_n: u16,
+    pub public_key: Point<Secp256k1>,
+    pub chain_code: Option<ChainCode>,
+}
+
+/// Dealer's VSS of its weighted share and Paillier/h1h2 setup of a new committee member
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Dealing {
+    pub vss_scheme: Option<VerifiableSS<Secp256k1>>,
+    pub paillier: Option<KeyGenBroadcastMessage1>,
+}
+
+/// Share dealt to a member of the new committee, `None` if sender or receiver doesn't have
+/// such role
+///
+/// Members of the new committee also prove to each other that their Paillier moduli have no
+/// small factors, the proof is made against `h1`, `h2` and `N_tilde` of the receiver.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DealtShare {
+    pub share: Option<Scalar<Secp256k1>>,
+    pub no_small_factor_proof: Option<NoSmallFactorProof>,
+}
+
+pub struct Round0 {
+    pub sid: SessionId,
+    pub roster: Roster,
+    pub i: u16,
+    pub old_key: Option<LocalKey<Secp256k1>>,
+    pub new_member: bool,
+    pub new_t: u16,
+    pub new_n: u16,
+}
+
+impl Round0 {
+    pub fn proceed<O>(self, mut output: O) -> Result<Round1>
+    where
+        O: Push<Msg<Announcement>>,
+    {
+        let announcement = Announcement {
+            dealer: self.old_key.as_ref().map(|key| DealerInfo {
+                old_index: key.i,
+                old_t: key.t,
+                old_n: key.n,
+                public_key: key.y_sum_s.clone(),
+                chain_code: key.chain_code,
+            }),
+            new_member: self.new_member,
+        };
+        output.push(Msg {
+            sender: self.i,
+            receiver: None,
+            body: announcement.clone(),
+        });
+        Ok(Round1 {
+            announcement,
+            sid: self.sid,
+            roster: self.roster,
+            i: self.i,
+            old_key: self.old_key,
+            new_t: self.new_t,
+            new_n: self.new_n,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+}
+
+pub struct Round1 {
+    announcement: Announcement,
+    sid: SessionId,
+    roster: Roster,
+    i: u16,
+    old_key: Option<LocalKey<Secp256k1>>,
+    new_t: u16,
+    new_n: u16,
+}
+
+impl Round1 {
+    pub fn proceed<O>(self, input: BroadcastMsgs<Announcement>, mut output: O) -> Result<Round2>
+    where
+        O: Push<Msg<Dealing>>,
+    {
+        let announcements = input.into_vec_including_me(self.announcement);
+        let old_roster = self.old_key.as_ref().and_then(|key| key.roster.as_ref());
+        let committee =
+            Committee::from_announcements(&announcements, self.new_n, &self.roster, old_roster)?;
+
+        let dealing_shares = self.old_key.as_ref().map(|key| {
+            let lambda = committee.lagrange_coefficient(key, key.i);
+            let w_i = lambda * &key.keys_linear.x_i;
+            let parties: Vec<_> = (1..=self.new_n).collect();
+            VerifiableSS::share_at_indices(self.new_t, self.new_n, &w_i, &parties)
+        });
+        let modulus_bits = self
+            .old_key
+            .as_ref()
+            .map_or(gg20::DEFAULT_MODULUS_BITS, |key| key.modulus_bits());
+        let keys = committee
+            .new_index(self.i)
+            .map(|new_i| Keys::create(usize::from(new_i), PreParams::generate(modulus_bits)));
+
+        let dealing = Dealing {
+            vss_scheme: dealing_shares.as_ref().map(|(vss, _)| vss.clone()),
+            paillier: keys.as_ref().map(|keys| {
+                keys.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(&self.sid)
+                    .0
+            }),
+        };
+        output.push(Msg {
+            sender: self.i,
+            receiver: None,
+            body: dealing.clone(),
+        });
+
+        Ok(Round2 {
+            dealing,
+            own_shares: dealing_shares.map(|(_, shares)| shares.to_vec()),
+            keys,
+            committee,
+            i: self.i,
+            old_key: self.old_key,
+            new_t: self.new_t,
+            new_n: self.new_n,
+            sid: self.sid,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<Announcement>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+}
+
+pub struct Round2 {
+    dealing: Dealing,
+    own_shares: Option<Vec<Scalar<Secp256k1>>>,
+    keys: Option<Keys>,
+    committee: Committee,
+    i: u16,
+    old_key: Option<LocalKey<Secp256k1>>,
+    new_t: u16,
+    new_n: u16,
+    sid: SessionId,
+}
+
+impl Round2 {
+    pub fn proceed<O>(self, input: BroadcastMsgs<Dealing>, mut output: O) -> Result<Round3>
+    where
+        O: Push<Msg<DealtShare>>,
+    {
+        let dealings = input.into_vec_including_me(self.dealing);
+
+        let bad_actors: Vec<usize> = dealings
+            .iter()
+            .enumerate()
+            .filter(|(k, dealing)| {
+                let room_i = *k as u16 + 1;
+                let vss_ok = match (&dealing.vss_scheme, self.committee.old_index(room_i)) {
+                    (Some(vss), Some(old_i)) => {
+                        vss.parameters.threshold == self.new_t
+                            && vss.parameters.share_count == self.new_n
+                            && vss.commitments.len() == usize::from(self.new_t) + 1
+                            // dealers can check each other against the old public shares
+                            && match &self.old_key {
+                                Some(key) => {
+                                    vss.commitments[0]
+                                        == &key.pk_vec[usize::from(old_i - 1)]
+                                            * self.committee.lagrange_coefficient(key, old_i)
+                                }
+                                None => true,
+                            }
+                    }
+                    (None, None) => true,
+                    _ => false,
+                };
+                let paillier_ok = match (&dealing.paillier, self.committee.new_index(room_i)) {
+                    // Paillier keys matter to the new committee only
+                    (Some(bc1), Some(_)) => {
+                        self.keys.is_none() || bc1.verify_correct_key_and_h1h2(&self.sid)
+                    }
+                    (None, None) => true,
+                    _ => false,
+                };
+                !(vss_ok && paillier_ok)
+            })
+            .map(|(k, _)| k)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round2InvalidDealing(ErrorType {
+                error_type: "invalid dealing".to_string(),
+                bad_actors,
+            }));
+        }
+
+        let vss_schemes: Vec<_> = dealings
+            .iter()
+            .filter_map(|dealing| dealing.vss_scheme.clone())
+            .collect();
+        let first = vss_schemes[0].commitments[0].clone();
+        let y = vss_schemes[1..]
+            .iter()
+            .fold(first, |acc, vss| acc + &vss.commitments[0]);
+        if y != self.committee.public_key {
+            return Err(ProceedError::Round2PublicKeyChanged);
+        }
+
+        let paillier_vec: Vec<_> = self
+            .committee
+            .new_members
+            .iter()
+            .filter_map(|&room_i| dealings[usize::from(room_i - 1)].paillier.clone())
+            .collect();
+        // proofs come in order of the other new members, which is the order of their room indexes
+        let mut no_small_factor_proofs = match &self.keys {
+            Some(keys) => keys
+                .phase2_prove_no_small_factor(&self.sid, &paillier_vec)
+                .map_err(|err| {
+                    ProceedError::Round2ProveNoSmallFactor(self.committee.in_room(err))
+                })?,
+            None => vec![],
+        }
+        .into_iter();
+
+        let mut own_share = None;
+        for room_i in 1..=dealings.len() as u16 {
+            let new_index = self.committee.new_index(room_i);
+            let share = match (&self.own_shares, new_index) {
+                (Some(shares), Some(new_i)) => Some(shares[usize::from(new_i - 1)].clone()),
+                _ => None,
+            };
+            if room_i == self.i {
+                own_share = share;
+                continue;
+            }
+            let no_small_factor_proof = match new_index {
+                Some(_) => no_small_factor_proofs.next(),
+                None => None,
+            };
+            output.push(Msg {
+                sender: self.i,
+                receiver: Some(room_i),
+                body: DealtShare {
+                    share,
+                    no_small_factor_proof,
+                },
+            })
+        }
+
+        Ok(Round3 {
+            own_share: DealtShare {
+                share: own_share,
+                no_small_factor_proof: None,
+            },
+            vss_schemes,
+            paillier_vec,
+            keys: self.keys,
+            committee: self.committee,
+            new_t: self.new_t,
+            new_n: self.new_n,
+            sid: self.sid,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<Dealing>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+}
+
+pub struct Round3 {
+    own_share: DealtShare,
+    vss_schemes: Vec<VerifiableSS<Secp256k1>>,
+    paillier_vec: Vec<KeyGenBroadcastMessage1>,
+    keys: Option<Keys>,
+    committee: Committee,
+    new_t: u16,
+    new_n: u16,
+    sid: SessionId,
+}
+
+impl Round3 {
+    pub fn proceed(self, input: P2PMsgs<DealtShare>) -> Result<Option<LocalKey<Secp256k1>>> {
+        let keys = match self.keys {
+            Some(keys) => keys,
+            // party leaves the committee, nothing to construct
+            None => return Ok(None),
+        };
+        let new_i = keys.party_index as u16;
+        let received = input.into_vec_including_me(self.own_share);
+
+        let mut bad_actors = vec![];
+        let mut x_i = Scalar::<Secp256k1>::zero();
+        for ((room_i, _), vss) in self.committee.dealers.iter().zip(&self.vss_schemes) {
+            match &received[usize::from(room_i - 1)].share {
+                Some(share) if vss.validate_share(share, new_i).is_ok() => x_i = x_i + share,
+                _ => bad_actors.push(usize::from(room_i - 1)),
+            }
+        }
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round3InvalidShare(ErrorType {
+                error_type: "invalid share".to_string(),
+                bad_actors,
+            }));
+        }
+
+        let mut missing_proofs = vec![];
+        let mut no_small_factor_proofs = vec![];
+        for (_, &room_j) in (1..)
+            .zip(&self.committee.new_members)
+            .filter(|(j, _)| *j != new_i)
+        {
+            match &received[usize::from(room_j - 1)].no_small_factor_proof {
+                Some(proof) => no_small_factor_proofs.push(proof),
+                None => missing_proofs.push(usize::from(room_j - 1)),
+            }
+        }
+        if !missing_proofs.is_empty() {
+            return Err(ProceedError::Round3VerifyNoSmallFactor(ErrorType {
+                error_type: "missing no small factor proof".to_string(),
+                bad_actors: missing_proofs,
+            }));
+        }
+        keys.phase3_verify_no_small_factor(&self.sid, &self.paillier_vec, &no_small_factor_proofs)
+            .map_err(|err| ProceedError::Round3VerifyNoSmallFactor(self.committee.in_room(err)))?;
+
+        let pk_vec: Vec<_> = (1..=self.new_n)
+            .map(|j| {
+                let first = self.vss_schemes[0].get_point_commitment(j);
+                self.vss_schemes[1..]
+                    .iter()
+                    .fold(first, |acc, vss| acc + vss.get_point_commitment(j))
+            })
+            .collect();
+        let mut vss_scheme = self.vss_schemes[0].clone();
+        vss_scheme.commitments = (0..=usize::from(self.new_t))
+            .map(|k| {
+                let first = self.vss_schemes[0].commitments[k].clone();
+                self.vss_schemes[1..]
+                    .iter()
+                    .fold(first, |acc, vss| acc + &vss.commitments[k])
+            })
+            .collect();
+
+        let paillier_key_vec = self
+            .paillier_vec
+            .iter()
+            .map(|bc1| bc1.e.clone())
+            .collect::<Vec<EncryptionKey>>();
+        let h1_h2_n_tilde_vec = self
+            .paillier_vec
+            .iter()
+            .map(|bc1| bc1.dlog_statement.clone())
+            .collect::<Vec<DLogStatement>>();
+
+        let y = self.committee.public_key;
+        Ok(Some(LocalKey {
+            paillier_dk: keys.dk.clone(),
+            pk_vec,
+
+            keys_linear: SharedKeys { y: y.clone(), x_i },
+            paillier_key_vec,
+            y_sum_s: y,
+            h1_h2_n_tilde_vec,
+
+            vss_scheme,
+
+            i: new_i,
+            t: self.new_t,
+            n: self.new_n,
+            chain_code: self.committee.chain_code,
+            roster: Some(self.committee.roster),
+        }))
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<P2PMsgs<DealtShare>> {
+        containers::P2PMsgsStore::new(i, n)
+    }
+}
+
+/// Roles of resharing participants, agreed on after the first round
+struct Committee {
+    /// Room index and old party index of every dealer
+    dealers: Vec<(u16, u16)>,
+    /// Room indexes of the new committee ordered by the new party index
+    new_members: Vec<u16>,
+    /// Identity keys of the new committee by the new party index
+    roster: Roster,
+    public_key: Point<Secp256k1>,
+    chain_code: Option<ChainCode>,
+}
+
+impl Committee {
+    /// Agrees on roles of the participants with identity keys `roster` by room index
+    ///
+    /// Dealers holding an old key with `old_roster` must join the room with the identity keys
+    /// they had with the old key.
+    fn from_announcements(
+        announcements: &[Announcement],
+        new_n: u16,
+        roster: &Roster,
+        old_roster: Option<&Roster>,
+    ) -> Result<Self> {
+        let new_members: Vec<u16> = (1..)
+            .zip(announcements)
+            .filter(|(_, a)| a.new_member)
+            .map(|(room_i, _)| room_i)
+            .collect();
+        if new_members.len() != usize::from(new_n) {
+            return Err(ProceedError::Round1NewCommitteeSize {
+                expected: new_n,
+                actual: new_members.len(),
+            });
+        }
+
+        let dealers: Vec<(u16, &DealerInfo)> = (1..)
+            .zip(announcements)
+            .filter_map(|(room_i, a)| a.dealer.as_ref().map(|d| (room_i, d)))
+            .collect();
+        let first = match dealers.first() {
+            Some((_, first)) => *first,
+            None => return Err(ProceedError::Round1TooFewDealers),
+        };
+        let bad_actors: Vec<usize> = dealers
+            .iter()
+            .filter(|(room_i, d)| {
+                d.public_key != first.public_key
+                    || d.chain_code != first.chain_code
+                    || d.old_t != first.old_t
+                    || d.old_n != first.old_n
+                    || d.old_index == 0
+                    || d.old_index > d.old_n
+                    || dealers
+                        .iter()
+                        .any(|(other, o)| other < room_i && o.old_index == d.old_index)
+            })
+            .map(|(room_i, _)| usize::from(room_i - 1))
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round1InvalidDealer(ErrorType {
+                error_type: "inconsistent old key".to_string(),
+                bad_actors,
+            }));
+        }
+        if let Some(old_roster) = old_roster {
+            let bad_actors: Vec<usize> = dealers
+                .iter()
+                .filter(|(room_i, d)| match old_roster.get(&d.old_index) {
+                    Some(expected) => roster.get(room_i) != Some(expected),
+                    None => false,
+                })
+                .map(|(room_i, _)| usize::from(room_i - 1))
+                .collect();
+            if !bad_actors.is_empty() {
+                return Err(ProceedError::Round1InvalidDealer(ErrorType {
+                    error_type: "identity key doesn't match the roster of the old key".to_string(),
+                    bad_actors,
+                }));
+            }
+        }
+        if dealers.len() <= usize::from(first.old_t) {
+            return Err(ProceedError::Round1TooFewDealers);
+        }
+
+        Ok(Self {
+            public_key: first.public_key.clone(),
+            chain_code: first.chain_code,
+            dealers: dealers
+                .iter()
+                .map(|(room_i, d)| (*room_i, d.old_index))
+                .collect(),
+            roster: (1..)
+                .zip(&new_members)
+                .filter_map(|(new_i, room_i)| roster.get(room_i).map(|key| (new_i, *key)))
+                .collect(),
+            new_members,
+        })
+    }
+
+    fn old_index(&self, room_i: u16) -> Option<u16> {
+        self.dealers
+            .iter()
+            .find(|(dealer, _)| *dealer == room_i)
+            .map(|(_, old_i)| *old_i)
+    }
+
+    fn new_index(&self, room_i: u16) -> Option<u16> {
+        self.new_members
+            .iter()
+            .position(|member| *member == room_i)
+            .map(|position| position as u16 + 1)
+    }
+
+    /// Maps culprits of `err` from positions in the new committee to positions in the room
+    fn in_room(&self, err: ErrorType) -> ErrorType {
+        ErrorType {
+            bad_actors: err
+                .bad_actors
+                .iter()
+                .map(|&k| usize::from(self.new_members[k] - 1))
+                .collect(),
+            ..err
+        }
+    }
+
+    /// Lagrange coefficient of dealer with old index `old_i` over the set of all dealers
+    fn lagrange_coefficient(&self, old_key: &LocalKey<Secp256k1>, old_i: u16) -> Scalar<Secp256k1> {
+        let s: Vec<u16> = self.dealers.iter().map(|(_, j)| j - 1).collect();
+        VerifiableSS::<Secp256k1>::map_share_to_new_params(
+            &old_key.vss_scheme.parameters,
+            old_i - 1,
+            &s,
+        )
+    }
+}
+
+// Errors
+
+type Result<T> = std::result::Result<T, ProceedError>;
+
+/// Proceeding protocol error
+///
+/// Subset of [reshare errors](enum@super::Error) that can occur at protocol proceeding (i.e. after
+/// every message was received and pre-validated).
+#[derive(Debug, Error)]
+pub enum ProceedError {
+    #[error("round 1: {actual} parties joined the new committee, expected {expected}")]
+    Round1NewCommitteeSize { expected: u16, actual: usize },
+    #[error("round 1: not enough holders of the old key to deal it")]
+    Round1TooFewDealers,
+    #[error("round 1: dealers disagree on the old key: {0:?}")]
+    Round1InvalidDealer(ErrorType),
+    #[error("round 2: invalid dealing: {0:?}")]
+    Round2InvalidDealing(ErrorType),
+    #[error("round 2: dealt secret doesn't match the public key")]
+    Round2PublicKeyChanged,
+    #[error("round 2: prove no small factor: {0:?}")]
+    Round2ProveNoSmallFactor(ErrorType),
+    #[error("round 3: invalid share: {0:?}")]
+    Round3InvalidShare(ErrorType),
+    #[error("round 3: verify no small factor: {0:?}")]
+    Round3VerifyNoSmallFactor(ErrorType),
+}
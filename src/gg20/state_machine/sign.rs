@@ -15,6 +15,15 @@
 //! `StateMachine`, but rather provides methods to construct messages and final signature manually
 //! (refer to [SignManual] documentation to see how to use it).
 //!
+//! [BatchOfflineStage] carries out many offline stages for the same parties at once, in the
+//! same number of rounds as a single one, and outputs a `CompletedOfflineStage` for each of them.
+//!
+//! ## Identifiable abort
+//!
+//! If a party cheats in MtA, offline stage fails at round 5 or 6. Then parties reveal their
+//! secrets of the discarded presignature in an extra round to find out who cheated, and the
+//! protocol ends with an error naming them (see [Error::bad_actors]).
+//!
 //! [keygen module]: super::keygen
 //! [Keygen]: super::keygen::Keygen
 //! [LocalKey]: super::keygen::LocalKey
@@ -29,20 +38,53 @@ use round_based::{IsCritical, Msg, StateMachine};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::error::Blame;
 use crate::gg20::mta::MessageA;
 
 use crate::gg20::party_i::{SignBroadcastPhase1, SignDecommitPhase1, SignatureRecid};
 use crate::gg20::state_machine::keygen::LocalKey;
+use crate::gg20::state_machine::traits::RoundBlame;
+use crate::utils::session_id::SessionId;
 use curv::elliptic::curves::secp256_k1::Secp256k1;
 
+mod batch;
 mod fmt;
 mod rounds;
 
 use crate::gg20::zk_pdl_with_slack::PDLwSlackProof;
+pub use batch::{BatchOfflineProtocolMessage, BatchOfflineStage};
 use curv::BigInt;
 use rounds::*;
 pub use rounds::{CompletedOfflineStage, Error as ProceedError, PartialSignature};
 
+/// Checks that party `i` can run offline stage with parties `s_l` of a key shared by `keygen_n`
+/// parties, returns number of parties taking part
+fn check_parties(i: u16, s_l: &[u16], keygen_n: u16) -> Result<u16> {
+    if s_l.len() < 2 {
+        return Err(Error::TooFewParties);
+    }
+    if i == 0 || usize::from(i) > s_l.len() {
+        return Err(Error::InvalidPartyIndex);
+    }
+
+    if s_l.iter().any(|&i| i == 0 || i > keygen_n) {
+        return Err(Error::InvalidSl);
+    }
+    {
+        // Check if s_l has duplicates
+        let mut s_l_sorted = s_l.to_vec();
+        s_l_sorted.sort_unstable();
+        let mut s_l_sorted_deduped = s_l_sorted.clone();
+        s_l_sorted_deduped.dedup();
+
+        if s_l_sorted != s_l_sorted_deduped {
+            return Err(Error::InvalidSl);
+        }
+    }
+
+    u16::try_from(s_l.len()).map_err(|_| Error::TooManyParties { n: s_l.len() })
+}
+
 /// Offline Stage of GG20 signing
 ///
 /// Successfully carried out Offline Stage will produce [CompletedOfflineStage] that can
@@ -56,11 +98,15 @@ pub struct OfflineStage {
     msgs4: Option<Store<BroadcastMsgs<SignDecommitPhase1>>>,
     msgs5: Option<Store<BroadcastMsgs<(RDash, Vec<PDLwSlackProof>)>>>,
     msgs6: Option<Store<BroadcastMsgs<(SI, HEGProof)>>>,
+    msgs_blame5: Option<Store<BroadcastMsgs<Phase5Reveal>>>,
+    msgs_blame6: Option<Store<BroadcastMsgs<Phase6Reveal>>>,
 
     msgs_queue: MsgQueue,
 
     party_i: u16,
     party_n: u16,
+
+    round_timeout: Option<Duration>,
 }
 
 impl OfflineStage {
@@ -70,38 +116,26 @@ impl OfflineStage {
     /// need to exchange a single set of messages).
     ///
     /// Takes party index `i` (in range `[1; n]`), list `s_l` of parties' indexes from keygen protocol
-    /// (`s_l[i]` must be an index of party `i` that was used by this party in keygen protocol), and
-    /// party local secret share `local_key`.
+    /// (`s_l[i]` must be an index of party `i` that was used by this party in keygen protocol),
+    /// party local secret share `local_key`, and identifier `sid` of the signing session which
+    /// every proof and commitment is bound to.
     ///
     /// Returns error if given arguments are contradicting.
-    pub fn new(i: u16, s_l: Vec<u16>, local_key: LocalKey<Secp256k1>) -> Result<Self> {
-        if s_l.len() < 2 {
-            return Err(Error::TooFewParties);
-        }
-        if i == 0 || usize::from(i) > s_l.len() {
-            return Err(Error::InvalidPartyIndex);
-        }
-
-        let keygen_n = local_key.n;
-        if s_l.iter().any(|&i| i == 0 || i > keygen_n) {
-            return Err(Error::InvalidSl);
-        }
-        {
-            // Check if s_l has duplicates
-            let mut s_l_sorted = s_l.clone();
-            s_l_sorted.sort_unstable();
-            let mut s_l_sorted_deduped = s_l_sorted.clone();
-            s_l_sorted_deduped.dedup();
-
-            if s_l_sorted != s_l_sorted_deduped {
-                return Err(Error::InvalidSl);
-            }
-        }
-
-        let n = u16::try_from(s_l.len()).map_err(|_| Error::TooManyParties { n: s_l.len() })?;
+    pub fn new(
+        i: u16,
+        s_l: Vec<u16>,
+        local_key: LocalKey<Secp256k1>,
+        sid: SessionId,
+    ) -> Result<Self> {
+        let n = check_parties(i, &s_l, local_key.n)?;
 
         Ok(Self {
-            round: OfflineR::R0(Round0 { i, s_l, local_key }),
+            round: OfflineR::R0(Round0 {
+                i,
+                s_l,
+                local_key,
+                sid,
+            }),
 
             msgs1: Some(Round1::expects_messages(i, n)),
             msgs2: Some(Round2::expects_messages(i, n)),
@@ -109,14 +143,26 @@ impl OfflineStage {
             msgs4: Some(Round4::expects_messages(i, n)),
             msgs5: Some(Round5::expects_messages(i, n)),
             msgs6: Some(Round6::expects_messages(i, n)),
+            msgs_blame5: Some(Round5Blame::expects_messages(i, n)),
+            msgs_blame6: Some(Round6Blame::expects_messages(i, n)),
 
             msgs_queue: MsgQueue(vec![]),
 
             party_i: i,
             party_n: n,
+
+            round_timeout: None,
         })
     }
 
+    /// Sets how long every round waits for messages of other parties
+    ///
+    /// Once the deadline is reached, offline stage fails with [Error::RoundTimeout]. Waits
+    /// forever by default.
+    pub fn set_round_timeout(&mut self, timeout: Option<Duration>) {
+        self.round_timeout = timeout;
+    }
+
     // fn proceed_state(&mut self, may_block: bool) -> Result<()> {
     //     self.proceed_round(may_block)?;
     //     self.proceed_decommit_round(may_block)
@@ -129,6 +175,16 @@ impl OfflineStage {
         let store4_wants_more = self.msgs4.as_ref().map(|s| s.wants_more()).unwrap_or(false);
         let store5_wants_more = self.msgs5.as_ref().map(|s| s.wants_more()).unwrap_or(false);
         let store6_wants_more = self.msgs6.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store_blame5_wants_more = self
+            .msgs_blame5
+            .as_ref()
+            .map(|s| s.wants_more())
+            .unwrap_or(false);
+        let store_blame6_wants_more = self
+            .msgs_blame6
+            .as_ref()
+            .map(|s| s.wants_more())
+            .unwrap_or(false);
 
         let next_state: OfflineR;
         let try_again: bool = match replace(&mut self.round, OfflineR::Gone) {
@@ -208,10 +264,13 @@ impl OfflineStage {
                 let msgs = store
                     .finish()
                     .map_err(InternalError::RetrieveMessagesFromStore)?;
-                next_state = round
+                next_state = match round
                     .proceed(msgs, &mut self.msgs_queue)
-                    .map(OfflineR::R6)
-                    .map_err(Error::ProceedRound)?;
+                    .map_err(Error::ProceedRound)?
+                {
+                    AfterRound5::Round6(round) => OfflineR::R6(round),
+                    AfterRound5::Blame(round) => OfflineR::Blame5(round),
+                };
                 false
             }
             s @ OfflineR::R5(_) => {
@@ -223,16 +282,45 @@ impl OfflineStage {
                 let msgs = store
                     .finish()
                     .map_err(InternalError::RetrieveMessagesFromStore)?;
-                next_state = round
-                    .proceed(msgs)
-                    .map(OfflineR::Finished)
-                    .map_err(Error::ProceedRound)?;
+                next_state = match round
+                    .proceed(msgs, &mut self.msgs_queue)
+                    .map_err(Error::ProceedRound)?
+                {
+                    AfterRound6::Completed(output) => OfflineR::Finished(output),
+                    AfterRound6::Blame(round) => OfflineR::Blame6(round),
+                };
                 false
             }
             s @ OfflineR::R6(_) => {
                 next_state = s;
                 false
             }
+            OfflineR::Blame5(round)
+                if !store_blame5_wants_more && (!round.is_expensive() || may_block) =>
+            {
+                let store = self.msgs_blame5.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                return Err(Error::ProceedRound(round.proceed(msgs)));
+            }
+            s @ OfflineR::Blame5(_) => {
+                next_state = s;
+                false
+            }
+            OfflineR::Blame6(round)
+                if !store_blame6_wants_more && (!round.is_expensive() || may_block) =>
+            {
+                let store = self.msgs_blame6.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                return Err(Error::ProceedRound(round.proceed(msgs)));
+            }
+            s @ OfflineR::Blame6(_) => {
+                next_state = s;
+                false
+            }
             s @ OfflineR::Finished(_) | s @ OfflineR::Gone => {
                 next_state = s;
                 false
@@ -353,6 +441,38 @@ impl StateMachine for OfflineStage {
                     })
                     .map_err(Error::HandleMessage)?;
             }
+            OfflineProtocolMessage(OfflineM::Blame5(m)) => {
+                let store = self
+                    .msgs_blame5
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 6,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+            }
+            OfflineProtocolMessage(OfflineM::Blame6(m)) => {
+                let store = self
+                    .msgs_blame6
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 7,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+            }
         }
         self.proceed_round(false)
     }
@@ -368,6 +488,16 @@ impl StateMachine for OfflineStage {
         let store4_wants_more = self.msgs4.as_ref().map(|s| s.wants_more()).unwrap_or(false);
         let store5_wants_more = self.msgs5.as_ref().map(|s| s.wants_more()).unwrap_or(false);
         let store6_wants_more = self.msgs6.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store_blame5_wants_more = self
+            .msgs_blame5
+            .as_ref()
+            .map(|s| s.wants_more())
+            .unwrap_or(false);
+        let store_blame6_wants_more = self
+            .msgs_blame6
+            .as_ref()
+            .map(|s| s.wants_more())
+            .unwrap_or(false);
 
         match &self.round {
             OfflineR::R0(_) => true,
@@ -377,6 +507,8 @@ impl StateMachine for OfflineStage {
             OfflineR::R4(_) => !store4_wants_more,
             OfflineR::R5(_) => !store5_wants_more,
             OfflineR::R6(_) => !store6_wants_more,
+            OfflineR::Blame5(_) => !store_blame5_wants_more,
+            OfflineR::Blame6(_) => !store_blame6_wants_more,
             OfflineR::Finished(_) | OfflineR::Gone => false,
         }
     }
@@ -386,11 +518,16 @@ impl StateMachine for OfflineStage {
     }
 
     fn round_timeout(&self) -> Option<Duration> {
-        None
+        self.round_timeout
     }
 
     fn round_timeout_reached(&mut self) -> Self::Err {
-        panic!("no timeout was set")
+        let (missing_messages, missing_parties) = self.round_blame();
+        Error::RoundTimeout {
+            round: self.current_round(),
+            missing_messages,
+            missing_parties,
+        }
     }
 
     fn is_finished(&self) -> bool {
@@ -418,8 +555,8 @@ impl StateMachine for OfflineStage {
             OfflineR::R3(_) => 3,
             OfflineR::R4(_) => 4,
             OfflineR::R5(_) => 5,
-            OfflineR::R6(_) => 6,
-            OfflineR::Finished(_) | OfflineR::Gone => 7,
+            OfflineR::R6(_) | OfflineR::Blame5(_) => 6,
+            OfflineR::Blame6(_) | OfflineR::Finished(_) | OfflineR::Gone => 7,
         }
     }
 
@@ -436,7 +573,7 @@ impl StateMachine for OfflineStage {
     }
 }
 
-impl super::traits::RoundBlame for OfflineStage {
+impl RoundBlame for OfflineStage {
     /// RoundBlame returns number of unwilling parties and a vector of their party indexes.
     fn round_blame(&self) -> (u16, Vec<u16>) {
         let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
@@ -445,6 +582,16 @@ impl super::traits::RoundBlame for OfflineStage {
         let store4_blame = self.msgs4.as_ref().map(|s| s.blame()).unwrap_or_default();
         let store5_blame = self.msgs5.as_ref().map(|s| s.blame()).unwrap_or_default();
         let store6_blame = self.msgs6.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store_blame5_blame = self
+            .msgs_blame5
+            .as_ref()
+            .map(|s| s.blame())
+            .unwrap_or_default();
+        let store_blame6_blame = self
+            .msgs_blame6
+            .as_ref()
+            .map(|s| s.blame())
+            .unwrap_or_default();
 
         let default = (0, vec![]);
         match &self.round {
@@ -455,6 +602,8 @@ impl super::traits::RoundBlame for OfflineStage {
             OfflineR::R4(_) => store4_blame,
             OfflineR::R5(_) => store5_blame,
             OfflineR::R6(_) => store6_blame,
+            OfflineR::Blame5(_) => store_blame5_blame,
+            OfflineR::Blame6(_) => store_blame6_blame,
             OfflineR::Finished(_) => store6_blame,
             OfflineR::Gone => default,
         }
@@ -470,6 +619,8 @@ enum OfflineR {
     R4(Round4),
     R5(Round5),
     R6(Round6),
+    Blame5(Round5Blame),
+    Blame6(Round6Blame),
     Finished(CompletedOfflineStage),
     Gone,
 }
@@ -486,6 +637,8 @@ enum OfflineM {
     M4(SignDecommitPhase1),
     M5((RDash, Vec<PDLwSlackProof>)),
     M6((SI, HEGProof)),
+    Blame5(Phase5Reveal),
+    Blame6(Phase6Reveal),
 }
 
 struct MsgQueue(Vec<Msg<OfflineProtocolMessage>>);
@@ -513,6 +666,8 @@ make_pushable! {
     M4 SignDecommitPhase1,
     M5 (RDash, Vec<PDLwSlackProof>),
     M6 (SI, HEGProof),
+    Blame5 Phase5Reveal,
+    Blame6 Phase6Reveal,
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -533,6 +688,9 @@ pub enum Error {
     /// participated in DKG (`exist i. s_l[i] = 0 || s_l[i] > keygen_n`).
     #[error("invalid s_l")]
     InvalidSl,
+    /// [BatchOfflineStage] asked to produce no presignatures
+    #[error("batch must contain at least one presignature")]
+    EmptyBatch,
 
     /// Round proceeding resulted in protocol error
     #[error("proceeding round: {0}")]
@@ -546,6 +704,21 @@ pub enum Error {
     /// Received message didn't pass pre-validation
     #[error("received message didn't pass pre-validation: {0}")]
     HandleMessage(#[source] StoreErr),
+    /// Party `sender` sent [BatchOfflineStage] a message carrying another number of presignatures
+    /// than the batch has
+    #[error("party {sender} sent a batch of wrong size in round {msg_round}")]
+    MalformedBatch { sender: u16, msg_round: u16 },
+    /// Round deadline set by [OfflineStage::set_round_timeout] was reached
+    ///
+    /// `missing_parties` are indexes (in range `[1; n]`) of parties we didn't hear from this round.
+    #[error(
+        "round {round} timed out waiting for {missing_messages} messages from parties {missing_parties:?}"
+    )]
+    RoundTimeout {
+        round: u16,
+        missing_messages: u16,
+        missing_parties: Vec<u16>,
+    },
 
     /// [OfflineStage::pick_output] called twice
     #[error("pick_output called twice")]
@@ -564,6 +737,32 @@ pub enum InternalError {
     RetrieveMessagesFromStore(StoreErr),
     #[error("decommit round expected to be in NotStarted state")]
     DecommitRoundWasntInInitialState,
+    #[error("couldn't split batch messages into messages of every presignature")]
+    SplitBatch(StoreErr),
+}
+
+impl Error {
+    /// Indexes (in range `[1; n]`) of parties proven to misbehave
+    ///
+    /// Party `i` took part in signing with keygen index `s_l[i - 1]`. Empty if the error
+    /// doesn't identify anyone, e.g. if a party simply went offline.
+    pub fn bad_actors(&self) -> Vec<u16> {
+        match self {
+            Error::ProceedRound(err) => err
+                .bad_actors()
+                .iter()
+                .filter_map(|&i| u16::try_from(i + 1).ok())
+                .collect(),
+            Error::MalformedBatch { sender, .. } => vec![*sender],
+            _ => vec![],
+        }
+    }
+}
+
+impl Blame for Error {
+    fn culprits(&self) -> Vec<u16> {
+        self.bad_actors()
+    }
 }
 
 impl From<InternalError> for Error {
@@ -579,9 +778,12 @@ impl IsCritical for Error {
             Error::TooManyParties { .. } => true,
             Error::InvalidPartyIndex => true,
             Error::InvalidSl => true,
+            Error::EmptyBatch => true,
             Error::ProceedRound(_) => true,
             Error::ReceivedOutOfOrderMessage { .. } => false,
             Error::HandleMessage(_) => false,
+            Error::MalformedBatch { .. } => true,
+            Error::RoundTimeout { .. } => true,
             Error::DoublePickOutput => true,
             Error::Bug(_) => true,
         }
@@ -653,7 +855,7 @@ pub enum SignError {
 }
 
 #[cfg(test)]
-mod test {
+pub mod test {
     use curv::arithmetic::Converter;
     use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
     use round_based::dev::Simulation;
@@ -663,7 +865,7 @@ mod test {
     use crate::gg20::party_i::verify;
     use crate::gg20::state_machine::keygen::test::simulate_keygen;
 
-    fn simulate_offline_stage(
+    pub fn simulate_offline_stage(
         local_keys: Vec<LocalKey<Secp256k1>>,
         s_l: &[u16],
     ) -> Vec<CompletedOfflineStage> {
@@ -676,6 +878,7 @@ mod test {
                     i,
                     s_l.to_vec(),
                     local_keys[usize::from(keygen_i - 1)].clone(),
+                    session_id(s_l),
                 )
                 .unwrap(),
             );
@@ -689,7 +892,11 @@ mod test {
         stages
     }
 
-    fn simulate_signing(offline: Vec<CompletedOfflineStage>, message: &[u8]) {
+    pub fn session_id(s_l: &[u16]) -> SessionId {
+        SessionId::new("ecdsa-presign", "simulation", s_l)
+    }
+
+    pub fn simulate_signing(offline: Vec<CompletedOfflineStage>, message: &[u8]) {
         let message = Sha256::new()
             .chain_bigint(&BigInt::from_bytes(message))
             .result_bigint();
@@ -718,6 +925,179 @@ mod test {
             .all(|signature| verify(&signature, &pk, &message).is_ok()));
     }
 
+    /// Runs offline stage letting `cheat` tamper with the party `cheater` every time it proceeds
+    fn simulate_offline_stage_with_cheater(
+        local_keys: Vec<LocalKey<Secp256k1>>,
+        s_l: &[u16],
+        cheater: u16,
+        cheat: impl Fn(&mut OfflineStage),
+    ) -> Vec<Result<CompletedOfflineStage, Error>> {
+        let mut parties: Vec<_> = (1..)
+            .zip(s_l)
+            .map(|(i, &keygen_i)| {
+                OfflineStage::new(
+                    i,
+                    s_l.to_vec(),
+                    local_keys[usize::from(keygen_i - 1)].clone(),
+                    session_id(s_l),
+                )
+                .unwrap()
+            })
+            .collect();
+        let mut results: Vec<Option<Result<CompletedOfflineStage, Error>>> =
+            parties.iter().map(|_| None).collect();
+
+        let mut progressed = true;
+        while progressed {
+            progressed = false;
+            for i in 0..parties.len() {
+                if results[i].is_some() {
+                    continue;
+                }
+                if parties[i].wants_to_proceed() {
+                    if parties[i].party_ind() == cheater {
+                        cheat(&mut parties[i]);
+                    }
+                    if let Err(err) = parties[i].proceed() {
+                        results[i] = Some(Err(err));
+                    }
+                    progressed = true;
+                }
+                let msgs: Vec<_> = parties[i].message_queue().drain(..).collect();
+                for msg in msgs {
+                    for j in 0..parties.len() {
+                        let receiver = parties[j].party_ind();
+                        if j == i
+                            || results[j].is_some()
+                            || msg.receiver.map_or(false, |r| r != receiver)
+                        {
+                            continue;
+                        }
+                        if let Err(err) = parties[j].handle_incoming(msg.clone()) {
+                            results[j] = Some(Err(err));
+                        }
+                    }
+                    progressed = true;
+                }
+                if results[i].is_none() && parties[i].is_finished() {
+                    results[i] = parties[i].pick_output();
+                }
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("party got stuck"))
+            .collect()
+    }
+
+    #[test]
+    fn blames_party_cheating_in_delta() {
+        let local_keys = simulate_keygen(1, 3);
+        let results = simulate_offline_stage_with_cheater(local_keys, &[1, 2, 3], 2, |stage| {
+            if let OfflineR::R2(round) = &mut stage.round {
+                round.corrupt_delta_i()
+            }
+        });
+        for result in results {
+            let err = result.err().expect("offline stage must fail");
+            assert!(
+                matches!(err, Error::ProceedRound(ProceedError::Round5Blame(_))),
+                "{:?}",
+                err
+            );
+            assert_eq!(err.bad_actors(), vec![2]);
+        }
+    }
+
+    #[test]
+    fn blames_party_cheating_in_sigma() {
+        let local_keys = simulate_keygen(1, 3);
+        let results = simulate_offline_stage_with_cheater(local_keys, &[1, 3], 1, |stage| {
+            if let OfflineR::R2(round) = &mut stage.round {
+                round.corrupt_sigma_i()
+            }
+        });
+        for result in results {
+            let err = result.err().expect("offline stage must fail");
+            assert!(
+                matches!(err, Error::ProceedRound(ProceedError::Round6Blame(_))),
+                "{:?}",
+                err
+            );
+            assert_eq!(err.bad_actors(), vec![1]);
+        }
+    }
+
+    #[test]
+    fn rejects_mta_of_another_session() {
+        let local_keys = simulate_keygen(1, 3);
+        let mut simulation = Simulation::new();
+        let other = SessionId::new("ecdsa-presign", "another room", &[1, 2]);
+        simulation
+            .add_party(OfflineStage::new(1, vec![1, 2], local_keys[0].clone(), other).unwrap());
+        simulation.add_party(
+            OfflineStage::new(2, vec![1, 2], local_keys[1].clone(), session_id(&[1, 2])).unwrap(),
+        );
+        match simulation.run() {
+            Err(Error::ProceedRound(ProceedError::Round1(_))) => (),
+            res => panic!("unexpected result: {:?}", res.map(|_| ()).err()),
+        }
+    }
+
+    #[test]
+    fn round_timeout_blames_silent_parties() {
+        let local_keys = simulate_keygen(1, 3);
+        let mut stage =
+            OfflineStage::new(1, vec![1, 3], local_keys[0].clone(), session_id(&[1, 3])).unwrap();
+        stage.set_round_timeout(Some(Duration::from_secs(30)));
+        assert_eq!(stage.round_timeout(), Some(Duration::from_secs(30)));
+
+        stage.proceed().unwrap();
+        let err = stage.round_timeout_reached();
+        assert!(
+            matches!(
+                &err,
+                Error::RoundTimeout {
+                    round: 1,
+                    missing_messages: 1,
+                    missing_parties,
+                } if missing_parties == &[2]
+            ),
+            "{:?}",
+            err
+        );
+        assert!(err.bad_actors().is_empty());
+    }
+
+    #[test]
+    fn reads_presignatures_holding_local_key() {
+        let local_keys = simulate_keygen(1, 2);
+        let offline = simulate_offline_stage(local_keys.clone(), &[1, 2]);
+        assert!(
+            serde_json::to_string(&offline[0]).unwrap().len()
+                < serde_json::to_string(&local_keys[0]).unwrap().len()
+        );
+
+        let legacy: Vec<CompletedOfflineStage> = offline
+            .iter()
+            .zip(&local_keys)
+            .map(|(stage, local_key)| {
+                let mut json = serde_json::to_value(stage).unwrap();
+                let fields = json.as_object_mut().unwrap();
+                fields.remove("public_key");
+                fields.remove("pk_vec");
+                fields.insert("local_key".into(), serde_json::to_value(local_key).unwrap());
+                serde_json::from_value(json).unwrap()
+            })
+            .collect();
+        assert!(legacy
+            .iter()
+            .zip(&local_keys)
+            .all(|(stage, local_key)| stage.is_for_key(local_key)));
+        simulate_signing(legacy, b"KeyPuzzle");
+    }
+
     #[test]
     fn simulate_offline_stage_t1_n2_s2() {
         let local_keys = simulate_keygen(1, 2);
@@ -0,0 +1,759 @@
+//! Offline stage producing many presignatures in one run
+//!
+//! [BatchOfflineStage] runs several instances of [OfflineStage](super::OfflineStage) side by side.
+//! Every round a party sends one message carrying its messages of all the instances, so a batch
+//! takes as many round trips as a single presignature. Instance `k` is bound to session
+//! `sid.indexed(k)`, proofs of one instance don't verify in another.
+
+use std::fmt;
+use std::mem::replace;
+use std::time::Duration;
+
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+use round_based::containers::push::Push;
+use round_based::containers::{self, BroadcastMsgs, MessageStore, P2PMsgs, Store, StoreErr};
+use round_based::{Msg, StateMachine};
+use serde::{Deserialize, Serialize};
+
+use super::rounds::*;
+use super::{check_parties, Error, InternalError, ProceedError, Result};
+use crate::gg20::mta::MessageA;
+use crate::gg20::party_i::{SignBroadcastPhase1, SignDecommitPhase1};
+use crate::gg20::state_machine::keygen::LocalKey;
+use crate::gg20::state_machine::traits::RoundBlame;
+use crate::gg20::zk_pdl_with_slack::PDLwSlackProof;
+use crate::utils::session_id::SessionId;
+
+/// Offline Stage of GG20 signing producing a batch of presignatures
+///
+/// Outputs one [CompletedOfflineStage] per presignature, each of them signs a single message.
+pub struct BatchOfflineStage {
+    round: BatchR,
+
+    msgs1: Option<Store<BroadcastMsgs<Vec<(MessageA, SignBroadcastPhase1)>>>>,
+    msgs2: Option<Store<P2PMsgs<Vec<(GammaI, WI)>>>>,
+    msgs3: Option<Store<BroadcastMsgs<Vec<(DeltaI, TI, TIProof)>>>>,
+    msgs4: Option<Store<BroadcastMsgs<Vec<SignDecommitPhase1>>>>,
+    msgs5: Option<Store<BroadcastMsgs<Vec<(RDash, Vec<PDLwSlackProof>)>>>>,
+    msgs6: Option<Store<BroadcastMsgs<Vec<(SI, HEGProof)>>>>,
+    msgs_blame5: Option<Store<BroadcastMsgs<Phase5Reveal>>>,
+    msgs_blame6: Option<Store<BroadcastMsgs<Phase6Reveal>>>,
+
+    msgs_queue: Vec<Msg<BatchOfflineProtocolMessage>>,
+
+    batch_size: usize,
+    party_i: u16,
+    party_n: u16,
+
+    round_timeout: Option<Duration>,
+}
+
+impl BatchOfflineStage {
+    /// Construct a party of offline stage producing `count` presignatures
+    ///
+    /// Takes the same arguments as [OfflineStage::new](super::OfflineStage::new). Every party must
+    /// ask for the same `count`.
+    ///
+    /// Returns error if given arguments are contradicting or `count` is zero.
+    pub fn new(
+        i: u16,
+        s_l: Vec<u16>,
+        local_key: LocalKey<Secp256k1>,
+        sid: SessionId,
+        count: u16,
+    ) -> Result<Self> {
+        let n = check_parties(i, &s_l, local_key.n)?;
+        if count == 0 {
+            return Err(Error::EmptyBatch);
+        }
+
+        let instances = (0..count)
+            .map(|k| Round0 {
+                i,
+                s_l: s_l.clone(),
+                local_key: local_key.clone(),
+                sid: sid.indexed(k),
+            })
+            .collect();
+
+        Ok(Self {
+            round: BatchR::R0(instances),
+
+            msgs1: Some(containers::BroadcastMsgsStore::new(i, n)),
+            msgs2: Some(containers::P2PMsgsStore::new(i, n)),
+            msgs3: Some(containers::BroadcastMsgsStore::new(i, n)),
+            msgs4: Some(containers::BroadcastMsgsStore::new(i, n)),
+            msgs5: Some(containers::BroadcastMsgsStore::new(i, n)),
+            msgs6: Some(containers::BroadcastMsgsStore::new(i, n)),
+            msgs_blame5: Some(Round5Blame::expects_messages(i, n)),
+            msgs_blame6: Some(Round6Blame::expects_messages(i, n)),
+
+            msgs_queue: vec![],
+
+            batch_size: usize::from(count),
+            party_i: i,
+            party_n: n,
+
+            round_timeout: None,
+        })
+    }
+
+    /// Sets how long every round waits for messages of other parties
+    ///
+    /// Once the deadline is reached, offline stage fails with [Error::RoundTimeout]. Waits
+    /// forever by default.
+    pub fn set_round_timeout(&mut self, timeout: Option<Duration>) {
+        self.round_timeout = timeout;
+    }
+
+    /// Splits received batch messages into messages of every instance
+    fn split<S>(
+        &self,
+        receiver: Option<u16>,
+        store: fn(u16, u16) -> S,
+        batch: Vec<Vec<S::M>>,
+    ) -> Result<Vec<S::Output>>
+    where
+        S: MessageStore<Err = StoreErr>,
+    {
+        let (i, n) = (self.party_i, self.party_n);
+        let mut stores: Vec<S> = (0..self.batch_size).map(|_| store(i, n)).collect();
+        // Stores give messages ordered by sender, skipping the local party
+        let senders = (1..=n).filter(|&j| j != i);
+        for (sender, msgs) in senders.zip(batch) {
+            for (store, body) in stores.iter_mut().zip(msgs) {
+                store
+                    .push_msg(Msg {
+                        sender,
+                        receiver,
+                        body,
+                    })
+                    .map_err(InternalError::SplitBatch)?;
+            }
+        }
+        stores
+            .into_iter()
+            .map(|store| {
+                store
+                    .finish()
+                    .map_err(|err| InternalError::SplitBatch(err).into())
+            })
+            .collect()
+    }
+
+    /// Proceeds round state if every party sent its batch and `may_block == true`
+    ///
+    /// Every round of a batch is expensive, so it never proceeds while handling a message.
+    fn proceed_round(&mut self, may_block: bool) -> Result<()> {
+        if !may_block || !self.wants_to_proceed() {
+            return Ok(());
+        }
+
+        let next_state: BatchR;
+        let try_again: bool = match replace(&mut self.round, BatchR::Gone) {
+            BatchR::R0(rounds) => {
+                let inputs = vec![(); rounds.len()];
+                let (next, msgs): (_, Vec<Msg<Vec<(MessageA, SignBroadcastPhase1)>>>) =
+                    proceed_instances(rounds, inputs, |round, (), out| round.proceed(out))?;
+                self.send(msgs, BatchM::M1);
+                next_state = BatchR::R1(next);
+                true
+            }
+            BatchR::R1(rounds) => {
+                let store = self.msgs1.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                let inputs = self.split(None, Round1::expects_messages, msgs.into_vec())?;
+                let (next, msgs): (_, Vec<Msg<Vec<(GammaI, WI)>>>) =
+                    proceed_instances(rounds, inputs, |round, input, out| {
+                        round.proceed(input, out)
+                    })?;
+                self.send(msgs, BatchM::M2);
+                next_state = BatchR::R2(next);
+                true
+            }
+            BatchR::R2(rounds) => {
+                let store = self.msgs2.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                let inputs = self.split(
+                    Some(self.party_i),
+                    Round2::expects_messages,
+                    msgs.into_vec(),
+                )?;
+                let (next, msgs): (_, Vec<Msg<Vec<(DeltaI, TI, TIProof)>>>) =
+                    proceed_instances(rounds, inputs, |round, input, out| {
+                        round.proceed(input, out)
+                    })?;
+                self.send(msgs, BatchM::M3);
+                next_state = BatchR::R3(next);
+                true
+            }
+            BatchR::R3(rounds) => {
+                let store = self.msgs3.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                let inputs = self.split(None, Round3::expects_messages, msgs.into_vec())?;
+                let (next, msgs): (_, Vec<Msg<Vec<SignDecommitPhase1>>>) =
+                    proceed_instances(rounds, inputs, |round, input, out| {
+                        round.proceed(input, out)
+                    })?;
+                self.send(msgs, BatchM::M4);
+                next_state = BatchR::R4(next);
+                true
+            }
+            BatchR::R4(rounds) => {
+                let store = self.msgs4.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                let inputs = self.split(None, Round4::expects_messages, msgs.into_vec())?;
+                let (next, msgs): (_, Vec<Msg<Vec<(RDash, Vec<PDLwSlackProof>)>>>) =
+                    proceed_instances(rounds, inputs, |round, input, out| {
+                        round.proceed(input, out)
+                    })?;
+                self.send(msgs, BatchM::M5);
+                next_state = BatchR::R5(next);
+                false
+            }
+            BatchR::R5(rounds) => {
+                let store = self.msgs5.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                let inputs = self.split(None, Round5::expects_messages, msgs.into_vec())?;
+                let mut next = Vec::with_capacity(rounds.len());
+                let mut msgs = vec![];
+                let mut blame = None;
+                for (round, input) in rounds.into_iter().zip(inputs) {
+                    let mut out = Round5Output::default();
+                    match round
+                        .proceed(input, &mut out)
+                        .map_err(Error::ProceedRound)?
+                    {
+                        AfterRound5::Round6(round) => {
+                            next.push(round);
+                            merge(&mut msgs, out.next);
+                        }
+                        // Every honest party finds the same first failing instance, the batch
+                        // is discarded and its parties reveal their secrets of that instance
+                        AfterRound5::Blame(round) => {
+                            blame = Some((round, out.reveal));
+                            break;
+                        }
+                    }
+                }
+                next_state = match blame {
+                    Some((round, reveal)) => {
+                        self.msgs_queue.extend(reveal.into_iter().map(|m| {
+                            m.map_body(|m| BatchOfflineProtocolMessage(BatchM::Blame5(m)))
+                        }));
+                        BatchR::Blame5(round)
+                    }
+                    None => {
+                        self.send(msgs, BatchM::M6);
+                        BatchR::R6(next)
+                    }
+                };
+                false
+            }
+            BatchR::R6(rounds) => {
+                let store = self.msgs6.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                let inputs = self.split(None, Round6::expects_messages, msgs.into_vec())?;
+                let mut completed = Vec::with_capacity(rounds.len());
+                let mut blame = None;
+                for (round, input) in rounds.into_iter().zip(inputs) {
+                    let mut reveal: Vec<Msg<Phase6Reveal>> = vec![];
+                    match round
+                        .proceed(input, &mut reveal)
+                        .map_err(Error::ProceedRound)?
+                    {
+                        AfterRound6::Completed(output) => completed.push(output),
+                        AfterRound6::Blame(round) => {
+                            blame = Some((round, reveal));
+                            break;
+                        }
+                    }
+                }
+                next_state = match blame {
+                    Some((round, reveal)) => {
+                        self.msgs_queue.extend(reveal.into_iter().map(|m| {
+                            m.map_body(|m| BatchOfflineProtocolMessage(BatchM::Blame6(m)))
+                        }));
+                        BatchR::Blame6(round)
+                    }
+                    None => BatchR::Finished(completed),
+                };
+                false
+            }
+            BatchR::Blame5(round) => {
+                let store = self.msgs_blame5.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                return Err(Error::ProceedRound(round.proceed(msgs)));
+            }
+            BatchR::Blame6(round) => {
+                let store = self.msgs_blame6.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                return Err(Error::ProceedRound(round.proceed(msgs)));
+            }
+            s @ BatchR::Finished(_) | s @ BatchR::Gone => {
+                next_state = s;
+                false
+            }
+        };
+
+        self.round = next_state;
+        if try_again {
+            self.proceed_round(may_block)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn send<T>(&mut self, msgs: Vec<Msg<Vec<T>>>, constructor: fn(Vec<T>) -> BatchM) {
+        self.msgs_queue.extend(
+            msgs.into_iter()
+                .map(|m| m.map_body(|m| BatchOfflineProtocolMessage(constructor(m)))),
+        )
+    }
+
+    /// Checks that batch message `msg` carries one message per instance
+    fn check_batch_size<T>(&self, msg: &Msg<Vec<T>>, msg_round: u16) -> Result<()> {
+        if msg.body.len() != self.batch_size {
+            return Err(Error::MalformedBatch {
+                sender: msg.sender,
+                msg_round,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl StateMachine for BatchOfflineStage {
+    type MessageBody = BatchOfflineProtocolMessage;
+    type Err = Error;
+    type Output = Vec<CompletedOfflineStage>;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<(), Self::Err> {
+        let current_round = self.current_round();
+        let out_of_order = |msg_round| Error::ReceivedOutOfOrderMessage {
+            current_round,
+            msg_round,
+        };
+
+        match msg.body {
+            BatchOfflineProtocolMessage(BatchM::M1(m)) => {
+                let msg = Msg {
+                    sender: msg.sender,
+                    receiver: msg.receiver,
+                    body: m,
+                };
+                self.check_batch_size(&msg, 1)?;
+                let store = self.msgs1.as_mut().ok_or_else(|| out_of_order(1))?;
+                store.push_msg(msg).map_err(Error::HandleMessage)?;
+            }
+            BatchOfflineProtocolMessage(BatchM::M2(m)) => {
+                let msg = Msg {
+                    sender: msg.sender,
+                    receiver: msg.receiver,
+                    body: m,
+                };
+                self.check_batch_size(&msg, 2)?;
+                let store = self.msgs2.as_mut().ok_or_else(|| out_of_order(2))?;
+                store.push_msg(msg).map_err(Error::HandleMessage)?;
+            }
+            BatchOfflineProtocolMessage(BatchM::M3(m)) => {
+                let msg = Msg {
+                    sender: msg.sender,
+                    receiver: msg.receiver,
+                    body: m,
+                };
+                self.check_batch_size(&msg, 3)?;
+                let store = self.msgs3.as_mut().ok_or_else(|| out_of_order(3))?;
+                store.push_msg(msg).map_err(Error::HandleMessage)?;
+            }
+            BatchOfflineProtocolMessage(BatchM::M4(m)) => {
+                let msg = Msg {
+                    sender: msg.sender,
+                    receiver: msg.receiver,
+                    body: m,
+                };
+                self.check_batch_size(&msg, 4)?;
+                let store = self.msgs4.as_mut().ok_or_else(|| out_of_order(4))?;
+                store.push_msg(msg).map_err(Error::HandleMessage)?;
+            }
+            BatchOfflineProtocolMessage(BatchM::M5(m)) => {
+                let msg = Msg {
+                    sender: msg.sender,
+                    receiver: msg.receiver,
+                    body: m,
+                };
+                self.check_batch_size(&msg, 5)?;
+                let store = self.msgs5.as_mut().ok_or_else(|| out_of_order(5))?;
+                store.push_msg(msg).map_err(Error::HandleMessage)?;
+            }
+            BatchOfflineProtocolMessage(BatchM::M6(m)) => {
+                let msg = Msg {
+                    sender: msg.sender,
+                    receiver: msg.receiver,
+                    body: m,
+                };
+                self.check_batch_size(&msg, 6)?;
+                let store = self.msgs6.as_mut().ok_or_else(|| out_of_order(6))?;
+                store.push_msg(msg).map_err(Error::HandleMessage)?;
+            }
+            BatchOfflineProtocolMessage(BatchM::Blame5(m)) => {
+                let store = self.msgs_blame5.as_mut().ok_or_else(|| out_of_order(6))?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+            }
+            BatchOfflineProtocolMessage(BatchM::Blame6(m)) => {
+                let store = self.msgs_blame6.as_mut().ok_or_else(|| out_of_order(7))?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+            }
+        }
+        self.proceed_round(false)
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        &mut self.msgs_queue
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store4_wants_more = self.msgs4.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store5_wants_more = self.msgs5.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store6_wants_more = self.msgs6.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store_blame5_wants_more = self
+            .msgs_blame5
+            .as_ref()
+            .map(|s| s.wants_more())
+            .unwrap_or(false);
+        let store_blame6_wants_more = self
+            .msgs_blame6
+            .as_ref()
+            .map(|s| s.wants_more())
+            .unwrap_or(false);
+
+        match &self.round {
+            BatchR::R0(_) => true,
+            BatchR::R1(_) => !store1_wants_more,
+            BatchR::R2(_) => !store2_wants_more,
+            BatchR::R3(_) => !store3_wants_more,
+            BatchR::R4(_) => !store4_wants_more,
+            BatchR::R5(_) => !store5_wants_more,
+            BatchR::R6(_) => !store6_wants_more,
+            BatchR::Blame5(_) => !store_blame5_wants_more,
+            BatchR::Blame6(_) => !store_blame6_wants_more,
+            BatchR::Finished(_) | BatchR::Gone => false,
+        }
+    }
+
+    fn proceed(&mut self) -> Result<(), Self::Err> {
+        self.proceed_round(true)
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        self.round_timeout
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        let (missing_messages, missing_parties) = self.round_blame();
+        Error::RoundTimeout {
+            round: self.current_round(),
+            missing_messages,
+            missing_parties,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(&self.round, BatchR::Finished(_))
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output, Self::Err>> {
+        match self.round {
+            BatchR::Finished(_) => (),
+            BatchR::Gone => return Some(Err(Error::DoublePickOutput)),
+            _ => return None,
+        }
+
+        match replace(&mut self.round, BatchR::Gone) {
+            BatchR::Finished(result) => Some(Ok(result)),
+            _ => unreachable!("guaranteed by match expression above"),
+        }
+    }
+
+    fn current_round(&self) -> u16 {
+        match &self.round {
+            BatchR::R0(_) => 0,
+            BatchR::R1(_) => 1,
+            BatchR::R2(_) => 2,
+            BatchR::R3(_) => 3,
+            BatchR::R4(_) => 4,
+            BatchR::R5(_) => 5,
+            BatchR::R6(_) | BatchR::Blame5(_) => 6,
+            BatchR::Blame6(_) | BatchR::Finished(_) | BatchR::Gone => 7,
+        }
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        Some(6)
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.party_i
+    }
+
+    fn parties(&self) -> u16 {
+        self.party_n
+    }
+}
+
+impl RoundBlame for BatchOfflineStage {
+    /// RoundBlame returns number of unwilling parties and a vector of their party indexes.
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store3_blame = self.msgs3.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store4_blame = self.msgs4.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store5_blame = self.msgs5.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store6_blame = self.msgs6.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store_blame5_blame = self
+            .msgs_blame5
+            .as_ref()
+            .map(|s| s.blame())
+            .unwrap_or_default();
+        let store_blame6_blame = self
+            .msgs_blame6
+            .as_ref()
+            .map(|s| s.blame())
+            .unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            BatchR::R0(_) => default,
+            BatchR::R1(_) => store1_blame,
+            BatchR::R2(_) => store2_blame,
+            BatchR::R3(_) => store3_blame,
+            BatchR::R4(_) => store4_blame,
+            BatchR::R5(_) => store5_blame,
+            BatchR::R6(_) => store6_blame,
+            BatchR::Blame5(_) => store_blame5_blame,
+            BatchR::Blame6(_) => store_blame6_blame,
+            BatchR::Finished(_) => store6_blame,
+            BatchR::Gone => default,
+        }
+    }
+}
+
+impl fmt::Debug for BatchOfflineStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (missing_messages, _) = self.round_blame();
+        write!(
+            f,
+            "{{BatchOfflineStage of {} at round={} missing_msgs={} queue=[len={}]}}",
+            self.batch_size,
+            self.current_round(),
+            missing_messages,
+            self.msgs_queue.len()
+        )
+    }
+}
+
+/// Rounds of every instance of the batch, or the blame round of the first failed one
+#[allow(clippy::large_enum_variant)]
+enum BatchR {
+    R0(Vec<Round0>),
+    R1(Vec<Round1>),
+    R2(Vec<Round2>),
+    R3(Vec<Round3>),
+    R4(Vec<Round4>),
+    R5(Vec<Round5>),
+    R6(Vec<Round6>),
+    Blame5(Round5Blame),
+    Blame6(Round6Blame),
+    Finished(Vec<CompletedOfflineStage>),
+    Gone,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchOfflineProtocolMessage(BatchM);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+enum BatchM {
+    M1(Vec<(MessageA, SignBroadcastPhase1)>),
+    M2(Vec<(GammaI, WI)>),
+    M3(Vec<(DeltaI, TI, TIProof)>),
+    M4(Vec<SignDecommitPhase1>),
+    M5(Vec<(RDash, Vec<PDLwSlackProof>)>),
+    M6(Vec<(SI, HEGProof)>),
+    Blame5(Phase5Reveal),
+    Blame6(Phase6Reveal),
+}
+
+/// Proceeds every instance of the batch with its input, merging their outgoing messages
+fn proceed_instances<R, I, N, T, F>(
+    rounds: Vec<R>,
+    inputs: Vec<I>,
+    mut proceed: F,
+) -> Result<(Vec<N>, Vec<Msg<Vec<T>>>)>
+where
+    F: FnMut(R, I, &mut Vec<Msg<T>>) -> Result<N, ProceedError>,
+{
+    let mut next = Vec::with_capacity(rounds.len());
+    let mut msgs = vec![];
+    for (round, input) in rounds.into_iter().zip(inputs) {
+        let mut out = vec![];
+        next.push(proceed(round, input, &mut out).map_err(Error::ProceedRound)?);
+        merge(&mut msgs, out);
+    }
+    Ok((next, msgs))
+}
+
+/// Appends messages of one instance to the batch messages going to the same receivers
+fn merge<T>(batch: &mut Vec<Msg<Vec<T>>>, msgs: Vec<Msg<T>>) {
+    for msg in msgs {
+        match batch.iter_mut().find(|m| m.receiver == msg.receiver) {
+            Some(m) => m.body.push(msg.body),
+            None => batch.push(Msg {
+                sender: msg.sender,
+                receiver: msg.receiver,
+                body: vec![msg.body],
+            }),
+        }
+    }
+}
+
+/// Messages of one instance in round 5, either to round 6 or to the blame round
+#[derive(Default)]
+struct Round5Output {
+    next: Vec<Msg<(SI, HEGProof)>>,
+    reveal: Vec<Msg<Phase5Reveal>>,
+}
+
+impl Push<Msg<(SI, HEGProof)>> for Round5Output {
+    fn push(&mut self, m: Msg<(SI, HEGProof)>) {
+        self.next.push(m)
+    }
+}
+
+impl Push<Msg<Phase5Reveal>> for Round5Output {
+    fn push(&mut self, m: Msg<Phase5Reveal>) {
+        self.reveal.push(m)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use round_based::dev::Simulation;
+
+    use super::*;
+    use crate::gg20::state_machine::keygen::test::simulate_keygen;
+    use crate::gg20::state_machine::sign::test::{session_id, simulate_signing};
+
+    fn simulate_batch_offline_stage(
+        local_keys: Vec<LocalKey<Secp256k1>>,
+        s_l: &[u16],
+        count: u16,
+    ) -> Vec<Vec<CompletedOfflineStage>> {
+        let mut simulation = Simulation::new();
+
+        for (i, &keygen_i) in (1..).zip(s_l) {
+            simulation.add_party(
+                BatchOfflineStage::new(
+                    i,
+                    s_l.to_vec(),
+                    local_keys[usize::from(keygen_i - 1)].clone(),
+                    session_id(s_l),
+                    count,
+                )
+                .unwrap(),
+            );
+        }
+
+        simulation.run().unwrap()
+    }
+
+    #[test]
+    fn simulate_batch_signing_t1_n3_s2() {
+        let local_keys = simulate_keygen(1, 3);
+        let batches = simulate_batch_offline_stage(local_keys, &[1, 3], 3);
+        assert!(batches.iter().all(|batch| batch.len() == 3));
+
+        let first = serde_json::to_string(&batches[0][0]).unwrap();
+        assert_ne!(first, serde_json::to_string(&batches[0][1]).unwrap());
+        for k in 0..3 {
+            let offline = batches.iter().map(|batch| batch[k].clone()).collect();
+            simulate_signing(offline, b"KeyPuzzle");
+        }
+    }
+
+    #[test]
+    fn simulate_batch_signing_t2_n3_s3() {
+        let local_keys = simulate_keygen(2, 3);
+        let batches = simulate_batch_offline_stage(local_keys, &[1, 2, 3], 2);
+        for k in 0..2 {
+            let offline = batches.iter().map(|batch| batch[k].clone()).collect();
+            simulate_signing(offline, b"KeyPuzzle");
+        }
+    }
+
+    #[test]
+    fn rejects_empty_batch() {
+        let local_keys = simulate_keygen(1, 2);
+        let result =
+            BatchOfflineStage::new(1, vec![1, 2], local_keys[0].clone(), session_id(&[1, 2]), 0);
+        assert!(matches!(result, Err(Error::EmptyBatch)));
+    }
+
+    #[test]
+    fn blames_party_sending_short_batch() {
+        let local_keys = simulate_keygen(1, 2);
+        let mut stage =
+            BatchOfflineStage::new(1, vec![1, 2], local_keys[0].clone(), session_id(&[1, 2]), 2)
+                .unwrap();
+        let mut other =
+            BatchOfflineStage::new(2, vec![1, 2], local_keys[1].clone(), session_id(&[1, 2]), 1)
+                .unwrap();
+        other.proceed().unwrap();
+        let msg = other.message_queue().remove(0);
+
+        let err = stage.handle_incoming(msg).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::MalformedBatch {
+                    sender: 2,
+                    msg_round: 1
+                }
+            ),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.bad_actors(), vec![2]);
+    }
+}
@@ -17,6 +17,9 @@ pub struct OfflineStageProgress {
     round3_msgs: ReceivedMessages,
     round4_msgs: ReceivedMessages,
     round5_msgs: ReceivedMessages,
+    round6_msgs: ReceivedMessages,
+    blame5_msgs: ReceivedMessages,
+    blame6_msgs: ReceivedMessages,
 
     msgs_queue: OutgoingMessages,
 }
@@ -32,6 +35,8 @@ impl From<&super::OfflineStage> for OfflineStageProgress {
                 super::OfflineR::R4(_) => OfflineR::R4,
                 super::OfflineR::R5(_) => OfflineR::R5,
                 super::OfflineR::R6(_) => OfflineR::R6,
+                super::OfflineR::Blame5(_) => OfflineR::Blame5,
+                super::OfflineR::Blame6(_) => OfflineR::Blame6,
                 super::OfflineR::Finished(_) => OfflineR::Finished,
                 super::OfflineR::Gone => OfflineR::Gone,
             },
@@ -41,6 +46,9 @@ impl From<&super::OfflineStage> for OfflineStageProgress {
             round3_msgs: ReceivedMessages::from_broadcast(state.msgs3.as_ref()),
             round4_msgs: ReceivedMessages::from_broadcast(state.msgs4.as_ref()),
             round5_msgs: ReceivedMessages::from_broadcast(state.msgs5.as_ref()),
+            round6_msgs: ReceivedMessages::from_broadcast(state.msgs6.as_ref()),
+            blame5_msgs: ReceivedMessages::from_broadcast(state.msgs_blame5.as_ref()),
+            blame6_msgs: ReceivedMessages::from_broadcast(state.msgs_blame6.as_ref()),
 
             msgs_queue: OutgoingMessages {
                 len: state.msgs_queue.0.len(),
@@ -58,6 +66,8 @@ pub enum OfflineR {
     R4,
     R5,
     R6,
+    Blame5,
+    Blame6,
     Finished,
     Gone,
 }
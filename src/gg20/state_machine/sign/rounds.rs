@@ -8,7 +8,7 @@ use thiserror::Error;
 
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
 use curv::BigInt;
-use sha2::Sha256;
+use paillier::EncryptionKey;
 
 use round_based::containers::push::Push;
 use round_based::containers::{self, BroadcastMsgs, P2PMsgs, Store};
@@ -17,9 +17,12 @@ use round_based::Msg;
 use crate::gg20::mta::{MessageA, MessageB};
 
 use crate::gg20;
+use crate::gg20::blame::{
+    GlobalStatePhase5, GlobalStatePhase6, LocalStatePhase5, LocalStatePhase6,
+};
+use crate::gg20::sigma::{HomoELGamalProof, PedersenProof};
 use crate::gg20::zk_pdl_with_slack::PDLwSlackProof;
-use curv::cryptographic_primitives::proofs::sigma_correct_homomorphic_elgamal_enc::HomoELGamalProof;
-use curv::cryptographic_primitives::proofs::sigma_valid_pedersen::PedersenProof;
+use crate::utils::session_id::SessionId;
 use gg20::party_i::{
     LocalSignature, SignBroadcastPhase1, SignDecommitPhase1, SignKeys, SignatureRecid,
 };
@@ -40,13 +43,29 @@ pub struct DeltaI(Scalar<Secp256k1>);
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TI(pub Point<Secp256k1>);
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TIProof(pub PedersenProof<Secp256k1, Sha256>);
+pub struct TIProof(pub PedersenProof);
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RDash(Point<Secp256k1>);
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SI(pub Point<Secp256k1>);
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct HEGProof(pub HomoELGamalProof<Secp256k1, Sha256>);
+pub struct HEGProof(pub HomoELGamalProof);
+
+/// Secrets of MtA (with `b = gamma_i`) revealed once the check of round 5 failed
+///
+/// MtA messages are sent point-to-point, so every party also reveals the messages it has received.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Phase5Reveal {
+    local_state: LocalStatePhase5,
+    m_b_gamma_received: Vec<MessageB>,
+}
+
+/// Secrets of MtAwc (with `b = w_i`) revealed once the check of round 6 failed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Phase6Reveal {
+    local_state: LocalStatePhase6,
+    m_b_w_received: Vec<MessageB>,
+}
 
 pub struct Round0 {
     /// Index of this party
@@ -62,6 +81,9 @@ pub struct Round0 {
 
     /// Party local secret share
     pub local_key: LocalKey<Secp256k1>,
+
+    /// Identifier of the signing session, the same for all parties
+    pub sid: SessionId,
 }
 
 impl Round0 {
@@ -79,10 +101,15 @@ impl Round0 {
                 .map(|&i| usize::from(i) - 1)
                 .collect::<Vec<_>>(),
         );
-        let (bc1, decom1) = sign_keys.phase1_broadcast();
+        let (bc1, decom1) = sign_keys.phase1_broadcast(&self.sid);
 
         let party_ek = self.local_key.paillier_key_vec[usize::from(self.local_key.i - 1)].clone();
-        let m_a = MessageA::a(&sign_keys.k_i, &party_ek, &self.local_key.h1_h2_n_tilde_vec);
+        let m_a = MessageA::a(
+            &self.sid,
+            &sign_keys.k_i,
+            &party_ek,
+            &self.local_key.h1_h2_n_tilde_vec,
+        );
 
         output.push(Msg {
             sender: self.i,
@@ -92,6 +119,7 @@ impl Round0 {
 
         let round1 = Round1 {
             i: self.i,
+            sid: self.sid,
             s_l: self.s_l.clone(),
             local_key: self.local_key,
             m_a,
@@ -110,6 +138,7 @@ impl Round0 {
 
 pub struct Round1 {
     i: u16,
+    sid: SessionId,
     s_l: Vec<u16>,
     local_key: LocalKey<Secp256k1>,
     m_a: (MessageA, BigInt),
@@ -134,6 +163,8 @@ impl Round1 {
 
         let mut m_b_gamma_vec = Vec::new();
         let mut beta_vec = Vec::new();
+        let mut beta_randomness_vec = Vec::new();
+        let mut beta_tag_vec = Vec::new();
         let mut m_b_w_vec = Vec::new();
         let mut ni_vec = Vec::new();
 
@@ -148,7 +179,8 @@ impl Round1 {
         for j in 0..ttag - 1 {
             let ind = if j < i { j } else { j + 1 };
 
-            let (m_b_gamma, beta_gamma, _beta_randomness, _beta_tag) = MessageB::b(
+            let (m_b_gamma, beta_gamma, beta_randomness, beta_tag) = MessageB::b(
+                &self.sid,
                 &self.sign_keys.gamma_i,
                 &self.local_key.paillier_key_vec[l_s[ind]],
                 m_a_vec[ind].clone(),
@@ -162,6 +194,7 @@ impl Round1 {
             })?;
 
             let (m_b_w, beta_wi, _, _) = MessageB::b(
+                &self.sid,
                 &self.sign_keys.w_i,
                 &self.local_key.paillier_key_vec[l_s[ind]],
                 m_a_vec[ind].clone(),
@@ -176,6 +209,8 @@ impl Round1 {
 
             m_b_gamma_vec.push(m_b_gamma);
             beta_vec.push(beta_gamma);
+            beta_randomness_vec.push(beta_randomness);
+            beta_tag_vec.push(beta_tag);
             m_b_w_vec.push(m_b_w);
             ni_vec.push(beta_wi);
         }
@@ -191,8 +226,20 @@ impl Round1 {
             });
         }
 
+        // kept in case phase 5 blame is needed
+        let phase5_state = LocalStatePhase5 {
+            k: self.sign_keys.k_i.clone(),
+            k_randomness: self.m_a.1.clone(),
+            gamma: self.sign_keys.gamma_i.clone(),
+            beta_randomness: beta_randomness_vec,
+            beta_tag: beta_tag_vec,
+            encryption_key: self.local_key.paillier_key_vec[usize::from(self.local_key.i - 1)]
+                .clone(),
+        };
+
         Ok(Round2 {
             i: self.i,
+            sid: self.sid,
             s_l: self.s_l,
             local_key: self.local_key,
             sign_keys: self.sign_keys,
@@ -202,6 +249,7 @@ impl Round1 {
             bc_vec,
             m_a_vec,
             phase1_decom: self.phase1_decom,
+            phase5_state,
         })
     }
 
@@ -219,6 +267,7 @@ impl Round1 {
 
 pub struct Round2 {
     i: u16,
+    sid: SessionId,
     s_l: Vec<u16>,
     local_key: LocalKey<Secp256k1>,
     sign_keys: SignKeys,
@@ -228,6 +277,7 @@ pub struct Round2 {
     bc_vec: Vec<SignBroadcastPhase1>,
     m_a_vec: Vec<MessageA>,
     phase1_decom: SignDecommitPhase1,
+    phase5_state: LocalStatePhase5,
 }
 
 impl Round2 {
@@ -243,6 +293,7 @@ impl Round2 {
 
         let mut alpha_vec = Vec::new();
         let mut miu_vec = Vec::new();
+        let mut miu_bigint_vec = Vec::new();
 
         let ttag = self.s_l.len();
         let index = usize::from(self.i) - 1;
@@ -262,7 +313,11 @@ impl Round2 {
             let m_b = m_b_gamma_s[j].clone();
 
             let alpha_ij_gamma = m_b
-                .verify_proofs_get_alpha(&self.local_key.paillier_dk, &self.sign_keys.k_i)
+                .verify_proofs_get_alpha(
+                    &self.sid,
+                    &self.local_key.paillier_dk,
+                    &self.sign_keys.k_i,
+                )
                 .map_err(|e| {
                     Error::Round3(ErrorType {
                         error_type: e.to_string(),
@@ -271,23 +326,33 @@ impl Round2 {
                 })?;
             let m_b = m_b_w_s[j].clone();
             let alpha_ij_wi = m_b
-                .verify_proofs_get_alpha(&self.local_key.paillier_dk, &self.sign_keys.k_i)
+                .verify_proofs_get_alpha(
+                    &self.sid,
+                    &self.local_key.paillier_dk,
+                    &self.sign_keys.k_i,
+                )
                 .map_err(|e| {
                     Error::Round3(ErrorType {
                         error_type: e.to_string(),
                         bad_actors: vec![],
                     })
                 })?;
-            assert_eq!(m_b.b_proof.pk, g_w_vec[ind]); //TODO: return error
+            if m_b.b_proof.pk != g_w_vec[ind] {
+                return Err(Error::Round3(ErrorType {
+                    error_type: "MtAwc used wrong w_j".to_string(),
+                    bad_actors: vec![ind],
+                }));
+            }
 
             alpha_vec.push(alpha_ij_gamma.0);
             miu_vec.push(alpha_ij_wi.0);
+            miu_bigint_vec.push(alpha_ij_wi.1);
         }
 
         let delta_i = self.sign_keys.phase2_delta_i(&alpha_vec, &self.beta_vec);
 
         let sigma_i = self.sign_keys.phase2_sigma_i(&miu_vec, &self.ni_vec);
-        let (t_i, l_i, t_i_proof) = SignKeys::phase3_compute_t_i(&sigma_i);
+        let (t_i, l_i, t_i_proof) = SignKeys::phase3_compute_t_i(&self.sid, &sigma_i);
         output.push(Msg {
             sender: self.i,
             receiver: None,
@@ -300,11 +365,14 @@ impl Round2 {
 
         Ok(Round3 {
             i: self.i,
+            sid: self.sid,
             s_l: self.s_l,
             local_key: self.local_key,
             sign_keys: self.sign_keys,
             m_a: self.m_a,
             mb_gamma_s: m_b_gamma_s,
+            mb_w_s: m_b_w_s,
+            miu_bigint_vec,
             bc_vec: self.bc_vec,
             m_a_vec: self.m_a_vec,
             delta_i,
@@ -313,6 +381,7 @@ impl Round2 {
             sigma_i,
             t_i_proof,
             phase1_decom: self.phase1_decom,
+            phase5_state: self.phase5_state,
         })
     }
 
@@ -327,20 +396,24 @@ impl Round2 {
 
 pub struct Round3 {
     i: u16,
+    sid: SessionId,
     s_l: Vec<u16>,
     local_key: LocalKey<Secp256k1>,
     sign_keys: SignKeys,
     m_a: (MessageA, BigInt),
     mb_gamma_s: Vec<MessageB>,
+    mb_w_s: Vec<MessageB>,
+    miu_bigint_vec: Vec<BigInt>,
     bc_vec: Vec<SignBroadcastPhase1>,
     m_a_vec: Vec<MessageA>,
     delta_i: Scalar<Secp256k1>,
     t_i: Point<Secp256k1>,
     l_i: Scalar<Secp256k1>,
     sigma_i: Scalar<Secp256k1>,
-    t_i_proof: PedersenProof<Secp256k1, Sha256>,
+    t_i_proof: PedersenProof,
 
     phase1_decom: SignDecommitPhase1,
+    phase5_state: LocalStatePhase5,
 }
 
 impl Round3 {
@@ -369,7 +442,7 @@ impl Round3 {
         let delta_inv = SignKeys::phase3_reconstruct_delta(&delta_vec);
         let ttag = self.s_l.len();
         for proof in t_proof_vec.iter().take(ttag) {
-            PedersenProof::verify(proof).map_err(|e| {
+            proof.verify(&self.sid).map_err(|e| {
                 Error::Round3(ErrorType {
                     error_type: e.to_string(),
                     bad_actors: vec![],
@@ -385,17 +458,22 @@ impl Round3 {
 
         Ok(Round4 {
             i: self.i,
+            sid: self.sid,
             s_l: self.s_l,
             local_key: self.local_key,
             sign_keys: self.sign_keys,
             m_a: self.m_a,
             mb_gamma_s: self.mb_gamma_s,
+            mb_w_s: self.mb_w_s,
+            miu_bigint_vec: self.miu_bigint_vec,
             bc_vec: self.bc_vec,
             m_a_vec: self.m_a_vec,
             t_i: self.t_i,
             l_i: self.l_i,
             sigma_i: self.sigma_i,
             phase1_decom: self.phase1_decom,
+            phase5_state: self.phase5_state,
+            delta_vec,
             delta_inv,
             t_vec,
         })
@@ -412,19 +490,24 @@ impl Round3 {
 
 pub struct Round4 {
     i: u16,
+    sid: SessionId,
     s_l: Vec<u16>,
     local_key: LocalKey<Secp256k1>,
     sign_keys: SignKeys,
     m_a: (MessageA, BigInt),
     mb_gamma_s: Vec<MessageB>,
+    mb_w_s: Vec<MessageB>,
+    miu_bigint_vec: Vec<BigInt>,
     bc_vec: Vec<SignBroadcastPhase1>,
     m_a_vec: Vec<MessageA>,
     t_i: Point<Secp256k1>,
     l_i: Scalar<Secp256k1>,
     sigma_i: Scalar<Secp256k1>,
+    delta_vec: Vec<Scalar<Secp256k1>>,
     delta_inv: Scalar<Secp256k1>,
     t_vec: Vec<Point<Secp256k1>>,
     phase1_decom: SignDecommitPhase1,
+    phase5_state: LocalStatePhase5,
 }
 
 impl Round4 {
@@ -437,10 +520,15 @@ impl Round4 {
         O: Push<Msg<(RDash, Vec<PDLwSlackProof>)>>,
     {
         let decom_vec: Vec<_> = decommit_round1.into_vec_including_me(self.phase1_decom.clone());
+        let g_gamma_vec: Vec<_> = decom_vec
+            .iter()
+            .map(|decom| decom.g_gamma_i.clone())
+            .collect();
 
         let ttag = self.s_l.len();
         let b_proof_vec: Vec<_> = (0..ttag - 1).map(|i| &self.mb_gamma_s[i].b_proof).collect();
         let R = SignKeys::phase4(
+            &self.sid,
             &self.delta_inv,
             &b_proof_vec[..],
             decom_vec,
@@ -463,6 +551,7 @@ impl Round4 {
         for j in 0..ttag - 1 {
             let ind = if j < index { j } else { j + 1 };
             let proof = LocalSignature::phase5_proof_pdl(
+                &self.sid,
                 &R_dash,
                 &R,
                 &self.m_a.0.c,
@@ -483,14 +572,21 @@ impl Round4 {
 
         Ok(Round5 {
             i: self.i,
+            sid: self.sid,
             s_l: self.s_l,
             local_key: self.local_key,
             sign_keys: self.sign_keys,
             t_vec: self.t_vec,
             m_a_vec: self.m_a_vec,
+            mb_gamma_s: self.mb_gamma_s,
+            mb_w_s: self.mb_w_s,
+            miu_bigint_vec: self.miu_bigint_vec,
             t_i: self.t_i,
             l_i: self.l_i,
             sigma_i: self.sigma_i,
+            delta_vec: self.delta_vec,
+            g_gamma_vec,
+            phase5_state: self.phase5_state,
             R,
             R_dash,
             phase5_proofs_vec,
@@ -508,14 +604,21 @@ impl Round4 {
 
 pub struct Round5 {
     i: u16,
+    sid: SessionId,
     s_l: Vec<u16>,
     local_key: LocalKey<Secp256k1>,
     sign_keys: SignKeys,
     t_vec: Vec<Point<Secp256k1>>,
     m_a_vec: Vec<MessageA>,
+    mb_gamma_s: Vec<MessageB>,
+    mb_w_s: Vec<MessageB>,
+    miu_bigint_vec: Vec<BigInt>,
     t_i: Point<Secp256k1>,
     l_i: Scalar<Secp256k1>,
     sigma_i: Scalar<Secp256k1>,
+    delta_vec: Vec<Scalar<Secp256k1>>,
+    g_gamma_vec: Vec<Point<Secp256k1>>,
+    phase5_state: LocalStatePhase5,
     R: Point<Secp256k1>,
     R_dash: Point<Secp256k1>,
     phase5_proofs_vec: Vec<PDLwSlackProof>,
@@ -526,9 +629,9 @@ impl Round5 {
         self,
         input: BroadcastMsgs<(RDash, Vec<PDLwSlackProof>)>,
         mut output: O,
-    ) -> Result<Round6>
+    ) -> Result<AfterRound5>
     where
-        O: Push<Msg<(SI, HEGProof)>>,
+        O: Push<Msg<(SI, HEGProof)>> + Push<Msg<Phase5Reveal>>,
     {
         let (r_dash_vec, pdl_proof_mat_inc_me): (Vec<_>, Vec<_>) = input
             .into_vec_including_me((RDash(self.R_dash), self.phase5_proofs_vec))
@@ -545,6 +648,7 @@ impl Round5 {
         let ttag = self.s_l.len();
         for i in 0..ttag {
             LocalSignature::phase5_verify_pdl(
+                &self.sid,
                 &pdl_proof_mat_inc_me[i],
                 &r_dash_vec[i],
                 &self.R,
@@ -556,14 +660,31 @@ impl Round5 {
             )
             .map_err(|e| Error::Round5(e))?;
         }
-        LocalSignature::phase5_check_R_dash_sum(&r_dash_vec).map_err(|e| {
-            Error::Round5(ErrorType {
-                error_type: e.to_string(),
-                bad_actors: vec![],
-            })
-        })?;
+        if LocalSignature::phase5_check_R_dash_sum(&r_dash_vec).is_err() {
+            // someone cheated in computing delta_i, this presignature is discarded so MtA secrets
+            // can be opened to find out who
+            let reveal = Phase5Reveal {
+                local_state: self.phase5_state,
+                m_b_gamma_received: self.mb_gamma_s,
+            };
+            output.push(Msg {
+                sender: self.i,
+                receiver: None,
+                body: reveal.clone(),
+            });
+            return Ok(AfterRound5::Blame(Round5Blame {
+                sid: self.sid,
+                s_l: self.s_l,
+                local_key: self.local_key,
+                delta_vec: self.delta_vec,
+                g_gamma_vec: self.g_gamma_vec,
+                m_a_vec: self.m_a_vec,
+                reveal,
+            }));
+        }
 
         let (S_i, homo_elgamal_proof) = LocalSignature::phase6_compute_S_i_and_proof_of_consistency(
+            &self.sid,
             &self.R,
             &self.t_i,
             &self.sigma_i,
@@ -576,19 +697,26 @@ impl Round5 {
             body: (SI(S_i.clone()), HEGProof(homo_elgamal_proof.clone())),
         });
 
-        Ok(Round6 {
+        Ok(AfterRound5::Round6(Round6 {
             S_i,
             homo_elgamal_proof,
+            sid: self.sid,
             s_l: self.s_l,
+            k_randomness: self.phase5_state.k_randomness,
+            m_a_vec: self.m_a_vec,
+            mb_w_s: self.mb_w_s,
+            miu_bigint_vec: self.miu_bigint_vec,
+            local_key: self.local_key,
             protocol_output: CompletedOfflineStage {
                 i: self.i,
-                local_key: self.local_key,
+                public_key: self.local_key.y_sum_s.clone(),
+                pk_vec: self.local_key.pk_vec.clone(),
                 sign_keys: self.sign_keys,
                 t_vec: self.t_vec,
                 R: self.R,
                 sigma_i: self.sigma_i,
             },
-        })
+        }))
     }
 
     pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<(RDash, Vec<PDLwSlackProof>)>> {
@@ -600,19 +728,36 @@ impl Round5 {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
+pub enum AfterRound5 {
+    Round6(Round6),
+    /// Check of round 5 failed, parties have to find out who's responsible
+    Blame(Round5Blame),
+}
+
 pub struct Round6 {
     S_i: Point<Secp256k1>,
-    homo_elgamal_proof: HomoELGamalProof<Secp256k1, Sha256>,
+    homo_elgamal_proof: HomoELGamalProof,
+    sid: SessionId,
     s_l: Vec<u16>,
+    k_randomness: BigInt,
+    m_a_vec: Vec<MessageA>,
+    mb_w_s: Vec<MessageB>,
+    miu_bigint_vec: Vec<BigInt>,
+    local_key: LocalKey<Secp256k1>,
     /// Round 6 guards protocol output until final checks are taken the place
     protocol_output: CompletedOfflineStage,
 }
 
 impl Round6 {
-    pub fn proceed(
+    pub fn proceed<O>(
         self,
         input: BroadcastMsgs<(SI, HEGProof)>,
-    ) -> Result<CompletedOfflineStage, Error> {
+        mut output: O,
+    ) -> Result<AfterRound6, Error>
+    where
+        O: Push<Msg<Phase6Reveal>>,
+    {
         let (S_i_vec, hegp_vec): (Vec<_>, Vec<_>) = input
             .into_vec_including_me((SI(self.S_i), HEGProof(self.homo_elgamal_proof)))
             .into_iter()
@@ -623,16 +768,57 @@ impl Round6 {
             .collect();
 
         LocalSignature::phase6_verify_proof(
+            &self.sid,
             &S_i_vec,
             &hegp_vec,
             &R_vec,
             &self.protocol_output.t_vec,
         )
         .map_err(Error::Round6VerifyProof)?;
-        LocalSignature::phase6_check_S_i_sum(&self.protocol_output.local_key.y_sum_s, &S_i_vec)
-            .map_err(Error::Round6CheckSig)?;
+        if LocalSignature::phase6_check_S_i_sum(&self.protocol_output.public_key, &S_i_vec).is_ok()
+        {
+            return Ok(AfterRound6::Completed(self.protocol_output));
+        }
 
-        Ok(self.protocol_output)
+        // someone cheated in computing sigma_i
+        let stage = self.protocol_output;
+        let miu_randomness = self
+            .mb_w_s
+            .iter()
+            .map(|m_b| {
+                GlobalStatePhase6::extract_paillier_randomness(&m_b.c, &self.local_key.paillier_dk)
+            })
+            .collect();
+        let proof_of_eq_dlog = GlobalStatePhase6::ecddh_proof(
+            &self.sid,
+            &stage.sigma_i,
+            &stage.R,
+            &S_i_vec[usize::from(stage.i - 1)],
+        );
+        let reveal = Phase6Reveal {
+            local_state: LocalStatePhase6 {
+                k: stage.sign_keys.k_i,
+                k_randomness: self.k_randomness,
+                miu: self.miu_bigint_vec,
+                miu_randomness,
+                proof_of_eq_dlog,
+            },
+            m_b_w_received: self.mb_w_s,
+        };
+        output.push(Msg {
+            sender: stage.i,
+            receiver: None,
+            body: reveal.clone(),
+        });
+        Ok(AfterRound6::Blame(Round6Blame {
+            sid: self.sid,
+            s_l: self.s_l,
+            local_key: self.local_key,
+            S_vec: S_i_vec,
+            R: stage.R,
+            m_a_vec: self.m_a_vec,
+            reveal,
+        }))
     }
 
     pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<(SI, HEGProof)>> {
@@ -644,19 +830,236 @@ impl Round6 {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
+pub enum AfterRound6 {
+    Completed(CompletedOfflineStage),
+    /// Check of round 6 failed, parties have to find out who's responsible
+    Blame(Round6Blame),
+}
+
+/// Phase 5 blame: opens MtA of every party and recomputes `delta_i`
+pub struct Round5Blame {
+    sid: SessionId,
+    s_l: Vec<u16>,
+    local_key: LocalKey<Secp256k1>,
+    delta_vec: Vec<Scalar<Secp256k1>>,
+    g_gamma_vec: Vec<Point<Secp256k1>>,
+    m_a_vec: Vec<MessageA>,
+    reveal: Phase5Reveal,
+}
+
+impl Round5Blame {
+    /// Always ends the protocol with an error, naming the culprits if they were found
+    pub fn proceed(self, input: BroadcastMsgs<Phase5Reveal>) -> Error {
+        let reveals = input.into_vec_including_me(self.reveal);
+        let others = self.s_l.len() - 1;
+        let malformed = bad_actors(&reveals, |reveal| {
+            reveal.local_state.beta_randomness.len() != others
+                || reveal.local_state.beta_tag.len() != others
+                || reveal.m_b_gamma_received.len() != others
+        });
+        if !malformed.is_empty() {
+            return Error::Round5Blame(ErrorType {
+                error_type: "malformed phase5 blame message".to_string(),
+                bad_actors: malformed,
+            });
+        }
+
+        let (local_state_vec, m_b_mat): (Vec<_>, Vec<_>) = reveals
+            .into_iter()
+            .map(|reveal| (reveal.local_state, reveal.m_b_gamma_received))
+            .unzip();
+        let global_state = GlobalStatePhase5::local_state_to_global_state(
+            &encryption_keys(&self.local_key, &self.s_l),
+            &self.delta_vec,
+            &self.g_gamma_vec,
+            &self.m_a_vec,
+            m_b_mat,
+            &local_state_vec,
+        );
+        match global_state.phase5_blame(&self.sid) {
+            Err(err) if !err.bad_actors.is_empty() => Error::Round5Blame(err),
+            _ => Error::Round5(ErrorType {
+                error_type: gg20::Error::Phase5BadSum.to_string(),
+                bad_actors: vec![],
+            }),
+        }
+    }
+
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<Phase5Reveal>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+}
+
+/// Phase 6 blame: opens MtAwc of every party and checks `S_i` against recomputed `g^sigma_i`
+pub struct Round6Blame {
+    sid: SessionId,
+    s_l: Vec<u16>,
+    local_key: LocalKey<Secp256k1>,
+    S_vec: Vec<Point<Secp256k1>>,
+    R: Point<Secp256k1>,
+    m_a_vec: Vec<MessageA>,
+    reveal: Phase6Reveal,
+}
+
+impl Round6Blame {
+    /// Always ends the protocol with an error, naming the culprits if they were found
+    pub fn proceed(self, input: BroadcastMsgs<Phase6Reveal>) -> Error {
+        let reveals = input.into_vec_including_me(self.reveal);
+        let others = self.s_l.len() - 1;
+        let malformed = bad_actors(&reveals, |reveal| {
+            reveal.local_state.miu.len() != others
+                || reveal.local_state.miu_randomness.len() != others
+                || reveal.m_b_w_received.len() != others
+        });
+        if !malformed.is_empty() {
+            return Error::Round6Blame(ErrorType {
+                error_type: "malformed phase6 blame message".to_string(),
+                bad_actors: malformed,
+            });
+        }
+
+        let l_s: Vec<_> = self.s_l.iter().map(|&i| usize::from(i) - 1).collect();
+        let g_w_vec = SignKeys::g_w_vec(&self.local_key.pk_vec, &l_s, &self.local_key.vss_scheme);
+        let (local_state_vec, m_b_mat): (Vec<_>, Vec<_>) = reveals
+            .into_iter()
+            .map(|reveal| (reveal.local_state, reveal.m_b_w_received))
+            .unzip();
+        let global_state = GlobalStatePhase6::local_state_to_global_state(
+            &encryption_keys(&self.local_key, &self.s_l),
+            &self.S_vec,
+            &g_w_vec,
+            &self.m_a_vec,
+            m_b_mat,
+            &local_state_vec,
+        );
+        match global_state.phase6_blame(&self.sid, &self.R) {
+            Err(err) if !err.bad_actors.is_empty() => Error::Round6Blame(err),
+            _ => Error::Round6CheckSig(gg20::Error::Phase6Error),
+        }
+    }
+
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<Phase6Reveal>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+}
+
+/// Paillier keys of signers, ordered as in `s_l`
+fn encryption_keys(local_key: &LocalKey<Secp256k1>, s_l: &[u16]) -> Vec<EncryptionKey> {
+    s_l.iter()
+        .map(|&i| local_key.paillier_key_vec[usize::from(i) - 1].clone())
+        .collect()
+}
+
+fn bad_actors<T>(msgs: &[T], is_bad: impl Fn(&T) -> bool) -> Vec<usize> {
+    msgs.iter()
+        .enumerate()
+        .filter(|(_, msg)| is_bad(msg))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(test)]
+impl Round2 {
+    /// Makes this party cheat in MtA: its `delta_i` won't match the values it sent
+    pub fn corrupt_delta_i(&mut self) {
+        self.beta_vec[0] = &self.beta_vec[0] + Scalar::from(1u64);
+    }
+
+    /// Makes this party cheat in MtAwc: its `sigma_i` won't match the values it sent
+    pub fn corrupt_sigma_i(&mut self) {
+        self.ni_vec[0] = &self.ni_vec[0] + Scalar::from(1u64);
+    }
+}
+
+/// Presignature of a party, completes into a signature of one message
+///
+/// Refers to the key it was produced from by the public key shares `pk_vec` rather than holding
+/// the [LocalKey], which is stored once next to all of its presignatures.
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "StoredOfflineStage")]
 pub struct CompletedOfflineStage {
     i: u16,
-    local_key: LocalKey<Secp256k1>,
+    public_key: Point<Secp256k1>,
+    pk_vec: Vec<Point<Secp256k1>>,
     sign_keys: SignKeys,
     t_vec: Vec<Point<Secp256k1>>,
     R: Point<Secp256k1>,
     sigma_i: Scalar<Secp256k1>,
 }
 
+/// Serialized [CompletedOfflineStage]
+///
+/// Presignatures written by previous versions carry a whole `local_key` instead of
+/// `public_key` and `pk_vec`, they're read by taking both from it.
+#[derive(Deserialize)]
+struct StoredOfflineStage {
+    i: u16,
+    public_key: Option<Point<Secp256k1>>,
+    pk_vec: Option<Vec<Point<Secp256k1>>>,
+    local_key: Option<LocalKey<Secp256k1>>,
+    sign_keys: SignKeys,
+    t_vec: Vec<Point<Secp256k1>>,
+    R: Point<Secp256k1>,
+    sigma_i: Scalar<Secp256k1>,
+}
+
+impl TryFrom<StoredOfflineStage> for CompletedOfflineStage {
+    type Error = &'static str;
+
+    fn try_from(stored: StoredOfflineStage) -> Result<Self, Self::Error> {
+        let (public_key, pk_vec) = match (stored.public_key, stored.pk_vec, stored.local_key) {
+            (Some(public_key), Some(pk_vec), _) => (public_key, pk_vec),
+            (_, _, Some(local_key)) => (local_key.y_sum_s.clone(), local_key.pk_vec.clone()),
+            _ => return Err("presignature doesn't refer to a key"),
+        };
+        Ok(Self {
+            i: stored.i,
+            public_key,
+            pk_vec,
+            sign_keys: stored.sign_keys,
+            t_vec: stored.t_vec,
+            R: stored.R,
+            sigma_i: stored.sigma_i,
+        })
+    }
+}
+
 impl CompletedOfflineStage {
     pub fn public_key(&self) -> &Point<Secp256k1> {
-        &self.local_key.y_sum_s
+        &self.public_key
+    }
+
+    /// Point `R` of the presignature, the same for all its signers
+    pub fn r(&self) -> &Point<Secp256k1> {
+        &self.R
+    }
+
+    /// Checks whether this presignature was produced from the given key shares
+    ///
+    /// Presignatures become stale once the key is [refreshed](crate::gg20::state_machine::refresh).
+    pub fn is_for_key(&self, local_key: &LocalKey<Secp256k1>) -> bool {
+        self.pk_vec == local_key.pk_vec
+    }
+
+    /// Adapts presignature to the child key `Y + tweak * G`
+    ///
+    /// Every signer must apply the same `tweak`, e.g. one [derived](crate::gg20::derivation)
+    /// along a BIP32 path. Partial signatures of the adapted presignatures combine into a
+    /// signature valid for the child key.
+    pub fn derive_child(mut self, tweak: &Scalar<Secp256k1>) -> Self {
+        // sum of sigma_i is k * x, shifting each by k_i * tweak gives k * (x + tweak)
+        self.sigma_i = &self.sigma_i + &self.sign_keys.k_i * tweak;
+        self.public_key = &self.public_key + Point::generator() * tweak;
+        self
     }
 }
 
@@ -678,7 +1081,7 @@ impl Round7 {
             message,
             &completed_offline_stage.R,
             &completed_offline_stage.sigma_i,
-            &completed_offline_stage.local_key.y_sum_s,
+            &completed_offline_stage.public_key,
         );
         let partial = PartialSignature(local_signature.s_i.clone());
         Ok((Self { local_signature }, partial))
@@ -708,10 +1111,32 @@ pub enum Error {
     Round6VerifyProof(ErrorType),
     #[error("round 6: check sig: {0:?}")]
     Round6CheckSig(crate::gg20::Error),
+    #[error("round 5: blame: {0:?}")]
+    Round5Blame(ErrorType),
+    #[error("round 6: blame: {0:?}")]
+    Round6Blame(ErrorType),
     #[error("round 7: {0:?}")]
     Round7(crate::gg20::Error),
 }
 
+impl Error {
+    /// Positions in `s_l` (starting from 0) of parties proven to misbehave
+    ///
+    /// Empty if the error doesn't identify anyone.
+    pub fn bad_actors(&self) -> &[usize] {
+        match self {
+            Error::Round1(err)
+            | Error::Round2Stage4(err)
+            | Error::Round3(err)
+            | Error::Round5(err)
+            | Error::Round6VerifyProof(err)
+            | Error::Round5Blame(err)
+            | Error::Round6Blame(err) => &err.bad_actors[..],
+            Error::Round2Stage3(_) | Error::Round6CheckSig(_) | Error::Round7(_) => &[],
+        }
+    }
+}
+
 trait IteratorExt: Iterator {
     fn unzip3<A, B, C>(self) -> (Vec<A>, Vec<B>, Vec<C>)
     where
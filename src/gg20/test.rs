@@ -6,15 +6,15 @@ use crate::gg20::blame::{
 use crate::gg20::mta::{MessageA, MessageB};
 use crate::gg20::party_i::SignatureRecid;
 use crate::gg20::party_i::{
-    KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys, LocalSignature, Parameters, SharedKeys,
-    SignKeys,
+    KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys, LocalSignature, Parameters, PreParams,
+    SharedKeys, SignKeys, DEFAULT_MODULUS_BITS,
 };
 use curv::arithmetic::traits::Converter;
 
+use crate::gg20::sigma::DLogProof;
 use crate::gg20::zk_pdl_with_slack::PDLwSlackProof;
+use crate::utils::session_id::SessionId;
 use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
-use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
-use curv::cryptographic_primitives::proofs::sigma_valid_pedersen::PedersenProof;
 use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
 use paillier::*;
@@ -152,12 +152,15 @@ fn keygen_t_n_parties(
         threshold: t,
         share_count: n,
     };
+    let sid = SessionId::new("ecdsa-keygen", "test", &(1..=n).collect::<Vec<_>>());
     let (t, n) = (t as usize, n as usize);
-    let party_keys_vec = (0..n).map(Keys::create).collect::<Vec<Keys>>();
+    let party_keys_vec = (0..n)
+        .map(|i| Keys::create(i, PreParams::generate(DEFAULT_MODULUS_BITS)))
+        .collect::<Vec<Keys>>();
 
     let (bc1_vec, decom_vec): (Vec<_>, Vec<_>) = party_keys_vec
         .iter()
-        .map(|k| k.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2())
+        .map(|k| k.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(&sid))
         .unzip();
 
     let e_vec = bc1_vec
@@ -185,7 +188,7 @@ fn keygen_t_n_parties(
         .iter()
         .map(|k| {
             k.phase1_verify_com_phase3_verify_correct_key_verify_dlog_phase2_distribute(
-                &params, &decom_vec, &bc1_vec,
+                &sid, &params, &decom_vec, &bc1_vec,
             )
             .expect("")
         })
@@ -214,6 +217,7 @@ fn keygen_t_n_parties(
     let mut dlog_proof_vec = Vec::new();
     for (i, key) in party_keys_vec.iter().enumerate() {
         let res = key.phase2_verify_vss_construct_keypair_phase3_pok_dlog(
+            &sid,
             &params,
             &y_vec,
             &party_shares[i],
@@ -233,6 +237,7 @@ fn keygen_t_n_parties(
         .collect::<Vec<Point<Secp256k1>>>();
 
     let dlog_verification = Keys::verify_dlog_proofs_check_against_vss(
+        &sid,
         &params,
         &dlog_proof_vec,
         &y_vec,
@@ -289,6 +294,11 @@ fn sign(
     assert!(ttag > t);
     let ttag = ttag as usize;
     assert_eq!(s.len(), ttag);
+    let sid = SessionId::new(
+        "ecdsa-sign",
+        "test",
+        &s.iter().map(|&i| i as u16 + 1).collect::<Vec<_>>(),
+    );
 
     // each party creates a signing key. This happens in parallel IRL. In this test we
     // create a vector of signing keys, one for each party.
@@ -298,8 +308,10 @@ fn sign(
         .collect::<Vec<SignKeys>>();
 
     // each party computes [Ci,Di] = com(g^gamma_i) and broadcast the commitments
-    let (bc1_vec, decommit_vec1): (Vec<_>, Vec<_>) =
-        sign_keys_vec.iter().map(|k| k.phase1_broadcast()).unzip();
+    let (bc1_vec, decommit_vec1): (Vec<_>, Vec<_>) = sign_keys_vec
+        .iter()
+        .map(|k| k.phase1_broadcast(&sid))
+        .unzip();
 
     // each signer's dlog statement. in reality, parties prove statements
     // using only other parties' h1,h2,N_tilde. here we also use own parameters for simplicity
@@ -314,7 +326,14 @@ fn sign(
     let m_a_vec: Vec<_> = sign_keys_vec
         .iter()
         .enumerate()
-        .map(|(i, k)| MessageA::a(&k.k_i, &party_keys_vec[s[i]].ek, &signers_dlog_statements))
+        .map(|(i, k)| {
+            MessageA::a(
+                &sid,
+                &k.k_i,
+                &party_keys_vec[s[i]].ek,
+                &signers_dlog_statements,
+            )
+        })
         .collect();
 
     // #each party i sends responses to m_a_vec she received (one response with input gamma_i and one with w_i)
@@ -340,6 +359,7 @@ fn sign(
         for j in 0..ttag - 1 {
             let ind = if j < i { j } else { j + 1 };
             let (m_b_gamma, beta_gamma, beta_randomness, beta_tag) = MessageB::b(
+                &sid,
                 &sign_keys_vec[ind].gamma_i,
                 &ek_vec[s[i]],
                 m_a_vec[i].0.clone(),
@@ -347,6 +367,7 @@ fn sign(
             )
             .expect("Alice's range proofs in MtA failed");
             let (m_b_w, beta_wi, _, _) = MessageB::b(
+                &sid,
                 &sign_keys_vec[ind].w_i,
                 &ek_vec[s[i]],
                 m_a_vec[i].0.clone(),
@@ -393,11 +414,11 @@ fn sign(
 
             // TODO: identify these errors
             let alpha_ij_gamma = m_b
-                .verify_proofs_get_alpha(&party_keys_vec[s[i]].dk, &sign_keys_vec[i].k_i)
+                .verify_proofs_get_alpha(&sid, &party_keys_vec[s[i]].dk, &sign_keys_vec[i].k_i)
                 .expect("wrong dlog or m_b");
             let m_b = m_b_w_vec_i[j].clone();
             let alpha_ij_wi = m_b
-                .verify_proofs_get_alpha(&party_keys_vec[s[i]].dk, &sign_keys_vec[i].k_i)
+                .verify_proofs_get_alpha(&sid, &party_keys_vec[s[i]].dk, &sign_keys_vec[i].k_i)
                 .expect("wrong dlog or m_b");
 
             // since we actually run two MtAwc each party needs to make sure that the values B are the same as the public values
@@ -460,7 +481,7 @@ fn sign(
     let mut l_vec = Vec::new();
     let mut T_proof_vec = Vec::new();
     for i in 0..ttag {
-        let (T_i, l_i, T_proof_i) = SignKeys::phase3_compute_t_i(&sigma_vec[i]);
+        let (T_i, l_i, T_proof_i) = SignKeys::phase3_compute_t_i(&sid, &sigma_vec[i]);
         T_vec.push(T_i);
         l_vec.push(l_i);
         T_proof_vec.push(T_proof_i);
@@ -468,7 +489,7 @@ fn sign(
     // verify T_proof_vec
     for i in 0..ttag {
         assert_eq!(T_vec[i], T_proof_vec[i].com.clone());
-        PedersenProof::verify(&T_proof_vec[i]).expect("error T proof");
+        T_proof_vec[i].verify(&sid).expect("error T proof");
     }
     // de-commit to g^gamma_i from phase1, test comm correctness, and that it is the same value used in MtA.
     // Return R
@@ -479,9 +500,16 @@ fn sign(
             let m_b_gamma_vec = &m_b_gamma_vec_all[i];
             let b_proof_vec = (0..ttag - 1)
                 .map(|j| &m_b_gamma_vec[j].b_proof)
-                .collect::<Vec<&DLogProof<Secp256k1, Sha256>>>();
-            SignKeys::phase4(&delta_inv, &b_proof_vec, decommit_vec1.clone(), &bc1_vec, i)
-                .expect("") //TODO: propagate the error
+                .collect::<Vec<&DLogProof>>();
+            SignKeys::phase4(
+                &sid,
+                &delta_inv,
+                &b_proof_vec,
+                decommit_vec1.clone(),
+                &bc1_vec,
+                i,
+            )
+            .expect("") //TODO: propagate the error
         })
         .collect::<Vec<Point<Secp256k1>>>();
 
@@ -497,6 +525,7 @@ fn sign(
         for j in 0..ttag - 1 {
             let ind = if j < i { j } else { j + 1 };
             let proof = LocalSignature::phase5_proof_pdl(
+                &sid,
                 &R_dash_vec[i],
                 &R_vec[i],
                 &m_a_vec[i].0.c,
@@ -512,6 +541,7 @@ fn sign(
 
     for i in 0..ttag {
         let phase5_verify_zk = LocalSignature::phase5_verify_pdl(
+            &sid,
             &phase5_proofs_vec[i],
             &R_dash_vec[i],
             &R_vec[i],
@@ -576,13 +606,14 @@ fn sign(
             m_b_gamma_vec_all,
             &local_state_vec[..],
         );
-        global_state.phase5_blame()?;
+        global_state.phase5_blame(&sid)?;
     }
 
     let mut S_vec = Vec::new();
     let mut homo_elgamal_proof_vec = Vec::new();
     for i in 0..ttag {
         let (S_i, homo_elgamal_proof) = LocalSignature::phase6_compute_S_i_and_proof_of_consistency(
+            &sid,
             &R_vec[i],
             &T_vec[i],
             &sigma_vec[i],
@@ -592,7 +623,7 @@ fn sign(
         homo_elgamal_proof_vec.push(homo_elgamal_proof);
     }
 
-    LocalSignature::phase6_verify_proof(&S_vec, &homo_elgamal_proof_vec, &R_vec, &T_vec)?;
+    LocalSignature::phase6_verify_proof(&sid, &S_vec, &homo_elgamal_proof_vec, &R_vec, &T_vec)?;
 
     let phase6_check = LocalSignature::phase6_check_S_i_sum(&y, &S_vec);
     if phase6_check.is_err() {
@@ -611,7 +642,7 @@ fn sign(
                 );
                 miu_randomness_vec.push(rand);
             }
-            let proof = GlobalStatePhase6::ecddh_proof(&sigma_vec[i], &R_vec[i], &S_vec[i]);
+            let proof = GlobalStatePhase6::ecddh_proof(&sid, &sigma_vec[i], &R_vec[i], &S_vec[i]);
             let local_state = LocalStatePhase6 {
                 k: sign_keys_vec[i].k_i.clone(),
                 k_randomness: m_a_vec[i].1.clone(),
@@ -640,7 +671,7 @@ fn sign(
             m_b_w_vec_all,
             &local_state_vec[..],
         );
-        global_state.phase6_blame(&R_vec[0])?;
+        global_state.phase6_blame(&sid, &R_vec[0])?;
     }
 
     let message: [u8; 4] = [79, 77, 69, 82];
@@ -736,8 +767,10 @@ fn check_sig(r: &Scalar<Secp256k1>, s: &Scalar<Secp256k1>, msg: &BigInt, pk: &Po
 fn test_serialize_deserialize() {
     use serde_json;
 
-    let k = Keys::create(0);
-    let (commit, decommit) = k.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2();
+    let k = Keys::create(0, PreParams::generate(DEFAULT_MODULUS_BITS));
+    let sid = SessionId::new("ecdsa-keygen", "test", &[1]);
+    let (commit, decommit) =
+        k.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(&sid);
 
     let encoded = serde_json::to_string(&commit).unwrap();
     let decoded: KeyGenBroadcastMessage1 = serde_json::from_str(&encoded).unwrap();
@@ -748,16 +781,28 @@ fn test_serialize_deserialize() {
     assert_eq!(decommit.y_i, decoded.y_i);
 }
 #[test]
+fn test_honest_key_and_h1h2_proofs_verify() {
+    let k = Keys::create(0, PreParams::generate(DEFAULT_MODULUS_BITS));
+    let sid = SessionId::new("ecdsa-keygen", "test", &[1]);
+    let other = SessionId::new("ecdsa-keygen", "other", &[1]);
+    let (commit, _) = k.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(&sid);
+    assert!(commit.verify_correct_key_and_h1h2(&sid));
+    assert!(!commit.verify_correct_key_and_h1h2(&other));
+}
+#[test]
 fn test_small_paillier() {
     // parties shouldn't be able to choose small Paillier modulus
-    let mut k = Keys::create(0);
+    let mut k = Keys::create(0, PreParams::generate(DEFAULT_MODULUS_BITS));
     // creating 2046-bit Paillier
     let (ek, dk) = Paillier::keypair_with_modulus_size(2046).keys();
     k.dk = dk;
     k.ek = ek;
-    let (commit, decommit) = k.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2();
+    let sid = SessionId::new("ecdsa-keygen", "test", &[1]);
+    let (commit, decommit) =
+        k.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(&sid);
     assert!(k
         .phase1_verify_com_phase3_verify_correct_key_verify_dlog_phase2_distribute(
+            &sid,
             &Parameters {
                 threshold: 0,
                 share_count: 1
@@ -767,3 +812,46 @@ fn test_small_paillier() {
         )
         .is_err());
 }
+#[test]
+fn test_copied_paillier_modulus() {
+    // a party can't reuse Paillier key of another party along with its proofs
+    let k1 = Keys::create(1, PreParams::generate(DEFAULT_MODULUS_BITS));
+    let k2 = Keys::create(2, PreParams::generate(DEFAULT_MODULUS_BITS));
+    let sid = SessionId::new("ecdsa-keygen", "test", &[1, 2]);
+    let (commit1, decommit1) =
+        k1.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(&sid);
+    let (mut commit2, decommit2) =
+        k2.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(&sid);
+    commit2.e = commit1.e.clone();
+    commit2.correct_key_proof = commit1.correct_key_proof.clone();
+    commit2.modulus_proof = commit1.modulus_proof.clone();
+    assert!(commit2.verify_correct_key_and_h1h2(&sid));
+
+    let err = k1
+        .phase1_verify_com_phase3_verify_correct_key_verify_dlog_phase2_distribute(
+            &sid,
+            &Parameters {
+                threshold: 1,
+                share_count: 2,
+            },
+            &[decommit1, decommit2],
+            &[commit1, commit2],
+        )
+        .unwrap_err();
+    assert_eq!(err.bad_actors, vec![0, 1]);
+}
+#[test]
+fn test_debug_hides_secrets() {
+    let k = Keys::create(0, PreParams::generate(DEFAULT_MODULUS_BITS));
+    let printed = format!("{:?}", k);
+    assert!(printed.contains("[redacted]"));
+    assert!(!printed.contains(&k.dk.p.to_string()));
+    assert!(!printed.contains(&k.xhi.to_string()));
+
+    let shared_keys = SharedKeys {
+        y: Point::generator() * &k.u_i,
+        x_i: k.u_i.clone(),
+    };
+    let printed = format!("{:?}", shared_keys);
+    assert!(!printed.contains(&format!("{:?}", k.u_i)));
+}
@@ -0,0 +1,493 @@
+#![allow(non_snake_case)]
+
+//! Proofs that a Paillier modulus is well formed, as given in https://eprint.iacr.org/2021/060.pdf
+//!
+//! [PaillierBlumModulusProof] (Π^mod) shows that `N = pq` with primes `p = q = 3 mod 4` and
+//! `gcd(N, φ(N)) = 1`. [NoSmallFactorProof] (Π^fac) shows that both factors of `N` are about
+//! `sqrt(N)`, it is made against ring-Pedersen parameters (`N_tilde`, `h1`, `h2`) of the verifier.
+//! [RingPedersenProof] (Π^prm) shows that those parameters are well formed, i.e. `h2` is a power
+//! of `h1`. [CorrectKeyProof] is the proof of zk-paillier's `NiCorrectKeyProof`, that `N` is
+//! coprime with `φ(N)`.
+//!
+//! Challenges of all proofs are bound to the session the proof is made in.
+
+use curv::arithmetic::traits::*;
+use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
+use curv::BigInt;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use zk_paillier::zkproofs::DLogStatement;
+
+use crate::utils::session_id::SessionId;
+
+/// Number of challenges of Π^mod, soundness error is `2^-M`
+const M: usize = 80;
+/// Bit length of the secp256k1 group order
+const L: usize = 256;
+/// Slack of the ranges of Π^fac
+const EPSILON: usize = 512;
+/// Number of challenges of the correct key proof, soundness error is `ALPHA^-CORRECT_KEY_M`
+const CORRECT_KEY_M: usize = 11;
+/// Correct key has no prime factors below `ALPHA`
+const ALPHA: u32 = 6370;
+
+#[derive(Error, Debug)]
+pub enum ZkModulusError {
+    #[error("paillier-blum modulus verification failed")]
+    PaillierBlum,
+    #[error("no small factor verification failed")]
+    NoSmallFactor,
+    #[error("ring-pedersen parameters of the verifier are not invertible")]
+    InvalidSetup,
+    #[error("ring-pedersen parameters verification failed")]
+    RingPedersen,
+    #[error("correct key verification failed")]
+    CorrectKey,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PaillierBlumModulusProof {
+    w: BigInt,
+    responses: Vec<BlumResponse>,
+}
+
+/// Fourth root `x` of `(-1)^a * w^b * y` and `N`-th root `z` of `y` for challenge `y`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct BlumResponse {
+    x: BigInt,
+    a: bool,
+    b: bool,
+    z: BigInt,
+}
+
+impl PaillierBlumModulusProof {
+    /// Proves that `N = pq` is a Paillier-Blum integer, the proof doesn't verify unless
+    /// `p = q = 3 mod 4`
+    pub fn prove(sid: &SessionId, N: &BigInt, p: &BigInt, q: &BigInt) -> Self {
+        let one = BigInt::one();
+        let phi = (p - &one) * (q - &one);
+        // w is a quadratic residue modulo exactly one of p and q, i.e. its Jacobi symbol is -1
+        let w = loop {
+            let w = BigInt::sample_below(N);
+            if w.gcd(N) == one && is_residue(&w, p) != is_residue(&w, q) {
+                break w;
+            }
+        };
+        let N_inv = BigInt::mod_inv(N, &phi).unwrap_or_else(BigInt::zero);
+        let (N_inv_p, N_inv_q) = (N_inv.mod_floor(&(p - &one)), N_inv.mod_floor(&(q - &one)));
+
+        let responses = blum_challenges(sid, N, &w)
+            .into_iter()
+            .map(|y| {
+                let z = crt(
+                    &BigInt::mod_pow(&y, &N_inv_p, p),
+                    &BigInt::mod_pow(&y, &N_inv_q, q),
+                    p,
+                    q,
+                );
+                // -1 is a non-residue modulo both primes and w modulo one of them, so exactly
+                // one of y, -y, wy, -wy is a residue modulo both
+                let (a, b, y) = [(false, false), (true, false), (false, true), (true, true)]
+                    .iter()
+                    .map(|&(a, b)| (a, b, blum_adjust(N, &w, &y, a, b)))
+                    .find(|(_, _, y)| is_residue(y, p) && is_residue(y, q))
+                    .unwrap_or_else(|| (false, false, y.clone()));
+                let x = fourth_root(&y, p, q);
+                BlumResponse { x, a, b, z }
+            })
+            .collect();
+
+        PaillierBlumModulusProof { w, responses }
+    }
+
+    pub fn verify(&self, sid: &SessionId, N: &BigInt) -> Result<(), ZkModulusError> {
+        let one = BigInt::one();
+        // prime N passes every check below, Fermat test rejects it
+        if !N.test_bit(0)
+            || N <= &one
+            || BigInt::mod_pow(&BigInt::from(2), &(N - &one), N) == one
+            || self.responses.len() != M
+        {
+            return Err(ZkModulusError::PaillierBlum);
+        }
+        let four = BigInt::from(4);
+        let valid = blum_challenges(sid, N, &self.w)
+            .iter()
+            .zip(&self.responses)
+            .all(|(y, r)| {
+                BigInt::mod_pow(&r.z, N, N) == *y
+                    && BigInt::mod_pow(&r.x, &four, N) == blum_adjust(N, &self.w, y, r.a, r.b)
+            });
+        if valid {
+            Ok(())
+        } else {
+            Err(ZkModulusError::PaillierBlum)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NoSmallFactorProof {
+    P: BigInt,
+    Q: BigInt,
+    A: BigInt,
+    B: BigInt,
+    T: BigInt,
+    sigma: BigInt,
+    z1: BigInt,
+    z2: BigInt,
+    w1: BigInt,
+    w2: BigInt,
+    v: BigInt,
+}
+
+impl NoSmallFactorProof {
+    /// Proves to the owner of `setup` that factors `p` and `q` of `N0` are not small
+    pub fn prove(
+        sid: &SessionId,
+        N0: &BigInt,
+        p: &BigInt,
+        q: &BigInt,
+        setup: &DLogStatement,
+    ) -> Result<Self, ZkModulusError> {
+        let (N_hat, s, t) = (&setup.N, &setup.g, &setup.ni);
+        let one = BigInt::one();
+        if s.gcd(N_hat) != one || t.gcd(N_hat) != one {
+            return Err(ZkModulusError::InvalidSetup);
+        }
+        let two_l = pow2(L);
+        let two_l_eps = pow2(L + EPSILON);
+        let sqrt_N0 = sqrt_bound(N0);
+
+        let alpha = sample_signed(&(&two_l_eps * &sqrt_N0));
+        let beta = sample_signed(&(&two_l_eps * &sqrt_N0));
+        let mu = sample_signed(&(&two_l * N_hat));
+        let nu = sample_signed(&(&two_l * N_hat));
+        let sigma = sample_signed(&(&two_l * N0 * N_hat));
+        let r = sample_signed(&(&two_l_eps * N0 * N_hat));
+        let x = sample_signed(&(&two_l_eps * N_hat));
+        let y = sample_signed(&(&two_l_eps * N_hat));
+
+        let commit = |g: &BigInt, a: &BigInt, b: &BigInt| {
+            ring_pedersen(N_hat, g, t, a, b).ok_or(ZkModulusError::InvalidSetup)
+        };
+        let P = commit(s, p, &mu)?;
+        let Q = commit(s, q, &nu)?;
+        let A = commit(s, &alpha, &x)?;
+        let B = commit(s, &beta, &y)?;
+        let T = commit(&Q, &alpha, &r)?;
+
+        let e = fac_challenge(sid, N0, setup, &[&P, &Q, &A, &B, &T, &sigma]);
+        let sigma_hat = &sigma - &nu * p;
+        let z1 = &alpha + &e * p;
+        let z2 = &beta + &e * q;
+        let w1 = &x + &e * &mu;
+        let w2 = &y + &e * &nu;
+        let v = &r + &e * &sigma_hat;
+
+        Ok(NoSmallFactorProof {
+            P,
+            Q,
+            A,
+            B,
+            T,
+            sigma,
+            z1,
+            z2,
+            w1,
+            w2,
+            v,
+        })
+    }
+
+    /// Verifies the proof made against our own ring-Pedersen parameters `setup`
+    pub fn verify(
+        &self,
+        sid: &SessionId,
+        N0: &BigInt,
+        setup: &DLogStatement,
+    ) -> Result<(), ZkModulusError> {
+        let (N_hat, s, t) = (&setup.N, &setup.g, &setup.ni);
+        let bound = pow2(L + EPSILON) * sqrt_bound(N0);
+        let in_range = |z: &BigInt| z >= &-&bound && z <= &bound;
+        if !in_range(&self.z1) || !in_range(&self.z2) {
+            return Err(ZkModulusError::NoSmallFactor);
+        }
+
+        let e = fac_challenge(
+            sid,
+            N0,
+            setup,
+            &[&self.P, &self.Q, &self.A, &self.B, &self.T, &self.sigma],
+        );
+        let check = |g: &BigInt, a: &BigInt, b: &BigInt, base: &BigInt, com: &BigInt| {
+            let rhs = BigInt::mod_mul(base, &BigInt::mod_pow(com, &e, N_hat), N_hat);
+            ring_pedersen(N_hat, g, t, a, b) == Some(rhs)
+        };
+        let R = ring_pedersen(N_hat, s, t, N0, &self.sigma).ok_or(ZkModulusError::NoSmallFactor)?;
+        if check(s, &self.z1, &self.w1, &self.A, &self.P)
+            && check(s, &self.z2, &self.w2, &self.B, &self.Q)
+            && check(&self.Q, &self.z1, &self.v, &self.T, &R)
+        {
+            Ok(())
+        } else {
+            Err(ZkModulusError::NoSmallFactor)
+        }
+    }
+}
+
+/// Proof that `s = t^λ mod N` for ring-Pedersen parameters `N`, `t`, `s`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RingPedersenProof {
+    A: Vec<BigInt>,
+    z: Vec<BigInt>,
+}
+
+impl RingPedersenProof {
+    /// Proves that `setup.ni = setup.g^lambda mod setup.N`, `phi` is `φ(setup.N)`
+    pub fn prove(sid: &SessionId, setup: &DLogStatement, lambda: &BigInt, phi: &BigInt) -> Self {
+        let (N, t) = (&setup.N, &setup.g);
+        let a: Vec<BigInt> = (0..M).map(|_| BigInt::sample_below(phi)).collect();
+        let A: Vec<BigInt> = a.iter().map(|a| BigInt::mod_pow(t, a, N)).collect();
+        let z = prm_challenges(sid, setup, &A)
+            .into_iter()
+            .zip(&a)
+            .map(|(e, a)| {
+                if e {
+                    (a + lambda).mod_floor(phi)
+                } else {
+                    a.clone()
+                }
+            })
+            .collect();
+        RingPedersenProof { A, z }
+    }
+
+    pub fn verify(&self, sid: &SessionId, setup: &DLogStatement) -> Result<(), ZkModulusError> {
+        let (N, t, s) = (&setup.N, &setup.g, &setup.ni);
+        let one = BigInt::one();
+        if self.A.len() != M
+            || self.z.len() != M
+            || t.gcd(N) != one
+            || s.gcd(N) != one
+            || s.mod_floor(N) == one
+        {
+            return Err(ZkModulusError::RingPedersen);
+        }
+        let valid = prm_challenges(sid, setup, &self.A)
+            .into_iter()
+            .zip(self.A.iter().zip(&self.z))
+            .all(|(e, (A, z))| {
+                let rhs = if e {
+                    BigInt::mod_mul(A, s, N)
+                } else {
+                    A.mod_floor(N)
+                };
+                BigInt::mod_pow(t, z, N) == rhs
+            });
+        if valid {
+            Ok(())
+        } else {
+            Err(ZkModulusError::RingPedersen)
+        }
+    }
+}
+
+/// `N`-th roots of challenges derived from `N`, they exist for every challenge only if `N` is
+/// coprime with `φ(N)`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CorrectKeyProof {
+    sigma_vec: Vec<BigInt>,
+}
+
+impl CorrectKeyProof {
+    /// Proves that Paillier key `N = pq` is coprime with `φ(N)`
+    pub fn prove(sid: &SessionId, N: &BigInt, p: &BigInt, q: &BigInt) -> Self {
+        let one = BigInt::one();
+        let phi = (p - &one) * (q - &one);
+        let N_inv = BigInt::mod_inv(N, &phi).unwrap_or_else(BigInt::zero);
+        let sigma_vec = correct_key_challenges(sid, N)
+            .iter()
+            .map(|rho| BigInt::mod_pow(rho, &N_inv, N))
+            .collect();
+        CorrectKeyProof { sigma_vec }
+    }
+
+    pub fn verify(&self, sid: &SessionId, N: &BigInt) -> Result<(), ZkModulusError> {
+        let one = BigInt::one();
+        if N <= &one
+            || N.gcd(&small_primes_product()) != one
+            || self.sigma_vec.len() != CORRECT_KEY_M
+        {
+            return Err(ZkModulusError::CorrectKey);
+        }
+        let valid = correct_key_challenges(sid, N)
+            .iter()
+            .zip(&self.sigma_vec)
+            .all(|(rho, sigma)| BigInt::mod_pow(sigma, N, N) == *rho);
+        if valid {
+            Ok(())
+        } else {
+            Err(ZkModulusError::CorrectKey)
+        }
+    }
+}
+
+/// Euler's criterion, `p` is an odd prime
+fn is_residue(a: &BigInt, p: &BigInt) -> bool {
+    let e = (p - BigInt::one()).div_floor(&BigInt::from(2));
+    BigInt::mod_pow(a, &e, p) == BigInt::one()
+}
+
+/// `(-1)^a * w^b * y mod N`
+fn blum_adjust(N: &BigInt, w: &BigInt, y: &BigInt, a: bool, b: bool) -> BigInt {
+    let mut y = y.clone();
+    if a {
+        y = BigInt::mod_sub(&BigInt::zero(), &y, N);
+    }
+    if b {
+        y = BigInt::mod_mul(&y, w, N);
+    }
+    y
+}
+
+/// Fourth root of the quadratic residue `y` modulo `pq` for `p = q = 3 mod 4`
+fn fourth_root(y: &BigInt, p: &BigInt, q: &BigInt) -> BigInt {
+    // y^((p+1)/4) is a square root of y which is itself a residue, so raising it to the same
+    // power once again gives a fourth root
+    let root = |p: &BigInt| {
+        let e = (p + BigInt::one()).div_floor(&BigInt::from(4));
+        let e = BigInt::mod_mul(&e, &e, &(p - BigInt::one()));
+        BigInt::mod_pow(y, &e, p)
+    };
+    crt(&root(p), &root(q), p, q)
+}
+
+/// Number modulo `pq` equal to `x_p` modulo `p` and to `x_q` modulo `q`
+fn crt(x_p: &BigInt, x_q: &BigInt, p: &BigInt, q: &BigInt) -> BigInt {
+    let p_inv = BigInt::mod_inv(p, q).expect("p and q are distinct primes");
+    let h = BigInt::mod_mul(&BigInt::mod_sub(x_q, x_p, q), &p_inv, q);
+    x_p + p * h
+}
+
+fn blum_challenges(sid: &SessionId, N: &BigInt, w: &BigInt) -> Vec<BigInt> {
+    (0..M as u32)
+        .map(|i| {
+            let seed = Sha256::new()
+                .chain(sid)
+                .chain(b"paillier-blum")
+                .chain_bigint(N)
+                .chain_bigint(w)
+                .chain(i.to_be_bytes())
+                .finalize();
+            expand_below(seed.as_slice(), N)
+        })
+        .collect()
+}
+
+fn fac_challenge(
+    sid: &SessionId,
+    N0: &BigInt,
+    setup: &DLogStatement,
+    values: &[&BigInt],
+) -> BigInt {
+    values
+        .iter()
+        .fold(
+            Sha256::new()
+                .chain(sid)
+                .chain(b"no-small-factor")
+                .chain_bigint(N0)
+                .chain_bigint(&setup.N)
+                .chain_bigint(&setup.g)
+                .chain_bigint(&setup.ni),
+            |hasher, value| hasher.chain_bigint(value),
+        )
+        .result_bigint()
+}
+
+/// Challenge bits of Π^prm, one per commitment
+fn prm_challenges(sid: &SessionId, setup: &DLogStatement, A: &[BigInt]) -> Vec<bool> {
+    let hash = A
+        .iter()
+        .fold(
+            Sha256::new()
+                .chain(sid)
+                .chain(b"ring-pedersen")
+                .chain_bigint(&setup.N)
+                .chain_bigint(&setup.g)
+                .chain_bigint(&setup.ni),
+            |hasher, A| hasher.chain_bigint(A),
+        )
+        .finalize();
+    (0..M).map(|i| (hash[i / 8] >> (i % 8)) & 1 == 1).collect()
+}
+
+fn correct_key_challenges(sid: &SessionId, N: &BigInt) -> Vec<BigInt> {
+    (0..CORRECT_KEY_M as u32)
+        .map(|i| {
+            let seed = Sha256::new()
+                .chain(sid)
+                .chain(b"correct-key")
+                .chain_bigint(N)
+                .chain(i.to_be_bytes())
+                .finalize();
+            expand_below(seed.as_slice(), N)
+        })
+        .collect()
+}
+
+/// Product of the primes below [ALPHA]
+fn small_primes_product() -> BigInt {
+    (2..ALPHA)
+        .filter(|&k| (2..k).take_while(|d| d * d <= k).all(|d| k % d != 0))
+        .fold(BigInt::one(), |product, prime| {
+            product * BigInt::from(prime)
+        })
+}
+
+/// Expands `seed` into a number below `modulus`, with extra 256 bits to make the bias negligible
+fn expand_below(seed: &[u8], modulus: &BigInt) -> BigInt {
+    let blocks = modulus.bit_length() / 256 + 2;
+    let bytes: Vec<u8> = (0..blocks as u32)
+        .flat_map(|j| {
+            Sha256::new()
+                .chain(seed)
+                .chain(j.to_be_bytes())
+                .finalize()
+                .to_vec()
+        })
+        .collect();
+    BigInt::from_bytes(&bytes).mod_floor(modulus)
+}
+
+/// `g^a * h^b mod N` for possibly negative exponents, `None` if a base needs to be inverted but
+/// can't be
+fn ring_pedersen(N: &BigInt, g: &BigInt, h: &BigInt, a: &BigInt, b: &BigInt) -> Option<BigInt> {
+    let pow = |base: &BigInt, exp: &BigInt| {
+        if exp < &BigInt::zero() {
+            BigInt::mod_inv(base, N).map(|inv| BigInt::mod_pow(&inv, &-exp, N))
+        } else {
+            Some(BigInt::mod_pow(base, exp, N))
+        }
+    };
+    Some(BigInt::mod_mul(&pow(g, a)?, &pow(h, b)?, N))
+}
+
+fn pow2(bits: usize) -> BigInt {
+    BigInt::from(2).pow(bits as u32)
+}
+
+/// Power of two not less than `sqrt(n)`
+fn sqrt_bound(n: &BigInt) -> BigInt {
+    pow2((n.bit_length() + 1) / 2)
+}
+
+/// Uniform sample from `[-bound, bound)`
+fn sample_signed(bound: &BigInt) -> BigInt {
+    BigInt::sample_below(&(bound + bound)) - bound
+}
+
+#[cfg(test)]
+mod test;
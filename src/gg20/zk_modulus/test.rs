@@ -0,0 +1,100 @@
+#![allow(non_snake_case)]
+use crate::gg20::zk_modulus::*;
+use curv::BigInt;
+use paillier::traits::KeyGeneration;
+use paillier::Paillier;
+
+fn ring_pedersen_setup() -> DLogStatement {
+    ring_pedersen_setup_with_secret().0
+}
+
+/// Ring-Pedersen parameters together with `xhi` and `φ(N_tilde)`
+fn ring_pedersen_setup_with_secret() -> (DLogStatement, BigInt, BigInt) {
+    let (ek_tilde, dk_tilde) = Paillier::keypair_safe_primes_with_modulus_size(1024).keys();
+    let one = BigInt::one();
+    let phi = (&dk_tilde.p - &one) * (&dk_tilde.q - &one);
+    let r = BigInt::sample_below(&ek_tilde.n);
+    let h1 = BigInt::mod_mul(&r, &r, &ek_tilde.n);
+    let xhi = BigInt::sample_below(&phi);
+    let h2 = BigInt::mod_pow(&h1, &xhi, &ek_tilde.n);
+    let setup = DLogStatement {
+        N: ek_tilde.n,
+        g: h1,
+        ni: h2,
+    };
+    (setup, xhi, phi)
+}
+
+#[test]
+fn test_paillier_blum_modulus() {
+    let sid = SessionId::new("ecdsa-keygen", "room", &[1, 2, 3]);
+    let other = SessionId::new("ecdsa-keygen", "other room", &[1, 2, 3]);
+    let (ek, dk) = Paillier::keypair_safe_primes_with_modulus_size(1024).keys();
+
+    let proof = PaillierBlumModulusProof::prove(&sid, &ek.n, &dk.p, &dk.q);
+    assert!(proof.verify(&sid, &ek.n).is_ok());
+    assert!(proof.verify(&other, &ek.n).is_err());
+
+    let (another_ek, _) = Paillier::keypair_safe_primes_with_modulus_size(1024).keys();
+    assert!(proof.verify(&sid, &another_ek.n).is_err());
+}
+
+#[test]
+fn prime_modulus_is_rejected() {
+    let sid = SessionId::new("ecdsa-keygen", "room", &[1, 2]);
+    let (_, dk) = Paillier::keypair_safe_primes_with_modulus_size(1024).keys();
+    let proof = PaillierBlumModulusProof::prove(&sid, &(&dk.p * &dk.q), &dk.p, &dk.q);
+    assert!(proof.verify(&sid, &dk.p).is_err());
+}
+
+#[test]
+fn test_no_small_factor() {
+    let sid = SessionId::new("ecdsa-keygen", "room", &[1, 2, 3]);
+    let other = SessionId::new("ecdsa-keygen", "other room", &[1, 2, 3]);
+    let setup = ring_pedersen_setup();
+    let (ek, dk) = Paillier::keypair_safe_primes_with_modulus_size(2048).keys();
+
+    let proof = NoSmallFactorProof::prove(&sid, &ek.n, &dk.p, &dk.q, &setup).unwrap();
+    assert!(proof.verify(&sid, &ek.n, &setup).is_ok());
+    assert!(proof.verify(&other, &ek.n, &setup).is_err());
+    assert!(proof.verify(&sid, &ek.n, &ring_pedersen_setup()).is_err());
+}
+
+#[test]
+fn modulus_with_small_factor_is_rejected() {
+    let sid = SessionId::new("ecdsa-keygen", "room", &[1, 2]);
+    let setup = ring_pedersen_setup();
+    // 200-bit factor of a 2048-bit modulus is far below sqrt(N) / 2^768
+    let (_, small) = Paillier::keypair_with_modulus_size(400).keys();
+    let (_, big) = Paillier::keypair_with_modulus_size(3696).keys();
+    let N0 = &small.p * &big.p;
+
+    let proof = NoSmallFactorProof::prove(&sid, &N0, &small.p, &big.p, &setup).unwrap();
+    assert!(proof.verify(&sid, &N0, &setup).is_err());
+}
+
+#[test]
+fn test_ring_pedersen() {
+    let sid = SessionId::new("ecdsa-keygen", "room", &[1, 2, 3]);
+    let other = SessionId::new("ecdsa-keygen", "other room", &[1, 2, 3]);
+    let (setup, xhi, phi) = ring_pedersen_setup_with_secret();
+
+    let proof = RingPedersenProof::prove(&sid, &setup, &xhi, &phi);
+    assert!(proof.verify(&sid, &setup).is_ok());
+    assert!(proof.verify(&other, &setup).is_err());
+    assert!(proof.verify(&sid, &ring_pedersen_setup()).is_err());
+}
+
+#[test]
+fn test_correct_key() {
+    let sid = SessionId::new("ecdsa-keygen", "room", &[1, 2, 3]);
+    let other = SessionId::new("ecdsa-keygen", "other room", &[1, 2, 3]);
+    let (ek, dk) = Paillier::keypair_with_modulus_size(1024).keys();
+
+    let proof = CorrectKeyProof::prove(&sid, &ek.n, &dk.p, &dk.q);
+    assert!(proof.verify(&sid, &ek.n).is_ok());
+    assert!(proof.verify(&other, &ek.n).is_err());
+
+    let (another_ek, _) = Paillier::keypair_with_modulus_size(1024).keys();
+    assert!(proof.verify(&sid, &another_ek.n).is_err());
+}
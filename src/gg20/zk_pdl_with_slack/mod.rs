@@ -6,6 +6,7 @@
 //! Statement: (c, pk, Q, G)
 //! witness (x, r) such that Q = xG, c = Enc(pk, x, r)
 //! note that because of the range proof, the proof has a slack in the range: x in [-q^3, q^3]
+//! the challenge is bound to the session the proof is made in
 
 use curv::arithmetic::traits::*;
 use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
@@ -16,6 +17,8 @@ use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use thiserror::Error;
 
+use crate::utils::session_id::SessionId;
+
 #[derive(Error, Debug)]
 pub enum ZkPdlWithSlackError {
     #[error("zk pdl with slack verification failed")]
@@ -50,7 +53,11 @@ pub struct PDLwSlackProof {
 }
 
 impl PDLwSlackProof {
-    pub fn prove(witness: &PDLwSlackWitness, statement: &PDLwSlackStatement) -> Self {
+    pub fn prove(
+        sid: &SessionId,
+        witness: &PDLwSlackWitness,
+        statement: &PDLwSlackStatement,
+    ) -> Self {
         let q3 = Scalar::<Secp256k1>::group_order().pow(3);
         let q_N_tilde = Scalar::<Secp256k1>::group_order() * &statement.N_tilde;
         let q3_N_tilde = &q3 * &statement.N_tilde;
@@ -85,6 +92,7 @@ impl PDLwSlackProof {
         );
 
         let e = Sha256::new()
+            .chain(sid)
             .chain_bigint(&BigInt::from_bytes(statement.G.to_bytes(true).as_ref()))
             .chain_bigint(&BigInt::from_bytes(statement.Q.to_bytes(true).as_ref()))
             .chain_bigint(&statement.ciphertext)
@@ -109,8 +117,13 @@ impl PDLwSlackProof {
         }
     }
 
-    pub fn verify(&self, statement: &PDLwSlackStatement) -> Result<(), ZkPdlWithSlackError> {
+    pub fn verify(
+        &self,
+        sid: &SessionId,
+        statement: &PDLwSlackStatement,
+    ) -> Result<(), ZkPdlWithSlackError> {
         let e = Sha256::new()
+            .chain(sid)
             .chain_bigint(&BigInt::from_bytes(statement.G.to_bytes(true).as_ref()))
             .chain_bigint(&BigInt::from_bytes(statement.Q.to_bytes(true).as_ref()))
             .chain_bigint(&statement.ciphertext)
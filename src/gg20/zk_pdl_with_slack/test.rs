@@ -1,5 +1,6 @@
 #![allow(non_snake_case)]
 use crate::gg20::zk_pdl_with_slack::*;
+use crate::utils::session_id::SessionId;
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
 use curv::BigInt;
 use paillier::core::Randomness;
@@ -59,12 +60,16 @@ fn test_zk_pdl_with_slack() {
 
     let pdl_w_slack_witness = PDLwSlackWitness { x, r: randomness.0 };
 
-    let proof = PDLwSlackProof::prove(&pdl_w_slack_witness, &pdl_w_slack_statement);
+    let sid = SessionId::new("ecdsa-sign", "room", &[1, 2]);
+    let proof = PDLwSlackProof::prove(&sid, &pdl_w_slack_witness, &pdl_w_slack_statement);
     // verify h1,h2, N_tilde
     let setup_result = composite_dlog_proof.verify(&statement);
     assert!(setup_result.is_ok());
-    let result = proof.verify(&pdl_w_slack_statement);
+    let result = proof.verify(&sid, &pdl_w_slack_statement);
     assert!(result.is_ok());
+    // the proof can't be replayed in another session
+    let other = SessionId::new("ecdsa-sign", "another room", &[1, 2]);
+    assert!(proof.verify(&other, &pdl_w_slack_statement).is_err());
 }
 
 #[test]
@@ -120,10 +125,11 @@ fn test_zk_pdl_with_slack_soundness() {
 
     let pdl_w_slack_witness = PDLwSlackWitness { x, r: randomness.0 };
 
-    let proof = PDLwSlackProof::prove(&pdl_w_slack_witness, &pdl_w_slack_statement);
+    let sid = SessionId::new("ecdsa-sign", "room", &[1, 2]);
+    let proof = PDLwSlackProof::prove(&sid, &pdl_w_slack_witness, &pdl_w_slack_statement);
     // verify h1,h2, N_tilde
     let setup_result = composite_dlog_proof.verify(&statement);
     assert!(setup_result.is_ok());
-    let result = proof.verify(&pdl_w_slack_statement);
+    let result = proof.verify(&sid, &pdl_w_slack_statement);
     assert!(result.is_ok());
 }
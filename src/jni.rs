@@ -1,19 +1,43 @@
+use std::fmt;
+
 use robusta_jni::bridge;
 
+use crate::error::TssError;
+
+/// Java exception of a failed request, its message is the JSON of [TssError]
+fn jni_error(err: impl Into<TssError>) -> robusta_jni::jni::errors::Error {
+    robusta_jni::jni::errors::Error::from(err.into().to_json())
+}
+
+fn invalid_request(err: impl fmt::Display) -> robusta_jni::jni::errors::Error {
+    jni_error(TssError::invalid_input(format_args!(
+        "malformed request: {}",
+        err
+    )))
+}
+
 #[bridge]
 pub mod jni {
     use std::convert::Infallible;
 
-    use rustmodel::{
-        KeyScheme, NativeGenerateDynamicNonceRequest, NativeKeygenRequest, NativeSigningRequest,
-    };
+    use rustmodel::{NativeGenerateDynamicNonceRequest, NativeKeygenRequest, NativeSigningRequest};
 
-    use crate::t_ed25519::presignature::generate_dynamic_nonces;
+    use super::{invalid_request, jni_error};
+    use crate::error::TssError;
+    use crate::gg20;
     use crate::utils::common::{
-        decrypt_ecdsa, decrypt_eddsa, encrypt_eddsa_keygen_result, encrypt_keygen_result,
-        signing_state_base64_to_obj, signing_state_obj_to_base64,
+        decrypt_ecdsa, decrypt_eddsa, decrypt_keygen_result, derive_public_key_request,
+        encrypt_ecdsa_keygen_result, encrypt_eddsa_keygen_result, encrypt_keygen_result, reencrypt,
+        sign_request, xpub_request, KeygenResult,
+    };
+    use crate::utils::encryption::encrypt;
+    use crate::utils::model::{
+        NativeDerivePublicKeyRequest, NativeGeneratePreParamsRequest,
+        NativeGeneratePresignaturesRequest, NativeProtocolOptions, NativeRecoverRequest,
+        NativeReencryptRequest, NativeRefreshRequest, NativeReshareRequest, NativeSigningOptions,
+        NativeXpubRequest, PRE_PARAMS_ASSOCIATED_DATA,
     };
-    use crate::{gg20, t_ed25519};
+    use crate::utils::sm_client::SmClient;
 
     #[package(com.walletbackend.signingv2.jnitssv3)]
     pub struct JniTssv3();
@@ -22,46 +46,35 @@ pub mod jni {
         pub extern "jni" fn jniSign(
             rust_request: String,
         ) -> robusta_jni::jni::errors::Result<String> {
-            let request: NativeSigningRequest = serde_json::from_str(rust_request.as_str())
-                .map_err(|e| robusta_jni::jni::errors::Error::from(e.to_string()))?;
-            let data = hex::decode(request.hex_data)
-                .map_err(|e| robusta_jni::jni::errors::Error::from(e.to_string()))?;
-            let mut state = signing_state_base64_to_obj(&request.state_base64);
-            return match if request.key_scheme == KeyScheme::ECDSA {
-                gg20::signing::sign(
-                    &mut state,
-                    &(match decrypt_ecdsa(&request.encrypted_local_key, request.password.as_str()) {
-                        Ok(r) => r,
-                        Err(e) => {
-                            return Err(robusta_jni::jni::errors::Error::from(e.to_string()));
-                        }
-                    }),
-                    data,
-                    request.party_id as u16,
-                    request
-                        .signers
-                        .into_iter()
-                        .map(|x| x as u16)
-                        .collect::<Vec<u16>>(),
-                )
-            } else {
-                t_ed25519::signing::sign(
-                    &mut state,
-                    &decrypt_eddsa(&request.encrypted_local_key, request.password.as_str())
-                        .map_err(|e| robusta_jni::jni::errors::Error::from(e.to_string()))?,
-                    data,
-                    request.party_id as u16,
-                    request.nonce as usize,
-                )
-            } {
-                Ok(()) => {
-                    let state_result_base64 = signing_state_obj_to_base64(KeyScheme::ECDSA, &state);
-                    let state_result_base64_json = serde_json::to_string(&state_result_base64)
-                        .map_err(|e| robusta_jni::jni::errors::Error::from(e.to_string()))?;
-                    Ok(state_result_base64_json)
-                }
-                Err(e) => Err(robusta_jni::jni::errors::Error::from(e.to_string())),
-            };
+            let request: NativeSigningRequest =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let options: NativeSigningOptions =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let path = options.path().map_err(jni_error)?;
+            let response = sign_request(request, &path).map_err(jni_error)?;
+            serde_json::to_string(&response).map_err(|e| jni_error(TssError::internal(e)))
+        }
+
+        /// Returns extended public key of a stored ECDSA key, child keys which sign with a
+        /// `path` derive from it
+        pub extern "jni" fn jniXpub(
+            rust_request: String,
+        ) -> robusta_jni::jni::errors::Result<String> {
+            let request: NativeXpubRequest =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let response = xpub_request(request).map_err(jni_error)?;
+            serde_json::to_string(&response).map_err(|e| jni_error(TssError::internal(e)))
+        }
+
+        /// Returns public key of a child of a stored ECDSA or EdDSA key, the key signs for it
+        /// with the same `path`
+        pub extern "jni" fn jniDerivePublicKey(
+            rust_request: String,
+        ) -> robusta_jni::jni::errors::Result<String> {
+            let request: NativeDerivePublicKeyRequest =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let response = derive_public_key_request(request).map_err(jni_error)?;
+            serde_json::to_string(&response).map_err(|e| jni_error(TssError::internal(e)))
         }
     }
 
@@ -69,51 +82,116 @@ pub mod jni {
     pub struct JniTssv3Keygen();
 
     impl JniTssv3Keygen {
+        /// Generates Paillier key and `N_tilde` for a future ECDSA keygen or refresh, returns
+        /// them encrypted with the password of the request
+        ///
+        /// Blocks the caller until the safe primes are found, meant to be called from a
+        /// background thread.
+        pub extern "jni" fn jniGeneratePreParams(
+            rust_request: String,
+        ) -> robusta_jni::jni::errors::Result<String> {
+            let request: NativeGeneratePreParamsRequest =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let options: NativeProtocolOptions =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let kdf = options.kdf().map_err(jni_error)?;
+            let modulus_bits = options.modulus_bits().map_err(jni_error)?;
+            let pre_params = gg20::PreParams::generate(modulus_bits);
+            let pre_params_json =
+                serde_json::to_string(&pre_params).map_err(|e| jni_error(TssError::internal(e)))?;
+            encrypt(
+                &pre_params_json,
+                request.password.as_str(),
+                PRE_PARAMS_ASSOCIATED_DATA,
+                &kdf,
+            )
+            .map_err(jni_error)
+        }
+
+        /// Encrypts a stored wallet with a new password, returns the encrypted keygen result
+        ///
+        /// Fails without a result if the old password doesn't decrypt both keys.
+        pub extern "jni" fn jniReencrypt(
+            rust_request: String,
+        ) -> robusta_jni::jni::errors::Result<String> {
+            let request: NativeReencryptRequest =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let reencrypted = reencrypt(
+                request.encrypted_keygen_result,
+                request.old_password.as_str(),
+                request.new_password.as_str(),
+            )
+            .map_err(jni_error)?;
+            serde_json::to_string(&reencrypted).map_err(|e| jni_error(TssError::internal(e)))
+        }
+
         pub extern "jni" fn jniKeygen(
             rust_request: String,
         ) -> robusta_jni::jni::errors::Result<()> {
-            let request: NativeKeygenRequest = serde_json::from_str(rust_request.as_str())
-                .map_err(|e| robusta_jni::jni::errors::Error::from(e.to_string()))?;
+            let request: NativeKeygenRequest =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let options: NativeProtocolOptions =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let kdf = options.kdf().map_err(jni_error)?;
+            let identity = options.keygen_identity().map_err(jni_error)?;
+            let pre_params = options
+                .pre_params(request.password.as_str())
+                .map_err(jni_error)?;
+            let modulus_bits = options.modulus_bits().map_err(jni_error)?;
             let url =
                 surf::Url::parse(format!("{}/rooms/{}/", request.address, request.room).as_str())
-                    .map_err(|e| robusta_jni::jni::errors::Error::from(e.to_string()))?;
+                    .map_err(invalid_request)?;
             let http_client: surf::Client = surf::Config::new()
                 .set_base_url(url)
                 .set_timeout(None)
                 .try_into()
                 .map_err(|e: Infallible| robusta_jni::jni::errors::Error::from(e.to_string()))?;
+            let transport =
+                SmClient::connect(&request.request_id, &request.token, &request.address)
+                    .map_err(jni_error)?;
             std::thread::spawn(move || {
+                let pre_params =
+                    pre_params.unwrap_or_else(|| gg20::PreParams::generate(modulus_bits));
                 let runtime = tokio::runtime::Builder::new_current_thread()
                     .build()
                     .unwrap();
                 match runtime.block_on(crate::all_keygen::keygen_and_offline(
                     request.request_id.as_str(),
-                    request.token.as_str(),
-                    request.address.as_str(),
+                    &transport,
+                    &identity,
                     request.room.as_str(),
                     request.t as u16,
                     request.n as u16,
                     crate::utils::constants::CONST_MAX_NONCE_PER_REFRESH,
                     request.signer_name.as_str(),
+                    pre_params,
+                    !options.skip_presign,
+                    options.round_timeout(),
+                    options.echo_broadcast,
                 )) {
                     Ok(keygen_result) => {
                         runtime.block_on(
-                            std::thread::spawn(|| async move {
-                                let keygen_result_base64 =
-                                    encrypt_keygen_result(keygen_result, request.password.as_str());
-                                let encrypted_result =
-                                    match serde_json::to_string(&keygen_result_base64) {
-                                        Ok(r) => r,
-                                        Err(err) => {
-                                            http_client
-                                                .post("error")
-                                                .header("X-Request-ID", request.request_id.clone())
-                                                .header("X-Token", request.token.clone())
-                                                .body(format!("error: {}", err.to_string()))
-                                                .await;
-                                            format!("error: {}", err.to_string())
-                                        }
-                                    };
+                            std::thread::spawn(move || async move {
+                                let keygen_result_base64 = encrypt_keygen_result(
+                                    keygen_result,
+                                    request.password.as_str(),
+                                    &kdf,
+                                );
+                                let encrypted_result = match keygen_result_base64.and_then(|r| {
+                                    serde_json::to_string(&r).map_err(TssError::internal)
+                                }) {
+                                    Ok(r) => r,
+                                    Err(err) => {
+                                        http_client
+                                            .post("error")
+                                            .header("X-Request-ID", request.request_id.clone())
+                                            .header("X-Token", request.token.clone())
+                                            .header("Content-Type", "application/json")
+                                            .body(err.to_json())
+                                            .await;
+                                        err.to_json()
+                                    }
+                                };
                                 http_client
                                     .post("completed-keygen")
                                     .header("X-Request-ID", request.request_id.clone())
@@ -128,12 +206,13 @@ pub mod jni {
                     }
                     Err(err) => {
                         runtime.block_on(
-                            std::thread::spawn(|| async move {
+                            std::thread::spawn(move || async move {
                                 http_client
                                     .post("error")
                                     .header("X-Request-ID", request.request_id)
                                     .header("X-Token", request.token.clone())
-                                    .body(format!("error: {}", err.to_string()))
+                                    .header("Content-Type", "application/json")
+                                    .body(TssError::from(err).to_json())
                                     .await;
                             })
                             .join()
@@ -149,11 +228,14 @@ pub mod jni {
             rust_request: String,
         ) -> robusta_jni::jni::errors::Result<()> {
             let request: NativeGenerateDynamicNonceRequest =
-                serde_json::from_str(rust_request.as_str())
-                    .map_err(|e| robusta_jni::jni::errors::Error::from(e.to_string()))?;
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let options: NativeProtocolOptions =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let kdf = options.kdf().map_err(jni_error)?;
+            let identity = options.identity().map_err(jni_error)?;
             let url =
                 surf::Url::parse(format!("{}/rooms/{}/", request.address, request.room).as_str())
-                    .map_err(|e| robusta_jni::jni::errors::Error::from(e.to_string()))?;
+                    .map_err(invalid_request)?;
             let http_client: surf::Client = surf::Config::new()
                 .set_base_url(url)
                 .set_timeout(None)
@@ -163,44 +245,52 @@ pub mod jni {
                 match decrypt_eddsa(&request.encrypted_local_key, request.password.as_str()) {
                     Ok(r) => r,
                     Err(e) => {
-                        return Err(robusta_jni::jni::errors::Error::from(e.to_string()));
+                        return Err(jni_error(e));
                     }
                 };
+            let transport =
+                SmClient::connect(&request.request_id, &request.token, &request.address)
+                    .map_err(jni_error)?;
             std::thread::spawn(move || {
                 let runtime = tokio::runtime::Builder::new_current_thread()
                     .build()
                     .unwrap();
-                match runtime.block_on(generate_dynamic_nonces(
+                match runtime.block_on(crate::all_keygen::generate_nonces(
                     request.request_id.as_str(),
-                    request.token.as_str(),
-                    request.address.as_str(),
+                    &transport,
+                    &identity,
                     request.room.as_str(),
                     request.nonce_start_index as u16,
                     request.nonce_size as u16,
                     &local_key_data.local_key,
+                    options.round_timeout(),
+                    options.echo_broadcast,
                 )) {
                     Ok(keygen_result) => {
                         runtime.block_on(
-                            std::thread::spawn(|| async move {
+                            std::thread::spawn(move || async move {
                                 let keygen_result_base64 = encrypt_eddsa_keygen_result(
                                     &local_key_data.local_key,
                                     &keygen_result,
                                     request.password.as_str(),
                                     local_key_data.algorithm.as_str(),
+                                    &kdf,
                                 );
-                                let encrypted_result =
-                                    match serde_json::to_string(&keygen_result_base64) {
-                                        Ok(r) => r,
-                                        Err(err) => {
-                                            http_client
-                                                .post("error")
-                                                .header("X-Request-ID", request.request_id.clone())
-                                                .header("X-Token", request.token.clone())
-                                                .body(format!("error: {}", err.to_string()))
-                                                .await;
-                                            format!("error: {}", err.to_string())
-                                        }
-                                    };
+                                let encrypted_result = match keygen_result_base64.and_then(|r| {
+                                    serde_json::to_string(&r).map_err(TssError::internal)
+                                }) {
+                                    Ok(r) => r,
+                                    Err(err) => {
+                                        http_client
+                                            .post("error")
+                                            .header("X-Request-ID", request.request_id.clone())
+                                            .header("X-Token", request.token.clone())
+                                            .header("Content-Type", "application/json")
+                                            .body(err.to_json())
+                                            .await;
+                                        err.to_json()
+                                    }
+                                };
                                 http_client
                                     .post("completed-generate-nonce")
                                     .header("X-Request-ID", request.request_id.clone())
@@ -215,12 +305,541 @@ pub mod jni {
                     }
                     Err(err) => {
                         runtime.block_on(
-                            std::thread::spawn(|| async move {
+                            std::thread::spawn(move || async move {
+                                http_client
+                                    .post("error")
+                                    .header("X-Request-ID", request.request_id)
+                                    .header("X-Token", request.token.clone())
+                                    .header("Content-Type", "application/json")
+                                    .body(TssError::from(err).to_json())
+                                    .await;
+                            })
+                            .join()
+                            .unwrap(),
+                        );
+                    }
+                }
+            });
+            return Ok(());
+        }
+
+        pub extern "jni" fn jniGeneratePresignatures(
+            rust_request: String,
+        ) -> robusta_jni::jni::errors::Result<()> {
+            let request: NativeGeneratePresignaturesRequest =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let options: NativeProtocolOptions =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let kdf = options.kdf().map_err(jni_error)?;
+            let identity = options.identity().map_err(jni_error)?;
+            let url =
+                surf::Url::parse(format!("{}/rooms/{}/", request.address, request.room).as_str())
+                    .map_err(invalid_request)?;
+            let http_client: surf::Client = surf::Config::new()
+                .set_base_url(url)
+                .set_timeout(None)
+                .try_into()
+                .map_err(|e: Infallible| robusta_jni::jni::errors::Error::from(e.to_string()))?;
+            let mut local_key_data =
+                match decrypt_ecdsa(&request.encrypted_local_key, request.password.as_str()) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        return Err(jni_error(e));
+                    }
+                };
+            let transport =
+                SmClient::connect(&request.request_id, &request.token, &request.address)
+                    .map_err(jni_error)?;
+            std::thread::spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .build()
+                    .unwrap();
+                match runtime.block_on(crate::all_keygen::generate_presignatures(
+                    request.request_id.as_str(),
+                    &transport,
+                    &identity,
+                    request.room.as_str(),
+                    &local_key_data.local_key,
+                    request.signers.clone(),
+                    request.count,
+                    options.round_timeout(),
+                    options.echo_broadcast,
+                )) {
+                    Ok(presignatures) => {
+                        runtime.block_on(
+                            std::thread::spawn(move || async move {
+                                local_key_data.add_presignatures(presignatures);
+                                let keygen_result_base64 = encrypt_ecdsa_keygen_result(
+                                    &local_key_data.local_key,
+                                    &local_key_data.offline_data,
+                                    request.password.as_str(),
+                                    local_key_data.algorithm.as_str(),
+                                    &kdf,
+                                );
+                                let encrypted_result = match keygen_result_base64.and_then(|r| {
+                                    serde_json::to_string(&r).map_err(TssError::internal)
+                                }) {
+                                    Ok(r) => r,
+                                    Err(err) => {
+                                        http_client
+                                            .post("error")
+                                            .header("X-Request-ID", request.request_id.clone())
+                                            .header("X-Token", request.token.clone())
+                                            .header("Content-Type", "application/json")
+                                            .body(err.to_json())
+                                            .await;
+                                        err.to_json()
+                                    }
+                                };
+                                http_client
+                                    .post("completed-generate-presignatures")
+                                    .header("X-Request-ID", request.request_id.clone())
+                                    .header("X-Token", request.token.clone())
+                                    .header("Content-Type", "application/json")
+                                    .body(encrypted_result)
+                                    .await;
+                            })
+                            .join()
+                            .unwrap(),
+                        );
+                    }
+                    Err(err) => {
+                        runtime.block_on(
+                            std::thread::spawn(move || async move {
+                                http_client
+                                    .post("error")
+                                    .header("X-Request-ID", request.request_id)
+                                    .header("X-Token", request.token.clone())
+                                    .header("Content-Type", "application/json")
+                                    .body(TssError::from(err).to_json())
+                                    .await;
+                            })
+                            .join()
+                            .unwrap(),
+                        );
+                    }
+                }
+            });
+            return Ok(());
+        }
+
+        pub extern "jni" fn jniRefreshEcdsa(
+            rust_request: String,
+        ) -> robusta_jni::jni::errors::Result<()> {
+            let request: NativeRefreshRequest =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let options: NativeProtocolOptions =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let kdf = options.kdf().map_err(jni_error)?;
+            let identity = options.identity().map_err(jni_error)?;
+            let pre_params = options
+                .pre_params(request.password.as_str())
+                .map_err(jni_error)?;
+            let url =
+                surf::Url::parse(format!("{}/rooms/{}/", request.address, request.room).as_str())
+                    .map_err(invalid_request)?;
+            let http_client: surf::Client = surf::Config::new()
+                .set_base_url(url)
+                .set_timeout(None)
+                .try_into()
+                .map_err(|e: Infallible| robusta_jni::jni::errors::Error::from(e.to_string()))?;
+            let local_key_data =
+                match decrypt_ecdsa(&request.encrypted_local_key, request.password.as_str()) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        return Err(jni_error(e));
+                    }
+                };
+            let transport =
+                SmClient::connect(&request.request_id, &request.token, &request.address)
+                    .map_err(jni_error)?;
+            std::thread::spawn(move || {
+                let pre_params = pre_params.unwrap_or_else(|| {
+                    gg20::PreParams::generate(local_key_data.local_key.modulus_bits())
+                });
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .build()
+                    .unwrap();
+                match runtime.block_on(crate::all_keygen::refresh_and_offline(
+                    request.request_id.as_str(),
+                    &transport,
+                    &identity,
+                    request.room.as_str(),
+                    &local_key_data,
+                    pre_params,
+                    !options.skip_presign,
+                    options.round_timeout(),
+                    options.echo_broadcast,
+                )) {
+                    Ok(refreshed) => {
+                        runtime.block_on(
+                            std::thread::spawn(move || async move {
+                                let keygen_result_base64 = encrypt_ecdsa_keygen_result(
+                                    &refreshed.local_key,
+                                    &refreshed.offline_data,
+                                    request.password.as_str(),
+                                    refreshed.algorithm.as_str(),
+                                    &kdf,
+                                );
+                                let encrypted_result = match keygen_result_base64.and_then(|r| {
+                                    serde_json::to_string(&r).map_err(TssError::internal)
+                                }) {
+                                    Ok(r) => r,
+                                    Err(err) => {
+                                        http_client
+                                            .post("error")
+                                            .header("X-Request-ID", request.request_id.clone())
+                                            .header("X-Token", request.token.clone())
+                                            .header("Content-Type", "application/json")
+                                            .body(err.to_json())
+                                            .await;
+                                        err.to_json()
+                                    }
+                                };
+                                http_client
+                                    .post("completed-refresh")
+                                    .header("X-Request-ID", request.request_id.clone())
+                                    .header("X-Token", request.token.clone())
+                                    .header("Content-Type", "application/json")
+                                    .body(encrypted_result)
+                                    .await;
+                            })
+                            .join()
+                            .unwrap(),
+                        );
+                    }
+                    Err(err) => {
+                        runtime.block_on(
+                            std::thread::spawn(move || async move {
+                                http_client
+                                    .post("error")
+                                    .header("X-Request-ID", request.request_id)
+                                    .header("X-Token", request.token.clone())
+                                    .header("Content-Type", "application/json")
+                                    .body(TssError::from(err).to_json())
+                                    .await;
+                            })
+                            .join()
+                            .unwrap(),
+                        );
+                    }
+                }
+            });
+            return Ok(());
+        }
+
+        pub extern "jni" fn jniRefreshEddsa(
+            rust_request: String,
+        ) -> robusta_jni::jni::errors::Result<()> {
+            let request: NativeGenerateDynamicNonceRequest =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let options: NativeProtocolOptions =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let kdf = options.kdf().map_err(jni_error)?;
+            let identity = options.identity().map_err(jni_error)?;
+            let url =
+                surf::Url::parse(format!("{}/rooms/{}/", request.address, request.room).as_str())
+                    .map_err(invalid_request)?;
+            let http_client: surf::Client = surf::Config::new()
+                .set_base_url(url)
+                .set_timeout(None)
+                .try_into()
+                .map_err(|e: Infallible| robusta_jni::jni::errors::Error::from(e.to_string()))?;
+            let local_key_data =
+                match decrypt_eddsa(&request.encrypted_local_key, request.password.as_str()) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        return Err(jni_error(e));
+                    }
+                };
+            let transport =
+                SmClient::connect(&request.request_id, &request.token, &request.address)
+                    .map_err(jni_error)?;
+            std::thread::spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .build()
+                    .unwrap();
+                match runtime.block_on(crate::all_keygen::refresh_and_generate_nonces(
+                    request.request_id.as_str(),
+                    &transport,
+                    &identity,
+                    request.room.as_str(),
+                    request.nonce_start_index as u16,
+                    request.nonce_size as u16,
+                    &local_key_data,
+                    options.round_timeout(),
+                    options.echo_broadcast,
+                )) {
+                    Ok(refreshed) => {
+                        runtime.block_on(
+                            std::thread::spawn(move || async move {
+                                let keygen_result_base64 = encrypt_eddsa_keygen_result(
+                                    &refreshed.local_key,
+                                    &refreshed.offline_data,
+                                    request.password.as_str(),
+                                    refreshed.algorithm.as_str(),
+                                    &kdf,
+                                );
+                                let encrypted_result = match keygen_result_base64.and_then(|r| {
+                                    serde_json::to_string(&r).map_err(TssError::internal)
+                                }) {
+                                    Ok(r) => r,
+                                    Err(err) => {
+                                        http_client
+                                            .post("error")
+                                            .header("X-Request-ID", request.request_id.clone())
+                                            .header("X-Token", request.token.clone())
+                                            .header("Content-Type", "application/json")
+                                            .body(err.to_json())
+                                            .await;
+                                        err.to_json()
+                                    }
+                                };
+                                http_client
+                                    .post("completed-refresh")
+                                    .header("X-Request-ID", request.request_id.clone())
+                                    .header("X-Token", request.token.clone())
+                                    .header("Content-Type", "application/json")
+                                    .body(encrypted_result)
+                                    .await;
+                            })
+                            .join()
+                            .unwrap(),
+                        );
+                    }
+                    Err(err) => {
+                        runtime.block_on(
+                            std::thread::spawn(move || async move {
+                                http_client
+                                    .post("error")
+                                    .header("X-Request-ID", request.request_id)
+                                    .header("X-Token", request.token.clone())
+                                    .header("Content-Type", "application/json")
+                                    .body(TssError::from(err).to_json())
+                                    .await;
+                            })
+                            .join()
+                            .unwrap(),
+                        );
+                    }
+                }
+            });
+            return Ok(());
+        }
+
+        pub extern "jni" fn jniReshare(
+            rust_request: String,
+        ) -> robusta_jni::jni::errors::Result<()> {
+            let request: NativeReshareRequest =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let options: NativeProtocolOptions =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let kdf = options.kdf().map_err(jni_error)?;
+            let identity = options.identity().map_err(jni_error)?;
+            let url =
+                surf::Url::parse(format!("{}/rooms/{}/", request.address, request.room).as_str())
+                    .map_err(invalid_request)?;
+            let http_client: surf::Client = surf::Config::new()
+                .set_base_url(url)
+                .set_timeout(None)
+                .try_into()
+                .map_err(|e: Infallible| robusta_jni::jni::errors::Error::from(e.to_string()))?;
+            let old_keys = request
+                .encrypted_keygen_result
+                .as_ref()
+                .map(|result| decrypt_keygen_result(result, request.password.as_str()))
+                .transpose()
+                .map_err(jni_error)?;
+            let transport =
+                SmClient::connect(&request.request_id, &request.token, &request.address)
+                    .map_err(jni_error)?;
+            std::thread::spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .build()
+                    .unwrap();
+                match runtime.block_on(crate::all_keygen::reshare_and_offline(
+                    request.request_id.as_str(),
+                    &transport,
+                    &identity,
+                    request.room.as_str(),
+                    request.n as u16,
+                    old_keys.as_ref().map(|(ecdsa, eddsa)| (ecdsa, eddsa)),
+                    request.new_member,
+                    request.new_t as u16,
+                    request.new_n as u16,
+                    crate::utils::constants::CONST_MAX_NONCE_PER_REFRESH,
+                    request.signer_name.as_str(),
+                    !options.skip_presign,
+                    options.round_timeout(),
+                    options.echo_broadcast,
+                )) {
+                    Ok(keygen_result) => {
+                        runtime.block_on(
+                            std::thread::spawn(move || async move {
+                                let keygen_result_base64 = keygen_result
+                                    .map(|result| {
+                                        encrypt_keygen_result(
+                                            result,
+                                            request.password.as_str(),
+                                            &kdf,
+                                        )
+                                    })
+                                    .transpose();
+                                let encrypted_result = match keygen_result_base64.and_then(|r| {
+                                    serde_json::to_string(&r).map_err(TssError::internal)
+                                }) {
+                                    Ok(r) => r,
+                                    Err(err) => {
+                                        http_client
+                                            .post("error")
+                                            .header("X-Request-ID", request.request_id.clone())
+                                            .header("X-Token", request.token.clone())
+                                            .header("Content-Type", "application/json")
+                                            .body(err.to_json())
+                                            .await;
+                                        err.to_json()
+                                    }
+                                };
+                                http_client
+                                    .post("completed-reshare")
+                                    .header("X-Request-ID", request.request_id.clone())
+                                    .header("X-Token", request.token.clone())
+                                    .header("Content-Type", "application/json")
+                                    .body(encrypted_result)
+                                    .await;
+                            })
+                            .join()
+                            .unwrap(),
+                        );
+                    }
+                    Err(err) => {
+                        runtime.block_on(
+                            std::thread::spawn(move || async move {
+                                http_client
+                                    .post("error")
+                                    .header("X-Request-ID", request.request_id)
+                                    .header("X-Token", request.token.clone())
+                                    .header("Content-Type", "application/json")
+                                    .body(TssError::from(err).to_json())
+                                    .await;
+                            })
+                            .join()
+                            .unwrap(),
+                        );
+                    }
+                }
+            });
+            return Ok(());
+        }
+
+        pub extern "jni" fn jniRecover(
+            rust_request: String,
+        ) -> robusta_jni::jni::errors::Result<()> {
+            let request: NativeRecoverRequest =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let options: NativeProtocolOptions =
+                serde_json::from_str(rust_request.as_str()).map_err(invalid_request)?;
+            let kdf = options.kdf().map_err(jni_error)?;
+            let identity = options.identity().map_err(jni_error)?;
+            let url =
+                surf::Url::parse(format!("{}/rooms/{}/", request.address, request.room).as_str())
+                    .map_err(invalid_request)?;
+            let http_client: surf::Client = surf::Config::new()
+                .set_base_url(url)
+                .set_timeout(None)
+                .try_into()
+                .map_err(|e: Infallible| robusta_jni::jni::errors::Error::from(e.to_string()))?;
+            let old_keys = request
+                .encrypted_keygen_result
+                .as_ref()
+                .map(|result| decrypt_keygen_result(result, request.password.as_str()))
+                .transpose()
+                .map_err(jni_error)?;
+            let pre_params = options
+                .pre_params(request.password.as_str())
+                .map_err(jni_error)?;
+            let modulus_bits = options.modulus_bits().map_err(jni_error)?;
+            let transport =
+                SmClient::connect(&request.request_id, &request.token, &request.address)
+                    .map_err(jni_error)?;
+            std::thread::spawn(move || {
+                // only the replacement device comes with a new Paillier key
+                let pre_params = match old_keys {
+                    Some(_) => None,
+                    None => {
+                        Some(pre_params.unwrap_or_else(|| gg20::PreParams::generate(modulus_bits)))
+                    }
+                };
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .build()
+                    .unwrap();
+                match runtime.block_on(crate::all_keygen::recover_and_offline(
+                    request.request_id.as_str(),
+                    &transport,
+                    &identity,
+                    request.room.as_str(),
+                    request.t,
+                    request.n,
+                    old_keys.as_ref().map(|(ecdsa, eddsa)| (ecdsa, eddsa)),
+                    pre_params,
+                    request.lost_i,
+                    &request.helpers,
+                    &request.observers,
+                    !options.skip_presign,
+                    options.round_timeout(),
+                    options.echo_broadcast,
+                )) {
+                    Ok(keygen_result) => {
+                        runtime.block_on(
+                            std::thread::spawn(move || async move {
+                                let members = request
+                                    .encrypted_keygen_result
+                                    .map(|old| old.members)
+                                    .unwrap_or_default();
+                                let keygen_result_base64 = encrypt_keygen_result(
+                                    KeygenResult {
+                                        members,
+                                        ..keygen_result
+                                    },
+                                    request.password.as_str(),
+                                    &kdf,
+                                );
+                                let encrypted_result = match keygen_result_base64.and_then(|r| {
+                                    serde_json::to_string(&r).map_err(TssError::internal)
+                                }) {
+                                    Ok(r) => r,
+                                    Err(err) => {
+                                        http_client
+                                            .post("error")
+                                            .header("X-Request-ID", request.request_id.clone())
+                                            .header("X-Token", request.token.clone())
+                                            .header("Content-Type", "application/json")
+                                            .body(err.to_json())
+                                            .await;
+                                        err.to_json()
+                                    }
+                                };
+                                http_client
+                                    .post("completed-recover")
+                                    .header("X-Request-ID", request.request_id.clone())
+                                    .header("X-Token", request.token.clone())
+                                    .header("Content-Type", "application/json")
+                                    .body(encrypted_result)
+                                    .await;
+                            })
+                            .join()
+                            .unwrap(),
+                        );
+                    }
+                    Err(err) => {
+                        runtime.block_on(
+                            std::thread::spawn(move || async move {
                                 http_client
                                     .post("error")
                                     .header("X-Request-ID", request.request_id)
                                     .header("X-Token", request.token.clone())
-                                    .body(format!("error: {}", err.to_string()))
+                                    .header("Content-Type", "application/json")
+                                    .body(TssError::from(err).to_json())
                                     .await;
                             })
                             .join()
@@ -1,5 +1,6 @@
 pub mod all_keygen;
 pub mod cexport;
+pub mod error;
 pub mod gg20;
 #[cfg(any(target_os = "macos", target_os = "linux", target_os = "windows"))]
 #[cfg(feature = "jni")]
@@ -0,0 +1,167 @@
+//! Soft derivation of child keys from a threshold Ed25519 key
+//!
+//! Follows the same scheme as [ECDSA derivation](crate::gg20::derivation): child key is the
+//! aggregated public key shifted by a public tweak `A' = A + tweak * G`, where the tweak is taken
+//! from `HMAC-SHA512(chain_code, A || index)`. Unlike secp256k1, the left half of the HMAC output
+//! is reduced modulo the group order instead of being rejected, as the order is close to `2^252`.
+//!
+//! Parties sign for the child key with [LocalSig::compute_with_tweak] and the signature is
+//! aggregated with [generate_with_tweak]. Hardened indexes are rejected.
+//!
+//! [LocalSig::compute_with_tweak]: crate::t_ed25519::thresholdsig::LocalSig::compute_with_tweak
+//! [generate_with_tweak]: crate::t_ed25519::thresholdsig::generate_with_tweak
+
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use curv::BigInt;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha512;
+
+use crate::gg20::derivation::{ChainCode, DerivationError, HARDENED_OFFSET};
+use crate::t_ed25519::keygen::EddsaLocalKey;
+
+/// Child key derived from the aggregated key
+#[derive(Clone, Debug, PartialEq)]
+pub struct EddsaDerivedKey {
+    pub agg_pubkey: Point<Ed25519>,
+    pub chain_code: ChainCode,
+    /// Sum of tweaks along the path, `agg_pubkey = master_agg_pubkey + tweak * G`
+    pub tweak: Scalar<Ed25519>,
+}
+
+/// Derives a child of `agg_pubkey` at non-hardened `path`
+pub fn derive_path(
+    agg_pubkey: &Point<Ed25519>,
+    chain_code: &ChainCode,
+    path: &[u32],
+) -> Result<EddsaDerivedKey, DerivationError> {
+    let mut derived = EddsaDerivedKey {
+        agg_pubkey: agg_pubkey.clone(),
+        chain_code: *chain_code,
+        tweak: Scalar::zero(),
+    };
+    for &index in path {
+        let (child_pubkey, child_chain_code, tweak) =
+            derive_child(&derived.agg_pubkey, &derived.chain_code, index)?;
+        derived = EddsaDerivedKey {
+            agg_pubkey: child_pubkey,
+            chain_code: child_chain_code,
+            tweak: derived.tweak + tweak,
+        };
+    }
+    Ok(derived)
+}
+
+/// Derives child public key, its chain code and the tweak applied to the parent key
+pub fn derive_child(
+    agg_pubkey: &Point<Ed25519>,
+    chain_code: &ChainCode,
+    index: u32,
+) -> Result<(Point<Ed25519>, ChainCode, Scalar<Ed25519>), DerivationError> {
+    if index >= HARDENED_OFFSET {
+        return Err(DerivationError::HardenedIndex(index));
+    }
+    let mut mac = Hmac::<Sha512>::new_from_slice(chain_code).expect("any key size is accepted");
+    mac.update(&agg_pubkey.to_bytes(true));
+    mac.update(&index.to_be_bytes());
+    let i = mac.finalize().into_bytes();
+    let (il, ir) = i.split_at(32);
+
+    let tweak = Scalar::<Ed25519>::from(&BigInt::from_bytes(il));
+    let child_pubkey = agg_pubkey + Point::generator() * &tweak;
+    if child_pubkey.is_zero() {
+        return Err(DerivationError::InvalidChild(index));
+    }
+    let mut child_chain_code = [0u8; 32];
+    child_chain_code.copy_from_slice(ir);
+    Ok((child_pubkey, child_chain_code, tweak))
+}
+
+impl EddsaLocalKey {
+    /// Derives child key at non-hardened `path`
+    pub fn derive(&self, path: &[u32]) -> Result<EddsaDerivedKey, DerivationError> {
+        let chain_code = self
+            .chain_code
+            .as_ref()
+            .ok_or(DerivationError::MissingChainCode)?;
+        derive_path(&self.agg_pubkey, chain_code, path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::t_ed25519::refresh::test::wallet1;
+    use crate::t_ed25519::tests::deterministic_fast_rand;
+    use crate::t_ed25519::thresholdsig::test::tests::eph_keygen_t_n_parties;
+    use crate::t_ed25519::thresholdsig::{self, LocalSig};
+
+    use super::*;
+
+    #[test]
+    fn derives_deterministically() {
+        let mut key = wallet1()[0].local_key.clone();
+        assert_eq!(key.derive(&[0]), Err(DerivationError::MissingChainCode));
+
+        key.chain_code = Some([7u8; 32]);
+        let derived = key.derive(&[44, 501, 0, 0]).unwrap();
+        assert_eq!(derived, key.derive(&[44, 501, 0, 0]).unwrap());
+        assert_ne!(derived, key.derive(&[44, 501, 0, 1]).unwrap());
+        assert_eq!(
+            derived.agg_pubkey,
+            &key.agg_pubkey + Point::generator() * &derived.tweak
+        );
+        assert_eq!(
+            key.derive(&[HARDENED_OFFSET]),
+            Err(DerivationError::HardenedIndex(HARDENED_OFFSET))
+        );
+    }
+
+    #[test]
+    fn signs_for_derived_key() {
+        let keys: Vec<_> = wallet1()
+            .into_iter()
+            .map(|key| EddsaLocalKey {
+                chain_code: Some([7u8; 32]),
+                ..key.local_key
+            })
+            .collect();
+        let derived = keys[0].derive(&[44, 501, 0, 0]).unwrap();
+        assert_eq!(derived, keys[2].derive(&[44, 501, 0, 0]).unwrap());
+
+        let mut rng = deterministic_fast_rand("signs_for_derived_key", None);
+        let message = b"derived key signs";
+        let keypairs: Vec<_> = keys.iter().map(|key| key.keypair.clone()).collect();
+        let (nonce_shares, agg_nonce, nonce_vss_schemes) =
+            eph_keygen_t_n_parties(1, 3, &[1, 2, 3], &keypairs, message, &mut rng);
+
+        let signers = [0u16, 2];
+        let local_sigs: Vec<_> = signers
+            .iter()
+            .map(|&i| {
+                let i = usize::from(i);
+                LocalSig::compute_with_tweak(
+                    message,
+                    &nonce_shares[i],
+                    &keys[i].combined_share,
+                    &derived.tweak,
+                )
+            })
+            .collect();
+        let vss_sum_local_sigs = LocalSig::verify_local_sigs(
+            &local_sigs,
+            &signers,
+            &keys[0].vss_schemes,
+            &nonce_vss_schemes,
+        )
+        .unwrap();
+        let signature = thresholdsig::generate_with_tweak(
+            &vss_sum_local_sigs,
+            &local_sigs,
+            &signers,
+            agg_nonce,
+            &derived.tweak,
+        );
+        signature.verify(message, &derived.agg_pubkey).unwrap();
+        assert!(signature.verify(message, &keys[0].agg_pubkey).is_err());
+    }
+}
@@ -1,8 +1,13 @@
+use std::fmt;
 use std::mem::replace;
 use std::time::Duration;
 
+use crate::error::{culprits_of, Blame, TssError};
+use crate::gg20::derivation::ChainCode;
+use crate::gg20::state_machine::traits::RoundBlame;
 use crate::t_ed25519::ErrorType;
-use anyhow::{anyhow, Context};
+use anyhow::Context;
+use curv::arithmetic::Converter;
 use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
 use curv::elliptic::curves::{Ed25519, Point, Scalar};
 use curv::BigInt;
@@ -13,29 +18,40 @@ use round_based::containers::{
 };
 use round_based::{AsyncProtocol, IsCritical, Msg, StateMachine};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 use crate::t_ed25519::keygen::private::InternalError;
 use crate::t_ed25519::thresholdsig::{KeyGenBroadcastMessage1, Keys, Parameters, SharedKeys};
-use crate::utils::sm_client::join_computation;
+use crate::utils::echo_broadcast::EchoBroadcast;
+use crate::utils::secret::Redacted;
+use crate::utils::secure_channel::{Identity, Roster};
+use crate::utils::session_id::SessionId;
+use crate::utils::transport::{join_computation, Transport};
 
-pub async fn start_keygen(
+pub async fn start_keygen<T>(
     request_id: &str,
-    token: &str,
-    address: &str,
+    transport: &T,
+    identity: &Identity,
     room: &str,
     t: u16,
     n: u16,
     party_id: u16,
-) -> anyhow::Result<EddsaLocalKey> {
-    let (_, incoming, outgoing) = join_computation(
-        request_id,
-        token,
-        surf::Url::parse(address)?,
-        &format!("{}-eddsa", room),
-        (1..(n + 1)).collect(),
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> anyhow::Result<EddsaLocalKey>
+where
+    T: Transport + ?Sized,
+{
+    let room_id = format!("{}-eddsa", room);
+    let parties: Vec<u16> = (1..(n + 1)).collect();
+    let (_, roster, incoming, outgoing) = join_computation(
+        transport,
+        &room_id,
+        parties.clone(),
         Some(party_id),
         None,
+        identity,
     )
     .await
     .context("join computation")?;
@@ -44,12 +60,24 @@ pub async fn start_keygen(
     tokio::pin!(incoming);
     tokio::pin!(outgoing);
 
-    let keygen = EddsaKeygen::new(party_id, t, n)?;
+    let sid = SessionId::new("eddsa-keygen", &room_id, &parties).with_roster(&roster);
+    let mut keygen = EddsaKeygen::new(party_id, t, n, sid)?;
+    keygen.set_round_timeout(round_timeout);
+    let identity_keys = roster.values().copied().collect();
+    let keygen = EchoBroadcast::new(keygen, echo_broadcast, sid, identity, identity_keys)?;
     let local_share = AsyncProtocol::new(keygen, incoming, outgoing)
         .run()
         .await
-        .map_err(|e| anyhow!("protocol execution terminated with error: {}", e))?;
-    Ok(local_share)
+        .map_err(TssError::from)
+        .context("protocol execution terminated with error")?;
+    println!(
+        "requestId={} completed eddsa keygen for party: {}",
+        request_id, party_id
+    );
+    Ok(EddsaLocalKey {
+        roster: Some(roster),
+        ..local_share
+    })
 }
 
 pub struct EddsaKeygen {
@@ -59,6 +87,7 @@ pub struct EddsaKeygen {
     msgs_queue: Vec<Msg<EddsaProtocolMessage>>,
     party_i: u16,
     party_n: u16,
+    round_timeout: Option<Duration>,
 }
 // Rounds
 
@@ -71,12 +100,14 @@ enum R {
 }
 
 struct Round0 {
+    pub sid: SessionId,
     pub party_i: u16,
     pub t: u16,
     pub n: u16,
 }
 
 pub struct Round1 {
+    sid: SessionId,
     keypair: Keys,
     round_msg: EddsaKeyGenBroadcastForRound1,
 
@@ -89,6 +120,7 @@ pub struct Round2 {
     round_msg: EddsaKeyGenBroadcastForRound2,
     agg_pubkey: Point<Ed25519>,
     pubkeys_list: Vec<Point<Ed25519>>,
+    chain_code: ChainCode,
 
     keypair: Keys,
 
@@ -109,6 +141,41 @@ pub struct EddsaLocalKey {
     pub party_i: u16,
     pub t: u16,
     pub n: u16,
+    /// Chain code for soft derivation, `None` for keys generated before derivation was supported
+    #[serde(default)]
+    pub chain_code: Option<ChainCode>,
+    /// Identity keys of the parties, `None` for keys generated before identities were bound
+    #[serde(default)]
+    pub roster: Option<Roster>,
+}
+
+impl EddsaLocalKey {
+    /// Public shares `x_j * G` of every party, derived from VSS commitments
+    pub fn public_shares(&self) -> Vec<Point<Ed25519>> {
+        (1..=self.n)
+            .map(|j| {
+                let first = self.vss_schemes[0].get_point_commitment(j);
+                self.vss_schemes[1..]
+                    .iter()
+                    .fold(first, |acc, vss| acc + vss.get_point_commitment(j))
+            })
+            .collect()
+    }
+
+    /// Hex-encoded digest of VSS commitments, changes whenever the shares are refreshed or
+    /// reshared
+    ///
+    /// Nonces are bound to the epoch of the key they were generated for, see
+    /// [EddsaOfflineResult::key_epoch](crate::utils::common::EddsaOfflineResult::key_epoch).
+    pub fn epoch(&self) -> String {
+        let mut hasher = Sha256::new();
+        for vss in &self.vss_schemes {
+            for commitment in &vss.commitments {
+                hasher.update(commitment.to_bytes(true).to_vec());
+            }
+        }
+        hex::encode(hasher.finalize())
+    }
 }
 
 impl Round0 {
@@ -117,7 +184,7 @@ impl Round0 {
         O: Push<Msg<EddsaKeyGenBroadcastForRound1>>,
     {
         let keypair = Keys::phase1_create(self.party_i);
-        let (first_msg, first_msg_blind) = keypair.phase1_broadcast();
+        let (first_msg, first_msg_blind) = keypair.phase1_broadcast(&self.sid);
         let public_key = keypair.clone().keypair.public_key;
 
         let round_msg = EddsaKeyGenBroadcastForRound1 {
@@ -131,6 +198,7 @@ impl Round0 {
             body: round_msg.clone(),
         });
         Ok(Round1 {
+            sid: self.sid,
             round_msg,
             keypair,
             party_i: self.party_i,
@@ -167,6 +235,15 @@ impl Round1 {
             .into_iter()
             .map(|msg| msg.first_msg_blind)
             .collect();
+        // chain code is public, it only has to be the same for all parties
+        let chain_code = first_msg_blinds
+            .iter()
+            .fold(
+                Sha256::new().chain(b"t_ed25519 chain code"),
+                |hasher, blind| hasher.chain(blind.to_bytes()),
+            )
+            .finalize()
+            .into();
         let agg_pubkey = {
             let first_key = pubkeys_list[0].clone();
             pubkeys_list[1..].iter().fold(first_key, |acc, p| acc + p)
@@ -179,6 +256,7 @@ impl Round1 {
         let (vss_scheme, secret_share) = self
             .keypair
             .phase1_verify_com_phase2_distribute(
+                &self.sid,
                 &params,
                 &first_msg_blinds,
                 &pubkeys_list,
@@ -210,6 +288,7 @@ impl Round1 {
             round_msg,
             pubkeys_list,
             agg_pubkey,
+            chain_code,
             keypair: self.keypair,
             party_i: self.party_i,
             t: self.t,
@@ -264,6 +343,8 @@ impl Round2 {
             party_i: self.party_i,
             t: self.t,
             n: self.n,
+            chain_code: Some(self.chain_code),
+            roster: None,
         })
     }
     pub fn is_expensive(&self) -> bool {
@@ -276,7 +357,7 @@ impl Round2 {
 }
 
 impl EddsaKeygen {
-    pub fn new(i: u16, t: u16, n: u16) -> Result<Self> {
+    pub fn new(i: u16, t: u16, n: u16, sid: SessionId) -> Result<Self> {
         if n < 2 {
             return Err(Error::TooFewParties);
         }
@@ -287,7 +368,12 @@ impl EddsaKeygen {
             return Err(Error::InvalidPartyIndex);
         }
         let mut state = Self {
-            round: R::Round0(Round0 { party_i: i, t, n }),
+            round: R::Round0(Round0 {
+                sid,
+                party_i: i,
+                t,
+                n,
+            }),
 
             msgs1: Some(Round1::expects_messages(i, n)),
             msgs2: Some(Round2::expects_messages(i, n)),
@@ -295,12 +381,21 @@ impl EddsaKeygen {
             msgs_queue: vec![],
             party_i: i,
             party_n: n,
+            round_timeout: None,
         };
 
         state.proceed_round(false)?;
         Ok(state)
     }
 
+    /// Sets how long every keygen round waits for messages of other parties
+    ///
+    /// When the deadline is reached, keygen fails with [Error::RoundTimeout]. `None` (default)
+    /// waits forever.
+    pub fn set_round_timeout(&mut self, timeout: Option<Duration>) {
+        self.round_timeout = timeout;
+    }
+
     fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
     where
         F: FnMut(T) -> M + 'a,
@@ -453,11 +548,16 @@ impl StateMachine for EddsaKeygen {
     }
 
     fn round_timeout(&self) -> Option<Duration> {
-        None
+        self.round_timeout
     }
 
     fn round_timeout_reached(&mut self) -> Self::Err {
-        panic!("no timeout was set")
+        let (missing_messages, missing_parties) = self.round_blame();
+        Error::RoundTimeout {
+            round: self.current_round(),
+            missing_messages,
+            missing_parties,
+        }
     }
 
     fn is_finished(&self) -> bool {
@@ -499,6 +599,21 @@ impl StateMachine for EddsaKeygen {
     }
 }
 
+impl RoundBlame for EddsaKeygen {
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Round2(_) => store2_blame,
+            R::Final(_) | R::Gone => default,
+        }
+    }
+}
+
 mod private {
     #[derive(Debug)]
     #[non_exhaustive]
@@ -517,6 +632,17 @@ impl IsCritical for Error {
     }
 }
 
+impl Blame for Error {
+    fn culprits(&self) -> Vec<u16> {
+        match self {
+            Error::ProceedRound(
+                ProceedError::Round1Error(err) | ProceedError::Round2Error(err),
+            ) => culprits_of(&err.bad_actors),
+            _ => vec![],
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 #[non_exhaustive]
 pub enum Error {
@@ -546,6 +672,15 @@ pub enum Error {
     /// Received message didn't pass pre-validation
     #[error("received message didn't pass pre-validation: {0}")]
     HandleMessage(#[source] StoreErr),
+    /// Round deadline set by [EddsaKeygen::set_round_timeout] was reached
+    #[error(
+        "round {round} timed out waiting for {missing_messages} messages from parties {missing_parties:?}"
+    )]
+    RoundTimeout {
+        round: u16,
+        missing_messages: u16,
+        missing_parties: Vec<u16>,
+    },
 }
 
 #[derive(Debug, Error)]
@@ -563,12 +698,21 @@ pub struct EddsaKeyGenBroadcastForRound1 {
     pub public_key: Point<Ed25519>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct EddsaKeyGenBroadcastForRound2 {
     pub own_share: Scalar<Ed25519>,
     pub vss_scheme: VerifiableSS<Ed25519>,
 }
 
+impl fmt::Debug for EddsaKeyGenBroadcastForRound2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EddsaKeyGenBroadcastForRound2")
+            .field("own_share", &Redacted)
+            .field("vss_scheme", &self.vss_scheme)
+            .finish()
+    }
+}
+
 impl From<InternalError> for Error {
     fn from(err: InternalError) -> Self {
         Self::InternalError(err)
@@ -1,5 +1,9 @@
+pub mod derivation;
 pub mod keygen;
 pub mod presignature;
+pub mod recover;
+pub mod refresh;
+pub mod reshare;
 pub mod signing;
 
 use curv::arithmetic::Converter;
@@ -10,6 +14,8 @@ use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha512};
 
+use crate::utils::secret::Redacted;
+
 // simple ed25519 based on rfc8032
 // reference implementation: https://ed25519.cr.yp.to/python/ed25519.py
 pub mod thresholdsig;
@@ -38,12 +44,21 @@ impl fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ExpandedPrivateKey {
     pub prefix: Scalar<Ed25519>,
     private_key: Scalar<Ed25519>,
 }
 
+impl fmt::Debug for ExpandedPrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExpandedPrivateKey")
+            .field("prefix", &Redacted)
+            .field("private_key", &Redacted)
+            .finish()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ExpandedKeyPair {
     pub public_key: Point<Ed25519>,
@@ -118,12 +133,14 @@ impl Signature {
 #[cfg(test)]
 pub(crate) mod tests {
 
+    use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
     use curv::elliptic::curves::{Ed25519, Point, Scalar};
     use ed25519_dalek::Verifier;
     use rand::{thread_rng, Rng};
     use rand_xoshiro::rand_core::{RngCore, SeedableRng};
     use rand_xoshiro::Xoshiro256PlusPlus;
 
+    use crate::t_ed25519::keygen::EddsaKeyGenBroadcastForRound2;
     use crate::t_ed25519::{ExpandedKeyPair, Signature};
 
     pub fn verify_dalek(pk: &Point<Ed25519>, sig: &Signature, msg: &[u8]) -> bool {
@@ -191,4 +208,16 @@ pub(crate) mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_debug_hides_shares() {
+        let (vss_scheme, shares) = VerifiableSS::<Ed25519>::share(1, 2, &Scalar::random());
+        let msg = EddsaKeyGenBroadcastForRound2 {
+            own_share: shares[0].clone(),
+            vss_scheme,
+        };
+        let printed = format!("{:?}", msg);
+        assert!(printed.contains("[redacted]"));
+        assert!(!printed.contains(&format!("{:?}", shares[0])));
+    }
 }
@@ -1,7 +1,7 @@
 use std::time::Duration;
-use std::{collections::HashMap, mem::replace};
+use std::{collections::HashMap, fmt, mem::replace};
 
-use anyhow::{anyhow, Context};
+use anyhow::Context;
 use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
 use curv::elliptic::curves::{Ed25519, Point, Scalar};
 use curv::BigInt;
@@ -14,6 +14,8 @@ use round_based::{AsyncProtocol, IsCritical, Msg, StateMachine};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::error::{culprits_of, Blame, TssError};
+use crate::gg20::state_machine::traits::RoundBlame;
 use crate::t_ed25519::keygen::EddsaLocalKey;
 use crate::t_ed25519::presignature::private::InternalError;
 use crate::t_ed25519::thresholdsig::{
@@ -21,20 +23,29 @@ use crate::t_ed25519::thresholdsig::{
 };
 use crate::t_ed25519::ErrorType;
 use crate::utils::common::EddsaOfflineResult;
-use crate::utils::sm_client::join_computation;
+use crate::utils::echo_broadcast::EchoBroadcast;
+use crate::utils::secret::Redacted;
+use crate::utils::secure_channel::Identity;
+use crate::utils::session_id::SessionId;
+use crate::utils::transport::{join_computation, Transport};
 
-pub async fn generate_offline_signing(
+pub async fn generate_offline_signing<T>(
     request_id: &str,
-    token: &str,
+    transport: &T,
+    identity: &Identity,
     local_share: &Keys,
-    address: &str,
     room: &str,
     t: u16,
     n: u16,
     party_id: u16,
     parties: Vec<u16>,
     no_nonces: u16,
-) -> anyhow::Result<Vec<EddsaOffline>> {
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> anyhow::Result<Vec<EddsaOffline>>
+where
+    T: Transport + ?Sized,
+{
     println!(
         "requestId={} start offline for party: {} in group {:?} room {}",
         request_id,
@@ -43,14 +54,14 @@ pub async fn generate_offline_signing(
         room.clone()
     );
 
-    let (party_id, incoming, outgoing) = join_computation(
-        request_id,
-        token,
-        surf::Url::parse(address).unwrap(),
-        &format!("{}-offline", room),
+    let room_id = format!("{}-offline", room);
+    let (party_id, roster, incoming, outgoing) = join_computation(
+        transport,
+        &room_id,
         parties.clone(),
         Some(party_id),
         None,
+        identity,
     )
     .await
     .context("join offline computation")?;
@@ -64,7 +75,8 @@ pub async fn generate_offline_signing(
     tokio::pin!(incoming);
     tokio::pin!(outgoing);
 
-    let signing = EddsaOfflineGen::new(
+    let sid = SessionId::new("eddsa-presign", &room_id, &parties).with_roster(&roster);
+    let mut signing = EddsaOfflineGen::new(
         local_share,
         party_id,
         t,
@@ -72,15 +84,22 @@ pub async fn generate_offline_signing(
         n,
         no_nonces,
         request_id,
+        sid,
     )?;
+    signing.set_round_timeout(round_timeout);
+    let identity_keys = parties
+        .iter()
+        .filter_map(|j| roster.get(j).copied())
+        .collect();
+    let signing = EchoBroadcast::new(signing, echo_broadcast, sid, identity, identity_keys)?;
     let completed_offline_stage = AsyncProtocol::new(signing, incoming, outgoing)
         .run()
         .await
-        .map_err(|e| {
-            anyhow!(
-                "offline generation failed for parties {:?} with error: {}",
-                parties,
-                e
+        .map_err(TssError::from)
+        .with_context(|| {
+            format!(
+                "offline generation failed for parties {:?} with error",
+                parties
             )
         })?;
     println!(
@@ -90,21 +109,26 @@ pub async fn generate_offline_signing(
     Ok(completed_offline_stage)
 }
 
-pub async fn generate_dynamic_nonces(
+pub async fn generate_dynamic_nonces<T>(
     request_id: &str,
-    token: &str,
-    rust_address: &str,
+    transport: &T,
+    identity: &Identity,
     rust_room: &str,
     nonce_start_index: u16,
     max_nonce_per_refresh: u16,
     eddsa_local_key: &EddsaLocalKey,
-) -> anyhow::Result<EddsaOfflineResult> {
+    round_timeout: Option<Duration>,
+    echo_broadcast: bool,
+) -> anyhow::Result<EddsaOfflineResult>
+where
+    T: Transport + ?Sized,
+{
     let all_parties: Vec<u16> = (1..(eddsa_local_key.n + 1)).collect();
     let completed_offline = crate::t_ed25519::presignature::generate_offline_signing(
         request_id,
-        token,
+        transport,
+        identity,
         &eddsa_local_key.clone().keypair,
-        rust_address,
         format!(
             "{}-eddsa-offline-{}_{}",
             rust_room, nonce_start_index, max_nonce_per_refresh
@@ -115,6 +139,8 @@ pub async fn generate_dynamic_nonces(
         eddsa_local_key.party_i,
         all_parties.clone(),
         max_nonce_per_refresh,
+        round_timeout,
+        echo_broadcast,
     )
     .await?;
     let eddsa_offline_data = EddsaOfflineResult {
@@ -122,6 +148,8 @@ pub async fn generate_dynamic_nonces(
         nonce_start_index,
         nonce_size: nonce_start_index + max_nonce_per_refresh,
         completed_offline,
+        consumed_nonces: Default::default(),
+        key_epoch: Some(eddsa_local_key.epoch()),
     };
     Ok(eddsa_offline_data)
 }
@@ -133,6 +161,7 @@ pub struct EddsaOfflineGen {
     msgs_queue: Vec<Msg<EddsaProtocolMessage>>,
     party_i: u16,
     party_n: u16,
+    round_timeout: Option<Duration>,
 }
 // Rounds
 
@@ -145,6 +174,7 @@ enum R {
 }
 
 struct Round0 {
+    pub sid: SessionId,
     pub keypair: Keys,
     pub party_i: u16,
     pub t: u16,
@@ -155,6 +185,7 @@ struct Round0 {
 }
 
 pub struct Round1 {
+    sid: SessionId,
     round_msg: Vec<EddsaOfflineBroadcastForRound1>,
     nonce_key: Vec<EphemeralKey>,
 
@@ -205,7 +236,7 @@ impl Round0 {
                 self.party_i,
             );
             let (R, nonce_key_i) = (ephemeral_key.R_i.clone(), ephemeral_key);
-            let (first_msg, first_msg_blind) = nonce_key_i.phase1_broadcast();
+            let (first_msg, first_msg_blind) = nonce_key_i.phase1_broadcast(&self.sid);
             nonce_key.push(nonce_key_i);
 
             round_msg.push(EddsaOfflineBroadcastForRound1 {
@@ -224,6 +255,7 @@ impl Round0 {
             body: round_msg.clone(),
         });
         Ok(Round1 {
+            sid: self.sid,
             round_msg,
             nonce_key,
             keypair: self.keypair,
@@ -282,6 +314,7 @@ impl Round1 {
             });
             let (nonce_vss_scheme_i, nonce_secret_share_i) = self.nonce_key[i]
                 .phase1_verify_com_phase2_distribute(
+                    &self.sid,
                     &params,
                     &first_msg_blinds,
                     &Rs_i,
@@ -413,6 +446,7 @@ impl EddsaOfflineGen {
         n: u16,
         no_nonces: u16,
         request_id: &str,
+        sid: SessionId,
     ) -> Result<Self> {
         if n < 2 {
             return Err(Error::TooFewParties);
@@ -428,6 +462,7 @@ impl EddsaOfflineGen {
         }
         let mut state = Self {
             round: R::Round0(Round0 {
+                sid,
                 party_i: i,
                 keypair: keypair.clone(),
                 t,
@@ -443,12 +478,21 @@ impl EddsaOfflineGen {
             msgs_queue: vec![],
             party_i: i,
             party_n: n,
+            round_timeout: None,
         };
 
         state.proceed_round(false)?;
         Ok(state)
     }
 
+    /// Sets how long every round waits for nonce commitments and shares of other parties
+    ///
+    /// When the deadline is reached, nonce generation fails with [Error::RoundTimeout].
+    /// `None` (default) waits forever.
+    pub fn set_round_timeout(&mut self, timeout: Option<Duration>) {
+        self.round_timeout = timeout;
+    }
+
     fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
     where
         F: FnMut(T) -> M + 'a,
@@ -601,11 +645,16 @@ impl StateMachine for EddsaOfflineGen {
     }
 
     fn round_timeout(&self) -> Option<Duration> {
-        None
+        self.round_timeout
     }
 
     fn round_timeout_reached(&mut self) -> Self::Err {
-        panic!("no timeout was set")
+        let (missing_messages, missing_parties) = self.round_blame();
+        Error::RoundTimeout {
+            round: self.current_round(),
+            missing_messages,
+            missing_parties,
+        }
     }
 
     fn is_finished(&self) -> bool {
@@ -647,6 +696,21 @@ impl StateMachine for EddsaOfflineGen {
     }
 }
 
+impl RoundBlame for EddsaOfflineGen {
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Round2(_) => store2_blame,
+            R::Final(_) | R::Gone => default,
+        }
+    }
+}
+
 mod private {
     #[derive(Debug)]
     #[non_exhaustive]
@@ -665,6 +729,17 @@ impl IsCritical for Error {
     }
 }
 
+impl Blame for Error {
+    fn culprits(&self) -> Vec<u16> {
+        match self {
+            Error::ProceedRound(
+                ProceedError::Round1Error(err) | ProceedError::Round2Error(err),
+            ) => culprits_of(&err.bad_actors),
+            _ => vec![],
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 #[non_exhaustive]
 pub enum Error {
@@ -696,6 +771,15 @@ pub enum Error {
     /// Received message didn't pass pre-validation
     #[error("received message didn't pass pre-validation: {0}")]
     HandleMessage(#[source] StoreErr),
+    /// Round deadline set by [EddsaOfflineGen::set_round_timeout] was reached
+    #[error(
+        "round {round} timed out waiting for {missing_messages} messages from parties {missing_parties:?}"
+    )]
+    RoundTimeout {
+        round: u16,
+        missing_messages: u16,
+        missing_parties: Vec<u16>,
+    },
 }
 
 #[derive(Debug, Error)]
@@ -713,12 +797,21 @@ pub struct EddsaOfflineBroadcastForRound1 {
     pub R: Point<Ed25519>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct EddsaOfflineBroadcastForRound2 {
     pub nonce_vss_scheme: VerifiableSS<Ed25519>,
     pub nonce_own_share: Scalar<Ed25519>,
 }
 
+impl fmt::Debug for EddsaOfflineBroadcastForRound2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EddsaOfflineBroadcastForRound2")
+            .field("nonce_vss_scheme", &self.nonce_vss_scheme)
+            .field("nonce_own_share", &Redacted)
+            .finish()
+    }
+}
+
 impl From<InternalError> for Error {
     fn from(err: InternalError) -> Self {
         Self::InternalError(err)
@@ -0,0 +1,920 @@
+use std::fmt;
+use std::mem::replace;
+use std::time::Duration;
+
+use anyhow::Context;
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use futures::StreamExt;
+use round_based::containers::{
+    push::{Push, PushExt},
+    *,
+};
+use round_based::{AsyncProtocol, IsCritical, Msg, StateMachine};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::error::{culprits_of, Blame, TssError};
+use crate::gg20::derivation::ChainCode;
+use crate::gg20::recover::recovery_parties;
+use crate::gg20::state_machine::traits::RoundBlame;
+use crate::t_ed25519::keygen::EddsaLocalKey;
+use crate::t_ed25519::recover::private::InternalError;
+use crate::t_ed25519::thresholdsig::Keys;
+use crate::t_ed25519::ErrorType;
+use crate::utils::secret::Redacted;
+use crate::utils::secure_channel::{Identity, Roster};
+use crate::utils::transport::{join_subset_computation, Transport};
+
+/// Recovers EdDSA share of party `lost_i` on a replacement device with help of `t+1` `helpers`
+///
+/// Helpers blind their Lagrange-weighted shares in the same way as
+/// [KeyRecovery](crate::gg20::state_machine::recover::KeyRecovery) does it, so none of them learns
+/// the recovered share. The replacement device gets a new prefix for deterministic nonces.
+pub async fn start_recovery<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    room: &str,
+    t: u16,
+    n: u16,
+    local_key: Option<&EddsaLocalKey>,
+    lost_i: u16,
+    helpers: &[u16],
+    round_timeout: Option<Duration>,
+) -> anyhow::Result<EddsaLocalKey>
+where
+    T: Transport + ?Sized,
+{
+    let party_id = local_key.map(|key| key.party_i).unwrap_or(lost_i);
+    println!(
+        "requestId={} start eddsa recovery of party {} for party: {} with helpers {:?} room {}",
+        request_id, lost_i, party_id, helpers, room
+    );
+    let parties = recovery_parties(helpers, lost_i);
+    let (room_i, roster, incoming, outgoing) = join_subset_computation(
+        transport,
+        &format!("{}-eddsa-recover", room),
+        parties.clone(),
+        party_id,
+        identity,
+    )
+    .await
+    .context("join recovery computation")?;
+
+    let incoming = incoming.fuse();
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+
+    let mut recovery = EddsaRecovery::new(room_i, parties, t, n, local_key.cloned(), lost_i)?;
+    recovery.set_round_timeout(round_timeout);
+    let mut recovered_key = AsyncProtocol::new(recovery, incoming, outgoing)
+        .run()
+        .await
+        .map_err(TssError::from)
+        .context("recovery execution terminated with error")?;
+    println!(
+        "requestId={} completed eddsa recovery for party: {}",
+        request_id, party_id
+    );
+    let mut full_roster = recovered_key.roster.take().unwrap_or_default();
+    full_roster.extend(roster);
+    recovered_key.roster = Some(full_roster);
+    Ok(recovered_key)
+}
+
+pub struct EddsaRecovery {
+    round: R,
+    msgs1: Option<Store<P2PMsgs<EddsaRecoveryBlinding>>>,
+    msgs2: Option<Store<P2PMsgs<EddsaRecoveredPart>>>,
+    msgs_queue: Vec<Msg<EddsaRecoveryProtocolMessage>>,
+    party_i: u16,
+    party_n: u16,
+    round_timeout: Option<Duration>,
+}
+// Rounds
+
+enum R {
+    Round0(Round0),
+    Round1(Round1),
+    Round2(Round2),
+    Final(EddsaLocalKey),
+    Gone,
+}
+
+struct Round0 {
+    i: u16,
+    s_l: Vec<u16>,
+    t: u16,
+    n: u16,
+    local_key: Option<EddsaLocalKey>,
+    lost_i: u16,
+}
+
+pub struct Round1 {
+    own_blinding: EddsaRecoveryBlinding,
+    i: u16,
+    s_l: Vec<u16>,
+    t: u16,
+    n: u16,
+    local_key: Option<EddsaLocalKey>,
+    lost_i: u16,
+}
+
+pub struct Round2 {
+    s_l: Vec<u16>,
+    t: u16,
+    n: u16,
+    local_key: Option<EddsaLocalKey>,
+    lost_i: u16,
+}
+
+impl Round0 {
+    pub fn proceed<O>(self, mut output: O) -> std::result::Result<Round1, ProceedError>
+    where
+        O: Push<Msg<EddsaRecoveryBlinding>>,
+    {
+        let helpers: Vec<u16> = self
+            .s_l
+            .iter()
+            .copied()
+            .filter(|j| *j != self.lost_i)
+            .collect();
+        let mut own_blinding = EddsaRecoveryBlinding { delta: None };
+        if let Some(key) = &self.local_key {
+            // weighted share is split into random additive parts, one per helper
+            let lambda = lagrange_coefficient_at(self.lost_i, key.party_i, &helpers);
+            let mut own_delta = lambda * &key.combined_share.x_i;
+            for (j, _) in (1..).zip(&self.s_l).filter(|(_, k)| **k != self.lost_i) {
+                if j == self.i {
+                    continue;
+                }
+                let delta = Scalar::<Ed25519>::random();
+                own_delta = own_delta - &delta;
+                output.push(Msg {
+                    sender: self.i,
+                    receiver: Some(j),
+                    body: EddsaRecoveryBlinding { delta: Some(delta) },
+                });
+            }
+            own_blinding.delta = Some(own_delta);
+        }
+        for (j, &key_j) in (1..).zip(&self.s_l) {
+            if j == self.i || (self.local_key.is_some() && key_j != self.lost_i) {
+                continue;
+            }
+            output.push(Msg {
+                sender: self.i,
+                receiver: Some(j),
+                body: EddsaRecoveryBlinding { delta: None },
+            });
+        }
+        Ok(Round1 {
+            own_blinding,
+            i: self.i,
+            s_l: self.s_l,
+            t: self.t,
+            n: self.n,
+            local_key: self.local_key,
+            lost_i: self.lost_i,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+}
+
+impl Round1 {
+    pub fn proceed<O>(
+        self,
+        input: P2PMsgs<EddsaRecoveryBlinding>,
+        mut output: O,
+    ) -> std::result::Result<Round2, ProceedError>
+    where
+        O: Push<Msg<EddsaRecoveredPart>>,
+    {
+        let blindings = input.into_vec_including_me(self.own_blinding);
+        let lost = lost_position(&self.s_l, self.lost_i);
+
+        if let Some(key) = &self.local_key {
+            let mut bad_actors = vec![];
+            let mut sigma = Scalar::<Ed25519>::zero();
+            for (k, blinding) in blindings.iter().enumerate().filter(|(k, _)| *k != lost) {
+                match &blinding.delta {
+                    Some(delta) => sigma = sigma + delta,
+                    None => bad_actors.push(k),
+                }
+            }
+            if !bad_actors.is_empty() {
+                return Err(ProceedError::Round1MissingBlinding(ErrorType {
+                    error_type: "missing blinding".to_string(),
+                    bad_actors,
+                }));
+            }
+            output.push(Msg {
+                sender: self.i,
+                receiver: Some(lost as u16 + 1),
+                body: EddsaRecoveredPart {
+                    sigma: Some(sigma),
+                    helper: Some(EddsaHelperInfo {
+                        agg_pubkey: key.agg_pubkey.clone(),
+                        pubkeys_list: key.pubkeys_list.clone(),
+                        vss_schemes: key.vss_schemes.clone(),
+                        chain_code: key.chain_code,
+                        roster: key.roster.clone(),
+                    }),
+                },
+            });
+        }
+        for (j, &key_j) in (1..).zip(&self.s_l) {
+            if j == self.i || (self.local_key.is_some() && key_j == self.lost_i) {
+                continue;
+            }
+            output.push(Msg {
+                sender: self.i,
+                receiver: Some(j),
+                body: EddsaRecoveredPart {
+                    sigma: None,
+                    helper: None,
+                },
+            });
+        }
+
+        Ok(Round2 {
+            s_l: self.s_l,
+            t: self.t,
+            n: self.n,
+            local_key: self.local_key,
+            lost_i: self.lost_i,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+
+    pub fn expects_messages(i: u16, m: u16) -> Store<P2PMsgs<EddsaRecoveryBlinding>> {
+        P2PMsgsStore::new(i, m)
+    }
+}
+
+impl Round2 {
+    pub fn proceed(
+        self,
+        input: P2PMsgs<EddsaRecoveredPart>,
+    ) -> std::result::Result<EddsaLocalKey, ProceedError> {
+        if let Some(local_key) = self.local_key {
+            // shares of the helpers stay the same
+            return Ok(local_key);
+        }
+        let lost = lost_position(&self.s_l, self.lost_i);
+        let parts = input.into_vec_including_me(EddsaRecoveredPart {
+            sigma: None,
+            helper: None,
+        });
+
+        // the first helper's view of the key is taken, the others have to agree with it
+        let first_helper = if lost == 0 { 1 } else { 0 };
+        let reference = match &parts[first_helper].helper {
+            Some(helper) => helper.clone(),
+            None => {
+                return Err(ProceedError::Round2InvalidPart(ErrorType {
+                    error_type: "helper didn't share public key".to_string(),
+                    bad_actors: vec![first_helper],
+                }))
+            }
+        };
+        let mut bad_actors = vec![];
+        let mut x_i = Scalar::<Ed25519>::zero();
+        for (k, part) in parts.iter().enumerate().filter(|(k, _)| *k != lost) {
+            match (&part.sigma, &part.helper) {
+                (Some(sigma), Some(helper))
+                    if helper.agg_pubkey == reference.agg_pubkey
+                        && helper.pubkeys_list == reference.pubkeys_list
+                        && same_vss_schemes(&helper.vss_schemes, &reference.vss_schemes)
+                        && helper.chain_code == reference.chain_code
+                        && helper.roster == reference.roster =>
+                {
+                    x_i = x_i + sigma
+                }
+                _ => bad_actors.push(k),
+            }
+        }
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round2InvalidPart(ErrorType {
+                error_type: "invalid recovered part".to_string(),
+                bad_actors,
+            }));
+        }
+        if reference.vss_schemes.len() != usize::from(self.n)
+            || reference.vss_schemes.iter().any(|vss| {
+                vss.parameters.threshold != self.t || vss.parameters.share_count != self.n
+            })
+        {
+            return Err(ProceedError::Round2InvalidPart(ErrorType {
+                error_type: "helpers hold a key of other parameters".to_string(),
+                bad_actors: vec![],
+            }));
+        }
+
+        // new prefix for deterministic nonces, the lost one can't be recovered
+        let keypair = Keys::phase1_create(self.lost_i);
+        let recovered = EddsaLocalKey {
+            combined_share: keypair.shared_keys(reference.agg_pubkey.clone(), x_i),
+            vss_schemes: reference.vss_schemes,
+            agg_pubkey: reference.agg_pubkey,
+            pubkeys_list: reference.pubkeys_list,
+            keypair,
+            party_i: self.lost_i,
+            t: self.t,
+            n: self.n,
+            chain_code: reference.chain_code,
+            roster: reference.roster,
+        };
+        if recovered.public_shares()[usize::from(self.lost_i - 1)]
+            != Point::generator() * &recovered.combined_share.x_i
+        {
+            return Err(ProceedError::Round2PublicShareMismatch);
+        }
+        Ok(recovered)
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+
+    pub fn expects_messages(i: u16, m: u16) -> Store<P2PMsgs<EddsaRecoveredPart>> {
+        P2PMsgsStore::new(i, m)
+    }
+}
+
+/// Position of the replacement party in `s_l`, starting from 0
+fn lost_position(s_l: &[u16], lost_i: u16) -> usize {
+    s_l.iter()
+        .position(|j| *j == lost_i)
+        .expect("checked by the state machine constructor")
+}
+
+fn same_vss_schemes(a: &[VerifiableSS<Ed25519>], b: &[VerifiableSS<Ed25519>]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            a.parameters.threshold == b.parameters.threshold
+                && a.parameters.share_count == b.parameters.share_count
+                && a.commitments == b.commitments
+        })
+}
+
+/// Lagrange coefficient of party `j` for evaluating the shared polynomial at point `x` over the
+/// set of `parties`
+fn lagrange_coefficient_at(x: u16, j: u16, parties: &[u16]) -> Scalar<Ed25519> {
+    let scalar = |k: u16| Scalar::<Ed25519>::from(u64::from(k));
+    let (num, denom) = parties.iter().filter(|k| **k != j).fold(
+        (Scalar::<Ed25519>::from(1u64), Scalar::<Ed25519>::from(1u64)),
+        |(num, denom), &k| {
+            (
+                num * (scalar(x) - scalar(k)),
+                denom * (scalar(j) - scalar(k)),
+            )
+        },
+    );
+    num * denom
+        .invert()
+        .expect("party indexes are distinct, so the denominator is never zero")
+}
+
+impl EddsaRecovery {
+    /// Constructs a party of share recovery protocol
+    ///
+    /// Takes the same arguments as
+    /// [KeyRecovery::new](crate::gg20::state_machine::recover::KeyRecovery::new) except for
+    /// `helpers`, `pre_params` and the session identifier: there are no observers, so `s_l`
+    /// lists `t+1` helpers and `lost_i`, `i` is the position of the party in `s_l`.
+    pub fn new(
+        i: u16,
+        s_l: Vec<u16>,
+        t: u16,
+        n: u16,
+        local_key: Option<EddsaLocalKey>,
+        lost_i: u16,
+    ) -> Result<Self> {
+        if t == 0 || t + 1 >= n {
+            return Err(Error::InvalidThreshold);
+        }
+        if lost_i == 0 || lost_i > n {
+            return Err(Error::InvalidPartyIndex);
+        }
+        let mut distinct = s_l.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        if distinct.len() != usize::from(t) + 2
+            || s_l.len() != distinct.len()
+            || !s_l.contains(&lost_i)
+            || s_l.iter().any(|&j| j == 0 || j > n)
+        {
+            return Err(Error::InvalidHelpers);
+        }
+        if i == 0 || usize::from(i) > s_l.len() {
+            return Err(Error::InvalidPartyIndex);
+        }
+        let key_i = s_l[usize::from(i - 1)];
+        if local_key.is_some() == (key_i == lost_i) {
+            return Err(Error::InvalidRole);
+        }
+        if let Some(key) = &local_key {
+            if key.party_i != key_i || key.t != t || key.n != n || key.vss_schemes.is_empty() {
+                return Err(Error::InvalidLocalKey);
+            }
+        }
+        let m = t + 2;
+        let mut state = Self {
+            round: R::Round0(Round0 {
+                i,
+                s_l,
+                t,
+                n,
+                local_key,
+                lost_i,
+            }),
+
+            msgs1: Some(Round1::expects_messages(i, m)),
+            msgs2: Some(Round2::expects_messages(i, m)),
+
+            msgs_queue: vec![],
+            party_i: i,
+            party_n: m,
+            round_timeout: None,
+        };
+
+        state.proceed_round(false)?;
+        Ok(state)
+    }
+
+    /// Sets deadline for collecting messages of each recovery round
+    ///
+    /// Expired deadline fails the protocol with [Error::RoundTimeout]. `None` (default) means
+    /// no deadline.
+    pub fn set_round_timeout(&mut self, timeout: Option<Duration>) {
+        self.round_timeout = timeout;
+    }
+
+    fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
+    where
+        F: FnMut(T) -> M + 'a,
+    {
+        (&mut self.msgs_queue)
+            .gmap(move |m: Msg<T>| m.map_body(|m| EddsaRecoveryProtocolMessage(f(m))))
+    }
+
+    /// Proceeds round state if it received enough messages and if it's cheap to compute or
+    /// `may_block == true`
+    fn proceed_round(&mut self, may_block: bool) -> Result<()> {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        let next_state: R;
+        let try_again: bool = match replace(&mut self.round, R::Gone) {
+            R::Round0(round) if !round.is_expensive() || may_block => {
+                next_state = round
+                    .proceed(self.gmap_queue(M::Round1))
+                    .map(R::Round1)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round0(_) => {
+                next_state = s;
+                false
+            }
+            R::Round1(round) if !store1_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs1.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round2))
+                    .map(R::Round2)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round1(_) => {
+                next_state = s;
+                false
+            }
+            R::Round2(round) if !store2_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs2.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs)
+                    .map(R::Final)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round2(_) => {
+                next_state = s;
+                false
+            }
+            s @ R::Final(_) | s @ R::Gone => {
+                next_state = s;
+                false
+            }
+        };
+
+        self.round = next_state;
+        if try_again {
+            self.proceed_round(may_block)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Protocol message which parties send on wire
+///
+/// Hides actual messages structure so it could be changed without breaking semver policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EddsaRecoveryProtocolMessage(M);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum M {
+    Round1(EddsaRecoveryBlinding),
+    Round2(EddsaRecoveredPart),
+}
+
+impl StateMachine for EddsaRecovery {
+    type MessageBody = EddsaRecoveryProtocolMessage;
+    type Err = Error;
+    type Output = EddsaLocalKey;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<()> {
+        let current_round = self.current_round();
+
+        match msg.body {
+            EddsaRecoveryProtocolMessage(M::Round1(m)) => {
+                let store = self
+                    .msgs1
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 1,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            EddsaRecoveryProtocolMessage(M::Round2(m)) => {
+                let store = self
+                    .msgs2
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 2,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+        }
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        &mut self.msgs_queue
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        match &self.round {
+            R::Round0(_) => true,
+            R::Round1(_) => !store1_wants_more,
+            R::Round2(_) => !store2_wants_more,
+            R::Final(_) | R::Gone => false,
+        }
+    }
+
+    fn proceed(&mut self) -> Result<()> {
+        self.proceed_round(true)
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        self.round_timeout
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        let (missing_messages, missing_parties) = self.round_blame();
+        Error::RoundTimeout {
+            round: self.current_round(),
+            missing_messages,
+            missing_parties,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.round, R::Final(_))
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output>> {
+        match self.round {
+            R::Final(_) => (),
+            R::Gone => return Some(Err(Error::DoublePickOutput)),
+            _ => return None,
+        }
+
+        match replace(&mut self.round, R::Gone) {
+            R::Final(result) => Some(Ok(result)),
+            _ => unreachable!("guaranteed by match expression above"),
+        }
+    }
+
+    fn current_round(&self) -> u16 {
+        match &self.round {
+            R::Round0(_) => 0,
+            R::Round1(_) => 1,
+            R::Round2(_) => 2,
+            R::Final(_) | R::Gone => 3,
+        }
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        Some(2)
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.party_i
+    }
+
+    fn parties(&self) -> u16 {
+        self.party_n
+    }
+}
+
+impl RoundBlame for EddsaRecovery {
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Round2(_) => store2_blame,
+            R::Final(_) | R::Gone => default,
+        }
+    }
+}
+
+impl fmt::Debug for EddsaRecovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let current_round = match &self.round {
+            R::Round0(_) => "0",
+            R::Round1(_) => "1",
+            R::Round2(_) => "2",
+            R::Final(_) => "[Final]",
+            R::Gone => "[Gone]",
+        };
+        let msgs1 = match self.msgs1.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs2 = match self.msgs2.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        write!(
+            f,
+            "{{EddsaRecovery at round={} msgs1={} msgs2={} queue=[len={}]}}",
+            current_round,
+            msgs1,
+            msgs2,
+            self.msgs_queue.len()
+        )
+    }
+}
+
+mod private {
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum InternalError {
+        /// [Messages store](super::MessageStore) reported that it received all messages it wanted to receive,
+        /// but refused to return message container
+        RetrieveRoundMessages(super::StoreErr),
+        #[doc(hidden)]
+        StoreGone,
+    }
+}
+
+impl IsCritical for Error {
+    fn is_critical(&self) -> bool {
+        true
+    }
+}
+
+impl Blame for Error {
+    fn culprits(&self) -> Vec<u16> {
+        match self {
+            Error::ProceedRound(
+                ProceedError::Round1MissingBlinding(err) | ProceedError::Round2InvalidPart(err),
+            ) => culprits_of(&err.bad_actors),
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Round proceeding resulted in error
+    #[error("proceed round: {0}")]
+    ProceedRound(#[source] ProceedError),
+    /// Threshold value `t` is not in range `[1; n-2]`
+    #[error("threshold is not in range [1; n-2]")]
+    InvalidThreshold,
+    /// Participants are not `t+1` distinct helpers and the lost party
+    #[error("recovery takes t+1 distinct helpers and the lost party")]
+    InvalidHelpers,
+    /// Party index `i` or `lost_i` is out of range
+    #[error("party index is out of range")]
+    InvalidPartyIndex,
+    /// Helper came without its key, or replacement device came with one
+    #[error("only the replacement party comes without a local key")]
+    InvalidRole,
+    /// Local key doesn't match the party index and key parameters
+    #[error("local key is inconsistent with its parameters")]
+    InvalidLocalKey,
+    #[error("pick_output called twice")]
+    DoublePickOutput,
+    #[doc(hidden)]
+    #[error("internal error: {0:?}")]
+    InternalError(InternalError),
+    /// Received message which we didn't expect to receive now (e.g. message from previous round)
+    #[error(
+        "didn't expect to receive message from round {msg_round} (being at round {current_round})"
+    )]
+    ReceivedOutOfOrderMessage { current_round: u16, msg_round: u16 },
+
+    /// Received message didn't pass pre-validation
+    #[error("received message didn't pass pre-validation: {0}")]
+    HandleMessage(#[source] StoreErr),
+    /// Round deadline set by [EddsaRecovery::set_round_timeout] was reached
+    #[error(
+        "round {round} timed out waiting for {missing_messages} messages from parties {missing_parties:?}"
+    )]
+    RoundTimeout {
+        round: u16,
+        missing_messages: u16,
+        missing_parties: Vec<u16>,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum ProceedError {
+    #[error("round 1: helper didn't send its blinding: {0:?}")]
+    Round1MissingBlinding(ErrorType),
+    #[error("round 2: invalid part of the share: {0:?}")]
+    Round2InvalidPart(ErrorType),
+    #[error("round 2: recovered share doesn't match the public share")]
+    Round2PublicShareMismatch,
+}
+
+/// Additive share of a helper's weighted secret sent to another helper, `None` if sender or
+/// receiver is the replacement party
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EddsaRecoveryBlinding {
+    pub delta: Option<Scalar<Ed25519>>,
+}
+
+impl fmt::Debug for EddsaRecoveryBlinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EddsaRecoveryBlinding")
+            .field("delta", &self.delta.as_ref().map(|_| Redacted))
+            .finish()
+    }
+}
+
+/// Sum of blindings received by a helper together with public data of its key, sent to the
+/// replacement party only
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EddsaRecoveredPart {
+    pub sigma: Option<Scalar<Ed25519>>,
+    pub helper: Option<EddsaHelperInfo>,
+}
+
+impl fmt::Debug for EddsaRecoveredPart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EddsaRecoveredPart")
+            .field("sigma", &self.sigma.as_ref().map(|_| Redacted))
+            .field("helper", &self.helper)
+            .finish()
+    }
+}
+
+/// Public data of the key held by a helper
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EddsaHelperInfo {
+    pub agg_pubkey: Point<Ed25519>,
+    pub pubkeys_list: Vec<Point<Ed25519>>,
+    pub vss_schemes: Vec<VerifiableSS<Ed25519>>,
+    pub chain_code: Option<ChainCode>,
+    pub roster: Option<Roster>,
+}
+
+impl From<InternalError> for Error {
+    fn from(err: InternalError) -> Self {
+        Self::InternalError(err)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use round_based::dev::Simulation;
+
+    use crate::gg20::state_machine::reshare::test::room_roster;
+    use crate::t_ed25519::refresh::test::wallet1;
+    use crate::t_ed25519::reshare::test::simulate_reshare;
+
+    use super::*;
+
+    /// Recovers share of `lost_i` with `helpers`, returns keys of the participants ordered by
+    /// their key index
+    pub fn simulate_recovery(
+        keys: &[EddsaLocalKey],
+        lost_i: u16,
+        helpers: &[u16],
+    ) -> Vec<EddsaLocalKey> {
+        let mut simulation = Simulation::new();
+        simulation.enable_benchmarks(true);
+
+        let (t, n) = (keys[0].t, keys[0].n);
+        let s_l = recovery_parties(helpers, lost_i);
+        for (i, &key_i) in (1..).zip(&s_l) {
+            let local_key = if key_i == lost_i {
+                None
+            } else {
+                Some(keys[usize::from(key_i - 1)].clone())
+            };
+            simulation
+                .add_party(EddsaRecovery::new(i, s_l.clone(), t, n, local_key, lost_i).unwrap());
+        }
+
+        let keys = simulation.run().unwrap();
+
+        println!("Benchmark results:");
+        println!("{:#?}", simulation.benchmark_results().unwrap());
+
+        keys
+    }
+
+    #[test]
+    fn simulate_recovery_t1_n3() {
+        let keys: Vec<_> = wallet1().into_iter().map(|key| key.local_key).collect();
+        let recovered = simulate_recovery(&keys, 2, &[1, 3]);
+
+        let lost = &recovered[1];
+        assert_eq!(lost.party_i, 2);
+        assert_eq!(lost.combined_share.x_i, keys[1].combined_share.x_i);
+        assert_eq!(lost.agg_pubkey, keys[1].agg_pubkey);
+        assert_eq!(lost.epoch(), keys[1].epoch());
+    }
+
+    #[test]
+    fn simulate_recovery_t1_n4() {
+        let old_keys = wallet1();
+        let old_key = |i: usize| Some(old_keys[i].local_key.clone());
+        let keys: Vec<_> = simulate_reshare(
+            vec![
+                (old_key(0), true),
+                (old_key(1), true),
+                (old_key(2), true),
+                (None, true),
+            ],
+            &room_roster(4),
+            1,
+            4,
+        )
+        .into_iter()
+        .map(Option::unwrap)
+        .collect();
+
+        // party 2 stays offline
+        let recovered = simulate_recovery(&keys, 4, &[1, 3]);
+        assert_eq!(recovered.len(), 3);
+        let lost = &recovered[2];
+        assert_eq!(lost.party_i, 4);
+        assert_eq!(lost.combined_share.x_i, keys[3].combined_share.x_i);
+        assert_eq!(
+            lost.public_shares()[3],
+            Point::generator() * &lost.combined_share.x_i
+        );
+    }
+}
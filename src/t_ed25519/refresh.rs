@@ -0,0 +1,771 @@
+use std::fmt;
+use std::mem::replace;
+use std::time::Duration;
+
+use anyhow::Context;
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use futures::StreamExt;
+use round_based::containers::{
+    push::{Push, PushExt},
+    *,
+};
+use round_based::{AsyncProtocol, IsCritical, Msg, StateMachine};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::error::{culprits_of, Blame, TssError};
+use crate::gg20::state_machine::traits::RoundBlame;
+use crate::t_ed25519::keygen::EddsaLocalKey;
+use crate::t_ed25519::refresh::private::InternalError;
+use crate::t_ed25519::thresholdsig::{Keys, Parameters};
+use crate::t_ed25519::ErrorType;
+use crate::utils::secret::Redacted;
+use crate::utils::secure_channel::Identity;
+use crate::utils::transport::{join_computation, Transport};
+
+/// Refreshes EdDSA key shares of all `n` parties
+///
+/// Every party re-deals its Lagrange-weighted share with a fresh VSS, so the differences between
+/// old and new shares form a sharing of zero and `agg_pubkey` stays the same.
+pub async fn start_refresh<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    room: &str,
+    local_key: &EddsaLocalKey,
+    round_timeout: Option<Duration>,
+) -> anyhow::Result<EddsaLocalKey>
+where
+    T: Transport + ?Sized,
+{
+    println!(
+        "requestId={} start eddsa refresh for party: {} room {}",
+        request_id, local_key.party_i, room
+    );
+    let (_, roster, incoming, outgoing) = join_computation(
+        transport,
+        &format!("{}-eddsa-refresh", room),
+        (1..(local_key.n + 1)).collect(),
+        Some(local_key.party_i),
+        None,
+        identity,
+    )
+    .await
+    .context("join refresh computation")?;
+
+    let incoming = incoming.fuse();
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+
+    let mut refresh = EddsaRefresh::new(local_key.clone())?;
+    refresh.set_round_timeout(round_timeout);
+    let refreshed_key = AsyncProtocol::new(refresh, incoming, outgoing)
+        .run()
+        .await
+        .map_err(TssError::from)
+        .context("refresh execution terminated with error")?;
+    println!(
+        "requestId={} completed eddsa refresh for party: {}",
+        request_id, local_key.party_i
+    );
+    Ok(EddsaLocalKey {
+        roster: Some(roster),
+        ..refreshed_key
+    })
+}
+
+pub struct EddsaRefresh {
+    round: R,
+    msgs1: Option<Store<BroadcastMsgs<EddsaRefreshBroadcastForRound1>>>,
+    msgs2: Option<Store<P2PMsgs<EddsaRefreshBroadcastForRound2>>>,
+    msgs_queue: Vec<Msg<EddsaRefreshProtocolMessage>>,
+    party_i: u16,
+    party_n: u16,
+    round_timeout: Option<Duration>,
+}
+// Rounds
+
+enum R {
+    Round0(Round0),
+    Round1(Round1),
+    Round2(Round2),
+    Final(EddsaLocalKey),
+    Gone,
+}
+
+struct Round0 {
+    local_key: EddsaLocalKey,
+}
+
+pub struct Round1 {
+    round_msg: EddsaRefreshBroadcastForRound1,
+    secret_shares: Vec<Scalar<Ed25519>>,
+    expected_y_vec: Vec<Point<Ed25519>>,
+
+    local_key: EddsaLocalKey,
+}
+
+pub struct Round2 {
+    round_msg: EddsaRefreshBroadcastForRound2,
+    vss_schemes: Vec<VerifiableSS<Ed25519>>,
+    expected_y_vec: Vec<Point<Ed25519>>,
+
+    local_key: EddsaLocalKey,
+}
+
+impl Round0 {
+    pub fn proceed<O>(self, mut output: O) -> std::result::Result<Round1, ProceedError>
+    where
+        O: Push<Msg<EddsaRefreshBroadcastForRound1>>,
+    {
+        let (party_i, t, n) = (self.local_key.party_i, self.local_key.t, self.local_key.n);
+        let public_shares = self.local_key.public_shares();
+        let expected_y_vec: Vec<_> = (1..=n)
+            .map(|j| &public_shares[usize::from(j - 1)] * lagrange_coefficient(&self.local_key, j))
+            .collect();
+
+        let w_i =
+            lagrange_coefficient(&self.local_key, party_i) * &self.local_key.combined_share.x_i;
+        let parties: Vec<_> = (1..(n + 1)).collect();
+        let (vss_scheme, secret_shares) = VerifiableSS::share_at_indices(t, n, &w_i, &parties);
+
+        let round_msg = EddsaRefreshBroadcastForRound1 { vss_scheme };
+        output.push(Msg {
+            sender: party_i,
+            receiver: None,
+            body: round_msg.clone(),
+        });
+        Ok(Round1 {
+            round_msg,
+            secret_shares: secret_shares.to_vec(),
+            expected_y_vec,
+            local_key: self.local_key,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+}
+
+impl Round1 {
+    pub fn proceed<O>(
+        self,
+        input: BroadcastMsgs<EddsaRefreshBroadcastForRound1>,
+        mut output: O,
+    ) -> std::result::Result<Round2, ProceedError>
+    where
+        O: Push<Msg<EddsaRefreshBroadcastForRound2>>,
+    {
+        let party_i = self.local_key.party_i;
+        let vss_schemes: Vec<_> = input
+            .into_vec_including_me(self.round_msg)
+            .into_iter()
+            .map(|msg| msg.vss_scheme)
+            .collect();
+        let bad_actors: Vec<usize> = vss_schemes
+            .iter()
+            .zip(&self.expected_y_vec)
+            .enumerate()
+            .filter(|(_, (vss_scheme, expected_y))| {
+                vss_scheme.parameters.threshold != self.local_key.t
+                    || vss_scheme.parameters.share_count != self.local_key.n
+                    || vss_scheme.commitments.len() != usize::from(self.local_key.t) + 1
+                    || &vss_scheme.commitments[0] != *expected_y
+            })
+            .map(|(i, _)| i)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round1Error(ErrorType {
+                error_type: "unexpected vss commitments".to_string(),
+                bad_actors,
+            }));
+        }
+
+        for (i, share) in self.secret_shares.iter().enumerate() {
+            if i + 1 == usize::from(party_i) {
+                continue;
+            }
+
+            output.push(Msg {
+                sender: party_i,
+                receiver: Some(i as u16 + 1),
+                body: EddsaRefreshBroadcastForRound2 {
+                    own_share: share.clone(),
+                },
+            })
+        }
+
+        Ok(Round2 {
+            round_msg: EddsaRefreshBroadcastForRound2 {
+                own_share: self.secret_shares[usize::from(party_i - 1)].clone(),
+            },
+            vss_schemes,
+            expected_y_vec: self.expected_y_vec,
+            local_key: self.local_key,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+
+    pub fn expects_messages(
+        i: u16,
+        n: u16,
+    ) -> Store<BroadcastMsgs<EddsaRefreshBroadcastForRound1>> {
+        BroadcastMsgsStore::new(i, n)
+    }
+}
+
+impl Round2 {
+    pub fn proceed(
+        self,
+        input: P2PMsgs<EddsaRefreshBroadcastForRound2>,
+    ) -> std::result::Result<EddsaLocalKey, ProceedError> {
+        let (party_i, t, n) = (self.local_key.party_i, self.local_key.t, self.local_key.n);
+        let params = Parameters {
+            threshold: t,
+            share_count: n,
+        };
+        let parties_shares: Vec<_> = input
+            .into_vec_including_me(self.round_msg)
+            .into_iter()
+            .map(|msg| msg.own_share)
+            .collect();
+        let bad_actors: Vec<usize> = self
+            .vss_schemes
+            .iter()
+            .zip(&parties_shares)
+            .enumerate()
+            .filter(|(_, (vss_scheme, share))| vss_scheme.validate_share(share, party_i).is_err())
+            .map(|(i, _)| i)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round2Error(ErrorType {
+                error_type: "invalid share".to_string(),
+                bad_actors,
+            }));
+        }
+
+        // new prefix for deterministic nonces, old one may have leaked together with the share
+        let keypair = Keys::phase1_create(party_i);
+        let combined_share = keypair
+            .phase2_verify_vss_construct_keypair(
+                &params,
+                &self.expected_y_vec,
+                &parties_shares,
+                &self.vss_schemes,
+                party_i,
+            )
+            .map_err(|_| {
+                ProceedError::Round2Error(ErrorType {
+                    error_type: "invalid vss".to_string(),
+                    bad_actors: vec![],
+                })
+            })?;
+        if combined_share.y != self.local_key.agg_pubkey {
+            return Err(ProceedError::Round2Error(ErrorType {
+                error_type: "public key changed".to_string(),
+                bad_actors: vec![],
+            }));
+        }
+
+        Ok(EddsaLocalKey {
+            combined_share,
+            vss_schemes: self.vss_schemes,
+            agg_pubkey: self.local_key.agg_pubkey,
+            pubkeys_list: self.expected_y_vec,
+            keypair,
+            party_i,
+            t,
+            n,
+            chain_code: self.local_key.chain_code,
+            roster: self.local_key.roster,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+
+    pub fn expects_messages(i: u16, n: u16) -> Store<P2PMsgs<EddsaRefreshBroadcastForRound2>> {
+        P2PMsgsStore::new(i, n)
+    }
+}
+
+/// Lagrange coefficient of party `j` when all `n` parties participate
+fn lagrange_coefficient(local_key: &EddsaLocalKey, j: u16) -> Scalar<Ed25519> {
+    let s: Vec<u16> = (0..local_key.n).collect();
+    VerifiableSS::<Ed25519>::map_share_to_new_params(
+        &local_key.vss_schemes[0].parameters,
+        j - 1,
+        &s,
+    )
+}
+
+impl EddsaRefresh {
+    pub fn new(local_key: EddsaLocalKey) -> Result<Self> {
+        let (i, t, n) = (local_key.party_i, local_key.t, local_key.n);
+        if t == 0 || t >= n {
+            return Err(Error::InvalidThreshold);
+        }
+        if i == 0 || i > n {
+            return Err(Error::InvalidPartyIndex);
+        }
+        if local_key.vss_schemes.is_empty() {
+            return Err(Error::InvalidLocalKey);
+        }
+        let mut state = Self {
+            round: R::Round0(Round0 { local_key }),
+
+            msgs1: Some(Round1::expects_messages(i, n)),
+            msgs2: Some(Round2::expects_messages(i, n)),
+
+            msgs_queue: vec![],
+            party_i: i,
+            party_n: n,
+            round_timeout: None,
+        };
+
+        state.proceed_round(false)?;
+        Ok(state)
+    }
+
+    /// Sets deadline for collecting messages of each refresh round
+    ///
+    /// Expired deadline fails the protocol with [Error::RoundTimeout]. `None` (default) means
+    /// no deadline.
+    pub fn set_round_timeout(&mut self, timeout: Option<Duration>) {
+        self.round_timeout = timeout;
+    }
+
+    fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
+    where
+        F: FnMut(T) -> M + 'a,
+    {
+        (&mut self.msgs_queue)
+            .gmap(move |m: Msg<T>| m.map_body(|m| EddsaRefreshProtocolMessage(f(m))))
+    }
+
+    /// Proceeds round state if it received enough messages and if it's cheap to compute or
+    /// `may_block == true`
+    fn proceed_round(&mut self, may_block: bool) -> Result<()> {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        let next_state: R;
+        let try_again: bool = match replace(&mut self.round, R::Gone) {
+            R::Round0(round) if !round.is_expensive() || may_block => {
+                next_state = round
+                    .proceed(self.gmap_queue(M::Round1))
+                    .map(R::Round1)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round0(_) => {
+                next_state = s;
+                false
+            }
+            R::Round1(round) if !store1_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs1.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round2))
+                    .map(R::Round2)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round1(_) => {
+                next_state = s;
+                false
+            }
+            R::Round2(round) if !store2_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs2.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs)
+                    .map(R::Final)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round2(_) => {
+                next_state = s;
+                false
+            }
+            s @ R::Final(_) | s @ R::Gone => {
+                next_state = s;
+                false
+            }
+        };
+
+        self.round = next_state;
+        if try_again {
+            self.proceed_round(may_block)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Protocol message which parties send on wire
+///
+/// Hides actual messages structure so it could be changed without breaking semver policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EddsaRefreshProtocolMessage(M);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum M {
+    Round1(EddsaRefreshBroadcastForRound1),
+    Round2(EddsaRefreshBroadcastForRound2),
+}
+
+impl StateMachine for EddsaRefresh {
+    type MessageBody = EddsaRefreshProtocolMessage;
+    type Err = Error;
+    type Output = EddsaLocalKey;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<()> {
+        let current_round = self.current_round();
+
+        match msg.body {
+            EddsaRefreshProtocolMessage(M::Round1(m)) => {
+                let store = self
+                    .msgs1
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 1,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            EddsaRefreshProtocolMessage(M::Round2(m)) => {
+                let store = self
+                    .msgs2
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 2,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+        }
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        &mut self.msgs_queue
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        match &self.round {
+            R::Round0(_) => true,
+            R::Round1(_) => !store1_wants_more,
+            R::Round2(_) => !store2_wants_more,
+            R::Final(_) | R::Gone => false,
+        }
+    }
+
+    fn proceed(&mut self) -> Result<()> {
+        self.proceed_round(true)
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        self.round_timeout
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        let (missing_messages, missing_parties) = self.round_blame();
+        Error::RoundTimeout {
+            round: self.current_round(),
+            missing_messages,
+            missing_parties,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.round, R::Final(_))
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output>> {
+        match self.round {
+            R::Final(_) => (),
+            R::Gone => return Some(Err(Error::DoublePickOutput)),
+            _ => return None,
+        }
+
+        match replace(&mut self.round, R::Gone) {
+            R::Final(result) => Some(Ok(result)),
+            _ => unreachable!("guaranteed by match expression above"),
+        }
+    }
+
+    fn current_round(&self) -> u16 {
+        match &self.round {
+            R::Round0(_) => 0,
+            R::Round1(_) => 1,
+            R::Round2(_) => 2,
+            R::Final(_) | R::Gone => 3,
+        }
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        Some(2)
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.party_i
+    }
+
+    fn parties(&self) -> u16 {
+        self.party_n
+    }
+}
+
+impl RoundBlame for EddsaRefresh {
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Round2(_) => store2_blame,
+            R::Final(_) | R::Gone => default,
+        }
+    }
+}
+
+impl fmt::Debug for EddsaRefresh {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let current_round = match &self.round {
+            R::Round0(_) => "0",
+            R::Round1(_) => "1",
+            R::Round2(_) => "2",
+            R::Final(_) => "[Final]",
+            R::Gone => "[Gone]",
+        };
+        let msgs1 = match self.msgs1.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs2 = match self.msgs2.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        write!(
+            f,
+            "{{EddsaRefresh at round={} msgs1={} msgs2={} queue=[len={}]}}",
+            current_round,
+            msgs1,
+            msgs2,
+            self.msgs_queue.len()
+        )
+    }
+}
+
+mod private {
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum InternalError {
+        /// [Messages store](super::MessageStore) reported that it received all messages it wanted to receive,
+        /// but refused to return message container
+        RetrieveRoundMessages(super::StoreErr),
+        #[doc(hidden)]
+        StoreGone,
+    }
+}
+
+impl IsCritical for Error {
+    fn is_critical(&self) -> bool {
+        true
+    }
+}
+
+impl Blame for Error {
+    fn culprits(&self) -> Vec<u16> {
+        match self {
+            Error::ProceedRound(
+                ProceedError::Round1Error(err) | ProceedError::Round2Error(err),
+            ) => culprits_of(&err.bad_actors),
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Round proceeding resulted in error
+    #[error("proceed round: {0}")]
+    ProceedRound(#[source] ProceedError),
+    /// Threshold value `t` is not in range `[1; n-1]`
+    #[error("threshold is not in range [1; n-1]")]
+    InvalidThreshold,
+    /// Local key doesn't hold any VSS scheme
+    #[error("local key is inconsistent with its parameters")]
+    InvalidLocalKey,
+    #[error("pick_output called twice")]
+    DoublePickOutput,
+    /// Party index `i` is not in range `[1; n]`
+    #[error("party index is not in range [1; n]")]
+    InvalidPartyIndex,
+    #[doc(hidden)]
+    #[error("internal error: {0:?}")]
+    InternalError(InternalError),
+    /// Received message which we didn't expect to receive now (e.g. message from previous round)
+    #[error(
+        "didn't expect to receive message from round {msg_round} (being at round {current_round})"
+    )]
+    ReceivedOutOfOrderMessage { current_round: u16, msg_round: u16 },
+
+    /// Received message didn't pass pre-validation
+    #[error("received message didn't pass pre-validation: {0}")]
+    HandleMessage(#[source] StoreErr),
+    /// Round deadline set by [EddsaRefresh::set_round_timeout] was reached
+    #[error(
+        "round {round} timed out waiting for {missing_messages} messages from parties {missing_parties:?}"
+    )]
+    RoundTimeout {
+        round: u16,
+        missing_messages: u16,
+        missing_parties: Vec<u16>,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum ProceedError {
+    #[error("round 1: VSS commitments: {0:?}")]
+    Round1Error(ErrorType),
+    #[error("round 2: Shares: {0:?}")]
+    Round2Error(ErrorType),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EddsaRefreshBroadcastForRound1 {
+    pub vss_scheme: VerifiableSS<Ed25519>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EddsaRefreshBroadcastForRound2 {
+    pub own_share: Scalar<Ed25519>,
+}
+
+impl fmt::Debug for EddsaRefreshBroadcastForRound2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EddsaRefreshBroadcastForRound2")
+            .field("own_share", &Redacted)
+            .finish()
+    }
+}
+
+impl From<InternalError> for Error {
+    fn from(err: InternalError) -> Self {
+        Self::InternalError(err)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use curv::elliptic::curves::Point;
+    use round_based::dev::Simulation;
+
+    use crate::t_ed25519::signing::sign;
+    use crate::utils::common::{EddsaLocalKeyData, KeygenResult, SigningState};
+    use crate::utils::test_wallets;
+
+    use super::*;
+
+    pub fn simulate_refresh(keys: Vec<EddsaLocalKey>) -> Vec<EddsaLocalKey> {
+        let mut simulation = Simulation::new();
+        simulation.enable_benchmarks(true);
+
+        for key in keys {
+            simulation.add_party(EddsaRefresh::new(key).unwrap());
+        }
+
+        let keys = simulation.run().unwrap();
+
+        println!("Benchmark results:");
+        println!("{:#?}", simulation.benchmark_results().unwrap());
+
+        keys
+    }
+
+    pub fn wallet1() -> Vec<EddsaLocalKeyData> {
+        [
+            test_wallets::wallet1_shard1(),
+            test_wallets::wallet1_shard2(),
+            test_wallets::wallet1_shard3(),
+        ]
+        .iter()
+        .map(|shard| serde_json::from_str::<KeygenResult>(shard).unwrap().eddsa)
+        .collect()
+    }
+
+    #[test]
+    fn simulate_refresh_t1_n3() {
+        let old_keys = wallet1();
+        let new_keys = simulate_refresh(old_keys.iter().map(|key| key.local_key.clone()).collect());
+
+        for (old, new) in old_keys.iter().zip(&new_keys) {
+            assert_eq!(old.local_key.agg_pubkey, new.agg_pubkey);
+            assert_eq!(new.combined_share.y, new.agg_pubkey);
+            assert_ne!(old.local_key.combined_share.x_i, new.combined_share.x_i);
+            assert_eq!(
+                new.public_shares()[usize::from(new.party_i - 1)],
+                Point::generator() * &new.combined_share.x_i
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_nonces_of_the_key_before_refresh() {
+        let old_keys = wallet1();
+        let new_keys = simulate_refresh(old_keys.iter().map(|key| key.local_key.clone()).collect());
+        let mut refreshed: Vec<_> = old_keys
+            .into_iter()
+            .zip(new_keys)
+            .map(|(old, local_key)| EddsaLocalKeyData {
+                local_key,
+                offline_data: old.offline_data,
+                algorithm: old.algorithm,
+            })
+            .collect();
+
+        let mut state = SigningState {
+            t: 1,
+            n: 3,
+            signing_parts: vec![],
+            signature: None,
+        };
+        let message_to_sign =
+            hex::decode("bd82be05afedc3f399efde5cda2e590c69b6478bf888dc38c961b12105485333")
+                .unwrap();
+        let err = sign(&mut state, &mut refreshed[0], message_to_sign, 1, 0).unwrap_err();
+        assert_eq!(TssError::from(err).code(), "NONCE_EXHAUSTED");
+        assert!(state.signing_parts.is_empty());
+        assert!(refreshed[0].offline_data.consumed_nonces.is_empty());
+    }
+}
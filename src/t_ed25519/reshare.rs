@@ -0,0 +1,1145 @@
+use std::fmt;
+use std::mem::replace;
+use std::time::Duration;
+
+use anyhow::Context;
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use futures::StreamExt;
+use round_based::containers::{
+    push::{Push, PushExt},
+    *,
+};
+use round_based::{AsyncProtocol, IsCritical, Msg, StateMachine};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::error::{culprits_of, Blame, TssError};
+use crate::gg20::derivation::ChainCode;
+use crate::gg20::state_machine::traits::RoundBlame;
+use crate::t_ed25519::keygen::EddsaLocalKey;
+use crate::t_ed25519::reshare::private::InternalError;
+use crate::t_ed25519::thresholdsig::Keys;
+use crate::t_ed25519::ErrorType;
+use crate::utils::secret::Redacted;
+use crate::utils::secure_channel::{Identity, Roster};
+use crate::utils::transport::{join_computation, Transport};
+
+/// Reshares EdDSA key to the new committee of `new_n` parties with threshold `new_t`
+///
+/// `party_id` is the room index issued to the party when ECDSA key was reshared, all `n`
+/// participants of the room take part. Returns `None` if the party doesn't join the new
+/// committee, otherwise the new key comes with identity keys of the new committee.
+pub async fn start_reshare<T>(
+    request_id: &str,
+    transport: &T,
+    identity: &Identity,
+    room: &str,
+    n: u16,
+    party_id: u16,
+    old_key: Option<&EddsaLocalKey>,
+    new_member: bool,
+    new_t: u16,
+    new_n: u16,
+    round_timeout: Option<Duration>,
+) -> anyhow::Result<Option<EddsaLocalKey>>
+where
+    T: Transport + ?Sized,
+{
+    println!(
+        "requestId={} start eddsa reshare for party: {} room {}",
+        request_id, party_id, room
+    );
+    let (_, roster, incoming, outgoing) = join_computation(
+        transport,
+        &format!("{}-eddsa-reshare", room),
+        (1..(n + 1)).collect(),
+        Some(party_id),
+        None,
+        identity,
+    )
+    .await
+    .context("join reshare computation")?;
+
+    let incoming = incoming.fuse();
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+
+    let mut reshare = EddsaReshare::new(
+        party_id,
+        n,
+        old_key.cloned(),
+        new_member,
+        new_t,
+        new_n,
+        roster,
+    )?;
+    reshare.set_round_timeout(round_timeout);
+    let reshared_key = AsyncProtocol::new(reshare, incoming, outgoing)
+        .run()
+        .await
+        .map_err(TssError::from)
+        .context("reshare execution terminated with error")?;
+    println!(
+        "requestId={} completed eddsa reshare for party: {}",
+        request_id, party_id
+    );
+    Ok(reshared_key)
+}
+
+pub struct EddsaReshare {
+    round: R,
+    msgs1: Option<Store<BroadcastMsgs<EddsaReshareBroadcastForRound1>>>,
+    msgs2: Option<Store<BroadcastMsgs<EddsaReshareBroadcastForRound2>>>,
+    msgs3: Option<Store<P2PMsgs<EddsaReshareBroadcastForRound3>>>,
+    msgs_queue: Vec<Msg<EddsaReshareProtocolMessage>>,
+    party_i: u16,
+    party_n: u16,
+    round_timeout: Option<Duration>,
+}
+// Rounds
+
+enum R {
+    Round0(Round0),
+    Round1(Round1),
+    Round2(Round2),
+    Round3(Round3),
+    Final(Option<EddsaLocalKey>),
+    Gone,
+}
+
+struct Round0 {
+    party_i: u16,
+    roster: Roster,
+    old_key: Option<EddsaLocalKey>,
+    new_member: bool,
+    new_t: u16,
+    new_n: u16,
+}
+
+pub struct Round1 {
+    round_msg: EddsaReshareBroadcastForRound1,
+    party_i: u16,
+    roster: Roster,
+    old_key: Option<EddsaLocalKey>,
+    new_t: u16,
+    new_n: u16,
+}
+
+pub struct Round2 {
+    round_msg: EddsaReshareBroadcastForRound2,
+    secret_shares: Option<Vec<Scalar<Ed25519>>>,
+    committee: Committee,
+    party_i: u16,
+    old_key: Option<EddsaLocalKey>,
+    new_t: u16,
+    new_n: u16,
+}
+
+pub struct Round3 {
+    round_msg: EddsaReshareBroadcastForRound3,
+    vss_schemes: Vec<VerifiableSS<Ed25519>>,
+    committee: Committee,
+    party_i: u16,
+    new_t: u16,
+    new_n: u16,
+}
+
+/// Roles of resharing participants, agreed on after the first round
+struct Committee {
+    /// Room index and old party index of every dealer
+    dealers: Vec<(u16, u16)>,
+    /// Room indexes of the new committee ordered by the new party index
+    new_members: Vec<u16>,
+    /// Identity keys of the new committee by the new party index
+    roster: Roster,
+    agg_pubkey: Point<Ed25519>,
+    chain_code: Option<ChainCode>,
+}
+
+impl Round0 {
+    pub fn proceed<O>(self, mut output: O) -> std::result::Result<Round1, ProceedError>
+    where
+        O: Push<Msg<EddsaReshareBroadcastForRound1>>,
+    {
+        let round_msg = EddsaReshareBroadcastForRound1 {
+            dealer: self.old_key.as_ref().map(|key| EddsaDealerInfo {
+                old_index: key.party_i,
+                old_t: key.t,
+                old_n: key.n,
+                agg_pubkey: key.agg_pubkey.clone(),
+                chain_code: key.chain_code,
+            }),
+            new_member: self.new_member,
+        };
+        output.push(Msg {
+            sender: self.party_i,
+            receiver: None,
+            body: round_msg.clone(),
+        });
+        Ok(Round1 {
+            round_msg,
+            party_i: self.party_i,
+            roster: self.roster,
+            old_key: self.old_key,
+            new_t: self.new_t,
+            new_n: self.new_n,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+}
+
+impl Round1 {
+    pub fn proceed<O>(
+        self,
+        input: BroadcastMsgs<EddsaReshareBroadcastForRound1>,
+        mut output: O,
+    ) -> std::result::Result<Round2, ProceedError>
+    where
+        O: Push<Msg<EddsaReshareBroadcastForRound2>>,
+    {
+        let round_msgs = input.into_vec_including_me(self.round_msg);
+        let old_roster = self.old_key.as_ref().and_then(|key| key.roster.as_ref());
+        let committee =
+            Committee::from_round_msgs(&round_msgs, self.new_n, &self.roster, old_roster)?;
+
+        let dealing = self.old_key.as_ref().map(|key| {
+            let w_i = committee.lagrange_coefficient(key, key.party_i) * &key.combined_share.x_i;
+            let parties: Vec<_> = (1..(self.new_n + 1)).collect();
+            VerifiableSS::share_at_indices(self.new_t, self.new_n, &w_i, &parties)
+        });
+
+        let round_msg = EddsaReshareBroadcastForRound2 {
+            vss_scheme: dealing.as_ref().map(|(vss_scheme, _)| vss_scheme.clone()),
+        };
+        output.push(Msg {
+            sender: self.party_i,
+            receiver: None,
+            body: round_msg.clone(),
+        });
+        Ok(Round2 {
+            round_msg,
+            secret_shares: dealing.map(|(_, secret_shares)| secret_shares.to_vec()),
+            committee,
+            party_i: self.party_i,
+            old_key: self.old_key,
+            new_t: self.new_t,
+            new_n: self.new_n,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+
+    pub fn expects_messages(
+        i: u16,
+        n: u16,
+    ) -> Store<BroadcastMsgs<EddsaReshareBroadcastForRound1>> {
+        BroadcastMsgsStore::new(i, n)
+    }
+}
+
+impl Round2 {
+    pub fn proceed<O>(
+        self,
+        input: BroadcastMsgs<EddsaReshareBroadcastForRound2>,
+        mut output: O,
+    ) -> std::result::Result<Round3, ProceedError>
+    where
+        O: Push<Msg<EddsaReshareBroadcastForRound3>>,
+    {
+        let round_msgs = input.into_vec_including_me(self.round_msg);
+        // dealers can check each other against the old public shares
+        let old_public_shares = self.old_key.as_ref().map(|key| key.public_shares());
+
+        let bad_actors: Vec<usize> = round_msgs
+            .iter()
+            .enumerate()
+            .filter(
+                |(k, msg)| match (&msg.vss_scheme, self.committee.old_index(*k as u16 + 1)) {
+                    (Some(vss_scheme), Some(old_i)) => {
+                        let consistent = match (&self.old_key, &old_public_shares) {
+                            (Some(key), Some(public_shares)) => {
+                                vss_scheme.commitments[0]
+                                    == &public_shares[usize::from(old_i - 1)]
+                                        * self.committee.lagrange_coefficient(key, old_i)
+                            }
+                            _ => true,
+                        };
+                        vss_scheme.parameters.threshold != self.new_t
+                            || vss_scheme.parameters.share_count != self.new_n
+                            || vss_scheme.commitments.len() != usize::from(self.new_t) + 1
+                            || !consistent
+                    }
+                    (None, None) => false,
+                    _ => true,
+                },
+            )
+            .map(|(k, _)| k)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round2Error(ErrorType {
+                error_type: "invalid vss commitments".to_string(),
+                bad_actors,
+            }));
+        }
+
+        let vss_schemes: Vec<_> = round_msgs
+            .into_iter()
+            .filter_map(|msg| msg.vss_scheme)
+            .collect();
+        let first = vss_schemes[0].commitments[0].clone();
+        let agg_pubkey = vss_schemes[1..]
+            .iter()
+            .fold(first, |acc, vss| acc + &vss.commitments[0]);
+        if agg_pubkey != self.committee.agg_pubkey {
+            return Err(ProceedError::Round2Error(ErrorType {
+                error_type: "public key changed".to_string(),
+                bad_actors: vec![],
+            }));
+        }
+
+        let mut own_share = None;
+        for room_i in 1..(self.committee.room_size() + 1) {
+            let share = match (&self.secret_shares, self.committee.new_index(room_i)) {
+                (Some(shares), Some(new_i)) => Some(shares[usize::from(new_i - 1)].clone()),
+                _ => None,
+            };
+            if room_i == self.party_i {
+                own_share = share;
+                continue;
+            }
+
+            output.push(Msg {
+                sender: self.party_i,
+                receiver: Some(room_i),
+                body: EddsaReshareBroadcastForRound3 { own_share: share },
+            })
+        }
+
+        Ok(Round3 {
+            round_msg: EddsaReshareBroadcastForRound3 { own_share },
+            vss_schemes,
+            committee: self.committee,
+            party_i: self.party_i,
+            new_t: self.new_t,
+            new_n: self.new_n,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+
+    pub fn expects_messages(
+        i: u16,
+        n: u16,
+    ) -> Store<BroadcastMsgs<EddsaReshareBroadcastForRound2>> {
+        BroadcastMsgsStore::new(i, n)
+    }
+}
+
+impl Round3 {
+    pub fn proceed(
+        self,
+        input: P2PMsgs<EddsaReshareBroadcastForRound3>,
+    ) -> std::result::Result<Option<EddsaLocalKey>, ProceedError> {
+        let new_i = match self.committee.new_index(self.party_i) {
+            Some(new_i) => new_i,
+            // party leaves the committee, nothing to construct
+            None => return Ok(None),
+        };
+        let round_msgs = input.into_vec_including_me(self.round_msg);
+
+        let mut bad_actors = vec![];
+        let mut parties_shares = vec![];
+        for ((room_i, _), vss_scheme) in self.committee.dealers.iter().zip(&self.vss_schemes) {
+            match &round_msgs[usize::from(room_i - 1)].own_share {
+                Some(share) if vss_scheme.validate_share(share, new_i).is_ok() => {
+                    parties_shares.push(share.clone())
+                }
+                _ => bad_actors.push(usize::from(room_i - 1)),
+            }
+        }
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round3Error(ErrorType {
+                error_type: "invalid share".to_string(),
+                bad_actors,
+            }));
+        }
+
+        let pubkeys_list: Vec<_> = self
+            .vss_schemes
+            .iter()
+            .map(|vss_scheme| vss_scheme.commitments[0].clone())
+            .collect();
+        let keypair = Keys::phase1_create(new_i);
+        let combined_share = keypair
+            .phase2_verify_dealt_vss_construct_keypair(
+                &pubkeys_list,
+                &parties_shares,
+                &self.vss_schemes,
+                new_i,
+            )
+            .map_err(|_| {
+                ProceedError::Round3Error(ErrorType {
+                    error_type: "invalid vss".to_string(),
+                    bad_actors: vec![],
+                })
+            })?;
+
+        Ok(Some(EddsaLocalKey {
+            combined_share,
+            vss_schemes: self.vss_schemes,
+            agg_pubkey: self.committee.agg_pubkey,
+            pubkeys_list,
+            keypair,
+            party_i: new_i,
+            t: self.new_t,
+            n: self.new_n,
+            chain_code: self.committee.chain_code,
+            roster: Some(self.committee.roster),
+        }))
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+
+    pub fn expects_messages(i: u16, n: u16) -> Store<P2PMsgs<EddsaReshareBroadcastForRound3>> {
+        P2PMsgsStore::new(i, n)
+    }
+}
+
+impl Committee {
+    /// Agrees on roles of the participants with identity keys `roster` by room index
+    ///
+    /// Dealers holding an old key with `old_roster` must join the room with the identity keys
+    /// they had with the old key.
+    fn from_round_msgs(
+        round_msgs: &[EddsaReshareBroadcastForRound1],
+        new_n: u16,
+        roster: &Roster,
+        old_roster: Option<&Roster>,
+    ) -> std::result::Result<Self, ProceedError> {
+        let new_members: Vec<u16> = (1..)
+            .zip(round_msgs)
+            .filter(|(_, msg)| msg.new_member)
+            .map(|(room_i, _)| room_i)
+            .collect();
+        if new_members.len() != usize::from(new_n) {
+            return Err(ProceedError::Round1Error(ErrorType {
+                error_type: format!(
+                    "{} parties joined the new committee, expected {}",
+                    new_members.len(),
+                    new_n
+                ),
+                bad_actors: vec![],
+            }));
+        }
+
+        let dealers: Vec<(u16, &EddsaDealerInfo)> = (1..)
+            .zip(round_msgs)
+            .filter_map(|(room_i, msg)| msg.dealer.as_ref().map(|dealer| (room_i, dealer)))
+            .collect();
+        let first = match dealers.first() {
+            Some((_, first)) => *first,
+            None => {
+                return Err(ProceedError::Round1Error(ErrorType {
+                    error_type: "no holders of the old key".to_string(),
+                    bad_actors: vec![],
+                }))
+            }
+        };
+        let bad_actors: Vec<usize> = dealers
+            .iter()
+            .filter(|(room_i, dealer)| {
+                dealer.agg_pubkey != first.agg_pubkey
+                    || dealer.chain_code != first.chain_code
+                    || dealer.old_t != first.old_t
+                    || dealer.old_n != first.old_n
+                    || dealer.old_index == 0
+                    || dealer.old_index > dealer.old_n
+                    || dealers
+                        .iter()
+                        .any(|(other, o)| other < room_i && o.old_index == dealer.old_index)
+            })
+            .map(|(room_i, _)| usize::from(room_i - 1))
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round1Error(ErrorType {
+                error_type: "inconsistent old key".to_string(),
+                bad_actors,
+            }));
+        }
+        if let Some(old_roster) = old_roster {
+            let bad_actors: Vec<usize> = dealers
+                .iter()
+                .filter(|(room_i, dealer)| match old_roster.get(&dealer.old_index) {
+                    Some(expected) => roster.get(room_i) != Some(expected),
+                    None => false,
+                })
+                .map(|(room_i, _)| usize::from(room_i - 1))
+                .collect();
+            if !bad_actors.is_empty() {
+                return Err(ProceedError::Round1Error(ErrorType {
+                    error_type: "identity key doesn't match the roster of the old key".to_string(),
+                    bad_actors,
+                }));
+            }
+        }
+        if dealers.len() <= usize::from(first.old_t) {
+            return Err(ProceedError::Round1Error(ErrorType {
+                error_type: "not enough holders of the old key".to_string(),
+                bad_actors: vec![],
+            }));
+        }
+
+        Ok(Self {
+            agg_pubkey: first.agg_pubkey.clone(),
+            chain_code: first.chain_code,
+            dealers: dealers
+                .iter()
+                .map(|(room_i, dealer)| (*room_i, dealer.old_index))
+                .collect(),
+            roster: (1..)
+                .zip(&new_members)
+                .filter_map(|(new_i, room_i)| roster.get(room_i).map(|key| (new_i, *key)))
+                .collect(),
+            new_members,
+        })
+    }
+
+    fn room_size(&self) -> u16 {
+        let last_dealer = self.dealers.iter().map(|(room_i, _)| *room_i).max();
+        let last_member = self.new_members.iter().copied().max();
+        last_dealer.max(last_member).unwrap_or(0)
+    }
+
+    fn old_index(&self, room_i: u16) -> Option<u16> {
+        self.dealers
+            .iter()
+            .find(|(dealer, _)| *dealer == room_i)
+            .map(|(_, old_i)| *old_i)
+    }
+
+    fn new_index(&self, room_i: u16) -> Option<u16> {
+        self.new_members
+            .iter()
+            .position(|member| *member == room_i)
+            .map(|position| position as u16 + 1)
+    }
+
+    /// Lagrange coefficient of dealer with old index `old_i` over the set of all dealers
+    fn lagrange_coefficient(&self, old_key: &EddsaLocalKey, old_i: u16) -> Scalar<Ed25519> {
+        let s: Vec<u16> = self.dealers.iter().map(|(_, j)| j - 1).collect();
+        VerifiableSS::<Ed25519>::map_share_to_new_params(
+            &old_key.vss_schemes[0].parameters,
+            old_i - 1,
+            &s,
+        )
+    }
+}
+
+impl EddsaReshare {
+    /// Constructs a party of resharing protocol
+    ///
+    /// `i` is party index in the room of `n` participants with identity keys `roster` by room
+    /// index. Parties holding the old key deal it, parties with `new_member == true` receive shares
+    /// of the new (`new_t`, `new_n`) key.
+    pub fn new(
+        i: u16,
+        n: u16,
+        old_key: Option<EddsaLocalKey>,
+        new_member: bool,
+        new_t: u16,
+        new_n: u16,
+        roster: Roster,
+    ) -> Result<Self> {
+        if n < 2 {
+            return Err(Error::TooFewParties);
+        }
+        if i == 0 || i > n {
+            return Err(Error::InvalidPartyIndex);
+        }
+        if new_t == 0 || new_t >= new_n {
+            return Err(Error::InvalidThreshold);
+        }
+        if old_key.is_none() && !new_member {
+            return Err(Error::NoRole);
+        }
+        if let Some(key) = &old_key {
+            if key.vss_schemes.is_empty() || key.party_i == 0 || key.party_i > key.n {
+                return Err(Error::InvalidLocalKey);
+            }
+        }
+        let mut state = Self {
+            round: R::Round0(Round0 {
+                party_i: i,
+                roster,
+                old_key,
+                new_member,
+                new_t,
+                new_n,
+            }),
+
+            msgs1: Some(Round1::expects_messages(i, n)),
+            msgs2: Some(Round2::expects_messages(i, n)),
+            msgs3: Some(Round3::expects_messages(i, n)),
+
+            msgs_queue: vec![],
+            party_i: i,
+            party_n: n,
+            round_timeout: None,
+        };
+
+        state.proceed_round(false)?;
+        Ok(state)
+    }
+
+    /// Sets deadline for collecting messages of each resharing round
+    ///
+    /// A participant which doesn't show up makes the protocol fail with [Error::RoundTimeout]
+    /// once the deadline passes. `None` (default) means no deadline.
+    pub fn set_round_timeout(&mut self, timeout: Option<Duration>) {
+        self.round_timeout = timeout;
+    }
+
+    fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
+    where
+        F: FnMut(T) -> M + 'a,
+    {
+        (&mut self.msgs_queue)
+            .gmap(move |m: Msg<T>| m.map_body(|m| EddsaReshareProtocolMessage(f(m))))
+    }
+
+    /// Proceeds round state if it received enough messages and if it's cheap to compute or
+    /// `may_block == true`
+    fn proceed_round(&mut self, may_block: bool) -> Result<()> {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        let next_state: R;
+        let try_again: bool = match replace(&mut self.round, R::Gone) {
+            R::Round0(round) if !round.is_expensive() || may_block => {
+                next_state = round
+                    .proceed(self.gmap_queue(M::Round1))
+                    .map(R::Round1)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round0(_) => {
+                next_state = s;
+                false
+            }
+            R::Round1(round) if !store1_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs1.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round2))
+                    .map(R::Round2)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round1(_) => {
+                next_state = s;
+                false
+            }
+            R::Round2(round) if !store2_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs2.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round3))
+                    .map(R::Round3)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round2(_) => {
+                next_state = s;
+                false
+            }
+            R::Round3(round) if !store3_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs3.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs)
+                    .map(R::Final)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round3(_) => {
+                next_state = s;
+                false
+            }
+            s @ R::Final(_) | s @ R::Gone => {
+                next_state = s;
+                false
+            }
+        };
+
+        self.round = next_state;
+        if try_again {
+            self.proceed_round(may_block)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Protocol message which parties send on wire
+///
+/// Hides actual messages structure so it could be changed without breaking semver policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EddsaReshareProtocolMessage(M);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum M {
+    Round1(EddsaReshareBroadcastForRound1),
+    Round2(EddsaReshareBroadcastForRound2),
+    Round3(EddsaReshareBroadcastForRound3),
+}
+
+impl StateMachine for EddsaReshare {
+    type MessageBody = EddsaReshareProtocolMessage;
+    type Err = Error;
+    type Output = Option<EddsaLocalKey>;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<()> {
+        let current_round = self.current_round();
+
+        match msg.body {
+            EddsaReshareProtocolMessage(M::Round1(m)) => {
+                let store = self
+                    .msgs1
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 1,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            EddsaReshareProtocolMessage(M::Round2(m)) => {
+                let store = self
+                    .msgs2
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 2,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            EddsaReshareProtocolMessage(M::Round3(m)) => {
+                let store = self
+                    .msgs3
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 3,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+        }
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        &mut self.msgs_queue
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        match &self.round {
+            R::Round0(_) => true,
+            R::Round1(_) => !store1_wants_more,
+            R::Round2(_) => !store2_wants_more,
+            R::Round3(_) => !store3_wants_more,
+            R::Final(_) | R::Gone => false,
+        }
+    }
+
+    fn proceed(&mut self) -> Result<()> {
+        self.proceed_round(true)
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        self.round_timeout
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        let (missing_messages, missing_parties) = self.round_blame();
+        Error::RoundTimeout {
+            round: self.current_round(),
+            missing_messages,
+            missing_parties,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.round, R::Final(_))
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output>> {
+        match self.round {
+            R::Final(_) => (),
+            R::Gone => return Some(Err(Error::DoublePickOutput)),
+            _ => return None,
+        }
+
+        match replace(&mut self.round, R::Gone) {
+            R::Final(result) => Some(Ok(result)),
+            _ => unreachable!("guaranteed by match expression above"),
+        }
+    }
+
+    fn current_round(&self) -> u16 {
+        match &self.round {
+            R::Round0(_) => 0,
+            R::Round1(_) => 1,
+            R::Round2(_) => 2,
+            R::Round3(_) => 3,
+            R::Final(_) | R::Gone => 4,
+        }
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        Some(3)
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.party_i
+    }
+
+    fn parties(&self) -> u16 {
+        self.party_n
+    }
+}
+
+impl RoundBlame for EddsaReshare {
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store3_blame = self.msgs3.as_ref().map(|s| s.blame()).unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Round2(_) => store2_blame,
+            R::Round3(_) => store3_blame,
+            R::Final(_) | R::Gone => default,
+        }
+    }
+}
+
+impl fmt::Debug for EddsaReshare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let current_round = match &self.round {
+            R::Round0(_) => "0",
+            R::Round1(_) => "1",
+            R::Round2(_) => "2",
+            R::Round3(_) => "3",
+            R::Final(_) => "[Final]",
+            R::Gone => "[Gone]",
+        };
+        let msgs1 = match self.msgs1.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs2 = match self.msgs2.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs3 = match self.msgs3.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        write!(
+            f,
+            "{{EddsaReshare at round={} msgs1={} msgs2={} msgs3={} queue=[len={}]}}",
+            current_round,
+            msgs1,
+            msgs2,
+            msgs3,
+            self.msgs_queue.len()
+        )
+    }
+}
+
+mod private {
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum InternalError {
+        /// [Messages store](super::MessageStore) reported that it received all messages it wanted to receive,
+        /// but refused to return message container
+        RetrieveRoundMessages(super::StoreErr),
+        #[doc(hidden)]
+        StoreGone,
+    }
+}
+
+impl IsCritical for Error {
+    fn is_critical(&self) -> bool {
+        true
+    }
+}
+
+impl Blame for Error {
+    fn culprits(&self) -> Vec<u16> {
+        match self {
+            Error::ProceedRound(
+                ProceedError::Round1Error(err)
+                | ProceedError::Round2Error(err)
+                | ProceedError::Round3Error(err),
+            ) => culprits_of(&err.bad_actors),
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Round proceeding resulted in error
+    #[error("proceed round: {0}")]
+    ProceedRound(#[source] ProceedError),
+    /// Too few parties (`n < 2`)
+    #[error("at least 2 parties are required for resharing")]
+    TooFewParties,
+    /// Threshold value `new_t` is not in range `[1; new_n-1]`
+    #[error("threshold is not in range [1; n-1]")]
+    InvalidThreshold,
+    /// Party neither deals an old key nor joins the new committee
+    #[error("party has nothing to do in resharing")]
+    NoRole,
+    /// Old key is inconsistent with its parameters
+    #[error("local key is inconsistent with its parameters")]
+    InvalidLocalKey,
+    #[error("pick_output called twice")]
+    DoublePickOutput,
+    /// Party index `i` is not in range `[1; n]`
+    #[error("party index is not in range [1; n]")]
+    InvalidPartyIndex,
+    #[doc(hidden)]
+    #[error("internal error: {0:?}")]
+    InternalError(InternalError),
+    /// Received message which we didn't expect to receive now (e.g. message from previous round)
+    #[error(
+        "didn't expect to receive message from round {msg_round} (being at round {current_round})"
+    )]
+    ReceivedOutOfOrderMessage { current_round: u16, msg_round: u16 },
+
+    /// Received message didn't pass pre-validation
+    #[error("received message didn't pass pre-validation: {0}")]
+    HandleMessage(#[source] StoreErr),
+    /// Round deadline set by [EddsaReshare::set_round_timeout] was reached
+    #[error(
+        "round {round} timed out waiting for {missing_messages} messages from parties {missing_parties:?}"
+    )]
+    RoundTimeout {
+        round: u16,
+        missing_messages: u16,
+        missing_parties: Vec<u16>,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum ProceedError {
+    #[error("round 1: Roles: {0:?}")]
+    Round1Error(ErrorType),
+    #[error("round 2: VSS commitments: {0:?}")]
+    Round2Error(ErrorType),
+    #[error("round 3: Shares: {0:?}")]
+    Round3Error(ErrorType),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EddsaDealerInfo {
+    pub old_index: u16,
+    pub old_t: u16,
+    pub old_n: u16,
+    pub agg_pubkey: Point<Ed25519>,
+    pub chain_code: Option<ChainCode>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EddsaReshareBroadcastForRound1 {
+    pub dealer: Option<EddsaDealerInfo>,
+    pub new_member: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EddsaReshareBroadcastForRound2 {
+    pub vss_scheme: Option<VerifiableSS<Ed25519>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EddsaReshareBroadcastForRound3 {
+    pub own_share: Option<Scalar<Ed25519>>,
+}
+
+impl fmt::Debug for EddsaReshareBroadcastForRound3 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EddsaReshareBroadcastForRound3")
+            .field("own_share", &self.own_share.as_ref().map(|_| Redacted))
+            .finish()
+    }
+}
+
+impl From<InternalError> for Error {
+    fn from(err: InternalError) -> Self {
+        Self::InternalError(err)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use round_based::dev::Simulation;
+
+    use crate::gg20::state_machine::reshare::test::room_roster;
+    use crate::t_ed25519::refresh::test::wallet1;
+    use crate::t_ed25519::tests::deterministic_fast_rand;
+    use crate::t_ed25519::thresholdsig::test::tests::eph_keygen_t_n_parties;
+    use crate::t_ed25519::thresholdsig::{self, LocalSig};
+
+    use super::*;
+
+    pub fn simulate_reshare(
+        participants: Vec<(Option<EddsaLocalKey>, bool)>,
+        roster: &Roster,
+        new_t: u16,
+        new_n: u16,
+    ) -> Vec<Option<EddsaLocalKey>> {
+        let mut simulation = Simulation::new();
+        simulation.enable_benchmarks(true);
+
+        let n = participants.len() as u16;
+        for (i, (old_key, new_member)) in (1..).zip(participants) {
+            let party = EddsaReshare::new(i, n, old_key, new_member, new_t, new_n, roster.clone());
+            simulation.add_party(party.unwrap());
+        }
+
+        let keys = simulation.run().unwrap();
+
+        println!("Benchmark results:");
+        println!("{:#?}", simulation.benchmark_results().unwrap());
+
+        keys
+    }
+
+    #[test]
+    fn simulate_reshare_t1_n3_to_t2_n4() {
+        let old_keys = wallet1();
+        let old_key = |i: usize| Some(old_keys[i].local_key.clone());
+        let roster = room_roster(4);
+        let reshared = simulate_reshare(
+            vec![
+                (old_key(0), true),
+                (old_key(2), true),
+                (None, true),
+                (None, true),
+            ],
+            &roster,
+            2,
+            4,
+        );
+        let reshared: Vec<_> = reshared.into_iter().map(Option::unwrap).collect();
+
+        for (i, key) in (1..).zip(&reshared) {
+            assert_eq!(key.party_i, i);
+            assert_eq!((key.t, key.n), (2, 4));
+            assert_eq!(key.agg_pubkey, old_keys[0].local_key.agg_pubkey);
+            assert_eq!(key.roster.as_ref(), Some(&roster));
+            assert_eq!(
+                key.public_shares()[usize::from(i - 1)],
+                Point::generator() * &key.combined_share.x_i
+            );
+        }
+
+        // nonces are shared among all parties of the new committee, like in presigning
+        let mut rng = deterministic_fast_rand("simulate_reshare_t1_n3_to_t2_n4", None);
+        let message = b"reshared key signs";
+        let keypairs: Vec<_> = reshared.iter().map(|key| key.keypair.clone()).collect();
+        let (nonce_shares, agg_nonce, nonce_vss_schemes) =
+            eph_keygen_t_n_parties(2, 4, &[1, 2, 3, 4], &keypairs, message, &mut rng);
+
+        let signers = [0u16, 1, 3];
+        let local_sigs: Vec<_> = signers
+            .iter()
+            .map(|&i| {
+                let i = usize::from(i);
+                LocalSig::compute(message, &nonce_shares[i], &reshared[i].combined_share)
+            })
+            .collect();
+        let vss_sum_local_sigs = LocalSig::verify_local_sigs(
+            &local_sigs,
+            &signers,
+            &reshared[0].vss_schemes,
+            &nonce_vss_schemes,
+        )
+        .unwrap();
+        let signature =
+            thresholdsig::generate(&vss_sum_local_sigs, &local_sigs, &signers, agg_nonce);
+        signature
+            .verify(message, &old_keys[0].local_key.agg_pubkey)
+            .unwrap();
+    }
+
+    #[test]
+    fn reshare_refuses_dealer_with_another_identity_key() {
+        let old_keys = wallet1();
+        let old_roster = room_roster(3);
+        let old_key = |i: usize| {
+            let mut key = old_keys[i].local_key.clone();
+            key.roster = Some(old_roster.clone());
+            Some(key)
+        };
+        // party 3 of the old key joins the room with another identity key
+        let mut roster = room_roster(3);
+        roster.insert(1, old_roster[&1]);
+        let participants = vec![(old_key(0), true), (old_key(2), true), (None, true)];
+
+        let mut simulation = Simulation::new();
+        for (i, (old_key, new_member)) in (1..).zip(participants) {
+            let party = EddsaReshare::new(i, 3, old_key, new_member, 1, 3, roster.clone());
+            simulation.add_party(party.unwrap());
+        }
+        match simulation.run().unwrap_err() {
+            Error::ProceedRound(ProceedError::Round1Error(err)) => {
+                assert_eq!(err.bad_actors, vec![1])
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+}
@@ -1,8 +1,10 @@
+use anyhow::Context;
 use anyhow::Result;
-use anyhow::{anyhow, Context};
 use chrono::prelude::*;
 use curv::arithmetic::Converter;
+use curv::elliptic::curves::Scalar;
 
+use crate::error::TssError;
 use crate::t_ed25519::thresholdsig;
 use crate::t_ed25519::thresholdsig::LocalSig;
 use rustmodel::SignatureRecidHex;
@@ -11,31 +13,51 @@ use crate::utils::common::{
     EddsaLocalKeyData, PartialSignatureType, SignedPartialSignature, SigningState,
 };
 
+/// Signs with nonce number `nonce`, which is marked consumed
+///
+/// All signers must pass the same `nonce`. Fails if the nonce already signed something: two
+/// messages signed with one nonce leak the key. Nonces generated before the key was refreshed
+/// or reshared don't sign either.
 pub fn sign(
     state: &mut SigningState,
-    local_key: &EddsaLocalKeyData,
+    local_key: &mut EddsaLocalKeyData,
     data_to_sign: Vec<u8>,
     party_id: u16,
     nonce: usize,
+) -> Result<()> {
+    sign_derived(state, local_key, data_to_sign, party_id, nonce, &[])
+}
+
+/// Signs with the child key at non-hardened `path`, empty path signs with the aggregated key
+pub fn sign_derived(
+    state: &mut SigningState,
+    local_key: &mut EddsaLocalKeyData,
+    data_to_sign: Vec<u8>,
+    party_id: u16,
+    nonce: usize,
+    path: &[u32],
 ) -> Result<()> {
     if state.signing_parts.len() as u16 > state.t {
         // this already full signed
-        Err(anyhow!("already signed"))
+        Err(TssError::invalid_input("already signed").into())
+    } else if local_key.offline_data.key_epoch != Some(local_key.local_key.epoch()) {
+        Err(TssError::nonce_exhausted("nonces were generated for another epoch of the key").into())
     } else {
-        let nonce_index = nonce - local_key.offline_data.nonce_start_index as usize;
-        if nonce_index < 0 || nonce_index >= local_key.offline_data.completed_offline.len() {
-            return Err(anyhow!(
-                "nonce index {} out of range [{},{}]",
-                nonce_index,
-                local_key.offline_data.nonce_start_index,
-                local_key.offline_data.completed_offline.len()
-                    + local_key.offline_data.nonce_start_index as usize
-            ));
-        }
-        let partial_signature = LocalSig::compute(
+        let nonce_index = local_key.offline_data.consume(nonce)?;
+        let (agg_pubkey, tweak) = if path.is_empty() {
+            (local_key.local_key.agg_pubkey.clone(), Scalar::zero())
+        } else {
+            let derived = local_key
+                .local_key
+                .derive(path)
+                .map_err(TssError::invalid_input)?;
+            (derived.agg_pubkey, derived.tweak)
+        };
+        let partial_signature = LocalSig::compute_with_tweak(
             &data_to_sign,
             &local_key.offline_data.completed_offline[nonce_index].combined_nonce_share,
             &local_key.local_key.combined_share,
+            &tweak,
         );
         if state.signing_parts.len() as u16 > state.t as u16 - 1 {
             // the last part signed. now combine into one signature
@@ -46,13 +68,16 @@ pub fn sign(
             });
 
             let parts: Vec<_> = state.signing_parts.clone();
-            let local_sig_vec: Vec<_> = parts
+            let local_sig_vec = parts
                 .into_iter()
                 .map(|x| match x.part {
-                    PartialSignatureType::EDDSA(p) => p,
-                    _ => panic!("wrong signature type"),
+                    PartialSignatureType::EDDSA(p) => Ok(p),
+                    _ => Err(TssError::invalid_input(format_args!(
+                        "party {} sent a partial signature of another scheme",
+                        x.party_id
+                    ))),
                 })
-                .collect();
+                .collect::<Result<Vec<_>, _>>()?;
             let verify_local_sig = LocalSig::verify_local_sigs(
                 &local_sig_vec,
                 &state
@@ -62,10 +87,10 @@ pub fn sign(
                     .map(|x| x.party_id - 1)
                     .collect::<Vec<_>>(),
                 &local_key.local_key.vss_schemes,
-                &local_key.offline_data.completed_offline[nonce].nonce_vss_schemes,
+                &local_key.offline_data.completed_offline[nonce_index].nonce_vss_schemes,
             );
             let vss_sum_local_sigs = verify_local_sig.context("verify local sig failed")?;
-            let signature = thresholdsig::generate(
+            let signature = thresholdsig::generate_with_tweak(
                 &vss_sum_local_sigs,
                 &local_sig_vec,
                 &state
@@ -74,19 +99,25 @@ pub fn sign(
                     .iter()
                     .map(|x| x.party_id - 1)
                     .collect::<Vec<_>>(),
-                local_key.offline_data.completed_offline[nonce]
+                local_key.offline_data.completed_offline[nonce_index]
                     .clone()
                     .agg_nonce,
+                &tweak,
             );
             state.signature = Some(SignatureRecidHex {
                 r: hex::encode(&signature.R.to_bytes(true).to_vec()),
                 s: hex::encode(&signature.s.to_bytes().to_vec()),
                 recid: 0,
             });
-            match signature.verify(&data_to_sign, &local_key.local_key.agg_pubkey) {
+            match signature.verify(&data_to_sign, &agg_pubkey) {
                 Ok(_) => (),
                 Err(_) => {
-                    return Err(anyhow!("signature verification failed"));
+                    // a wrong partial signature can come from any of the signers
+                    return Err(TssError::ProtocolAbort {
+                        culprits: vec![],
+                        message: "signature verification failed".to_owned(),
+                    }
+                    .into());
                 }
             }
         } else {
@@ -110,9 +141,9 @@ mod test {
 
     #[test]
     fn should_sign_a_message() {
-        let shard1: KeygenResult =
+        let mut shard1: KeygenResult =
             serde_json::from_str(test_wallets::wallet1_shard1().as_str()).unwrap();
-        let shard2: KeygenResult =
+        let mut shard2: KeygenResult =
             serde_json::from_str(test_wallets::wallet1_shard2().as_str()).unwrap();
         let _shard3: KeygenResult =
             serde_json::from_str(test_wallets::wallet1_shard3().as_str()).unwrap();
@@ -131,8 +162,8 @@ mod test {
         let message_to_sign =
             hex::decode("bd82be05afedc3f399efde5cda2e590c69b6478bf888dc38c961b12105485333")
                 .unwrap();
-        sign(&mut state, &shard1.eddsa, message_to_sign.clone(), 1, 0).unwrap();
-        sign(&mut state, &shard2.eddsa, message_to_sign.clone(), 2, 0).unwrap();
+        sign(&mut state, &mut shard1.eddsa, message_to_sign.clone(), 1, 0).unwrap();
+        sign(&mut state, &mut shard2.eddsa, message_to_sign.clone(), 2, 0).unwrap();
         assert_eq!(
             state.signature.clone().unwrap().r,
             "c778b1d931d96ce8709876d4c06708bfe0b7dd567ad24105118bad17352e5a83"
@@ -143,4 +174,38 @@ mod test {
         );
         assert_eq!(state.signature.unwrap().recid, 0);
     }
+
+    #[test]
+    fn nonce_signs_only_once() {
+        let mut shard1: KeygenResult =
+            serde_json::from_str(test_wallets::wallet1_shard1().as_str()).unwrap();
+        let remaining = shard1.eddsa.remaining_nonces();
+        let new_state = || SigningState {
+            t: 1,
+            n: 3,
+            signing_parts: vec![],
+            signature: None,
+        };
+
+        sign(&mut new_state(), &mut shard1.eddsa, b"first".to_vec(), 1, 0).unwrap();
+        assert_eq!(shard1.eddsa.remaining_nonces(), remaining - 1);
+        assert!(sign(
+            &mut new_state(),
+            &mut shard1.eddsa,
+            b"second".to_vec(),
+            1,
+            0
+        )
+        .is_err());
+        let out_of_range = remaining + usize::from(shard1.eddsa.offline_data.nonce_start_index);
+        assert!(sign(
+            &mut new_state(),
+            &mut shard1.eddsa,
+            b"second".to_vec(),
+            1,
+            out_of_range
+        )
+        .is_err());
+        assert_eq!(shard1.eddsa.remaining_nonces(), remaining - 1);
+    }
 }
@@ -1,14 +1,13 @@
 #![allow(non_snake_case)]
 
 #[cfg(test)]
-mod test;
+pub(crate) mod test;
 
 use crate::t_ed25519::Error::{self, InvalidKey, InvalidSS};
 
 use crate::t_ed25519::{ExpandedKeyPair, Signature};
+use crate::utils::session_id::SessionId;
 use curv::arithmetic::traits::*;
-use curv::cryptographic_primitives::commitments::hash_commitment::HashCommitment;
-use curv::cryptographic_primitives::commitments::traits::Commitment;
 use curv::cryptographic_primitives::hashing::DigestExt;
 use curv::cryptographic_primitives::secret_sharing::feldman_vss::{SecretShares, VerifiableSS};
 use curv::elliptic::curves::{Ed25519, Point, Scalar};
@@ -76,23 +75,25 @@ impl Keys {
         }
     }
 
-    pub fn phase1_broadcast(&self) -> (KeyGenBroadcastMessage1, BigInt) {
-        self.phase1_broadcast_rng(&mut thread_rng())
+    pub fn phase1_broadcast(&self, sid: &SessionId) -> (KeyGenBroadcastMessage1, BigInt) {
+        self.phase1_broadcast_rng(sid, &mut thread_rng())
     }
 
-    fn phase1_broadcast_rng(&self, rng: &mut impl Rng) -> (KeyGenBroadcastMessage1, BigInt) {
+    fn phase1_broadcast_rng(
+        &self,
+        sid: &SessionId,
+        rng: &mut impl Rng,
+    ) -> (KeyGenBroadcastMessage1, BigInt) {
         let blind_factor: [u8; SECURITY / 8] = rng.gen();
         let blind_factor = BigInt::from_bytes(&blind_factor);
-        let com = HashCommitment::<Sha512>::create_commitment_with_user_defined_randomness(
-            &self.keypair.public_key.y_coord().unwrap(),
-            &blind_factor,
-        );
+        let com = sid.commit::<Sha512>(&self.keypair.public_key.y_coord().unwrap(), &blind_factor);
         let bcm1 = KeyGenBroadcastMessage1 { com };
         (bcm1, blind_factor)
     }
 
     pub fn phase1_verify_com_phase2_distribute(
         &self,
+        sid: &SessionId,
         params: &Parameters,
         blind_vec: &[BigInt],
         y_vec: &[Point<Ed25519>],
@@ -109,10 +110,7 @@ impl Keys {
             .zip(blind_vec.iter())
             .zip(bc1_vec.iter())
             .all(|((y, blind), comm)| {
-                HashCommitment::<Sha512>::create_commitment_with_user_defined_randomness(
-                    &y.y_coord().unwrap(),
-                    blind,
-                ) == comm.com
+                sid.commit::<Sha512>(&y.y_coord().unwrap(), blind) == comm.com
             });
         if !correct_key_correct_decom_all {
             return Err(InvalidKey);
@@ -137,6 +135,26 @@ impl Keys {
         assert_eq!(secret_shares_vec.len(), usize::from(params.share_count));
         assert_eq!(vss_scheme_vec.len(), usize::from(params.share_count));
 
+        self.phase2_verify_dealt_vss_construct_keypair(
+            y_vec,
+            secret_shares_vec,
+            vss_scheme_vec,
+            index,
+        )
+    }
+
+    /// Like [Keys::phase2_verify_vss_construct_keypair], but the shares may be dealt by any
+    /// number of parties, not necessarily by all `n` of them
+    pub fn phase2_verify_dealt_vss_construct_keypair(
+        &self,
+        y_vec: &[Point<Ed25519>],
+        secret_shares_vec: &[Scalar<Ed25519>],
+        vss_scheme_vec: &[VerifiableSS<Ed25519>],
+        index: u16,
+    ) -> Result<SharedKeys, Error> {
+        assert_eq!(secret_shares_vec.len(), y_vec.len());
+        assert_eq!(vss_scheme_vec.len(), y_vec.len());
+
         let correct_ss_verify = vss_scheme_vec
             .iter()
             .zip(secret_shares_vec.iter())
@@ -153,11 +171,17 @@ impl Keys {
         let x_i = secret_shares_vec
             .iter()
             .fold(Scalar::zero(), |acc, x| acc + x);
-        Ok(SharedKeys {
+        Ok(self.shared_keys(y, x_i))
+    }
+
+    /// Shared keys of the party holding share `x_i` of the joint key `y`, e.g. a share
+    /// recovered with help of other parties
+    pub fn shared_keys(&self, y: Point<Ed25519>, x_i: Scalar<Ed25519>) -> SharedKeys {
+        SharedKeys {
             y,
             x_i,
             prefix: self.keypair.expanded_private_key.prefix.clone(),
-        })
+        }
     }
 }
 
@@ -200,23 +224,25 @@ impl EphemeralKey {
         }
     }
 
-    pub fn phase1_broadcast(&self) -> (KeyGenBroadcastMessage1, BigInt) {
-        self.phase1_broadcast_rng(&mut thread_rng())
+    pub fn phase1_broadcast(&self, sid: &SessionId) -> (KeyGenBroadcastMessage1, BigInt) {
+        self.phase1_broadcast_rng(sid, &mut thread_rng())
     }
 
-    pub fn phase1_broadcast_rng(&self, rng: &mut impl Rng) -> (KeyGenBroadcastMessage1, BigInt) {
+    pub fn phase1_broadcast_rng(
+        &self,
+        sid: &SessionId,
+        rng: &mut impl Rng,
+    ) -> (KeyGenBroadcastMessage1, BigInt) {
         let blind_factor: [u8; SECURITY / 8] = rng.gen();
         let blind_factor = BigInt::from_bytes(&blind_factor);
-        let com = HashCommitment::<Sha512>::create_commitment_with_user_defined_randomness(
-            &self.R_i.y_coord().unwrap(),
-            &blind_factor,
-        );
+        let com = sid.commit::<Sha512>(&self.R_i.y_coord().unwrap(), &blind_factor);
         let bcm1 = KeyGenBroadcastMessage1 { com };
         (bcm1, blind_factor)
     }
 
     pub fn phase1_verify_com_phase2_distribute(
         &self,
+        sid: &SessionId,
         params: &Parameters,
         blind_vec: &[BigInt],
         R_vec: &[Point<Ed25519>],
@@ -242,10 +268,7 @@ impl EphemeralKey {
             .zip(blind_vec.iter())
             .zip(bc1_vec.iter())
             .all(|((R, blind), comm)| {
-                HashCommitment::<Sha512>::create_commitment_with_user_defined_randomness(
-                    &R.y_coord().unwrap(),
-                    blind,
-                ) == comm.com
+                sid.commit::<Sha512>(&R.y_coord().unwrap(), blind) == comm.com
             });
 
         if !correct_key_correct_decom_all {
@@ -307,11 +330,30 @@ impl LocalSig {
         message: &[u8],
         local_ephemaral_key: &EphemeralSharedKeys,
         local_private_key: &SharedKeys,
+    ) -> LocalSig {
+        Self::compute_with_tweak(
+            message,
+            local_ephemaral_key,
+            local_private_key,
+            &Scalar::zero(),
+        )
+    }
+
+    /// Computes local signature for the key derived as `y + tweak * G`
+    ///
+    /// Shares stay untouched, the tweak only changes the challenge. It's added to the aggregated
+    /// signature in [generate_with_tweak].
+    pub fn compute_with_tweak(
+        message: &[u8],
+        local_ephemaral_key: &EphemeralSharedKeys,
+        local_private_key: &SharedKeys,
+        tweak: &Scalar<Ed25519>,
     ) -> LocalSig {
         let r_i = local_ephemaral_key.r_i.clone();
         let s_i = local_private_key.x_i.clone();
 
-        let k = Signature::k(&local_ephemaral_key.R, &local_private_key.y, message);
+        let y = &local_private_key.y + Point::generator() * tweak;
+        let k = Signature::k(&local_ephemaral_key.R, &y, message);
         let gamma_i = r_i + &k * s_i;
 
         LocalSig { gamma_i, k }
@@ -378,6 +420,24 @@ pub fn generate(
     local_sig_vec: &[LocalSig],
     parties_index_vec: &[u16],
     R: Point<Ed25519>,
+) -> Signature {
+    generate_with_tweak(
+        vss_sum_local_sigs,
+        local_sig_vec,
+        parties_index_vec,
+        R,
+        &Scalar::zero(),
+    )
+}
+
+/// Aggregates local signatures computed by [LocalSig::compute_with_tweak] into signature valid
+/// for the derived key `y + tweak * G`
+pub fn generate_with_tweak(
+    vss_sum_local_sigs: &VerifiableSS<Ed25519>,
+    local_sig_vec: &[LocalSig],
+    parties_index_vec: &[u16],
+    R: Point<Ed25519>,
+    tweak: &Scalar<Ed25519>,
 ) -> Signature {
     let reconstruct_limit = usize::from(vss_sum_local_sigs.parameters.threshold) + 1;
     let gamma_vec: Vec<_> = local_sig_vec[..reconstruct_limit]
@@ -385,5 +445,6 @@ pub fn generate(
         .map(|sig| sig.gamma_i.clone())
         .collect();
     let s = vss_sum_local_sigs.reconstruct(&parties_index_vec[0..reconstruct_limit], &gamma_vec);
+    let s = s + &local_sig_vec[0].k * tweak;
     Signature { s, R }
 }
@@ -1,10 +1,11 @@
 #![allow(non_snake_case)]
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::t_ed25519::tests::{deterministic_fast_rand, verify_dalek};
     use crate::t_ed25519::thresholdsig::{
         self, EphemeralKey, EphemeralSharedKeys, Keys, LocalSig, Parameters, SharedKeys,
     };
+    use crate::utils::session_id::SessionId;
     use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
     use curv::elliptic::curves::{Ed25519, Point};
     use itertools::{izip, Itertools};
@@ -219,10 +220,11 @@ mod tests {
         };
         assert_eq!(parties.len(), usize::from(n));
         let keypairs: Vec<_> = parties.iter().copied().map(Keys::phase1_create).collect();
+        let sid = SessionId::new("eddsa-keygen", "test", parties);
 
         let (first_msgs, first_msg_blinds): (Vec<_>, Vec<_>) = keypairs
             .iter()
-            .map(|keypair| Keys::phase1_broadcast_rng(keypair, rng))
+            .map(|keypair| Keys::phase1_broadcast_rng(keypair, &sid, rng))
             .unzip();
 
         let pubkeys_list: Vec<_> = keypairs
@@ -240,6 +242,7 @@ mod tests {
             .map(|keypair| {
                 keypair
                     .phase1_verify_com_phase2_distribute(
+                        &sid,
                         &params,
                         &first_msg_blinds,
                         &pubkeys_list,
@@ -292,6 +295,7 @@ mod tests {
             threshold: t,
             share_count: n,
         };
+        let sid = SessionId::new("eddsa-presign", "test", parties);
         // Generate Rs
         let (Rs, nonce_keys): (Vec<_>, Vec<_>) = parties
             .iter()
@@ -310,7 +314,7 @@ mod tests {
         // Generate first messages
         let (first_msgs, first_msg_blinds): (Vec<_>, Vec<_>) = nonce_keys
             .iter()
-            .map(|nonce| EphemeralKey::phase1_broadcast_rng(nonce, rng))
+            .map(|nonce| EphemeralKey::phase1_broadcast_rng(nonce, &sid, rng))
             .unzip();
 
         // Generate the aggregate nonce point
@@ -324,6 +328,7 @@ mod tests {
             .map(|nonce| {
                 nonce
                     .phase1_verify_com_phase2_distribute(
+                        &sid,
                         &params,
                         &first_msg_blinds,
                         &Rs,
@@ -1,5 +1,7 @@
 #![allow(dead_code)]
 
+use std::collections::BTreeSet;
+
 use anyhow::Context;
 use base64::engine::general_purpose;
 use base64::Engine;
@@ -7,18 +9,29 @@ use curv::{arithmetic::traits::Converter, elliptic::curves::secp256_k1::Secp256k
 use futures::TryStreamExt;
 use rustmodel::{
     EncryptedKeygenResult, EncryptedKeygenWithScheme, EncryptedLocalKey, KeyScheme, KeygenMember,
-    KeygenProgress, SignatureRecidHex, SignedPartialSignatureBase64, SigningStateBase64,
+    KeygenProgress, NativeSigningRequest, SignatureRecidHex, SignedPartialSignatureBase64,
+    SigningStateBase64,
 };
+use secp256k1::PublicKey;
 use serde::{Deserialize, Serialize};
 use sha2::Digest;
+use zeroize::Zeroizing;
 
+use crate::error::TssError;
+use crate::gg20;
+use crate::gg20::derivation::parse_path;
 use crate::gg20::state_machine::keygen::LocalKey;
-use crate::gg20::state_machine::sign;
 use crate::gg20::state_machine::sign::{CompletedOfflineStage, PartialSignature};
+use crate::t_ed25519;
 use crate::t_ed25519::keygen::EddsaLocalKey;
 use crate::t_ed25519::presignature::EddsaOffline;
 use crate::t_ed25519::thresholdsig::LocalSig;
-use crate::utils::encryption::{decrypt, encrypt};
+use crate::utils::encryption::{decrypt, encrypt, KdfParams};
+use crate::utils::model::{
+    NativeDerivePublicKeyRequest, NativeDerivePublicKeyResponse, NativeSigningResponse,
+    NativeXpubRequest, NativeXpubResponse,
+};
+use crate::utils::secure_channel::Roster;
 
 pub type Key = String;
 
@@ -40,10 +53,18 @@ pub struct SignedPartialSignature {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(untagged)]
 pub enum PartialSignatureType {
-    ECDSA(PartialSignature),
+    ECDSA(EcdsaPartialSignature),
     EDDSA(LocalSig),
 }
 
+/// Partial ECDSA signature together with the presignature it was made with
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EcdsaPartialSignature {
+    /// Hex-encoded point `R` of the presignature, see [CompletedOfflineStage::r]
+    pub presignature: String,
+    pub part: PartialSignature,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct EcdsaLocalKeyData {
     pub local_key: LocalKey<Secp256k1>,
@@ -52,34 +73,109 @@ pub struct EcdsaLocalKeyData {
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(from = "StoredEddsaLocalKeyData")]
 pub struct EddsaLocalKeyData {
     pub local_key: EddsaLocalKey,
     pub offline_data: EddsaOfflineResult,
     pub algorithm: String,
 }
 
+/// Serialized [EddsaLocalKeyData], see [EddsaLocalKeyData::stored]
+#[derive(Deserialize)]
+struct StoredEddsaLocalKeyData {
+    local_key: EddsaLocalKey,
+    offline_data: EddsaOfflineResult,
+    algorithm: String,
+}
+
+impl From<StoredEddsaLocalKeyData> for EddsaLocalKeyData {
+    fn from(stored: StoredEddsaLocalKeyData) -> Self {
+        Self::stored(stored.local_key, stored.offline_data, stored.algorithm)
+    }
+}
+
+impl EcdsaLocalKeyData {
+    /// Number of presignatures of the current key which haven't signed anything yet
+    pub fn remaining_presignatures(&self) -> usize {
+        self.offline_data
+            .iter()
+            .filter(|x| !x.consumed && x.completed_offline.is_for_key(&self.local_key))
+            .count()
+    }
+
+    /// Adds freshly generated presignatures to the pool
+    ///
+    /// Consumed presignatures and ones of a previous key are dropped at the same time.
+    pub fn add_presignatures(&mut self, presignatures: Vec<EcdsaOfflineResult>) {
+        let local_key = &self.local_key;
+        self.offline_data
+            .retain(|x| !x.consumed && x.completed_offline.is_for_key(local_key));
+        self.offline_data.extend(presignatures);
+    }
+}
+
+impl EddsaLocalKeyData {
+    /// Key and nonces read from storage
+    ///
+    /// Nonces stored before they were bound to key epochs are taken to belong to the key stored
+    /// next to them.
+    pub fn stored(
+        local_key: EddsaLocalKey,
+        mut offline_data: EddsaOfflineResult,
+        algorithm: String,
+    ) -> Self {
+        if offline_data.key_epoch.is_none() {
+            offline_data.key_epoch = Some(local_key.epoch());
+        }
+        Self {
+            local_key,
+            offline_data,
+            algorithm,
+        }
+    }
+
+    /// Number of nonces which haven't signed anything yet
+    pub fn remaining_nonces(&self) -> usize {
+        self.offline_data.remaining_nonces()
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct KeygenResult {
     pub party_id: u16,
     pub ecdsa: EcdsaLocalKeyData,
     pub eddsa: EddsaLocalKeyData,
     pub members: Vec<KeygenMember>,
+    /// Identity keys of the parties bound by keygen, local keys keep a copy of it
+    #[serde(default)]
+    pub roster: Roster,
 }
 
-pub fn encrypt_keygen_result(result: KeygenResult, password: &str) -> EncryptedKeygenResult {
-    return EncryptedKeygenResult {
+pub fn encrypt_keygen_result(
+    result: KeygenResult,
+    password: &str,
+    kdf: &KdfParams,
+) -> Result<EncryptedKeygenResult, TssError> {
+    Ok(EncryptedKeygenResult {
         party_id: result.party_id as i32,
         encrypted_keygen_with_scheme: vec![
-            encrypt_ecdsa_keygen_result(&result, password, result.ecdsa.algorithm.as_str()),
+            encrypt_ecdsa_keygen_result(
+                &result.ecdsa.local_key,
+                &result.ecdsa.offline_data,
+                password,
+                result.ecdsa.algorithm.as_str(),
+                kdf,
+            )?,
             encrypt_eddsa_keygen_result(
                 &result.eddsa.local_key,
                 &result.eddsa.offline_data,
                 password,
                 result.eddsa.algorithm.as_str(),
-            ),
+                kdf,
+            )?,
         ],
         members: result.members,
-    };
+    })
 }
 
 pub fn encrypt_eddsa_keygen_result(
@@ -87,60 +183,90 @@ pub fn encrypt_eddsa_keygen_result(
     offline_data: &EddsaOfflineResult,
     password: &str,
     algorithm: &str,
-) -> EncryptedKeygenWithScheme {
-    EncryptedKeygenWithScheme {
+    kdf: &KdfParams,
+) -> Result<EncryptedKeygenWithScheme, TssError> {
+    let pubkey = hex::encode(&local_key.agg_pubkey.to_bytes(true).to_vec());
+    Ok(EncryptedKeygenWithScheme {
         key_scheme: KeyScheme::EDDSA,
         nonce_start_index: offline_data.nonce_start_index as i32,
         nonce_size: offline_data.nonce_size as i32,
         encrypted_local_key: EncryptedLocalKey {
             algorithm: algorithm.to_string(),
-            pubkey: hex::encode(&local_key.agg_pubkey.to_bytes(true).to_vec()),
-            encrypted_key: encrypt(
-                serde_json::to_string(&local_key).unwrap().as_str(),
+            encrypted_key: encrypt_json(
+                local_key,
                 password,
-            )
-            .unwrap(),
-            encrypted_nonce: encrypt(
-                serde_json::to_string(&offline_data).unwrap().as_str(),
+                &associated_data("EDDSA", "key", &pubkey, algorithm),
+                kdf,
+            )?,
+            encrypted_nonce: encrypt_json(
+                offline_data,
                 password,
-            )
-            .unwrap(),
+                &associated_data("EDDSA", "nonce", &pubkey, algorithm),
+                kdf,
+            )?,
+            pubkey,
         },
-    }
+    })
 }
 
-fn encrypt_ecdsa_keygen_result(
-    result: &KeygenResult,
+pub fn encrypt_ecdsa_keygen_result(
+    local_key: &LocalKey<Secp256k1>,
+    offline_data: &[EcdsaOfflineResult],
     password: &str,
     algorithm: &str,
-) -> EncryptedKeygenWithScheme {
-    EncryptedKeygenWithScheme {
+    kdf: &KdfParams,
+) -> Result<EncryptedKeygenWithScheme, TssError> {
+    let pubkey = hex::encode(&local_key.public_key().to_bytes(true).to_vec());
+    Ok(EncryptedKeygenWithScheme {
         key_scheme: KeyScheme::ECDSA,
         nonce_start_index: 0,
         nonce_size: 1,
         encrypted_local_key: EncryptedLocalKey {
-            pubkey: hex::encode(&result.ecdsa.local_key.public_key().to_bytes(true).to_vec()),
             algorithm: algorithm.to_string(),
-            encrypted_key: encrypt(
-                serde_json::to_string(&result.ecdsa.local_key)
-                    .unwrap()
-                    .as_str(),
+            encrypted_key: encrypt_json(
+                local_key,
                 password,
-            )
-            .unwrap(),
-            encrypted_nonce: encrypt(
-                serde_json::to_string(&result.ecdsa.offline_data)
-                    .unwrap()
-                    .as_str(),
+                &associated_data("ECDSA", "key", &pubkey, algorithm),
+                kdf,
+            )?,
+            encrypted_nonce: encrypt_json(
+                offline_data,
                 password,
-            )
-            .unwrap(),
+                &associated_data("ECDSA", "nonce", &pubkey, algorithm),
+                kdf,
+            )?,
+            pubkey,
         },
+    })
+}
+
+/// Serializes `value` to JSON and [encrypt]s it, the plaintext is wiped afterwards
+fn encrypt_json<T: Serialize + ?Sized>(
+    value: &T,
+    password: &str,
+    associated_data: &[u8],
+    kdf: &KdfParams,
+) -> Result<String, TssError> {
+    let json = Zeroizing::new(serde_json::to_string(value).map_err(TssError::internal)?);
+    encrypt(json.as_str(), password, associated_data, kdf).map_err(TssError::from)
+}
+
+/// Associated data binding the encrypted `field` of a stored key to the scheme, public key and
+/// algorithm stored next to it in the clear
+fn associated_data(key_scheme: &str, field: &str, pubkey: &str, algorithm: &str) -> Vec<u8> {
+    let mut data = vec![];
+    for part in [key_scheme, field, pubkey, algorithm] {
+        data.extend_from_slice(&(part.len() as u64).to_be_bytes());
+        data.extend_from_slice(part.as_bytes());
     }
+    data
 }
 
-pub fn signing_state_obj_to_base64(scheme: KeyScheme, result: &SigningState) -> SigningStateBase64 {
-    return SigningStateBase64 {
+pub fn signing_state_obj_to_base64(
+    scheme: KeyScheme,
+    result: &SigningState,
+) -> Result<SigningStateBase64, TssError> {
+    Ok(SigningStateBase64 {
         t: result.t as i32,
         n: result.n as i32,
         key_scheme: scheme,
@@ -148,55 +274,70 @@ pub fn signing_state_obj_to_base64(scheme: KeyScheme, result: &SigningState) ->
         signing_parts_base64: result
             .signing_parts
             .iter()
-            .map(|x| SignedPartialSignatureBase64 {
-                party_id: x.party_id.clone() as i32,
-                part_base64: general_purpose::STANDARD
-                    .encode(serde_json::to_string(&x.part.clone()).unwrap()),
-                signed_at: x.signed_at.clone(),
+            .map(|x| {
+                let part = serde_json::to_string(&x.part).map_err(TssError::internal)?;
+                Ok(SignedPartialSignatureBase64 {
+                    party_id: x.party_id as i32,
+                    part_base64: general_purpose::STANDARD.encode(part),
+                    signed_at: x.signed_at.clone(),
+                })
             })
-            .collect(),
-    };
+            .collect::<Result<_, TssError>>()?,
+    })
 }
 
-pub fn signing_state_base64_to_obj(result: &SigningStateBase64) -> SigningState {
-    return SigningState {
-        t: result.t as u16,
-        n: result.n as u16,
-        signature: result.signature.clone(),
-        signing_parts: result
-            .signing_parts_base64
-            .iter()
-            .map(|x| SignedPartialSignature {
+/// Decodes signing state passed between signers, fails on a malformed partial signature
+pub fn signing_state_base64_to_obj(result: &SigningStateBase64) -> Result<SigningState, TssError> {
+    let signing_parts = result
+        .signing_parts_base64
+        .iter()
+        .map(|x| {
+            let part = general_purpose::STANDARD
+                .decode(&x.part_base64)
+                .map_err(|e| {
+                    TssError::invalid_input(format_args!(
+                        "partial signature of party {} is not base64: {}",
+                        x.party_id, e
+                    ))
+                })?;
+            let part = if result.key_scheme == KeyScheme::ECDSA {
+                PartialSignatureType::ECDSA(serde_json::from_slice(&part).map_err(|e| {
+                    TssError::invalid_input(format_args!(
+                        "malformed partial signature of party {}: {}",
+                        x.party_id, e
+                    ))
+                })?)
+            } else {
+                PartialSignatureType::EDDSA(serde_json::from_slice(&part).map_err(|e| {
+                    TssError::invalid_input(format_args!(
+                        "malformed partial signature of party {}: {}",
+                        x.party_id, e
+                    ))
+                })?)
+            };
+            Ok::<_, TssError>(SignedPartialSignature {
                 party_id: x.party_id.clone() as u16,
-                part: if result.key_scheme == KeyScheme::ECDSA {
-                    let r: sign::PartialSignature = serde_json::from_slice(
-                        general_purpose::STANDARD
-                            .decode(&x.part_base64)
-                            .unwrap()
-                            .as_slice(),
-                    )
-                    .unwrap();
-                    PartialSignatureType::ECDSA(r)
-                } else {
-                    let r: LocalSig = serde_json::from_slice::<LocalSig>(
-                        general_purpose::STANDARD
-                            .decode(&x.part_base64)
-                            .unwrap()
-                            .as_slice(),
-                    )
-                    .unwrap();
-                    PartialSignatureType::EDDSA(r)
-                },
+                part,
                 signed_at: x.signed_at.clone(),
             })
-            .collect(),
-    };
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(SigningState {
+        t: result.t as u16,
+        n: result.n as u16,
+        signature: result.signature.clone(),
+        signing_parts,
+    })
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct EcdsaOfflineResult {
     pub parties: Vec<u16>,
     pub completed_offline: CompletedOfflineStage,
+    /// Whether the presignature already signed a message, signing another one with it leaks
+    /// the key
+    #[serde(default)]
+    pub consumed: bool,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -205,6 +346,49 @@ pub struct EddsaOfflineResult {
     pub nonce_start_index: u16,
     pub nonce_size: u16,
     pub completed_offline: Vec<EddsaOffline>,
+    /// Nonces which already signed a message, numbered from `nonce_start_index`
+    #[serde(default)]
+    pub consumed_nonces: BTreeSet<usize>,
+    /// [EddsaLocalKey::epoch] of the key the nonces were generated for, they don't sign with
+    /// refreshed or reshared shares
+    ///
+    /// `None` for nonces generated before they were bound to key epochs, see
+    /// [EddsaLocalKeyData::stored].
+    #[serde(default)]
+    pub key_epoch: Option<String>,
+}
+
+impl EddsaOfflineResult {
+    /// Marks nonce number `nonce` consumed and returns its position in `completed_offline`
+    ///
+    /// Fails if there's no such nonce or it has already been consumed.
+    pub fn consume(&mut self, nonce: usize) -> anyhow::Result<usize> {
+        let start = usize::from(self.nonce_start_index);
+        let end = start + self.completed_offline.len();
+        let index = nonce
+            .checked_sub(start)
+            .filter(|index| *index < self.completed_offline.len())
+            .ok_or_else(|| {
+                TssError::invalid_input(format_args!(
+                    "nonce {} out of range [{}; {})",
+                    nonce, start, end
+                ))
+            })?;
+        if !self.consumed_nonces.insert(nonce) {
+            return Err(TssError::nonce_exhausted(format_args!(
+                "nonce {} has already been used",
+                nonce
+            ))
+            .into());
+        }
+        Ok(index)
+    }
+
+    pub fn remaining_nonces(&self) -> usize {
+        let start = usize::from(self.nonce_start_index);
+        let end = start + self.completed_offline.len();
+        self.completed_offline.len() - self.consumed_nonces.range(start..end).count()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -212,6 +396,9 @@ pub struct IssueIndexMsg {
     pub parties: Vec<u16>,
     pub party_id: Option<u16>,
     pub party_name: Option<String>,
+    /// Long-term identity key of the party
+    #[serde(default)]
+    pub identity: Option<PublicKey>,
 }
 
 pub fn powerset<T>(s: &[T]) -> Vec<Vec<T>>
@@ -240,68 +427,374 @@ where
     result
 }
 
+/// Decrypts an ECDSA key and its presignatures, the plaintext JSON is wiped once parsed
 pub fn decrypt_ecdsa(
     local_key: &EncryptedLocalKey,
     password: &str,
 ) -> anyhow::Result<EcdsaLocalKeyData> {
     Ok(EcdsaLocalKeyData {
         algorithm: local_key.algorithm.clone(),
-        local_key: serde_json::from_str(
-            decrypt(local_key.encrypted_key.as_str(), password)
-                .context("failed decrypt ECDSA localKey")?
-                .as_str(),
-        )?,
-        offline_data: serde_json::from_str(
-            decrypt(local_key.encrypted_nonce.as_str(), password)
-                .context("failed decrypt ECDSA Nonce")?
-                .as_str(),
-        )?,
+        local_key: serde_json::from_str(&Zeroizing::new(
+            decrypt(
+                local_key.encrypted_key.as_str(),
+                password,
+                &associated_data("ECDSA", "key", &local_key.pubkey, &local_key.algorithm),
+            )
+            .context("failed decrypt ECDSA localKey")?,
+        ))?,
+        offline_data: serde_json::from_str(&Zeroizing::new(
+            decrypt(
+                local_key.encrypted_nonce.as_str(),
+                password,
+                &associated_data("ECDSA", "nonce", &local_key.pubkey, &local_key.algorithm),
+            )
+            .context("failed decrypt ECDSA Nonce")?,
+        ))?,
     })
 }
 
+/// Decrypts an EdDSA key and its nonces, the plaintext JSON is wiped once parsed
 pub fn decrypt_eddsa(
     local_key: &EncryptedLocalKey,
     password: &str,
 ) -> anyhow::Result<EddsaLocalKeyData> {
-    Ok(EddsaLocalKeyData {
-        algorithm: local_key.algorithm.clone(),
-        local_key: serde_json::from_str(
-            decrypt(local_key.encrypted_key.as_str(), password)
-                .context("failed decrypt EDDSA localKey")?
-                .as_str(),
-        )?,
-        offline_data: serde_json::from_str(
-            decrypt(local_key.encrypted_nonce.as_str(), password)
-                .context("failed decrypt EDDSA Nonce")?
-                .as_str(),
-        )?,
+    Ok(EddsaLocalKeyData::stored(
+        serde_json::from_str(&Zeroizing::new(
+            decrypt(
+                local_key.encrypted_key.as_str(),
+                password,
+                &associated_data("EDDSA", "key", &local_key.pubkey, &local_key.algorithm),
+            )
+            .context("failed decrypt EDDSA localKey")?,
+        ))?,
+        serde_json::from_str(&Zeroizing::new(
+            decrypt(
+                local_key.encrypted_nonce.as_str(),
+                password,
+                &associated_data("EDDSA", "nonce", &local_key.pubkey, &local_key.algorithm),
+            )
+            .context("failed decrypt EDDSA Nonce")?,
+        ))?,
+        local_key.algorithm.clone(),
+    ))
+}
+
+/// Signs a message with the encrypted key of `request`
+///
+/// The presignature or nonce that signs the message is marked consumed in the key returned
+/// together with the signing state. The returned key must replace the key of the request before
+/// the state is passed on, otherwise the same material could sign another message.
+///
+/// Signs for the child key at `path`, empty path signs with the master key.
+pub fn sign_request(
+    request: NativeSigningRequest,
+    path: &[u32],
+) -> anyhow::Result<NativeSigningResponse> {
+    let data = hex::decode(&request.hex_data)
+        .map_err(|e| TssError::invalid_input(format_args!("message to sign is not hex: {}", e)))?;
+    let mut state = signing_state_base64_to_obj(&request.state_base64)?;
+    let password = request.password.as_str();
+    // the key is written back with the cost it was stored with
+    let kdf = KdfParams::of(&request.encrypted_local_key.encrypted_key).unwrap_or_default();
+    let (encrypted_local_key, remaining) = if request.key_scheme == KeyScheme::ECDSA {
+        let mut local_key = decrypt_ecdsa(&request.encrypted_local_key, password)?;
+        gg20::signing::sign_derived(
+            &mut state,
+            &mut local_key,
+            data,
+            request.party_id as u16,
+            request.signers.iter().map(|x| *x as u16).collect(),
+            path,
+        )?;
+        let encrypted = encrypt_ecdsa_keygen_result(
+            &local_key.local_key,
+            &local_key.offline_data,
+            password,
+            &local_key.algorithm,
+            &kdf,
+        )?;
+        (
+            encrypted.encrypted_local_key,
+            local_key.remaining_presignatures(),
+        )
+    } else {
+        let mut local_key = decrypt_eddsa(&request.encrypted_local_key, password)?;
+        t_ed25519::signing::sign_derived(
+            &mut state,
+            &mut local_key,
+            data,
+            request.party_id as u16,
+            request.nonce as usize,
+            path,
+        )?;
+        let encrypted = encrypt_eddsa_keygen_result(
+            &local_key.local_key,
+            &local_key.offline_data,
+            password,
+            &local_key.algorithm,
+            &kdf,
+        )?;
+        (encrypted.encrypted_local_key, local_key.remaining_nonces())
+    };
+    Ok(NativeSigningResponse {
+        state: signing_state_obj_to_base64(request.key_scheme, &state)?,
+        encrypted_local_key,
+        remaining,
     })
 }
 
+pub fn decrypt_keygen_result(
+    result: &EncryptedKeygenResult,
+    password: &str,
+) -> anyhow::Result<(EcdsaLocalKeyData, EddsaLocalKeyData)> {
+    let local_key = |scheme: KeyScheme| {
+        result
+            .encrypted_keygen_with_scheme
+            .iter()
+            .find(|keygen| keygen.key_scheme == scheme)
+            .map(|keygen| &keygen.encrypted_local_key)
+    };
+    let ecdsa = local_key(KeyScheme::ECDSA).context("missing ECDSA localKey")?;
+    let eddsa = local_key(KeyScheme::EDDSA).context("missing EDDSA localKey")?;
+    Ok((
+        decrypt_ecdsa(ecdsa, password)?,
+        decrypt_eddsa(eddsa, password)?,
+    ))
+}
+
+/// Encrypts both keys of `result` with `new_password` instead of `old_password`
+///
+/// Both keys and their presignatures or nonces are decrypted before anything is encrypted again,
+/// so a wrong `old_password` fails without producing a result. Keys keep the KDF cost they were
+/// stored with, keys stored in the legacy format get the default one.
+pub fn reencrypt(
+    result: EncryptedKeygenResult,
+    old_password: &str,
+    new_password: &str,
+) -> anyhow::Result<EncryptedKeygenResult> {
+    let (ecdsa, eddsa) = decrypt_keygen_result(&result, old_password)?;
+    let kdf = result
+        .encrypted_keygen_with_scheme
+        .iter()
+        .find_map(|keygen| KdfParams::of(&keygen.encrypted_local_key.encrypted_key))
+        .unwrap_or_default();
+    let result = KeygenResult {
+        party_id: result.party_id as u16,
+        ecdsa,
+        eddsa,
+        members: result.members,
+        roster: Roster::default(),
+    };
+    Ok(encrypt_keygen_result(result, new_password, &kdf)?)
+}
+
+/// Extended public key of a stored ECDSA key, see [LocalKey::xpub]
+pub fn xpub_request(request: NativeXpubRequest) -> anyhow::Result<NativeXpubResponse> {
+    let local_key = decrypt_ecdsa(&request.encrypted_local_key, request.password.as_str())?;
+    let xpub = local_key
+        .local_key
+        .xpub()
+        .map_err(TssError::invalid_input)?;
+    Ok(NativeXpubResponse { xpub })
+}
+
+/// Public key of the child at `path` of a stored key, the key signs for it with the same `path`
+pub fn derive_public_key_request(
+    request: NativeDerivePublicKeyRequest,
+) -> anyhow::Result<NativeDerivePublicKeyResponse> {
+    let path = parse_path(&request.path).map_err(TssError::invalid_input)?;
+    let password = request.password.as_str();
+    let public_key = if request.key_scheme == KeyScheme::ECDSA {
+        let local_key = decrypt_ecdsa(&request.encrypted_local_key, password)?;
+        let derived = local_key
+            .local_key
+            .derive(&path)
+            .map_err(TssError::invalid_input)?;
+        hex::encode(derived.public_key.to_bytes(true).to_vec())
+    } else {
+        let local_key = decrypt_eddsa(&request.encrypted_local_key, password)?;
+        let derived = local_key
+            .local_key
+            .derive(&path)
+            .map_err(TssError::invalid_input)?;
+        hex::encode(derived.agg_pubkey.to_bytes(true).to_vec())
+    };
+    Ok(NativeDerivePublicKeyResponse { public_key })
+}
+
 pub async fn get_progress(
     request_id: &str,
     token: &str,
     address: surf::Url,
     room_id: &str,
-) -> KeygenProgress {
+) -> Result<KeygenProgress, TssError> {
+    let base_url = address
+        .join(&format!("rooms/{}/", room_id))
+        .map_err(|e| TssError::invalid_input(format_args!("state manager address: {}", e)))?;
     let http_client: surf::Client = surf::Config::new()
-        .set_base_url(address.join(&format!("rooms/{}/", room_id)).unwrap())
+        .set_base_url(base_url)
         .set_timeout(None)
         .try_into()
-        .unwrap();
-    return http_client
+        .map_err(TssError::transport)?;
+    http_client
         .get("status")
         .header("X-Request-ID", request_id)
         .header("X-Token", token)
         .recv_json::<KeygenProgress>()
         .await
-        .map_err(|e| e.into_inner())
-        .unwrap();
+        .map_err(TssError::transport)
 }
 
 #[cfg(test)]
 mod test {
-    use crate::utils::common::powerset;
+    use rustmodel::{EncryptedKeygenResult, KeyScheme};
+
+    use crate::all_keygen::test::simulate_keygen_and_offline;
+    use crate::error::TssError;
+    use crate::gg20;
+    use crate::t_ed25519;
+    use crate::utils::common::{
+        decrypt_keygen_result, derive_public_key_request, encrypt_ecdsa_keygen_result,
+        encrypt_eddsa_keygen_result, encrypt_keygen_result, get_progress, powerset, reencrypt,
+        xpub_request, SigningState,
+    };
+    use crate::utils::encryption::KdfParams;
+    use crate::utils::model::{
+        NativeDerivePublicKeyRequest, NativeSigningOptions, NativeXpubRequest,
+    };
+
+    const CHEAP_KDF: KdfParams = KdfParams::MIN;
+
+    #[tokio::test]
+    async fn reencrypt_changes_password() {
+        let result = simulate_keygen_and_offline(1, 2, 1, false, false)
+            .await
+            .remove(0);
+        let encrypted =
+            serde_json::to_string(&encrypt_keygen_result(result, "old", &CHEAP_KDF).unwrap())
+                .unwrap();
+        let stored = || serde_json::from_str::<EncryptedKeygenResult>(&encrypted).unwrap();
+
+        assert!(reencrypt(stored(), "wrong", "new").is_err());
+        let reencrypted = reencrypt(stored(), "old", "new").unwrap();
+        assert!(decrypt_keygen_result(&reencrypted, "old").is_err());
+        let (ecdsa, eddsa) = decrypt_keygen_result(&reencrypted, "new").unwrap();
+        let (old_ecdsa, old_eddsa) = decrypt_keygen_result(&stored(), "old").unwrap();
+        assert_eq!(ecdsa.local_key.pk_vec, old_ecdsa.local_key.pk_vec);
+        assert_eq!(
+            eddsa.offline_data.nonce_size,
+            old_eddsa.offline_data.nonce_size
+        );
+        assert!(reencrypted
+            .encrypted_keygen_with_scheme
+            .iter()
+            .all(
+                |keygen| KdfParams::of(&keygen.encrypted_local_key.encrypted_key)
+                    == Some(CHEAP_KDF)
+            ));
+    }
+
+    #[tokio::test]
+    async fn signs_with_child_of_exported_xpub() {
+        let mut results = simulate_keygen_and_offline(1, 2, 1, false, true).await;
+        let stored = encrypt_ecdsa_keygen_result(
+            &results[0].ecdsa.local_key,
+            &results[0].ecdsa.offline_data,
+            "123",
+            "gg20",
+            &CHEAP_KDF,
+        )
+        .unwrap();
+        let xpub = xpub_request(NativeXpubRequest {
+            encrypted_local_key: stored.encrypted_local_key,
+            password: "123".to_string(),
+        })
+        .unwrap()
+        .xpub;
+        assert_eq!(xpub, results[0].ecdsa.local_key.xpub().unwrap());
+
+        let hardened: NativeSigningOptions = serde_json::from_str(r#"{"path":"m/44'/0"}"#).unwrap();
+        let err = TssError::from(hardened.path().unwrap_err());
+        assert_eq!(err.code(), "INVALID_INPUT");
+
+        let options: NativeSigningOptions = serde_json::from_str(r#"{"path":"m/0/7"}"#).unwrap();
+        let path = options.path().unwrap();
+        assert_eq!(path, vec![0, 7]);
+        let mut state = SigningState {
+            t: 1,
+            n: 2,
+            signing_parts: vec![],
+            signature: None,
+        };
+        for result in &mut results {
+            // the last signer checks the signature against the child key
+            gg20::signing::sign_derived(
+                &mut state,
+                &mut result.ecdsa,
+                b"hello".to_vec(),
+                result.party_id,
+                vec![1, 2],
+                &path,
+            )
+            .unwrap();
+        }
+        assert!(state.signature.is_some());
+    }
+
+    #[tokio::test]
+    async fn eddsa_signs_for_exported_child_key() {
+        let mut results = simulate_keygen_and_offline(1, 2, 1, false, false).await;
+        let stored = encrypt_eddsa_keygen_result(
+            &results[0].eddsa.local_key,
+            &results[0].eddsa.offline_data,
+            "123",
+            "t_ed25519",
+            &CHEAP_KDF,
+        )
+        .unwrap();
+        let child = derive_public_key_request(NativeDerivePublicKeyRequest {
+            key_scheme: KeyScheme::EDDSA,
+            encrypted_local_key: stored.encrypted_local_key,
+            password: "123".to_string(),
+            path: "m/0/7".to_string(),
+        })
+        .unwrap();
+        let derived = results[0].eddsa.local_key.derive(&[0, 7]).unwrap();
+        assert_eq!(
+            child.public_key,
+            hex::encode(derived.agg_pubkey.to_bytes(true).to_vec())
+        );
+
+        let mut state = SigningState {
+            t: 1,
+            n: 2,
+            signing_parts: vec![],
+            signature: None,
+        };
+        for result in &mut results {
+            // the last signer checks the signature against the child key
+            t_ed25519::signing::sign_derived(
+                &mut state,
+                &mut result.eddsa,
+                b"hello".to_vec(),
+                result.party_id,
+                0,
+                &[0, 7],
+            )
+            .unwrap();
+        }
+        assert!(state.signature.is_some());
+    }
+
+    #[tokio::test]
+    async fn unreachable_state_manager_is_a_transport_error() {
+        // nothing listens on the discard port
+        let address = surf::Url::parse("http://127.0.0.1:9/").unwrap();
+        let err = get_progress("request", "token", address, "room")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TssError::Transport { .. }), "{:?}", err);
+    }
 
     #[test]
     fn test_powerset() {
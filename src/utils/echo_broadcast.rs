@@ -0,0 +1,651 @@
+//! Reliable broadcast on top of protocol state machines
+//!
+//! The relay delivers a broadcasted message to every party separately, so a malicious party can
+//! show different commitments to different parties. [EchoBroadcast] wraps a state machine and
+//! holds broadcasted messages back until all parties confirm they received the same ones: once a
+//! party has the k-th broadcasted message of everyone, it sends their digests to the others
+//! ("echo") and passes the messages to the protocol only when echoes of all parties match its own
+//! digests.
+//!
+//! Senders sign digests of their broadcasts with their identity keys and echoes carry the
+//! signatures, so an echo can't be made up: an echoed digest which differs from the received one
+//! is either signed by the sender, and the protocol aborts with [Error::Equivocation] naming the
+//! sender, or it isn't, and the protocol aborts with [Error::InvalidEcho] naming the party which
+//! sent the echo.
+//!
+//! Every party of the wrapped protocol must broadcast exactly one message in each broadcasting
+//! round, which holds for keygen and presignature protocols.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use round_based::{IsCritical, Msg, StateMachine};
+use secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::error::Blame;
+use crate::utils::secure_channel::{self, Identity};
+use crate::utils::session_id::SessionId;
+
+type MessageDigest = [u8; 32];
+
+/// State machine with echo broadcast of another state machine's messages
+///
+/// Disabled echo broadcast passes messages through as they are, so parties can opt in to the
+/// extra round trip per broadcasting round.
+pub struct EchoBroadcast<SM: StateMachine> {
+    inner: SM,
+    enabled: bool,
+    sid: SessionId,
+    identity: Identity,
+    /// Identity keys of the parties ordered by their index in the protocol
+    identity_keys: Vec<PublicKey>,
+
+    rounds: BTreeMap<u16, BroadcastRound<SM::MessageBody>>,
+    /// Number of messages broadcasted by the party
+    sent: u16,
+    /// Number of messages broadcasted by every other party
+    received: BTreeMap<u16, u16>,
+    /// Number of broadcasting rounds the party sent echo for
+    echoed: u16,
+    /// Number of broadcasting rounds passed to the protocol
+    released: u16,
+
+    msgs_queue: Vec<Msg<EchoMessage<SM::MessageBody>>>,
+}
+
+struct BroadcastRound<B> {
+    /// Digests of messages received in the round by sender, including the party itself
+    digests: BTreeMap<u16, SignedDigest>,
+    /// Messages of the round which aren't confirmed by echoes yet
+    held: Vec<Msg<B>>,
+    /// Digests seen by other parties
+    echoes: BTreeMap<u16, BTreeMap<u16, SignedDigest>>,
+}
+
+impl<B> Default for BroadcastRound<B> {
+    fn default() -> Self {
+        Self {
+            digests: BTreeMap::new(),
+            held: vec![],
+            echoes: BTreeMap::new(),
+        }
+    }
+}
+
+impl<SM> EchoBroadcast<SM>
+where
+    SM: StateMachine,
+    SM::MessageBody: Serialize,
+{
+    /// Wraps a freshly constructed state machine of session `sid`
+    ///
+    /// The party signs digests of its broadcasts with `identity`, `identity_keys` of all parties
+    /// ordered by their index in the protocol verify signatures of the others.
+    pub fn new(
+        inner: SM,
+        enabled: bool,
+        sid: SessionId,
+        identity: &Identity,
+        identity_keys: Vec<PublicKey>,
+    ) -> Result<Self, Error<SM::Err>> {
+        if enabled && identity_keys.len() != usize::from(inner.parties()) {
+            return Err(Error::MissingIdentityKeys {
+                expected: inner.parties(),
+                actual: identity_keys.len(),
+            });
+        }
+        let mut state = Self {
+            inner,
+            enabled,
+            sid,
+            identity: identity.clone(),
+            identity_keys,
+            rounds: BTreeMap::new(),
+            sent: 0,
+            received: BTreeMap::new(),
+            echoed: 0,
+            released: 0,
+            msgs_queue: vec![],
+        };
+        state.forward_outgoing()?;
+        Ok(state)
+    }
+
+    /// Moves messages of the protocol to the outgoing queue, keeping digests of broadcasts
+    fn forward_outgoing(&mut self) -> Result<(), Error<SM::Err>> {
+        let me = self.inner.party_ind();
+        for msg in std::mem::take(self.inner.message_queue()) {
+            if self.enabled && msg.receiver.is_none() {
+                self.sent += 1;
+                let digest = digest(&msg.body)?;
+                let signature = self
+                    .identity
+                    .sign(&[&signed_message(&self.sid, me, self.sent, &digest)]);
+                let signed = SignedDigest {
+                    digest,
+                    signature: hex::encode(signature.serialize_compact()),
+                };
+                self.msgs_queue.push(Msg {
+                    sender: msg.sender,
+                    receiver: None,
+                    body: EchoMessage::Broadcast {
+                        body: msg.body,
+                        signature: signed.signature.clone(),
+                    },
+                });
+                self.rounds
+                    .entry(self.sent)
+                    .or_default()
+                    .digests
+                    .insert(me, signed);
+                continue;
+            }
+            self.msgs_queue.push(Msg {
+                sender: msg.sender,
+                receiver: msg.receiver,
+                body: EchoMessage::Protocol(msg.body),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks echoes of the broadcasting round `index` against digests received by the party
+    fn check_echoes(
+        &self,
+        index: u16,
+        round: &BroadcastRound<SM::MessageBody>,
+    ) -> Result<(), Error<SM::Err>> {
+        for (&reported_by, echo) in &round.echoes {
+            if echo.len() != round.digests.len() {
+                return Err(Error::InvalidEcho {
+                    round: index,
+                    sender: reported_by,
+                });
+            }
+            for (&sender, signed) in &round.digests {
+                let echoed = match echo.get(&sender) {
+                    Some(echoed) if echoed.digest == signed.digest => continue,
+                    Some(echoed) => echoed,
+                    None => {
+                        return Err(Error::InvalidEcho {
+                            round: index,
+                            sender: reported_by,
+                        })
+                    }
+                };
+                // two different digests signed by the sender prove it equivocated
+                return Err(if self.is_signed_by(sender, index, echoed) {
+                    Error::Equivocation {
+                        round: index,
+                        sender,
+                        reported_by,
+                    }
+                } else {
+                    Error::InvalidEcho {
+                        round: index,
+                        sender: reported_by,
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `sender` signed `signed` digest as its broadcast of `round`
+    fn is_signed_by(&self, sender: u16, round: u16, signed: &SignedDigest) -> bool {
+        let identity_key = match usize::from(sender)
+            .checked_sub(1)
+            .and_then(|k| self.identity_keys.get(k))
+        {
+            Some(key) => key,
+            None => return false,
+        };
+        let message = signed_message(&self.sid, sender, round, &signed.digest);
+        secure_channel::verify(identity_key, &signed.signature, &[&message]).is_ok()
+    }
+
+    /// Sends echoes of complete rounds and passes confirmed messages to the protocol
+    fn proceed_echoes(&mut self) -> Result<(), Error<SM::Err>> {
+        let parties = usize::from(self.inner.parties());
+        loop {
+            let next_echo = self.echoed + 1;
+            if let Some(round) = self
+                .rounds
+                .get(&next_echo)
+                .filter(|round| round.digests.len() == parties)
+            {
+                self.msgs_queue.push(Msg {
+                    sender: self.inner.party_ind(),
+                    receiver: None,
+                    body: EchoMessage::Echo(Echo {
+                        round: next_echo,
+                        digests: round.digests.clone(),
+                    }),
+                });
+                self.echoed = next_echo;
+                continue;
+            }
+
+            let next_release = self.released + 1;
+            if next_release > self.echoed {
+                return Ok(());
+            }
+            match self.rounds.get(&next_release) {
+                Some(round) if round.echoes.len() + 1 == parties => {
+                    self.check_echoes(next_release, round)?
+                }
+                _ => return Ok(()),
+            }
+            let held = self
+                .rounds
+                .get_mut(&next_release)
+                .map(|round| std::mem::take(&mut round.held))
+                .unwrap_or_default();
+            self.released = next_release;
+            for msg in held {
+                self.inner.handle_incoming(msg).map_err(Error::Protocol)?;
+                self.forward_outgoing()?;
+            }
+        }
+    }
+}
+
+impl<SM> StateMachine for EchoBroadcast<SM>
+where
+    SM: StateMachine,
+    SM::MessageBody: Serialize,
+{
+    type MessageBody = EchoMessage<SM::MessageBody>;
+    type Err = Error<SM::Err>;
+    type Output = SM::Output;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<(), Self::Err> {
+        match msg.body {
+            EchoMessage::Broadcast { body, signature }
+                if self.enabled && msg.receiver.is_none() =>
+            {
+                let count = self.received.get(&msg.sender).copied().unwrap_or(0) + 1;
+                let signed = SignedDigest {
+                    digest: digest(&body)?,
+                    signature,
+                };
+                if !self.is_signed_by(msg.sender, count, &signed) {
+                    return Err(Error::InvalidSignature {
+                        sender: msg.sender,
+                        round: count,
+                    });
+                }
+                self.received.insert(msg.sender, count);
+                let round = self.rounds.entry(count).or_default();
+                round.digests.insert(msg.sender, signed);
+                round.held.push(Msg {
+                    sender: msg.sender,
+                    receiver: None,
+                    body,
+                });
+            }
+            EchoMessage::Protocol(_) if self.enabled && msg.receiver.is_none() => {
+                return Err(Error::InvalidSignature {
+                    sender: msg.sender,
+                    round: self.received.get(&msg.sender).copied().unwrap_or(0) + 1,
+                })
+            }
+            EchoMessage::Protocol(body) | EchoMessage::Broadcast { body, .. } => {
+                self.inner
+                    .handle_incoming(Msg {
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body,
+                    })
+                    .map_err(Error::Protocol)?;
+                self.forward_outgoing()?;
+            }
+            EchoMessage::Echo(echo) if self.enabled && msg.receiver.is_none() => {
+                let round = self.rounds.entry(echo.round).or_default();
+                if round.echoes.insert(msg.sender, echo.digests).is_some() {
+                    return Err(Error::UnexpectedEcho {
+                        sender: msg.sender,
+                        round: echo.round,
+                    });
+                }
+            }
+            EchoMessage::Echo(echo) => {
+                return Err(Error::UnexpectedEcho {
+                    sender: msg.sender,
+                    round: echo.round,
+                })
+            }
+        }
+        self.proceed_echoes()
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        &mut self.msgs_queue
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        self.inner.wants_to_proceed()
+    }
+
+    fn proceed(&mut self) -> Result<(), Self::Err> {
+        self.inner.proceed().map_err(Error::Protocol)?;
+        self.forward_outgoing()?;
+        self.proceed_echoes()
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        self.inner.round_timeout()
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        let me = self.inner.party_ind();
+        let waiting = self
+            .rounds
+            .get(&(self.released + 1))
+            .filter(|_| self.released < self.echoed);
+        match waiting {
+            Some(round) => Error::EchoTimeout {
+                round: self.released + 1,
+                missing_parties: (1..=self.inner.parties())
+                    .filter(|i| *i != me && !round.echoes.contains_key(i))
+                    .collect(),
+            },
+            None => Error::Protocol(self.inner.round_timeout_reached()),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.inner.is_finished()
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output, Self::Err>> {
+        self.inner
+            .pick_output()
+            .map(|output| output.map_err(Error::Protocol))
+    }
+
+    fn current_round(&self) -> u16 {
+        self.inner.current_round()
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        self.inner.total_rounds()
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.inner.party_ind()
+    }
+
+    fn parties(&self) -> u16 {
+        self.inner.parties()
+    }
+}
+
+impl<SM> fmt::Debug for EchoBroadcast<SM>
+where
+    SM: StateMachine + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{{EchoBroadcast enabled={} echoed={} released={} inner={:?} queue=[len={}]}}",
+            self.enabled,
+            self.echoed,
+            self.released,
+            self.inner,
+            self.msgs_queue.len()
+        )
+    }
+}
+
+fn digest<B: Serialize, E>(body: &B) -> Result<MessageDigest, Error<E>> {
+    let serialized = serde_json::to_vec(body).map_err(Error::SerializeMessage)?;
+    Ok(Sha256::digest(&serialized).into())
+}
+
+/// What `sender` signs to vouch for its broadcast of `round`, all fields have fixed length
+fn signed_message(sid: &SessionId, sender: u16, round: u16, digest: &MessageDigest) -> Vec<u8> {
+    [
+        b"echo".as_ref(),
+        sid.as_ref(),
+        &sender.to_be_bytes(),
+        &round.to_be_bytes(),
+        digest,
+    ]
+    .concat()
+}
+
+// Messages
+
+/// Message of the wrapped protocol or echo of a broadcasting round
+///
+/// Broadcasts of the wrapped protocol come with signature of their digest when echo broadcast is
+/// enabled.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum EchoMessage<B> {
+    Protocol(B),
+    Broadcast { body: B, signature: String },
+    Echo(Echo),
+}
+
+/// Digests of messages the party received in a broadcasting round
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Echo {
+    round: u16,
+    digests: BTreeMap<u16, SignedDigest>,
+}
+
+/// Digest of a broadcasted message with signature of its sender
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedDigest {
+    digest: MessageDigest,
+    /// Hex-encoded signature by the identity key of the sender
+    signature: String,
+}
+
+// Error
+
+/// Error type of echo broadcast
+#[derive(Debug, Error)]
+pub enum Error<E> {
+    /// Wrapped protocol failed
+    #[error("{0}")]
+    Protocol(E),
+    /// `sender` broadcasted different messages to different parties
+    ///
+    /// Mismatch is found in the echo of `reported_by`, which carries a signature of `sender` for
+    /// another message.
+    #[error(
+        "party {sender} sent different messages in broadcast round {round} (reported by party {reported_by})"
+    )]
+    Equivocation {
+        round: u16,
+        sender: u16,
+        reported_by: u16,
+    },
+    /// Echo was sent twice or privately
+    #[error("unexpected echo of broadcast round {round} from party {sender}")]
+    UnexpectedEcho { sender: u16, round: u16 },
+    /// Echo of `sender` doesn't list every party or lists a digest its sender didn't sign
+    #[error("party {sender} sent invalid echo of broadcast round {round}")]
+    InvalidEcho { sender: u16, round: u16 },
+    /// Broadcast of `sender` came without a valid signature of its digest
+    #[error("party {sender} sent broadcast of round {round} without valid signature")]
+    InvalidSignature { sender: u16, round: u16 },
+    /// Identity keys weren't given for all parties of the protocol
+    #[error("echo broadcast needs identity keys of {expected} parties, got {actual}")]
+    MissingIdentityKeys { expected: u16, actual: usize },
+    /// Round deadline was reached while waiting for echoes of `missing_parties`
+    #[error("broadcast round {round} timed out waiting for echoes of parties {missing_parties:?}")]
+    EchoTimeout {
+        round: u16,
+        missing_parties: Vec<u16>,
+    },
+    /// Message couldn't be serialized to compute its digest
+    #[error("serialize message: {0}")]
+    SerializeMessage(#[source] serde_json::Error),
+}
+
+impl<E: IsCritical> IsCritical for Error<E> {
+    fn is_critical(&self) -> bool {
+        match self {
+            Error::Protocol(err) => err.is_critical(),
+            _ => true,
+        }
+    }
+}
+
+impl<E: Blame> Blame for Error<E> {
+    fn culprits(&self) -> Vec<u16> {
+        match self {
+            Error::Protocol(err) => err.culprits(),
+            Error::Equivocation { sender, .. }
+            | Error::UnexpectedEcho { sender, .. }
+            | Error::InvalidEcho { sender, .. }
+            | Error::InvalidSignature { sender, .. } => vec![*sender],
+            Error::EchoTimeout { .. }
+            | Error::MissingIdentityKeys { .. }
+            | Error::SerializeMessage(_) => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use round_based::dev::Simulation;
+
+    use crate::gg20::state_machine::keygen::test::{pre_params, session_id};
+    use crate::gg20::state_machine::keygen::Keygen;
+
+    use super::*;
+
+    type Party = EchoBroadcast<Keygen>;
+    type PartyMsg = Msg<<Party as StateMachine>::MessageBody>;
+
+    /// Keygen party `i` of three, signing with `identities[i-1]`
+    fn party(i: u16, identities: &[Identity]) -> Party {
+        let keygen = Keygen::new(i, 1, 3, pre_params(), session_id(3)).unwrap();
+        let identity_keys = identities.iter().map(Identity::public_key).collect();
+        let identity = &identities[usize::from(i) - 1];
+        EchoBroadcast::new(keygen, true, session_id(3), identity, identity_keys).unwrap()
+    }
+
+    /// Delivers messages of `parties` to each other, passing every message through `tamper`
+    /// together with its receiver, until nobody has anything to send
+    fn run(
+        parties: &mut [Party],
+        mut tamper: impl FnMut(u16, PartyMsg) -> PartyMsg,
+    ) -> Vec<<Party as StateMachine>::Err> {
+        let mut errors = vec![];
+        loop {
+            for party in parties.iter_mut() {
+                if party.wants_to_proceed() {
+                    party.proceed().unwrap();
+                }
+            }
+            let msgs: Vec<_> = parties
+                .iter_mut()
+                .flat_map(|party| std::mem::take(party.message_queue()))
+                .collect();
+            if msgs.is_empty() {
+                return errors;
+            }
+            for msg in msgs {
+                for receiver in 1..=3u16 {
+                    if receiver == msg.sender || msg.receiver.map_or(false, |r| r != receiver) {
+                        continue;
+                    }
+                    let msg = tamper(receiver, msg.clone());
+                    if let Err(err) = parties[usize::from(receiver) - 1].handle_incoming(msg) {
+                        errors.push(err);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn simulate_keygen_with_echo_broadcast() {
+        let identities: Vec<_> = (0..3).map(|_| Identity::generate()).collect();
+        let mut simulation = Simulation::new();
+        for i in 1..=3 {
+            simulation.add_party(party(i, &identities));
+        }
+        let keys = simulation.run().unwrap();
+        assert!(keys
+            .iter()
+            .all(|key| key.public_key() == keys[0].public_key()));
+    }
+
+    #[test]
+    fn equivocating_sender_is_blamed() {
+        let identities: Vec<_> = (0..3).map(|_| Identity::generate()).collect();
+        let mut parties: Vec<_> = (1..=3).map(|i| party(i, &identities)).collect();
+        // party 1 shows another commitment, signed as well, to party 3
+        let mut forged = Some(party(1, &identities).message_queue().remove(0));
+
+        let errors = run(&mut parties, |receiver, msg| {
+            if msg.sender == 1 && receiver == 3 {
+                if let Some(forged) = forged.take() {
+                    return forged;
+                }
+            }
+            msg
+        });
+
+        assert!(!errors.is_empty());
+        for err in errors {
+            assert!(
+                matches!(
+                    err,
+                    Error::Equivocation {
+                        round: 1,
+                        sender: 1,
+                        ..
+                    }
+                ),
+                "{:?}",
+                err
+            );
+            assert_eq!(err.culprits(), vec![1]);
+        }
+        assert!(parties.iter().all(|party| !party.is_finished()));
+    }
+
+    #[test]
+    fn forged_echo_blames_its_sender() {
+        let identities: Vec<_> = (0..3).map(|_| Identity::generate()).collect();
+        let mut parties: Vec<_> = (1..=3).map(|i| party(i, &identities)).collect();
+
+        // party 2 claims in its echo that party 1 broadcasted another message
+        let errors = run(&mut parties, |_, mut msg| {
+            if let EchoMessage::Echo(echo) = &mut msg.body {
+                if msg.sender == 2 {
+                    echo.digests.get_mut(&1).unwrap().digest[0] ^= 1;
+                }
+            }
+            msg
+        });
+
+        assert!(!errors.is_empty());
+        for err in errors {
+            assert!(
+                matches!(
+                    err,
+                    Error::InvalidEcho {
+                        round: 1,
+                        sender: 2
+                    }
+                ),
+                "{:?}",
+                err
+            );
+            assert_eq!(err.culprits(), vec![2]);
+        }
+        assert!(parties.iter().all(|party| !party.is_finished()));
+    }
+}
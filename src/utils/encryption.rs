@@ -1,14 +1,184 @@
-#![allow(dead_code)]
-
-use std::time::{SystemTime, UNIX_EPOCH};
+//! Password encryption of stored key shares
+//!
+//! [encrypt] writes a versioned envelope: the AES-256-GCM key is derived from the password and a
+//! random salt with Argon2id of configurable cost, the nonce is random, and the ciphertext is
+//! bound to associated data given by the caller. [decrypt] reads envelopes as well as the
+//! legacy `nonce:base64` format, whose key is an unsalted SHA-256 of the password.
 
+#![allow(dead_code)]
 
-use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::aead::{Aead, NewAead, Payload};
 use aes_gcm::{Aes256Gcm, Nonce};
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use argon2::{Algorithm, Argon2, Params, Version};
 use base64::engine::general_purpose;
 use base64::Engine;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::error::TssError;
+
+/// Version of the envelope written by [encrypt], the legacy format is version 1
+const ENVELOPE_VERSION: u16 = 2;
+const KDF_ARGON2ID: &str = "argon2id";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Cost of deriving the encryption key from a password with Argon2id
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory size in KiB
+    pub m_cost: u32,
+    /// Number of passes over the memory
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    /// 64 MiB and 3 passes, a second or less on a recent phone
+    fn default() -> Self {
+        Self {
+            m_cost: 64 * 1024,
+            t_cost: 3,
+            p_cost: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Cheapest parameters accepted, weaker ones would make guessing the password cheap
+    pub const MIN: Self = Self {
+        m_cost: 19 * 1024,
+        t_cost: 2,
+        p_cost: 1,
+    };
+    /// Most expensive parameters accepted, so that a tampered envelope can't exhaust memory or
+    /// time of the device
+    pub const MAX: Self = Self {
+        m_cost: 256 * 1024,
+        t_cost: 10,
+        p_cost: 8,
+    };
+
+    /// Fails if any of the costs is out of [MIN](Self::MIN) to [MAX](Self::MAX) bounds
+    pub fn check(&self) -> Result<(), TssError> {
+        let (min, max) = (Self::MIN, Self::MAX);
+        if !(min.m_cost..=max.m_cost).contains(&self.m_cost)
+            || !(min.t_cost..=max.t_cost).contains(&self.t_cost)
+            || !(min.p_cost..=max.p_cost).contains(&self.p_cost)
+        {
+            return Err(TssError::invalid_input(format_args!(
+                "KDF parameters {:?} out of bounds [{:?}; {:?}]",
+                self, min, max
+            )));
+        }
+        Ok(())
+    }
+
+    fn derive_key(&self, password: &str, salt: &[u8]) -> anyhow::Result<Zeroizing<[u8; 32]>> {
+        self.check()?;
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| anyhow!("invalid KDF parameters: {}", e))?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), salt, &mut *key)
+            .map_err(|e| anyhow!("key derivation failure: {}", e))?;
+        Ok(key)
+    }
+
+    /// Parameters `ciphertext` was encrypted with, `None` for the legacy format
+    pub fn of(ciphertext: &str) -> Option<Self> {
+        serde_json::from_str::<Envelope>(ciphertext)
+            .ok()
+            .map(|envelope| envelope.params)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    version: u16,
+    kdf: String,
+    params: KdfParams,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Encrypts `plaintext` with `password` into a versioned envelope
+///
+/// The envelope only decrypts with the same `associated_data`.
+pub fn encrypt(
+    plaintext: &str,
+    password: &str,
+    associated_data: &[u8],
+    kdf: &KdfParams,
+) -> anyhow::Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+    let key = kdf.derive_key(password, &salt)?;
+    let cipher = Aes256Gcm::new(aes_gcm::Key::from_slice(&*key));
+    let encrypted = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext.as_bytes(),
+                aad: associated_data,
+            },
+        )
+        .map_err(|e| anyhow!("encryption failure: {}", e))?;
+    let envelope = Envelope {
+        version: ENVELOPE_VERSION,
+        kdf: KDF_ARGON2ID.to_string(),
+        params: *kdf,
+        salt: general_purpose::STANDARD.encode(salt),
+        nonce: general_purpose::STANDARD.encode(nonce),
+        ciphertext: general_purpose::STANDARD.encode(encrypted),
+    };
+    Ok(serde_json::to_string(&envelope)?)
+}
+
+/// Decrypts `ciphertext` written by [encrypt] with the same `associated_data`, or in the legacy
+/// format, which has no associated data
+pub fn decrypt(ciphertext: &str, password: &str, associated_data: &[u8]) -> anyhow::Result<String> {
+    if !ciphertext.starts_with('{') {
+        return decrypt_legacy(ciphertext, password);
+    }
+    let envelope: Envelope = serde_json::from_str(ciphertext).context("parse key envelope")?;
+    if envelope.version != ENVELOPE_VERSION {
+        return Err(anyhow!(
+            "unsupported key envelope version {}",
+            envelope.version
+        ));
+    }
+    if envelope.kdf != KDF_ARGON2ID {
+        return Err(anyhow!("unsupported key derivation {}", envelope.kdf));
+    }
+    let salt = general_purpose::STANDARD.decode(envelope.salt.as_bytes())?;
+    let nonce = general_purpose::STANDARD.decode(envelope.nonce.as_bytes())?;
+    if nonce.len() != NONCE_LEN {
+        return Err(anyhow!("invalid nonce length {}", nonce.len()));
+    }
+    let key = envelope.params.derive_key(password, &salt)?;
+    let cipher = Aes256Gcm::new(aes_gcm::Key::from_slice(&*key));
+    let decrypted = cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: general_purpose::STANDARD
+                    .decode(envelope.ciphertext.as_bytes())?
+                    .as_slice(),
+                aad: associated_data,
+            },
+        )
+        .map_err(|e| TssError::wrong_password(format_args!("decryption failure: {}", e)))?;
+    Ok(String::from_utf8(decrypted)?)
+}
 
 fn derive_iv_from_nonce(nonce: u64) -> Vec<u8> {
     let mut iv = [0u8; 12];
@@ -26,12 +196,9 @@ fn get_key_from_password(password: &str) -> Vec<u8> {
     key.to_vec()
 }
 
-pub fn encrypt(plaintext: &str, password: &str) -> anyhow::Result<String> {
-    let nonce = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
-    return encrypt_with_nonce(plaintext, password, nonce);
-}
-
-pub fn encrypt_with_nonce(plaintext: &str, password: &str, nonce: u64) -> anyhow::Result<String> {
+/// Writes the legacy format, only to check that [decrypt] still reads it
+#[cfg(test)]
+fn encrypt_with_nonce(plaintext: &str, password: &str, nonce: u64) -> anyhow::Result<String> {
     let vec = get_key_from_password(password);
     let key = aes_gcm::Key::from_slice(&vec);
     let vec1 = derive_iv_from_nonce(nonce);
@@ -47,10 +214,15 @@ pub fn encrypt_with_nonce(plaintext: &str, password: &str, nonce: u64) -> anyhow
     ));
 }
 
-pub fn decrypt(ciphertext: &str, password: &str) -> anyhow::Result<String> {
+fn decrypt_legacy(ciphertext: &str, password: &str) -> anyhow::Result<String> {
     let mut split = ciphertext.split(":");
-    let nonce = split.next().expect("nonce not found").parse::<u64>()?;
-    let ciphertext = split.next().expect("cipher not found");
+    let nonce = split
+        .next()
+        .ok_or_else(|| TssError::invalid_input("nonce not found"))?
+        .parse::<u64>()?;
+    let ciphertext = split
+        .next()
+        .ok_or_else(|| TssError::invalid_input("cipher not found"))?;
     return decrypt_with_nonce(ciphertext, password, nonce);
 }
 
@@ -67,12 +239,71 @@ pub fn decrypt_with_nonce(ciphertext: &str, password: &str, nonce: u64) -> anyho
                 .decode(ciphertext.as_bytes())?
                 .as_slice(),
         )
-        .map_err(|e| anyhow!("decryption failure: {}", e))?;
+        .map_err(|e| TssError::wrong_password(format_args!("decryption failure: {}", e)))?;
     return Ok(String::from_utf8(decrypted)?);
 }
 
 #[cfg(test)]
 mod test {
+    use super::KdfParams;
+    use crate::error::TssError;
+
+    const CHEAP_KDF: KdfParams = KdfParams::MIN;
+
+    #[test]
+    fn envelope_roundtrip() {
+        let ciphertext = super::encrypt("hello", "my-password", b"ad", &CHEAP_KDF).unwrap();
+        assert_eq!(KdfParams::of(&ciphertext), Some(CHEAP_KDF));
+        assert_eq!(
+            super::decrypt(&ciphertext, "my-password", b"ad").unwrap(),
+            "hello"
+        );
+        assert!(super::decrypt(&ciphertext, "wrong-password", b"ad").is_err());
+        assert!(super::decrypt(&ciphertext, "my-password", b"other ad").is_err());
+        let err = super::decrypt(&ciphertext, "wrong-password", b"ad").unwrap_err();
+        assert_eq!(TssError::from(err).code(), "WRONG_PASSWORD");
+        // salt and nonce are random
+        assert_ne!(
+            ciphertext,
+            super::encrypt("hello", "my-password", b"ad", &CHEAP_KDF).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_kdf_parameters_out_of_bounds() {
+        let ciphertext = super::encrypt("hello", "my-password", b"ad", &CHEAP_KDF).unwrap();
+        for params in [
+            KdfParams {
+                m_cost: 8,
+                ..CHEAP_KDF
+            },
+            KdfParams {
+                m_cost: 16 * 1024 * 1024,
+                ..CHEAP_KDF
+            },
+            KdfParams {
+                t_cost: u32::MAX,
+                ..CHEAP_KDF
+            },
+        ] {
+            let mut envelope: serde_json::Value = serde_json::from_str(&ciphertext).unwrap();
+            envelope["params"] = serde_json::to_value(params).unwrap();
+            let err = super::decrypt(&envelope.to_string(), "my-password", b"ad").unwrap_err();
+            assert_eq!(TssError::from(err).code(), "INVALID_INPUT");
+            assert!(super::encrypt("hello", "my-password", b"ad", &params).is_err());
+        }
+    }
+
+    #[test]
+    fn reads_legacy_format() {
+        let ciphertext = "9999999:dYcX59XzlgaRJP82ogwUIb5zvxzX";
+        assert_eq!(KdfParams::of(ciphertext), None);
+        assert_eq!(
+            super::decrypt(ciphertext, "my-password", b"ignored").unwrap(),
+            "hello"
+        );
+    }
+
     #[test]
     fn test_encryption() {
         let plaintext = "hello";
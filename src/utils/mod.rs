@@ -1,6 +1,12 @@
 pub mod common;
 pub mod constants;
+pub mod echo_broadcast;
 pub mod encryption;
+pub mod model;
+pub mod secret;
+pub mod secure_channel;
+pub mod session_id;
 pub mod sm_client;
 #[cfg(test)]
 pub mod test_wallets;
+pub mod transport;
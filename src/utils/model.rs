@@ -0,0 +1,346 @@
+//! Native requests which are not part of `rustmodel` yet
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::Context;
+use rustmodel::{EncryptedKeygenResult, EncryptedLocalKey, KeyScheme, SigningStateBase64};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::error::TssError;
+use crate::gg20::derivation::parse_path;
+use crate::gg20::PreParams;
+use crate::utils::encryption::{decrypt, KdfParams};
+use crate::utils::secure_channel::{parse_public_key, Identity, Roster};
+
+#[derive(Serialize, Deserialize)]
+pub struct NativeRefreshRequest {
+    pub request_id: String,
+    pub token: String,
+    pub address: String,
+    pub room: String,
+    pub encrypted_local_key: EncryptedLocalKey,
+    pub password: String,
+    pub port: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NativeReshareRequest {
+    pub request_id: String,
+    pub token: String,
+    pub address: String,
+    pub room: String,
+    pub n: i32,
+    pub new_t: i32,
+    pub new_n: i32,
+    pub new_member: bool,
+    pub signer_name: String,
+    /// Both keys of the wallet, absent for parties which join without a share
+    pub encrypted_keygen_result: Option<EncryptedKeygenResult>,
+    pub password: String,
+    pub port: i64,
+}
+
+/// Request of [c_recover]
+///
+/// [c_recover]: crate::cexport::c_recover
+#[derive(Serialize, Deserialize)]
+pub struct NativeRecoverRequest {
+    pub request_id: String,
+    pub token: String,
+    pub address: String,
+    pub room: String,
+    pub t: u16,
+    pub n: u16,
+    /// Index of the party whose device is lost
+    pub lost_i: u16,
+    /// `t+1` surviving parties which help to recover the share, each participant sends the same
+    pub helpers: Vec<u16>,
+    /// Other surviving parties which take part only to learn the new Paillier key of the
+    /// replacement device, each participant sends the same
+    #[serde(default)]
+    pub observers: Vec<u16>,
+    /// Both keys of the wallet, absent for the replacement device
+    pub encrypted_keygen_result: Option<EncryptedKeygenResult>,
+    pub password: String,
+    pub port: i64,
+}
+
+/// Request of [c_generate_presignatures]
+///
+/// [c_generate_presignatures]: crate::cexport::c_generate_presignatures
+#[derive(Serialize, Deserialize)]
+pub struct NativeGeneratePresignaturesRequest {
+    pub request_id: String,
+    pub token: String,
+    pub address: String,
+    pub room: String,
+    pub encrypted_local_key: EncryptedLocalKey,
+    pub password: String,
+    /// Signer set of `t+1` parties including this one, each of them sends the same request
+    pub signers: Vec<u16>,
+    /// How many presignatures to generate
+    pub count: u16,
+    pub port: i64,
+}
+
+/// Port every asynchronous request is answered to
+///
+/// Read on its own, so that a request which doesn't parse can still be answered with the error.
+#[derive(Deserialize)]
+pub struct NativeRequestPort {
+    pub port: i64,
+}
+
+/// Request of [c_reencrypt], changes the password of a stored wallet
+///
+/// [c_reencrypt]: crate::cexport::c_reencrypt
+#[derive(Serialize, Deserialize)]
+pub struct NativeReencryptRequest {
+    pub encrypted_keygen_result: EncryptedKeygenResult,
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// Result of signing a message
+///
+/// Fields of the signing state stay at the top level, so it still parses as
+/// [SigningStateBase64].
+#[derive(Serialize, Deserialize)]
+pub struct NativeSigningResponse {
+    #[serde(flatten)]
+    pub state: SigningStateBase64,
+    /// Key of the request with the used presignature or nonce marked consumed, must replace
+    /// the stored key
+    pub encrypted_local_key: EncryptedLocalKey,
+    /// How many presignatures of the key or nonces are left unused
+    pub remaining: usize,
+}
+
+/// Signing options accepted next to the fields of `NativeSigningRequest`
+#[derive(Serialize, Deserialize, Default)]
+pub struct NativeSigningOptions {
+    /// Non-hardened derivation path like `m/0/7` of the child key to sign with, the master key
+    /// signs if absent
+    ///
+    /// Every signer must pass the same path. The master key stands for the last hardened node,
+    /// e.g. the account key, whose extended public key is returned by [c_xpub]. Public keys of
+    /// children of both ECDSA and EdDSA keys are returned by [c_derive_public_key].
+    ///
+    /// [c_xpub]: crate::cexport::c_xpub
+    /// [c_derive_public_key]: crate::cexport::c_derive_public_key
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+impl NativeSigningOptions {
+    /// Indexes of the derivation path, empty for the master key
+    pub fn path(&self) -> anyhow::Result<Vec<u32>> {
+        match &self.path {
+            Some(path) => parse_path(path).map_err(|e| TssError::invalid_input(e).into()),
+            None => Ok(vec![]),
+        }
+    }
+}
+
+/// Request of [c_xpub], reads the extended public key of a stored ECDSA key
+///
+/// [c_xpub]: crate::cexport::c_xpub
+#[derive(Serialize, Deserialize)]
+pub struct NativeXpubRequest {
+    pub encrypted_local_key: EncryptedLocalKey,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NativeXpubResponse {
+    /// Base58Check encoded extended public key of the master key
+    pub xpub: String,
+}
+
+/// Request of [c_derive_public_key]
+///
+/// [c_derive_public_key]: crate::cexport::c_derive_public_key
+#[derive(Serialize, Deserialize)]
+pub struct NativeDerivePublicKeyRequest {
+    pub key_scheme: KeyScheme,
+    pub encrypted_local_key: EncryptedLocalKey,
+    pub password: String,
+    /// Non-hardened derivation path like `m/0/7`, see [NativeSigningOptions::path]
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NativeDerivePublicKeyResponse {
+    /// Hex-encoded compressed public key of the child
+    pub public_key: String,
+}
+
+/// Request of [c_generate_pre_params], size of the moduli is taken from [NativeProtocolOptions]
+///
+/// [c_generate_pre_params]: crate::cexport::c_generate_pre_params
+#[derive(Serialize, Deserialize)]
+pub struct NativeGeneratePreParamsRequest {
+    /// Password which encrypts generated parameters
+    pub password: String,
+    pub port: i64,
+}
+
+/// Protocol options accepted next to the fields of `rustmodel` requests
+///
+/// Parsed from the same JSON as the request of every protocol, so clients that don't send them
+/// keep working.
+#[derive(Serialize, Deserialize, Default)]
+pub struct NativeProtocolOptions {
+    /// How many seconds each protocol round waits for other parties, waits forever if absent
+    #[serde(default)]
+    pub round_timeout_secs: Option<u64>,
+    /// Hex-encoded long-term identity key of the device, see [c_generate_identity_key]
+    ///
+    /// Every protocol requires it: a one-off identity would let the relay stand in for the
+    /// party, as nobody could tell its key from a key of the relay.
+    ///
+    /// [c_generate_identity_key]: crate::cexport::c_generate_identity_key
+    #[serde(default)]
+    pub identity_key: Option<String>,
+    /// Hex-encoded compressed identity keys of the other parties by their index, exchanged by the
+    /// devices beforehand
+    ///
+    /// Keygen refuses parties which aren't listed or present another key. Without them keygen
+    /// trusts the keys parties announce, and the resulting roster pins them for later sessions.
+    #[serde(default)]
+    pub peer_identity_keys: Option<BTreeMap<u16, String>>,
+    /// Whether parties confirm to each other every broadcasted message, all parties of the room
+    /// must agree on it
+    #[serde(default)]
+    pub echo_broadcast: bool,
+    /// Bit length of Paillier and `N_tilde` moduli generated by ECDSA keygen and recovery, 2048
+    /// if absent
+    ///
+    /// Parties of the room may use different sizes. Refresh keeps the size of the current key.
+    #[serde(default)]
+    pub paillier_modulus_bits: Option<usize>,
+    /// Pre-generated Paillier key and `N_tilde` of ECDSA keygen, refresh or the replacement
+    /// device in recovery, encrypted with the password of the request, see
+    /// [c_generate_pre_params]
+    ///
+    /// Without them the parameters are generated when the protocol starts, which may take
+    /// minutes on a phone.
+    ///
+    /// [c_generate_pre_params]: crate::cexport::c_generate_pre_params
+    #[serde(default)]
+    pub encrypted_pre_params: Option<String>,
+    /// Whether ECDSA keygen, refresh, reshare and recovery skip presigning for every signer set,
+    /// presignatures are then generated on demand with [c_generate_presignatures]
+    ///
+    /// [c_generate_presignatures]: crate::cexport::c_generate_presignatures
+    #[serde(default)]
+    pub skip_presign: bool,
+    /// Cost of deriving the key which encrypts key shares from the password, see [KdfParams]
+    /// for the default
+    #[serde(default)]
+    pub kdf: Option<KdfParams>,
+}
+
+/// Associated data of encrypted pre-generated parameters, see
+/// [NativeProtocolOptions::encrypted_pre_params]
+pub const PRE_PARAMS_ASSOCIATED_DATA: &[u8] = b"pre-params";
+
+impl NativeProtocolOptions {
+    pub fn round_timeout(&self) -> Option<Duration> {
+        self.round_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn identity(&self) -> anyhow::Result<Identity> {
+        match &self.identity_key {
+            Some(key) => Identity::from_hex(key).map_err(|e| {
+                TssError::invalid_input(format_args!("parse identity key: {:#}", e)).into()
+            }),
+            None => Err(TssError::invalid_input(
+                "identity_key is required, see c_generate_identity_key",
+            )
+            .into()),
+        }
+    }
+
+    /// Identity of keygen, pinned to [NativeProtocolOptions::peer_identity_keys] if they are given
+    pub fn keygen_identity(&self) -> anyhow::Result<Identity> {
+        let identity = self.identity()?;
+        let peers = match &self.peer_identity_keys {
+            Some(peers) => peers,
+            None => return Ok(identity),
+        };
+        let roster = peers
+            .iter()
+            .map(|(&i, key)| {
+                let key = parse_public_key(key).map_err(|e| {
+                    TssError::invalid_input(format_args!(
+                        "parse identity key of party {}: {:#}",
+                        i, e
+                    ))
+                })?;
+                Ok((i, key))
+            })
+            .collect::<anyhow::Result<Roster>>()?;
+        Ok(identity.with_complete_roster(&roster))
+    }
+
+    /// Bit length of a new Paillier modulus, fails if the requested one is out of
+    /// `[DEFAULT_MODULUS_BITS; MAX_MODULUS_BITS]` bounds of [gg20](crate::gg20)
+    pub fn modulus_bits(&self) -> anyhow::Result<usize> {
+        let bits = self
+            .paillier_modulus_bits
+            .unwrap_or(crate::gg20::DEFAULT_MODULUS_BITS);
+        if !(crate::gg20::DEFAULT_MODULUS_BITS..=crate::gg20::MAX_MODULUS_BITS).contains(&bits) {
+            return Err(TssError::invalid_input(format_args!(
+                "paillier_modulus_bits {} is not in range [{}; {}]",
+                bits,
+                crate::gg20::DEFAULT_MODULUS_BITS,
+                crate::gg20::MAX_MODULUS_BITS
+            ))
+            .into());
+        }
+        Ok(bits)
+    }
+
+    /// Cost of the KDF, fails if the requested one is out of [KdfParams::check] bounds
+    pub fn kdf(&self) -> anyhow::Result<KdfParams> {
+        let kdf = self.kdf.unwrap_or_default();
+        kdf.check()?;
+        Ok(kdf)
+    }
+
+    pub fn pre_params(&self, password: &str) -> anyhow::Result<Option<PreParams>> {
+        self.encrypted_pre_params
+            .as_ref()
+            .map(|encrypted| {
+                let pre_params = Zeroizing::new(
+                    decrypt(encrypted, password, PRE_PARAMS_ASSOCIATED_DATA)
+                        .context("decrypt pre params")?,
+                );
+                serde_json::from_str(&pre_params).map_err(|e| {
+                    TssError::invalid_input(format_args!("parse pre params: {}", e)).into()
+                })
+            })
+            .transpose()
+    }
+}
+
+/// Long-term identity key of a device
+#[derive(Serialize, Deserialize)]
+pub struct NativeIdentityKey {
+    /// Hex-encoded secret key, passed to protocols as `identity_key`
+    pub secret_key: String,
+    /// Hex-encoded compressed public key, other parties know the device by it
+    pub public_key: String,
+}
+
+impl From<&Identity> for NativeIdentityKey {
+    fn from(identity: &Identity) -> Self {
+        Self {
+            secret_key: identity.to_hex(),
+            public_key: hex::encode(identity.public_key().serialize()),
+        }
+    }
+}
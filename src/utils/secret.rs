@@ -0,0 +1,25 @@
+//! Handling of secret key material
+//!
+//! Scalars of `curv` wipe themselves when dropped, so types holding key shares only need to
+//! wipe big integers they own, such as factors of Paillier moduli. Their `Debug` output shows
+//! public fields and puts [Redacted] in place of the secret ones.
+
+use std::fmt;
+
+use paillier::DecryptionKey;
+use zeroize::Zeroize;
+
+/// Stands in for a secret field in `Debug` output
+pub struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+/// Overwrites factors of a Paillier modulus
+pub fn zeroize_decryption_key(dk: &mut DecryptionKey) {
+    dk.p.zeroize();
+    dk.q.zeroize();
+}
@@ -0,0 +1,661 @@
+//! Authenticated encryption of protocol messages passing through the relay
+//!
+//! Before running a protocol, parties of the room run a handshake: each one broadcasts a fresh
+//! ephemeral key signed by its identity key. Every pair of parties derives two AES-256-GCM keys,
+//! one per direction, from ECDH of their ephemeral keys and seals P2P messages with them. Broadcast
+//! messages go in plaintext signed by the identity key of the sender.
+//!
+//! Every party numbers its outgoing messages. The number is used as AEAD nonce of P2P messages
+//! and is signed together with broadcast messages, receivers reject numbers which don't grow, so
+//! the relay can't replay messages either.
+//!
+//! Every device has a long-term identity key. Keygen binds party indexes to identity keys of the
+//! parties, and the resulting [Roster] is kept with the key shares. Sessions run with those shares
+//! pin the roster with [Identity::with_roster], so that the relay can't impersonate or reorder
+//! parties. Identity of a party missing in the roster is trusted as announced in the handshake,
+//! unless the roster is pinned with [Identity::with_complete_roster]: keygen does so when the
+//! identity keys of all parties are known in advance.
+//!
+//! Handshake also carries [PROTOCOL_VERSION], parties speaking different versions refuse to run
+//! the protocol together.
+
+use std::collections::BTreeMap;
+
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use hmac::{Hmac, Mac, NewMac};
+use rand::{thread_rng, Rng};
+use round_based::Msg;
+use secp256k1::ecdh::SharedSecret;
+use secp256k1::{Message, PublicKey, SecretKey, Signature, SECP256K1};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::utils::session_id::PROTOCOL_VERSION;
+
+/// Identity keys of the parties by their index
+pub type Roster = BTreeMap<u16, PublicKey>;
+
+/// Long-lived key the party signs its messages with
+#[derive(Clone)]
+pub struct Identity {
+    secret: SecretKey,
+    roster: Option<Roster>,
+    /// Whether parties missing in `roster` are refused instead of trusted as announced
+    complete_roster: bool,
+}
+
+impl Identity {
+    pub fn new(secret: SecretKey) -> Self {
+        Self {
+            secret,
+            roster: None,
+            complete_roster: false,
+        }
+    }
+
+    pub fn generate() -> Self {
+        Self::new(random_secret_key())
+    }
+
+    /// Parses hex-encoded secret key
+    pub fn from_hex(secret: &str) -> Result<Self> {
+        Ok(Self::new(SecretKey::from_slice(&hex::decode(secret)?)?))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.secret[..])
+    }
+
+    /// Copy of the identity which accepts only keys listed in `roster` from the parties of it
+    pub fn with_roster(&self, roster: Option<&Roster>) -> Self {
+        Self {
+            secret: self.secret,
+            roster: roster.cloned(),
+            complete_roster: false,
+        }
+    }
+
+    /// Copy of the identity which accepts only the parties listed in `roster` with their keys
+    pub fn with_complete_roster(&self, roster: &Roster) -> Self {
+        Self {
+            secret: self.secret,
+            roster: Some(roster.clone()),
+            complete_roster: true,
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from_secret_key(SECP256K1, &self.secret)
+    }
+
+    pub(crate) fn sign(&self, parts: &[&[u8]]) -> Signature {
+        SECP256K1.sign(&digest(parts), &self.secret)
+    }
+}
+
+fn random_secret_key() -> SecretKey {
+    loop {
+        if let Ok(secret) = SecretKey::from_slice(&thread_rng().gen::<[u8; 32]>()) {
+            return secret;
+        }
+    }
+}
+
+/// Hashes length-prefixed parts, so that different splits of the same bytes don't collide
+fn digest(parts: &[&[u8]]) -> Message {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(&(part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    Message::from_slice(&hasher.finalize()).expect("sha256 output is 32 bytes")
+}
+
+pub(crate) fn verify(identity: &PublicKey, signature: &str, parts: &[&[u8]]) -> Result<()> {
+    let signature = Signature::from_compact(&hex::decode(signature)?)?;
+    SECP256K1
+        .verify(&digest(parts), &signature, identity)
+        .map_err(|_| anyhow!("invalid signature"))
+}
+
+pub(crate) fn parse_public_key(hex_key: &str) -> Result<PublicKey> {
+    Ok(PublicKey::from_slice(&hex::decode(hex_key)?)?)
+}
+
+/// Message as it is sent to the relay
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Envelope {
+    Handshake {
+        sender: u16,
+        /// Missing in handshakes of clients released before the field was introduced
+        #[serde(default = "legacy_version")]
+        version: u16,
+        identity: String,
+        ephemeral: String,
+        signature: String,
+    },
+    Broadcast {
+        sender: u16,
+        seq: u64,
+        body: String,
+        signature: String,
+    },
+    P2p {
+        sender: u16,
+        receiver: u16,
+        seq: u64,
+        ciphertext: String,
+    },
+}
+
+fn legacy_version() -> u16 {
+    1
+}
+
+impl Envelope {
+    /// Whether the protocol message is sent by someone else to the party `index`
+    pub(crate) fn is_for(&self, index: u16) -> bool {
+        match self {
+            Envelope::Handshake { .. } => false,
+            Envelope::Broadcast { sender, .. } => *sender != index,
+            Envelope::P2p {
+                sender, receiver, ..
+            } => *sender != index && *receiver == index,
+        }
+    }
+}
+
+/// Key exchange of the party with the other parties of the room
+pub(crate) struct Handshake {
+    room_id: String,
+    index: u16,
+    identity: Identity,
+    ephemeral: SecretKey,
+    pending: Vec<u16>,
+    peers: BTreeMap<u16, Peer>,
+}
+
+struct Peer {
+    identity: PublicKey,
+    ephemeral: PublicKey,
+    seal: Aes256Gcm,
+    open: Aes256Gcm,
+}
+
+impl Handshake {
+    pub(crate) fn new(room_id: &str, index: u16, parties: &[u16], identity: &Identity) -> Self {
+        Self {
+            room_id: room_id.to_owned(),
+            index,
+            identity: identity.clone(),
+            ephemeral: random_secret_key(),
+            pending: parties.iter().copied().filter(|&i| i != index).collect(),
+            peers: BTreeMap::new(),
+        }
+    }
+
+    /// Announcement of the ephemeral key to broadcast to the room
+    pub(crate) fn message(&self) -> Envelope {
+        let identity = self.identity.public_key().serialize();
+        let ephemeral = PublicKey::from_secret_key(SECP256K1, &self.ephemeral).serialize();
+        let signature = self.identity.sign(&[
+            b"handshake",
+            &PROTOCOL_VERSION.to_be_bytes(),
+            self.room_id.as_bytes(),
+            &self.index.to_be_bytes(),
+            &identity,
+            &ephemeral,
+        ]);
+        Envelope::Handshake {
+            sender: self.index,
+            version: PROTOCOL_VERSION,
+            identity: hex::encode(identity),
+            ephemeral: hex::encode(ephemeral),
+            signature: hex::encode(signature.serialize_compact()),
+        }
+    }
+
+    pub(crate) fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Derives keys shared with the sender of the announcement
+    pub(crate) fn receive(&mut self, envelope: Envelope) -> Result<()> {
+        let (sender, version, identity_hex, ephemeral_hex, signature) = match envelope {
+            Envelope::Handshake {
+                sender,
+                version,
+                identity,
+                ephemeral,
+                signature,
+            } => (sender, version, identity, ephemeral, signature),
+            _ => bail!("expected handshake message"),
+        };
+        if sender == self.index {
+            return Ok(());
+        }
+        ensure!(
+            version == PROTOCOL_VERSION,
+            "party {} speaks protocol version {}, this client speaks {}",
+            sender,
+            version,
+            PROTOCOL_VERSION
+        );
+        let identity = parse_public_key(&identity_hex).context("parse identity key")?;
+        let ephemeral = parse_public_key(&ephemeral_hex).context("parse ephemeral key")?;
+        match self.identity.roster.as_ref().map(|r| r.get(&sender)) {
+            Some(Some(expected)) => ensure!(
+                *expected == identity,
+                "identity key of party {} doesn't match the roster",
+                sender
+            ),
+            Some(None) if self.identity.complete_roster => {
+                bail!("party {} isn't in the roster", sender)
+            }
+            _ => {}
+        }
+        verify(
+            &identity,
+            &signature,
+            &[
+                b"handshake",
+                &version.to_be_bytes(),
+                self.room_id.as_bytes(),
+                &sender.to_be_bytes(),
+                &identity.serialize(),
+                &ephemeral.serialize(),
+            ],
+        )
+        .with_context(|| format!("handshake of party {}", sender))?;
+
+        if let Some(peer) = self.peers.get(&sender) {
+            ensure!(
+                peer.identity == identity && peer.ephemeral == ephemeral,
+                "party {} announced two different keys",
+                sender
+            );
+            return Ok(());
+        }
+        ensure!(
+            self.pending.contains(&sender),
+            "party {} doesn't take part in room {}",
+            sender,
+            self.room_id
+        );
+
+        let shared = SharedSecret::new(&ephemeral, &self.ephemeral);
+        let seal = self.directed_key(shared.as_ref(), self.index, sender);
+        let open = self.directed_key(shared.as_ref(), sender, self.index);
+        self.pending.retain(|&i| i != sender);
+        self.peers.insert(
+            sender,
+            Peer {
+                identity,
+                ephemeral,
+                seal,
+                open,
+            },
+        );
+        Ok(())
+    }
+
+    fn directed_key(&self, shared: &[u8], from: u16, to: u16) -> Aes256Gcm {
+        let mut mac = Hmac::<Sha256>::new_from_slice(shared).expect("any key size is accepted");
+        mac.update(b"p2p");
+        mac.update(self.room_id.as_bytes());
+        mac.update(&from.to_be_bytes());
+        mac.update(&to.to_be_bytes());
+        Aes256Gcm::new(Key::from_slice(&mac.finalize().into_bytes()))
+    }
+
+    pub(crate) fn finish(self) -> Result<Session> {
+        ensure!(
+            self.is_complete(),
+            "handshake is waiting for parties {:?}",
+            self.pending
+        );
+        Ok(Session {
+            room_id: self.room_id,
+            index: self.index,
+            identity: self.identity,
+            peers: self.peers,
+        })
+    }
+}
+
+/// Keys shared by the party with every other party of the room
+pub(crate) struct Session {
+    room_id: String,
+    index: u16,
+    identity: Identity,
+    peers: BTreeMap<u16, Peer>,
+}
+
+impl Session {
+    /// Identity keys of everyone in the room, including the party itself
+    pub(crate) fn roster(&self) -> Roster {
+        self.peers
+            .iter()
+            .map(|(&i, peer)| (i, peer.identity))
+            .chain(std::iter::once((self.index, self.identity.public_key())))
+            .collect()
+    }
+}
+
+fn nonce(seq: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&seq.to_be_bytes());
+    nonce
+}
+
+impl Session {
+    /// Encrypts or signs `seq`-th outgoing message of the party
+    pub(crate) fn seal<M: Serialize>(&self, seq: u64, msg: Msg<M>) -> Result<Envelope> {
+        let body = serde_json::to_string(&msg.body).context("serialize message")?;
+        match msg.receiver {
+            None => {
+                let signature = self.identity.sign(&[
+                    b"broadcast",
+                    self.room_id.as_bytes(),
+                    &self.index.to_be_bytes(),
+                    &seq.to_be_bytes(),
+                    body.as_bytes(),
+                ]);
+                Ok(Envelope::Broadcast {
+                    sender: self.index,
+                    seq,
+                    body,
+                    signature: hex::encode(signature.serialize_compact()),
+                })
+            }
+            Some(receiver) => {
+                let peer = self
+                    .peers
+                    .get(&receiver)
+                    .ok_or_else(|| anyhow!("no keys shared with party {}", receiver))?;
+                let ciphertext = peer
+                    .seal
+                    .encrypt(
+                        Nonce::from_slice(&nonce(seq)),
+                        Payload {
+                            msg: body.as_bytes(),
+                            aad: self.room_id.as_bytes(),
+                        },
+                    )
+                    .map_err(|e| anyhow!("encryption failure: {}", e))?;
+                Ok(Envelope::P2p {
+                    sender: self.index,
+                    receiver,
+                    seq,
+                    ciphertext: hex::encode(ciphertext),
+                })
+            }
+        }
+    }
+}
+
+/// Decrypts and authenticates incoming messages of the session
+pub(crate) struct Opener<S> {
+    session: S,
+    last_seq: BTreeMap<u16, u64>,
+}
+
+impl<S: std::ops::Deref<Target = Session>> Opener<S> {
+    pub(crate) fn new(session: S) -> Self {
+        Self {
+            session,
+            last_seq: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn open<M: DeserializeOwned>(&mut self, envelope: Envelope) -> Result<Msg<M>> {
+        let session = &*self.session;
+        let (sender, receiver, seq, body) = match envelope {
+            Envelope::Broadcast {
+                sender,
+                seq,
+                body,
+                signature,
+            } => {
+                let peer = session.peer(sender)?;
+                verify(
+                    &peer.identity,
+                    &signature,
+                    &[
+                        b"broadcast",
+                        session.room_id.as_bytes(),
+                        &sender.to_be_bytes(),
+                        &seq.to_be_bytes(),
+                        body.as_bytes(),
+                    ],
+                )
+                .with_context(|| format!("broadcast message of party {}", sender))?;
+                (sender, None, seq, body)
+            }
+            Envelope::P2p {
+                sender,
+                receiver,
+                seq,
+                ciphertext,
+            } => {
+                ensure!(
+                    receiver == session.index,
+                    "message is addressed to party {}",
+                    receiver
+                );
+                let peer = session.peer(sender)?;
+                let body = peer
+                    .open
+                    .decrypt(
+                        Nonce::from_slice(&nonce(seq)),
+                        Payload {
+                            msg: &hex::decode(ciphertext)?,
+                            aad: session.room_id.as_bytes(),
+                        },
+                    )
+                    .map_err(|_| anyhow!("message of party {} is not authentic", sender))?;
+                (sender, Some(receiver), seq, String::from_utf8(body)?)
+            }
+            Envelope::Handshake { .. } => bail!("unexpected handshake message"),
+        };
+        if let Some(&last) = self.last_seq.get(&sender) {
+            ensure!(seq > last, "party {} replayed message {}", sender, seq);
+        }
+        self.last_seq.insert(sender, seq);
+        Ok(Msg {
+            sender,
+            receiver,
+            body: serde_json::from_str(&body).context("deserialize message")?,
+        })
+    }
+}
+
+impl Session {
+    fn peer(&self, index: u16) -> Result<&Peer> {
+        self.peers
+            .get(&index)
+            .ok_or_else(|| anyhow!("party {} didn't take part in the handshake", index))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sessions(identities: &[Identity]) -> Vec<Session> {
+        let parties: Vec<u16> = (1..=identities.len() as u16).collect();
+        let mut handshakes: Vec<_> = identities
+            .iter()
+            .zip(&parties)
+            .map(|(identity, &i)| Handshake::new("room", i, &parties, identity))
+            .collect();
+        let messages: Vec<_> = handshakes.iter().map(Handshake::message).collect();
+        for handshake in &mut handshakes {
+            for message in &messages {
+                let message = serde_json::to_string(message).unwrap();
+                handshake
+                    .receive(serde_json::from_str(&message).unwrap())
+                    .unwrap();
+            }
+        }
+        handshakes
+            .into_iter()
+            .map(|handshake| handshake.finish().unwrap())
+            .collect()
+    }
+
+    fn msg(sender: u16, receiver: Option<u16>, body: &str) -> Msg<String> {
+        Msg {
+            sender,
+            receiver,
+            body: body.to_owned(),
+        }
+    }
+
+    #[test]
+    fn seals_messages_between_parties() {
+        let sessions = sessions(&[
+            Identity::generate(),
+            Identity::generate(),
+            Identity::generate(),
+        ]);
+        let mut opener2 = Opener::new(&sessions[1]);
+        let mut opener3 = Opener::new(&sessions[2]);
+
+        let p2p = sessions[0].seal(0, msg(1, Some(3), "share")).unwrap();
+        assert!(!serde_json::to_string(&p2p).unwrap().contains("share"));
+        assert!(!p2p.is_for(2));
+        let opened: Msg<String> = opener3.open(p2p).unwrap();
+        assert_eq!(opened, msg(1, Some(3), "share"));
+
+        let broadcast = sessions[0].seal(1, msg(1, None, "commitment")).unwrap();
+        let opened: Msg<String> = opener2.open(broadcast).unwrap();
+        assert_eq!(opened, msg(1, None, "commitment"));
+    }
+
+    #[test]
+    fn rejects_forged_and_replayed_messages() {
+        let sessions = sessions(&[Identity::generate(), Identity::generate()]);
+        let mut opener = Opener::new(&sessions[1]);
+
+        let forged = match sessions[0].seal(0, msg(1, None, "commitment")).unwrap() {
+            Envelope::Broadcast {
+                sender,
+                seq,
+                signature,
+                ..
+            } => Envelope::Broadcast {
+                sender,
+                seq,
+                body: "\"forged\"".to_owned(),
+                signature,
+            },
+            _ => unreachable!(),
+        };
+        assert!(opener.open::<String>(forged).is_err());
+
+        let tampered = match sessions[0].seal(1, msg(1, Some(2), "share")).unwrap() {
+            Envelope::P2p {
+                sender,
+                receiver,
+                ciphertext,
+                ..
+            } => Envelope::P2p {
+                sender,
+                receiver,
+                seq: 2,
+                ciphertext,
+            },
+            _ => unreachable!(),
+        };
+        assert!(opener.open::<String>(tampered).is_err());
+
+        let p2p =
+            serde_json::to_string(&sessions[0].seal(3, msg(1, Some(2), "share")).unwrap()).unwrap();
+        opener
+            .open::<String>(serde_json::from_str(&p2p).unwrap())
+            .unwrap();
+        assert!(opener
+            .open::<String>(serde_json::from_str(&p2p).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn pins_identity_keys_of_roster() {
+        let identities = [
+            Identity::generate(),
+            Identity::generate(),
+            Identity::generate(),
+        ];
+        // party 3 isn't listed, so its key is taken from the handshake
+        let roster: Roster = vec![
+            (1, identities[0].public_key()),
+            (2, Identity::generate().public_key()),
+        ]
+        .into_iter()
+        .collect();
+        let mut handshake = Handshake::new(
+            "room",
+            1,
+            &[1, 2, 3],
+            &identities[0].with_roster(Some(&roster)),
+        );
+        let impostor = Handshake::new("room", 2, &[1, 2, 3], &identities[1]);
+        assert!(handshake.receive(impostor.message()).is_err());
+        let newcomer = Handshake::new("room", 3, &[1, 2, 3], &identities[2]);
+        handshake.receive(newcomer.message()).unwrap();
+        assert!(handshake.finish().is_err());
+    }
+
+    #[test]
+    fn complete_roster_refuses_unlisted_parties() {
+        let identities = [Identity::generate(), Identity::generate()];
+        let roster: Roster = vec![(1, identities[0].public_key())].into_iter().collect();
+        let mut handshake = Handshake::new(
+            "room",
+            1,
+            &[1, 2],
+            &identities[0].with_complete_roster(&roster),
+        );
+        let unlisted = Handshake::new("room", 2, &[1, 2], &identities[1]);
+        let err = handshake.receive(unlisted.message()).unwrap_err();
+        assert_eq!(err.to_string(), "party 2 isn't in the roster");
+    }
+
+    #[test]
+    fn rejects_parties_of_another_protocol_version() {
+        let mut handshake = Handshake::new("room", 1, &[1, 2], &Identity::generate());
+        let peer = Handshake::new("room", 2, &[1, 2], &Identity::generate());
+        let mut message = serde_json::to_value(peer.message()).unwrap();
+        message.as_object_mut().unwrap().remove("version");
+        let err = handshake
+            .receive(serde_json::from_value(message).unwrap())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "party 2 speaks protocol version 1, this client speaks {}",
+                PROTOCOL_VERSION
+            )
+        );
+        handshake.receive(peer.message()).unwrap();
+        assert!(handshake.is_complete());
+    }
+
+    #[test]
+    fn session_roster_lists_every_party() {
+        let identities = [Identity::generate(), Identity::generate()];
+        let sessions = sessions(&identities);
+        let expected: Roster = vec![
+            (1, identities[0].public_key()),
+            (2, identities[1].public_key()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(sessions[0].roster(), expected);
+        assert_eq!(sessions[1].roster(), expected);
+    }
+}
@@ -0,0 +1,176 @@
+//! Identifier of a single protocol run
+//!
+//! Every Fiat-Shamir challenge and every hash commitment of the protocols starts with the
+//! [SessionId] of the run, so a proof or a commitment taken from one room, one set of parties or
+//! one protocol doesn't verify in any other run. Protocols bind the identifier to identity keys
+//! of the parties with [SessionId::with_roster] once the handshake is done.
+
+use std::collections::BTreeSet;
+
+use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
+use curv::BigInt;
+use sha2::Sha256;
+
+use crate::utils::secure_channel::Roster;
+
+/// Version of the messages exchanged by the protocols
+///
+/// Parties exchange it during the handshake (see [secure_channel](crate::utils::secure_channel))
+/// and refuse to talk to a party speaking another version. Version 1 didn't bind proofs and
+/// commitments to the session, version 2 didn't prove that Paillier moduli are well formed,
+/// version 3 didn't bind Paillier key and `h1`/`h2` proofs and identity keys to the session.
+pub const PROTOCOL_VERSION: u16 = 4;
+
+const DOMAIN: &[u8] = b"tssv3-session-id";
+const INDEX_DOMAIN: &[u8] = b"tssv3-session-id-index";
+const ROSTER_DOMAIN: &[u8] = b"tssv3-session-id-roster";
+
+/// Hash of the protocol name, the room and the parties taking part in it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SessionId([u8; 32]);
+
+impl SessionId {
+    /// Derives identifier of `protocol` run by `parties` in the room `room_id`
+    ///
+    /// Order of `parties` doesn't matter.
+    pub fn new(protocol: &str, room_id: &str, parties: &[u16]) -> Self {
+        let parties: BTreeSet<u16> = parties.iter().copied().collect();
+        let mut hasher = Sha256::new()
+            .chain(DOMAIN)
+            .chain(PROTOCOL_VERSION.to_be_bytes());
+        for field in [protocol.as_bytes(), room_id.as_bytes()] {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field);
+        }
+        hasher.update((parties.len() as u64).to_be_bytes());
+        for party in parties {
+            hasher.update(party.to_be_bytes());
+        }
+        let mut id = [0u8; 32];
+        id.copy_from_slice(&hasher.finalize());
+        SessionId(id)
+    }
+
+    /// Identifier bound to identity keys of the parties, as the handshake announced them
+    ///
+    /// Parties which got different identity keys from the relay derive different identifiers, so
+    /// their proofs don't verify for each other.
+    pub fn with_roster(&self, roster: &Roster) -> Self {
+        let mut hasher = Sha256::new()
+            .chain(ROSTER_DOMAIN)
+            .chain(self)
+            .chain((roster.len() as u64).to_be_bytes());
+        for (index, key) in roster {
+            hasher.update(index.to_be_bytes());
+            hasher.update(key.serialize());
+        }
+        let mut id = [0u8; 32];
+        id.copy_from_slice(&hasher.finalize());
+        SessionId(id)
+    }
+
+    /// Identifier of the `index`-th of several protocol instances run together in the session
+    pub fn indexed(&self, index: u16) -> Self {
+        let hash = Sha256::new()
+            .chain(INDEX_DOMAIN)
+            .chain(self)
+            .chain(index.to_be_bytes())
+            .finalize();
+        let mut id = [0u8; 32];
+        id.copy_from_slice(&hash);
+        SessionId(id)
+    }
+
+    /// Hash commitment to `message`, bound to the session
+    pub fn commit<H: Digest + Clone>(&self, message: &BigInt, blind_factor: &BigInt) -> BigInt {
+        H::new()
+            .chain(self)
+            .chain_bigint(message)
+            .chain_bigint(blind_factor)
+            .result_bigint()
+    }
+}
+
+impl AsRef<[u8]> for SessionId {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sha2::Sha512;
+
+    use crate::utils::secure_channel::Identity;
+
+    use super::*;
+
+    #[test]
+    fn session_id_depends_on_every_field() {
+        let sid = SessionId::new("ecdsa-keygen", "room", &[1, 2, 3]);
+        assert_eq!(sid, SessionId::new("ecdsa-keygen", "room", &[3, 1, 2]));
+        assert_ne!(sid, SessionId::new("eddsa-keygen", "room", &[1, 2, 3]));
+        assert_ne!(sid, SessionId::new("ecdsa-keygen", "room2", &[1, 2, 3]));
+        assert_ne!(sid, SessionId::new("ecdsa-keygen", "room", &[1, 2]));
+        // fields are length-prefixed, moving bytes between them gives another id
+        assert_ne!(
+            SessionId::new("ab", "c", &[1]),
+            SessionId::new("a", "bc", &[1])
+        );
+    }
+
+    #[test]
+    fn indexed_ids_are_distinct() {
+        let sid = SessionId::new("ecdsa-presign", "room", &[1, 2]);
+        assert_eq!(sid.indexed(0), sid.indexed(0));
+        assert_ne!(sid.indexed(0), sid);
+        assert_ne!(sid.indexed(0), sid.indexed(1));
+        let other = SessionId::new("ecdsa-presign", "other room", &[1, 2]);
+        assert_ne!(sid.indexed(0), other.indexed(0));
+    }
+
+    #[test]
+    fn roster_changes_session_id() {
+        let sid = SessionId::new("ecdsa-keygen", "room", &[1, 2]);
+        let keys = [
+            Identity::generate(),
+            Identity::generate(),
+            Identity::generate(),
+        ];
+        let roster = |a: usize, b: usize| -> Roster {
+            vec![(1, keys[a].public_key()), (2, keys[b].public_key())]
+                .into_iter()
+                .collect()
+        };
+        assert_eq!(
+            sid.with_roster(&roster(0, 1)),
+            sid.with_roster(&roster(0, 1))
+        );
+        assert_ne!(sid.with_roster(&roster(0, 1)), sid);
+        // relay swapping identity of a party or the order of parties gives another id
+        assert_ne!(
+            sid.with_roster(&roster(0, 1)),
+            sid.with_roster(&roster(0, 2))
+        );
+        assert_ne!(
+            sid.with_roster(&roster(0, 1)),
+            sid.with_roster(&roster(1, 0))
+        );
+    }
+
+    #[test]
+    fn commitment_is_bound_to_session() {
+        let message = BigInt::from(42);
+        let blind_factor = BigInt::from(7);
+        let sid = SessionId::new("ecdsa-keygen", "room", &[1, 2, 3]);
+        let other = SessionId::new("ecdsa-keygen", "other room", &[1, 2, 3]);
+        assert_eq!(
+            sid.commit::<Sha512>(&message, &blind_factor),
+            sid.commit::<Sha512>(&message, &blind_factor)
+        );
+        assert_ne!(
+            sid.commit::<Sha512>(&message, &blind_factor),
+            other.commit::<Sha512>(&message, &blind_factor)
+        );
+    }
+}
@@ -1,138 +1,104 @@
 use std::convert::TryInto;
 
 use anyhow::{Context, Result};
-use futures::{Sink, Stream, StreamExt, TryStreamExt};
-use round_based::Msg;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use futures::stream::LocalBoxStream;
+use futures::StreamExt;
+use rustmodel::KeygenMember;
+use serde::Deserialize;
 use structopt::StructOpt;
+use surf::utils::async_trait;
 
-use crate::utils::common::IssueIndexMsg;
-
-pub async fn join_computation<M>(
-    request_id: &str,
-    token: &str,
-    address: surf::Url,
-    room_id: &str,
-    parties: Vec<u16>,
-    party_id: Option<u16>,
-    party_name: Option<String>,
-) -> Result<(
-    u16,
-    impl Stream<Item = Result<Msg<M>>>,
-    impl Sink<Msg<M>, Error = anyhow::Error>,
-)>
-where
-    M: Serialize + DeserializeOwned,
-{
-    let client =
-        SmClient::new(request_id, token, address, room_id).context("construct SmClient")?;
-
-    // Obtain party index
-    let serialized = serde_json::to_string(&IssueIndexMsg {
-        parties,
-        party_id,
-        party_name,
-    })
-    .context("serialize message")?;
-    let index = client
-        .issue_index(&serialized)
-        .await
-        .context("issue an index")?;
-
-    // Construct channel of incoming messages
-    let incoming = client
-        .subscribe()
-        .await
-        .context("subscribe")?
-        .and_then(|msg| async move {
-            serde_json::from_str::<Msg<M>>(&msg).context("deserialize message")
-        });
-
-    // Ignore incoming messages addressed to someone else
-    let incoming = incoming.try_filter(move |msg| {
-        futures::future::ready(
-            msg.sender != index && (msg.receiver.is_none() || msg.receiver == Some(index)),
-        )
-    });
-
-    // Construct channel of outgoing messages
-    let outgoing = futures::sink::unfold(client, |client, message: Msg<M>| async move {
-        let serialized = serde_json::to_string(&message).context("serialize message")?;
-        client
-            .broadcast(&serialized)
-            .await
-            .context("broadcast message")?;
-        Ok::<_, anyhow::Error>(client)
-    });
-
-    Ok((index, incoming, outgoing))
-}
+use crate::error::TssError;
+use crate::utils::common::{get_progress, IssueIndexMsg};
+use crate::utils::transport::Transport;
 
+/// [Transport] over HTTP state manager, messages are received as server-sent events
 pub struct SmClient {
     request_id: String,
     token: String,
+    address: surf::Url,
     http_client: surf::Client,
 }
 
 impl SmClient {
-    pub fn new(request_id: &str, token: &str, address: surf::Url, room_id: &str) -> Result<Self> {
+    pub fn new(request_id: &str, token: &str, address: surf::Url) -> Result<Self> {
         let config = surf::Config::new()
-            .set_base_url(address.join(&format!("rooms/{}/", room_id))?)
+            .set_base_url(address.clone())
             .set_timeout(None);
         Ok(Self {
             request_id: request_id.to_owned(),
             token: token.to_owned(),
+            address,
             http_client: config.try_into()?,
         })
     }
 
-    pub async fn issue_index(&self, message: &str) -> Result<u16> {
+    /// Client of the state manager at `address` named by a request of the app
+    pub fn connect(request_id: &str, token: &str, address: &str) -> Result<Self, TssError> {
+        let address = surf::Url::parse(address)
+            .map_err(|e| TssError::invalid_input(format_args!("state manager address: {}", e)))?;
+        Self::new(request_id, token, address).map_err(TssError::from)
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for SmClient {
+    async fn issue_index(&self, room_id: &str, msg: &IssueIndexMsg) -> Result<u16> {
+        let message = serde_json::to_string(msg).context("serialize message")?;
         let response = self
             .http_client
-            .post("issue_unique_idx")
+            .post(format!("rooms/{}/issue_unique_idx", room_id))
             .header("Content-Type", "application/json")
             .header("X-Request-ID", self.request_id.as_str())
             .header("X-Token", self.token.as_str())
             .body(message)
             .recv_json::<IssuedUniqueIdx>()
             .await
-            .map_err(|e| e.into_inner())?;
+            .map_err(TssError::transport)?;
         Ok(response.unique_idx)
     }
 
-    pub async fn broadcast(&self, message: &str) -> Result<()> {
-        self.http_client
-            .post("broadcast")
+    async fn subscribe(&self, room_id: &str) -> Result<LocalBoxStream<'static, Result<String>>> {
+        let response = self
+            .http_client
+            .get(format!("rooms/{}/subscribe", room_id))
             .header("X-Request-ID", self.request_id.as_str())
             .header("X-Token", self.token.as_str())
-            .body(message)
             .await
-            .map_err(|e| e.into_inner())?;
-        Ok(())
+            .map_err(TssError::transport)?;
+        let events = async_sse::decode(response);
+        Ok(events
+            .filter_map(|msg| async {
+                match msg {
+                    Ok(async_sse::Event::Message(msg)) => Some(
+                        String::from_utf8(msg.into_bytes())
+                            .context("SSE message is not valid UTF-8 string"),
+                    ),
+                    Ok(_) => {
+                        // ignore other types of events
+                        None
+                    }
+                    Err(e) => Some(Err(TssError::transport(e).into())),
+                }
+            })
+            .boxed_local())
     }
 
-    pub async fn subscribe(&self) -> Result<impl Stream<Item = Result<String>>> {
-        let response = self
-            .http_client
-            .get("subscribe")
+    async fn broadcast(&self, room_id: &str, message: &str) -> Result<()> {
+        self.http_client
+            .post(format!("rooms/{}/broadcast", room_id))
             .header("X-Request-ID", self.request_id.as_str())
             .header("X-Token", self.token.as_str())
+            .body(message)
             .await
-            .map_err(|e| e.into_inner())?;
-        let events = async_sse::decode(response);
-        Ok(events.filter_map(|msg| async {
-            match msg {
-                Ok(async_sse::Event::Message(msg)) => Some(
-                    String::from_utf8(msg.into_bytes())
-                        .context("SSE message is not valid UTF-8 string"),
-                ),
-                Ok(_) => {
-                    // ignore other types of events
-                    None
-                }
-                Err(e) => Some(Err(e.into_inner())),
-            }
-        }))
+            .map_err(TssError::transport)?;
+        Ok(())
+    }
+
+    async fn members(&self, room_id: &str) -> Result<Vec<KeygenMember>> {
+        let progress =
+            get_progress(&self.request_id, &self.token, self.address.clone(), room_id).await?;
+        Ok(progress.members)
     }
 }
 
@@ -0,0 +1,370 @@
+//! Message relay used by protocols to reach other parties
+//!
+//! Every protocol runs in a room of the relay: parties get a unique index in the room, subscribe
+//! to messages sent to the room and broadcast their own messages to everyone in it. Messages
+//! addressed to a single party are broadcasted as well, encrypted for the receiver and filtered
+//! out by other parties in [join_computation], see [secure_channel](crate::utils::secure_channel).
+//!
+//! [SmClient](crate::utils::sm_client::SmClient) talks to the HTTP state manager,
+//! [InMemoryTransport] connects parties running in the same process.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use futures::channel::mpsc;
+use futures::stream::LocalBoxStream;
+use futures::{Sink, SinkExt, Stream, StreamExt, TryStreamExt};
+use round_based::Msg;
+use rustmodel::KeygenMember;
+use serde::{de::DeserializeOwned, Serialize};
+use surf::utils::async_trait;
+
+use crate::error::TssError;
+use crate::utils::common::IssueIndexMsg;
+use crate::utils::secure_channel::{Envelope, Handshake, Identity, Opener, Roster};
+
+/// Relay of protocol messages between parties of a room
+#[async_trait(?Send)]
+pub trait Transport {
+    /// Issues an index of the party in the room
+    ///
+    /// Party asks for a specific index with `msg.party_id`, otherwise it gets one of free
+    /// `msg.parties`.
+    async fn issue_index(&self, room_id: &str, msg: &IssueIndexMsg) -> Result<u16>;
+    /// Subscribes to messages broadcasted to the room
+    async fn subscribe(&self, room_id: &str) -> Result<LocalBoxStream<'static, Result<String>>>;
+    /// Sends a message to everyone in the room
+    async fn broadcast(&self, room_id: &str, message: &str) -> Result<()>;
+    /// Members who joined the room, reported back to the app with the keygen result
+    async fn members(&self, room_id: &str) -> Result<Vec<KeygenMember>>;
+}
+
+/// Joins the room and wraps it into channels of protocol messages
+///
+/// Returns index issued to the party, identity keys of the parties in the room, stream of messages
+/// addressed to the party and sink of outgoing messages which can be passed to
+/// [AsyncProtocol](round_based::AsyncProtocol). Channels are secured by a handshake with every
+/// other member of `parties`, so the call waits until all of them join the room.
+pub async fn join_computation<'t, T, M>(
+    transport: &'t T,
+    room_id: &str,
+    parties: Vec<u16>,
+    party_id: Option<u16>,
+    party_name: Option<String>,
+    identity: &Identity,
+) -> Result<(
+    u16,
+    Roster,
+    impl Stream<Item = Result<Msg<M>>>,
+    impl Sink<Msg<M>, Error = anyhow::Error> + 't,
+)>
+where
+    T: Transport + ?Sized,
+    M: Serialize + DeserializeOwned,
+{
+    // Obtain party index
+    let index = transport
+        .issue_index(
+            room_id,
+            &IssueIndexMsg {
+                parties: parties.clone(),
+                party_id,
+                party_name,
+                identity: Some(identity.public_key()),
+            },
+        )
+        .await
+        .context("issue an index")?;
+
+    let mut messages = transport
+        .subscribe(room_id)
+        .await
+        .context("subscribe")?
+        .and_then(|msg| async move {
+            serde_json::from_str::<Envelope>(&msg).context("deserialize message")
+        })
+        .boxed_local();
+
+    // Exchange keys with other parties, keeping protocol messages of those who were faster
+    let mut handshake = Handshake::new(room_id, index, &parties, identity);
+    let serialized = serde_json::to_string(&handshake.message()).context("serialize message")?;
+    transport
+        .broadcast(room_id, &serialized)
+        .await
+        .context("broadcast handshake")?;
+    let mut early = vec![];
+    while !handshake.is_complete() {
+        match messages.next().await {
+            Some(Ok(envelope @ Envelope::Handshake { .. })) => {
+                handshake.receive(envelope).context("handshake")?
+            }
+            Some(envelope) => early.push(envelope),
+            None => return Err(TssError::transport("room closed during handshake").into()),
+        }
+    }
+    let session = Arc::new(handshake.finish()?);
+    let roster = session.roster();
+
+    // Ignore incoming messages addressed to someone else
+    let mut opener = Opener::new(session.clone());
+    let incoming = futures::stream::iter(early)
+        .chain(messages)
+        .try_filter(move |envelope| futures::future::ready(envelope.is_for(index)))
+        .map(move |envelope| envelope.and_then(|envelope| opener.open(envelope)));
+
+    // Construct channel of outgoing messages
+    let outgoing = futures::sink::unfold(
+        (transport, room_id.to_owned(), session, 0u64),
+        |(transport, room_id, session, seq), message: Msg<M>| async move {
+            let envelope = session.seal(seq, message)?;
+            let serialized = serde_json::to_string(&envelope).context("serialize message")?;
+            transport
+                .broadcast(&room_id, &serialized)
+                .await
+                .context("broadcast message")?;
+            Ok::<_, anyhow::Error>((transport, room_id, session, seq + 1))
+        },
+    );
+
+    Ok((index, roster, incoming, outgoing))
+}
+
+/// Joins the room of a protocol run by the subset `parties` of the key holders
+///
+/// Parties take their key indexes in the room, so that the roster of the key applies to them, while
+/// messages number parties by their position in `parties` (starting from 1) as protocols of a
+/// signer set expect. Returns position of the party and the rest of [join_computation] output.
+pub async fn join_subset_computation<'t, T, M>(
+    transport: &'t T,
+    room_id: &str,
+    parties: Vec<u16>,
+    party_id: u16,
+    identity: &Identity,
+) -> Result<(
+    u16,
+    Roster,
+    impl Stream<Item = Result<Msg<M>>>,
+    impl Sink<Msg<M>, Error = anyhow::Error> + 't,
+)>
+where
+    T: Transport + ?Sized,
+    M: Serialize + DeserializeOwned,
+{
+    let position = position_of(&parties, party_id)?;
+    let (_, roster, incoming, outgoing) = join_computation(
+        transport,
+        room_id,
+        parties.clone(),
+        Some(party_id),
+        None,
+        identity,
+    )
+    .await?;
+
+    let members = parties.clone();
+    let incoming = incoming
+        .and_then(move |msg| futures::future::ready(renumber(msg, |i| position_of(&members, i))));
+    let outgoing = outgoing
+        .with(move |msg: Msg<M>| futures::future::ready(renumber(msg, |k| party_at(&parties, k))));
+    Ok((position, roster, incoming, outgoing))
+}
+
+fn position_of(parties: &[u16], i: u16) -> Result<u16> {
+    parties
+        .iter()
+        .position(|&j| j == i)
+        .map(|k| k as u16 + 1)
+        .ok_or_else(|| anyhow!("party {} is not one of the parties {:?}", i, parties))
+}
+
+fn party_at(parties: &[u16], position: u16) -> Result<u16> {
+    usize::from(position)
+        .checked_sub(1)
+        .and_then(|k| parties.get(k))
+        .copied()
+        .ok_or_else(|| anyhow!("no party at position {} of {:?}", position, parties))
+}
+
+fn renumber<M>(msg: Msg<M>, index: impl Fn(u16) -> Result<u16>) -> Result<Msg<M>> {
+    Ok(Msg {
+        sender: index(msg.sender)?,
+        receiver: msg.receiver.map(&index).transpose()?,
+        body: msg.body,
+    })
+}
+
+/// Relay connecting parties within a single process
+///
+/// Clones share the same rooms, so every party gets its own clone. Subscribers receive all
+/// messages broadcasted to the room since it was created, thus a party which subscribes late
+/// doesn't miss messages of the others.
+#[derive(Clone, Default)]
+pub struct InMemoryTransport {
+    rooms: Arc<Mutex<HashMap<String, Room>>>,
+}
+
+#[derive(Default)]
+struct Room {
+    issued: BTreeSet<u16>,
+    history: Vec<String>,
+    subscribers: Vec<mpsc::UnboundedSender<String>>,
+}
+
+impl InMemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for InMemoryTransport {
+    async fn issue_index(&self, room_id: &str, msg: &IssueIndexMsg) -> Result<u16> {
+        let mut rooms = self
+            .rooms
+            .lock()
+            .map_err(|_| anyhow!("rooms lock poisoned"))?;
+        let room = rooms.entry(room_id.to_owned()).or_default();
+        let index = match msg.party_id {
+            Some(party_id) if !msg.parties.contains(&party_id) => {
+                return Err(anyhow!(
+                    "index {} is not one of the parties of room {}",
+                    party_id,
+                    room_id
+                ))
+            }
+            Some(party_id) => party_id,
+            None => msg
+                .parties
+                .iter()
+                .copied()
+                .find(|i| !room.issued.contains(i))
+                .ok_or_else(|| anyhow!("room {} is full", room_id))?,
+        };
+        room.issued.insert(index);
+        Ok(index)
+    }
+
+    async fn subscribe(&self, room_id: &str) -> Result<LocalBoxStream<'static, Result<String>>> {
+        let mut rooms = self
+            .rooms
+            .lock()
+            .map_err(|_| anyhow!("rooms lock poisoned"))?;
+        let room = rooms.entry(room_id.to_owned()).or_default();
+        let (sender, receiver) = mpsc::unbounded();
+        room.subscribers.push(sender);
+        let history = futures::stream::iter(room.history.clone());
+        Ok(history.chain(receiver).map(Ok).boxed_local())
+    }
+
+    async fn broadcast(&self, room_id: &str, message: &str) -> Result<()> {
+        let mut rooms = self
+            .rooms
+            .lock()
+            .map_err(|_| anyhow!("rooms lock poisoned"))?;
+        let room = rooms.entry(room_id.to_owned()).or_default();
+        room.history.push(message.to_owned());
+        room.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(message.to_owned()).is_ok());
+        Ok(())
+    }
+
+    /// Parties in memory join without a device name, so there are no members to report
+    async fn members(&self, _room_id: &str) -> Result<Vec<KeygenMember>> {
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::SinkExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn in_memory_transport_routes_messages() {
+        let transport = InMemoryTransport::new();
+        let identities = [
+            Identity::generate(),
+            Identity::generate(),
+            Identity::generate(),
+        ];
+        let join = |identity| {
+            join_computation::<_, String>(&transport, "room", vec![1, 2, 3], None, None, identity)
+        };
+        let (party1, party2, party3) = futures::join!(
+            join(&identities[0]),
+            join(&identities[1]),
+            join(&identities[2])
+        );
+        let (i1, roster, _incoming1, outgoing1) = party1.unwrap();
+        let (i2, _, incoming2, _outgoing2) = party2.unwrap();
+        let (i3, _, incoming3, _outgoing3) = party3.unwrap();
+        assert_eq!((i1, i2, i3), (1, 2, 3));
+        assert_eq!(roster[&3], identities[2].public_key());
+
+        tokio::pin!(outgoing1);
+        outgoing1
+            .send(Msg {
+                sender: 1,
+                receiver: Some(3),
+                body: "p2p".to_owned(),
+            })
+            .await
+            .unwrap();
+        outgoing1
+            .send(Msg {
+                sender: 1,
+                receiver: None,
+                body: "broadcast".to_owned(),
+            })
+            .await
+            .unwrap();
+
+        tokio::pin!(incoming2);
+        let msg = incoming2.next().await.unwrap().unwrap();
+        assert_eq!((msg.receiver, msg.body.as_str()), (None, "broadcast"));
+
+        tokio::pin!(incoming3);
+        let msg = incoming3.next().await.unwrap().unwrap();
+        assert_eq!((msg.receiver, msg.body.as_str()), (Some(3), "p2p"));
+
+        // relay can't read messages addressed to a single party
+        let rooms = transport.rooms.lock().unwrap();
+        assert!(!rooms["room"]
+            .history
+            .iter()
+            .any(|msg| msg.contains(r#"\"p2p\""#)));
+    }
+
+    #[tokio::test]
+    async fn subset_computation_numbers_parties_by_position() {
+        let transport = InMemoryTransport::new();
+        let identities = [Identity::generate(), Identity::generate()];
+        let join = |party_id, identity| {
+            join_subset_computation::<_, String>(&transport, "room", vec![1, 3], party_id, identity)
+        };
+        let (party1, party3) = futures::join!(join(1, &identities[0]), join(3, &identities[1]));
+        let (i1, roster, _incoming1, outgoing1) = party1.unwrap();
+        let (i3, _, incoming3, _outgoing3) = party3.unwrap();
+        assert_eq!((i1, i3), (1, 2));
+        // roster keeps key indexes
+        assert_eq!(roster[&3], identities[1].public_key());
+
+        tokio::pin!(outgoing1);
+        outgoing1
+            .send(Msg {
+                sender: 1,
+                receiver: Some(2),
+                body: "p2p".to_owned(),
+            })
+            .await
+            .unwrap();
+        tokio::pin!(incoming3);
+        let msg = incoming3.next().await.unwrap().unwrap();
+        assert_eq!(
+            (msg.sender, msg.receiver, msg.body.as_str()),
+            (1, Some(2), "p2p")
+        );
+    }
+}